@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf};
+use zk_por_core::{
+    anchor::{AnchorCommitment, ETH_ANCHOR_FUNCTION},
+    error::PoRError,
+    Info, Proof,
+};
+
+use super::verifier::verify_global;
+
+/// The anchor payloads of a round, with the commitment they hold in readable form.
+#[derive(Serialize, Deserialize)]
+struct AnchorFile {
+    round_num: u64,
+    root_hash: String,
+    vd_digest: String,
+    equity_sum: u64,
+    debt_sum: u64,
+    /// The sha256 hash of the commitment in hex, which is the one in the OP_RETURN output.
+    commitment_hash: String,
+    /// The script of the OP_RETURN output in hex.
+    btc_op_return_script: String,
+    eth_function: String,
+    /// The calldata of `eth_function` in 0x-prefixed hex.
+    eth_calldata: String,
+}
+
+/// The commitment of the round of the root proof, which is verified first against the circuit of the round, and its global info.
+fn load_commitment(
+    global_proof_path: PathBuf,
+    global_info_path: PathBuf,
+) -> Result<AnchorCommitment, PoRError> {
+    verify_global(global_proof_path.clone(), true, false)?;
+    let proof = Proof::load(&global_proof_path)?;
+    let info_bytes = fs::read(&global_info_path).map_err(|e| {
+        PoRError::InvalidParameter(format!(
+            "fail to open {:?} due to error {:?}",
+            global_info_path, e
+        ))
+    })?;
+    let info: Info = serde_json::from_slice(&info_bytes).map_err(|e| {
+        PoRError::InvalidParameter(format!("fail to parse global info due to error {:?}", e))
+    })?;
+    AnchorCommitment::new(&proof, &info)
+}
+
+/// Build the BTC OP_RETURN script and the ethereum calldata committing to the round, and write them to `output_path`. Nothing is
+/// broadcast.
+pub fn anchor(
+    global_proof_path: PathBuf,
+    global_info_path: PathBuf,
+    output_path: PathBuf,
+) -> Result<(), PoRError> {
+    let commitment = load_commitment(global_proof_path, global_info_path)?;
+    let (btc_op_return_script, eth_calldata) =
+        (commitment.btc_op_return_script(), commitment.eth_calldata());
+    commitment.verify_payloads(&btc_op_return_script, &eth_calldata)?;
+
+    let anchor_file = AnchorFile {
+        round_num: commitment.round_num,
+        root_hash: hex::encode(commitment.root_hash),
+        vd_digest: hex::encode(commitment.vd_digest),
+        equity_sum: commitment.equity_sum,
+        debt_sum: commitment.debt_sum,
+        commitment_hash: hex::encode(commitment.commitment_hash()),
+        btc_op_return_script: hex::encode(&btc_op_return_script),
+        eth_function: ETH_ANCHOR_FUNCTION.to_string(),
+        eth_calldata: format!("0x{}", hex::encode(&eth_calldata)),
+    };
+    let anchor_bytes =
+        serde_json::to_vec_pretty(&anchor_file).expect("fail to serialize anchor payloads");
+    fs::write(&output_path, anchor_bytes)?;
+
+    println!(
+        "the anchor payloads of round {} are written to {:?}",
+        commitment.round_num, output_path
+    );
+    println!("btc OP_RETURN script: {}", anchor_file.btc_op_return_script);
+    println!("eth calldata of {}: {}", anchor_file.eth_function, anchor_file.eth_calldata);
+    Ok(())
+}
+
+/// Re-parse the payloads in the anchor file, which must commit to the round of the root proof and its global info.
+pub fn verify_anchor(
+    anchor_path: PathBuf,
+    global_proof_path: PathBuf,
+    global_info_path: PathBuf,
+    verbose: bool,
+) -> Result<(), PoRError> {
+    let commitment = load_commitment(global_proof_path, global_info_path)?;
+    let anchor_bytes = fs::read(&anchor_path).map_err(|e| {
+        PoRError::InvalidParameter(format!("fail to open {:?} due to error {:?}", anchor_path, e))
+    })?;
+    let anchor_file: AnchorFile = serde_json::from_slice(&anchor_bytes)
+        .map_err(|e| PoRError::InvalidAnchor(format!("fail to parse anchor file: {}", e)))?;
+    let decode = |what: &str, hex_str: &str| {
+        hex::decode(hex_str.trim_start_matches("0x"))
+            .map_err(|e| PoRError::InvalidAnchor(format!("fail to decode {}: {}", what, e)))
+    };
+    let btc_op_return_script = decode("OP_RETURN script", &anchor_file.btc_op_return_script)?;
+    let eth_calldata = decode("calldata", &anchor_file.eth_calldata)?;
+    commitment.verify_payloads(&btc_op_return_script, &eth_calldata)?;
+    if anchor_file.commitment_hash != hex::encode(commitment.commitment_hash()) {
+        return Err(PoRError::InvalidAnchor(
+            "the commitment hash does not match the round".to_string(),
+        ));
+    }
+
+    if verbose {
+        println!(
+            "successfully verify the anchor payloads of round {}, root hash {}, total equity {} and debt {}",
+            commitment.round_num,
+            hex::encode(commitment.root_hash),
+            commitment.equity_sum,
+            commitment.debt_sum
+        );
+    }
+    Ok(())
+}
@@ -1,20 +1,46 @@
 use super::{constant::DEFAULT_BATCH_SIZE, prover::calculate_per_parse_account_num};
-use plonky2_field::types::{Field, PrimeField64};
+use plonky2_field::types::PrimeField64;
 
 use std::str::FromStr;
 use zk_por_core::{
     config::ProverConfig,
     error::PoRError,
     parser::{AccountParser, FileAccountReader, FileManager, FilesCfg},
-    types::F,
+    types::{F, HAIRCUT_LOG, MAX_POSITIVE_AMOUNT_LOG},
+    valuation::Valuation,
 };
 use zk_por_tracing::{init_tracing, TraceConfig};
 
+const MAX_VALUED_AMOUNT: u128 = (1 << MAX_POSITIVE_AMOUNT_LOG) - 1;
+
+/// Value the token amounts over integers, mirroring `Valuation::value` but without wrapping around the field.
+fn value_amounts(amounts: &[F], valuation: &Valuation) -> Vec<u128> {
+    let prices = valuation.prices.clone().unwrap_or(vec![1; amounts.len()]);
+    amounts
+        .iter()
+        .zip(prices.iter())
+        .map(|(amount, price)| amount.to_canonical_u64() as u128 * *price as u128)
+        .collect()
+}
+
+/// The haircut collateral of the valued equity, mirroring `Valuation::collateral`.
+fn collateral(valued_equity: &[u128], valuation: &Valuation) -> u128 {
+    match &valuation.haircuts {
+        Some(haircuts) => valued_equity
+            .iter()
+            .zip(haircuts.iter())
+            .map(|(value, haircut)| (value * *haircut as u128) >> HAIRCUT_LOG)
+            .sum(),
+        None => valued_equity.iter().sum(),
+    }
+}
+
 pub fn check_non_neg_user(cfg: ProverConfig) -> Result<(), PoRError> {
     let trace_cfg: TraceConfig = cfg.log.into();
     let _g = init_tracing(trace_cfg);
 
     let batch_size = cfg.prover.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    let valuation = Valuation::from_config(&cfg.prover)?;
     let file_manager = FileManager {};
     let mut account_parser = FileAccountReader::new(
         FilesCfg {
@@ -56,15 +82,27 @@ pub fn check_non_neg_user(cfg: ProverConfig) -> Result<(), PoRError> {
         offset += per_parse_account_num;
 
         for account in accounts {
-            let equity_sum =
-                account.equity.iter().fold(F::ZERO, |acc, x| acc + *x).to_canonical_u64();
-            let debt_sum = account.debt.iter().fold(F::ZERO, |acc, x| acc + *x).to_canonical_u64();
+            let valued_equity = value_amounts(&account.equity, &valuation);
+            let equity_sum = valued_equity.iter().sum::<u128>();
+            let debt_sum = value_amounts(&account.debt, &valuation).iter().sum::<u128>();
+
+            if equity_sum > MAX_VALUED_AMOUNT || debt_sum > MAX_VALUED_AMOUNT {
+                tracing::error!(
+                    "account {} exceeds the max amount, the equity sum {}, the debt sum {}",
+                    account.id,
+                    equity_sum,
+                    debt_sum
+                );
+                return Err(PoRError::InvalidUser);
+            }
 
-            if equity_sum < debt_sum {
+            let collateral_sum = collateral(&valued_equity, &valuation);
+            if collateral_sum < debt_sum {
                 tracing::error!(
-                    "account {} has negative equity, the equity sum {}, the debt sum {}",
+                    "account {} has negative equity, the equity sum {}, the collateral sum {}, the debt sum {}",
                     account.id,
                     equity_sum,
+                    collateral_sum,
                     debt_sum
                 );
                 return Err(PoRError::InvalidUser);
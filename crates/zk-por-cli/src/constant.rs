@@ -1,6 +1,19 @@
-pub const RECURSION_BRANCHOUT_NUM: usize = 64;
+use plonky2::plonk::circuit_data::CircuitConfig;
+pub use zk_por_core::pipeline::{GLOBAL_INFO_FILENAME, GLOBAL_PROOF_FILENAME, USER_PROOF_DIRNAME};
+use zk_por_core::{
+    account::LeafHashMode, circuit_config::STANDARD_CONFIG, pipeline::UserProofFormat,
+};
+
+pub const DEFAULT_RECURSION_BRANCHOUT_NUM: usize = 64;
+pub const SUPPORTED_RECURSION_BRANCHOUT_NUMS: [usize; 4] = [8, 16, 32, 64];
 pub const DEFAULT_BATCH_SIZE: usize = 1024;
-pub const GLOBAL_PROOF_FILENAME: &str = "sum_proof_data.json";
-pub const GLOBAL_INFO_FILENAME: &str = "global_info.json";
-pub const USER_PROOF_DIRNAME: &str = "user_proofs";
+pub const DEFAULT_LEAF_HASH_MODE: LeafHashMode = LeafHashMode::PerToken;
+pub const DEFAULT_HIDE_LIABILITIES: bool = false;
+pub const DEFAULT_USER_PROOF_FORMAT: UserProofFormat = UserProofFormat::Json;
+pub const DEFAULT_COMPRESS_ROOT_PROOF: bool = false;
+pub const DEFAULT_WRAP_ROOT_PROOF: bool = false;
+// the config of the wrap circuit, with which a verifier rebuilds it.
+pub const WRAP_CIRCUIT_CONFIG: CircuitConfig = STANDARD_CONFIG;
+pub const SOLVENCY_PROOF_FILENAME: &str = "solvency_proof.json";
+pub const CHECKPOINT_DIRNAME: &str = "checkpoint";
 pub const DEFAULT_USER_PROOF_FILE_PATTERN: &str = "*_inclusion_proof.json";
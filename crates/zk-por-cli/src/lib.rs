@@ -1,4 +1,8 @@
+pub mod anchor;
 pub mod checker;
 pub mod constant;
+pub mod proof_file;
 pub mod prover;
+pub mod reserves;
 pub mod verifier;
+pub mod worker;
@@ -6,12 +6,16 @@ use std::{
 
 use clap::{Parser, Subcommand};
 use zk_por_cli::{
+    anchor::{anchor, verify_anchor},
     checker::check_non_neg_user,
     constant::{DEFAULT_USER_PROOF_FILE_PATTERN, GLOBAL_PROOF_FILENAME},
+    proof_file::{convert_proof, inspect_proof},
     prover::prove,
-    verifier::{verify_global, verify_user},
+    reserves::verify_reserves,
+    verifier::{verify_global, verify_solvency, verify_user, verify_wrap, UserProofSource},
+    worker::serve_worker,
 };
-use zk_por_core::error::PoRError;
+use zk_por_core::{error::PoRError, proof_file::ProofFormat};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -31,12 +35,21 @@ pub enum ZkPorCommands {
         cfg_path: String, // path to config file
         #[arg(short, long)]
         output_path: String, // path to output file
+        #[arg(long)]
+        resume: bool, // resume from the checkpoint in the output path
     },
     CheckNonNegUser {
         #[arg(short, long)]
         cfg_path: String, // path to config file
     },
 
+    Worker {
+        #[arg(short, long)]
+        cfg_path: String, // path to the config file of the prover
+        #[arg(short, long)]
+        listen_addr: String, // address to accept the coordinator on
+    },
+
     VerifyGlobal {
         #[arg(short, long)]
         proof_path: String,
@@ -45,8 +58,73 @@ pub enum ZkPorCommands {
     VerifyUser {
         #[arg(short, long)]
         global_proof_path: String,
+        #[arg(short, long, required_unless_present = "user_proof_archive_path")]
+        user_proof_path_pattern: Option<String>,
+        #[arg(long, conflicts_with = "user_proof_path_pattern")]
+        user_proof_archive_path: Option<String>, // path to the user proof archive, used instead of the user proof files
+        #[arg(long, requires = "user_proof_archive_path", value_delimiter = ',')]
+        user_ids: Option<Vec<String>>, // comma-separated ids of the users to verify in the archive, all users if absent
+    },
+
+    InspectProof {
+        #[arg(short, long)]
+        proof_path: String, // path to the global proof file, in json or in the binary format
+    },
+
+    ConvertProof {
+        #[arg(short, long)]
+        proof_path: String, // path to the global proof file, in json or in the binary format
+        #[arg(short, long)]
+        output_path: String, // path to the converted proof file
+        #[arg(short, long)]
+        format: ProofFormat, // json or binary
+        #[arg(long)]
+        compress: bool, // compress the root proof
+    },
+
+    VerifySolvency {
+        #[arg(short, long)]
+        proof_path: String,
+        #[arg(short, long)]
+        reserves_path: String, // path to the signed reserve addresses, whose reserves the solvency proof must expose
+        #[arg(short, long, required_unless_present = "global_info_path")]
+        global_proof_path: Option<String>,
+        #[arg(long, conflicts_with = "global_proof_path")]
+        global_info_path: Option<String>, // used instead of the global proof if the root proof is not published
+    },
+
+    VerifyWrap {
+        #[arg(short, long)]
+        global_proof_path: String,
+        #[arg(short, long)]
+        wrap_dir: String, // directory of the wrap proof and the wrap circuit data
+    },
+
+    VerifyReserves {
+        #[arg(short, long)]
+        reserves_path: String, // path to the signed reserve addresses
+        #[arg(short, long)]
+        global_proof_path: Option<String>,
+        #[arg(long)]
+        global_info_path: Option<String>, // used instead of the global proof, whose liabilities are not verified
+    },
+
+    Anchor {
+        #[arg(short, long)]
+        global_proof_path: String,
+        #[arg(long)]
+        global_info_path: String,
         #[arg(short, long)]
-        user_proof_path_pattern: String,
+        output_path: String, // path to the anchor file of the payloads
+    },
+
+    VerifyAnchor {
+        #[arg(short, long)]
+        anchor_path: String,
+        #[arg(short, long)]
+        global_proof_path: String,
+        #[arg(long)]
+        global_info_path: String,
     },
 
     ShowCommitHash,
@@ -55,12 +133,12 @@ pub enum ZkPorCommands {
 impl Execute for Option<ZkPorCommands> {
     fn execute(&self) -> std::result::Result<(), PoRError> {
         match self {
-            Some(ZkPorCommands::Prove { cfg_path, output_path }) => {
+            Some(ZkPorCommands::Prove { cfg_path, output_path, resume }) => {
                 let cfg = zk_por_core::config::ProverConfig::load(&cfg_path)
                     .map_err(|e| PoRError::ConfigError(e))?;
                 let prover_cfg = cfg.try_deserialize().unwrap();
                 let output_path = PathBuf::from_str(&output_path).unwrap();
-                prove(prover_cfg, output_path)
+                prove(prover_cfg, output_path, *resume)
             }
 
             Some(ZkPorCommands::CheckNonNegUser { cfg_path }) => {
@@ -70,14 +148,104 @@ impl Execute for Option<ZkPorCommands> {
                 check_non_neg_user(prover_cfg)
             }
 
+            Some(ZkPorCommands::Worker { cfg_path, listen_addr }) => {
+                let cfg = zk_por_core::config::ProverConfig::load(&cfg_path)
+                    .map_err(|e| PoRError::ConfigError(e))?;
+                let prover_cfg = cfg.try_deserialize().unwrap();
+                serve_worker(prover_cfg, listen_addr)
+            }
+
             Some(ZkPorCommands::VerifyGlobal { proof_path: global_proof_path }) => {
                 let global_proof_path = PathBuf::from_str(&global_proof_path).unwrap();
                 verify_global(global_proof_path, true, true)
             }
 
-            Some(ZkPorCommands::VerifyUser { global_proof_path, user_proof_path_pattern }) => {
+            Some(ZkPorCommands::VerifyUser {
+                global_proof_path,
+                user_proof_path_pattern,
+                user_proof_archive_path,
+                user_ids,
+            }) => {
+                let global_proof_path = PathBuf::from_str(&global_proof_path).unwrap();
+                let user_proof_source = match user_proof_archive_path {
+                    Some(path) => UserProofSource::Archive {
+                        path: PathBuf::from_str(path).unwrap(),
+                        user_ids: user_ids.clone(),
+                    },
+                    None => {
+                        UserProofSource::Files { pattern: user_proof_path_pattern.clone().unwrap() }
+                    }
+                };
+                verify_user(global_proof_path, &user_proof_source, true)
+            }
+
+            Some(ZkPorCommands::InspectProof { proof_path }) => {
+                inspect_proof(PathBuf::from_str(&proof_path).unwrap())
+            }
+
+            Some(ZkPorCommands::ConvertProof { proof_path, output_path, format, compress }) => {
+                let proof_path = PathBuf::from_str(&proof_path).unwrap();
+                let output_path = PathBuf::from_str(&output_path).unwrap();
+                convert_proof(proof_path, output_path, *format, *compress)
+            }
+
+            Some(ZkPorCommands::VerifySolvency {
+                proof_path,
+                reserves_path,
+                global_proof_path,
+                global_info_path,
+            }) => {
+                let solvency_proof_path = PathBuf::from_str(&proof_path).unwrap();
+                let reserves_path = PathBuf::from_str(&reserves_path).unwrap();
+                let global_proof_path =
+                    global_proof_path.as_ref().map(|path| PathBuf::from_str(path).unwrap());
+                let global_info_path =
+                    global_info_path.as_ref().map(|path| PathBuf::from_str(path).unwrap());
+                verify_solvency(
+                    solvency_proof_path,
+                    reserves_path,
+                    global_proof_path,
+                    global_info_path,
+                    true,
+                    true,
+                )
+            }
+
+            Some(ZkPorCommands::VerifyWrap { global_proof_path, wrap_dir }) => {
+                let global_proof_path = PathBuf::from_str(&global_proof_path).unwrap();
+                let wrap_dir = PathBuf::from_str(&wrap_dir).unwrap();
+                verify_wrap(global_proof_path, wrap_dir, true)
+            }
+
+            Some(ZkPorCommands::VerifyReserves {
+                reserves_path,
+                global_proof_path,
+                global_info_path,
+            }) => {
+                let reserves_path = PathBuf::from_str(&reserves_path).unwrap();
+                let global_proof_path =
+                    global_proof_path.as_ref().map(|path| PathBuf::from_str(path).unwrap());
+                let global_info_path =
+                    global_info_path.as_ref().map(|path| PathBuf::from_str(path).unwrap());
+                verify_reserves(reserves_path, global_proof_path, global_info_path, true)
+            }
+
+            Some(ZkPorCommands::Anchor { global_proof_path, global_info_path, output_path }) => {
+                let global_proof_path = PathBuf::from_str(&global_proof_path).unwrap();
+                let global_info_path = PathBuf::from_str(&global_info_path).unwrap();
+                let output_path = PathBuf::from_str(&output_path).unwrap();
+                anchor(global_proof_path, global_info_path, output_path)
+            }
+
+            Some(ZkPorCommands::VerifyAnchor {
+                anchor_path,
+                global_proof_path,
+                global_info_path,
+            }) => {
+                let anchor_path = PathBuf::from_str(&anchor_path).unwrap();
                 let global_proof_path = PathBuf::from_str(&global_proof_path).unwrap();
-                verify_user(global_proof_path, user_proof_path_pattern, true)
+                let global_info_path = PathBuf::from_str(&global_info_path).unwrap();
+                verify_anchor(anchor_path, global_proof_path, global_info_path, true)
             }
 
             Some(ZkPorCommands::ShowCommitHash) => {
@@ -105,7 +273,11 @@ impl Execute for Option<ZkPorCommands> {
                     .to_string();
 
                 let global_result = verify_global(global_proof_path.clone(), false, false);
-                let user_result = verify_user(global_proof_path, &user_proof_path_pattern, false);
+                let user_result = verify_user(
+                    global_proof_path,
+                    &UserProofSource::Files { pattern: user_proof_path_pattern },
+                    false,
+                );
 
                 if global_result.is_ok() {
                     println!("Total sum and non-negative constraint validation passed");
@@ -139,8 +311,11 @@ fn main() {
     let duration = start.elapsed();
     println!("Execution result: {:?}, duration: {:?}", r, duration);
 
-    let is_prove_command =
-        matches!(cli.command, Some(ZkPorCommands::Prove { cfg_path: _, output_path: _ }));
+    let is_prove_command = matches!(
+        cli.command,
+        Some(ZkPorCommands::Prove { cfg_path: _, output_path: _, resume: _ })
+            | Some(ZkPorCommands::Worker { cfg_path: _, listen_addr: _ })
+    );
     if !is_prove_command {
         println!("Press Enter to quit...");
         stdin().read_exact(&mut [0]).unwrap();
@@ -0,0 +1,54 @@
+use std::{fs, path::PathBuf};
+use zk_por_core::{error::PoRError, proof_file::ProofFormat, Proof};
+
+/// Print the format, the general info and the encoded sizes of a global proof file, without verifying the proof.
+pub fn inspect_proof(proof_path: PathBuf) -> Result<(), PoRError> {
+    let bytes = fs::read(&proof_path).map_err(|e| {
+        PoRError::InvalidParameter(format!("fail to open {:?} due to error {:?}", proof_path, e))
+    })?;
+    let format = ProofFormat::detect(&bytes);
+    let (proof, compressed) = Proof::decode(&bytes)?;
+    let general = &proof.general;
+
+    println!("format: {:?}, {} bytes, compressed root proof: {}", format, bytes.len(), compressed);
+    println!("round: {}, snapshot timestamp: {:?}", general.round_num, general.snapshot_timestamp);
+    println!(
+        "batch size: {}, last batch size: {:?}, recursion branch-out: {}",
+        general.batch_size, general.last_batch_size, general.recursion_branchout_num
+    );
+    println!("tokens: {:?}, leaf hash mode: {:?}", general.tokens, general.leaf_hash_mode);
+    println!("root verifier data digest: {:?}", proof.root_vd_digest.elements);
+    println!("public inputs: {}", proof.proof.public_inputs.len());
+    match &proof.circuits_info {
+        Some(circuits_info) => println!(
+            "recursive circuit configs: {}, root verifier data: {} bytes",
+            circuits_info.recursive_circuit_configs.len(),
+            circuits_info.root_verifier_data_hex.len() / 2
+        ),
+        None => println!(
+            "no circuits info, which the binary format and the compressed root proof require"
+        ),
+    }
+    Ok(())
+}
+
+/// Convert a global proof file in either format to `format`, with the compressed root proof if `compress`, which does not change the
+/// proof.
+pub fn convert_proof(
+    proof_path: PathBuf,
+    output_path: PathBuf,
+    format: ProofFormat,
+    compress: bool,
+) -> Result<(), PoRError> {
+    let proof = Proof::load(&proof_path)?;
+    proof.save(&output_path, format, compress)?;
+    println!(
+        "convert {:?} to {:?} in the {:?} format, compressed root proof: {}, {} bytes",
+        proof_path,
+        output_path,
+        format,
+        compress,
+        fs::metadata(&output_path)?.len()
+    );
+    Ok(())
+}
@@ -1,36 +1,46 @@
-use super::constant::{
-    DEFAULT_BATCH_SIZE, GLOBAL_INFO_FILENAME, GLOBAL_PROOF_FILENAME, RECURSION_BRANCHOUT_NUM,
-    USER_PROOF_DIRNAME,
+use super::{
+    constant::{
+        CHECKPOINT_DIRNAME, DEFAULT_BATCH_SIZE, DEFAULT_COMPRESS_ROOT_PROOF,
+        DEFAULT_HIDE_LIABILITIES, DEFAULT_LEAF_HASH_MODE, DEFAULT_RECURSION_BRANCHOUT_NUM,
+        DEFAULT_USER_PROOF_FORMAT, DEFAULT_WRAP_ROOT_PROOF, SOLVENCY_PROOF_FILENAME,
+        USER_PROOF_DIRNAME, WRAP_CIRCUIT_CONFIG,
+    },
+    verifier::check_recursion_branchout_num,
 };
-use indicatif::ProgressBar;
-use plonky2::{hash::hash_types::HashOut, util::serialization::DefaultGateSerializer};
-use plonky2_field::types::PrimeField64;
-use rayon::{iter::ParallelIterator, prelude::*};
+use plonky2::{plonk::circuit_data::CircuitConfig, util::serialization::DefaultGateSerializer};
 
 use std::{
     fs,
     fs::File,
     io::{BufWriter, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::{Arc, RwLock},
 };
 use zk_por_core::{
-    account::{persist_account_id_to_gmst_pos, Account},
-    circuit_config::{get_recursive_circuit_configs, STANDARD_CONFIG},
+    account::{LeafHashMode, SaltSeed},
+    checkpoint::{
+        hash_input_files, hash_salt_seed, Checkpoint, CheckpointParams, CheckpointProver,
+    },
+    circuit_config::{get_recursive_circuit_configs, STANDARD_CONFIG, STANDARD_ZK_CONFIG},
     circuit_registry::registry::CircuitRegistry,
     config::{ConfigProver, ProverConfig},
-    database::{init_db, PoRDB},
-    e2e::{batch_prove_accounts, prove_subproofs},
+    database::init_db,
+    distributed::{coordinator::Coordinator, protocol::RoundShape},
     error::PoRError,
-    global::{GlobalConfig, GlobalMst, GLOBAL_MST},
-    merkle_proof::MerkleProof,
-    merkle_sum_prover::circuits::merkle_sum_circuit::MerkleSumNodeTarget,
-    merkle_sum_tree::MerkleSumTree,
+    global::{GlobalConfig, GlobalMst},
     parser::{AccountParser, FileAccountReader, FileManager, FilesCfg},
-    recursive_prover::recursive_circuit::RecursiveTargets,
-    types::F,
-    CircuitsInfo, General, Info, Proof,
+    pipeline::{LocalProver, PipelineConfig, PipelineProver, ProvingPipeline},
+    proof_store::ProofStore,
+    reserves::ReserveSnapshot,
+    solvency_prover::{prover::SolvencyProver, solvency_circuit::build_solvency_circuit},
+    valuation::Valuation,
+    wrap_prover::{
+        artifacts::{WrapArtifacts, WRAP_DIRNAME},
+        prover::WrapProver,
+        wrap_circuit::build_wrap_circuit,
+    },
+    Proof, SolvencyProof,
 };
 use zk_por_tracing::{init_tracing, TraceConfig};
 
@@ -57,425 +67,367 @@ fn ensure_output_dir_empty(user_proof_dir: PathBuf) -> Result<(), PoRError> {
     return Ok(());
 }
 
-pub fn prove(cfg: ProverConfig, proof_output_path: PathBuf) -> Result<(), PoRError> {
+/// Prove the accounts in the user data path and dump the proofs to `proof_output_path`. The batch proofs of every parse round and the
+/// proofs of every recursive level are saved to a checkpoint in the output path, from which a crashed run continues if `resume`.
+pub fn prove(cfg: ProverConfig, proof_output_path: PathBuf, resume: bool) -> Result<(), PoRError> {
     let trace_cfg: TraceConfig = cfg.log.into();
 
     let _g = init_tracing(trace_cfg);
     let user_proof_output_path = proof_output_path.join(USER_PROOF_DIRNAME);
-    ensure_output_dir_empty(user_proof_output_path)?;
-
-    let mut database = init_db(cfg.db);
+    if resume {
+        // user proofs are only dumped after the root proof, hence the ones of a crashed run are overwritten.
+        fs::create_dir_all(&user_proof_output_path)?;
+    } else {
+        ensure_output_dir_empty(user_proof_output_path)?;
+    }
 
     let batch_size = cfg.prover.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
-    let token_num = cfg.prover.tokens.len();
+    let recursion_branchout_num =
+        cfg.prover.recursion_branchout_num.unwrap_or(DEFAULT_RECURSION_BRANCHOUT_NUM);
+    check_recursion_branchout_num(recursion_branchout_num)?;
+    let leaf_hash_mode = cfg.prover.leaf_hash_mode.unwrap_or(DEFAULT_LEAF_HASH_MODE);
     let batch_prove_threads_num = cfg.prover.batch_prove_threads_num;
     let recursive_prove_threads_num = cfg.prover.recursive_prove_threads_num;
+    let round_num = cfg.prover.round_no;
+    // the salts are derived from the round number unless an epoch is configured, such that the leaf hashes of a user are unlinkable
+    // across rounds by default.
+    let salt_epoch = cfg.prover.salt_epoch.unwrap_or(round_num);
+    // reusing proofs across rounds links the leaf hashes of unchanged users, hence it must be opted in by a fixed seed and epoch,
+    // without which the salts change every round and no proof would be reused anyway.
+    if cfg.prover.proof_store_dir.is_some()
+        && (cfg.prover.salt_seed.is_none() || cfg.prover.salt_epoch.is_none())
+    {
+        return Err(PoRError::InvalidParameter(
+            "proof_store_dir requires a configured salt_seed and salt_epoch, as the reused proofs make the leaf hashes of unchanged users linkable across the rounds of the epoch".to_string(),
+        ));
+    }
+    if resume && cfg.prover.salt_seed.is_none() {
+        return Err(PoRError::InvalidParameter(
+            "resuming requires a configured salt_seed, as the checkpointed proofs commit to the salts".to_string(),
+        ));
+    }
+    let salt_seed = match &cfg.prover.salt_seed {
+        Some(secret) => SaltSeed::from_secret(secret),
+        None => {
+            tracing::warn!("no salt seed is configured, use a random one for this round");
+            SaltSeed::rand()
+        }
+    };
+    let valuation = Valuation::from_config(&cfg.prover)?;
 
-    // the path to dump the final generated proof
-    let file_manager = FileManager {};
-    let mut account_parser = FileAccountReader::new(
-        FilesCfg {
-            dir: std::path::PathBuf::from_str(&cfg.prover.user_data_path).unwrap(),
-            batch_size: batch_size,
-            tokens: cfg.prover.tokens.clone(),
-        },
-        &file_manager,
-    );
-    account_parser.log_state();
-
-    let batch_num = account_parser.total_num_of_users().div_ceil(batch_size);
+    let checkpoint_dir = proof_output_path.join(CHECKPOINT_DIRNAME);
+    let config_hash = CheckpointParams {
+        round_no: round_num,
+        salt_epoch,
+        snapshot_timestamp: cfg.prover.snapshot_timestamp,
+        batch_size,
+        recursion_branchout_num,
+        tokens: &cfg.prover.tokens,
+        leaf_hash_mode,
+        valuation: &valuation,
+        salt_seed_hash: cfg.prover.salt_seed.as_deref().map(hash_salt_seed).unwrap_or_default(),
+    }
+    .hash();
+    let input_hash = hash_input_files(&cfg.prover.user_data_path)?;
+    let resumed_checkpoint = match resume {
+        true => Some(Checkpoint::resume(checkpoint_dir.clone(), &config_hash, &input_hash)?),
+        false => None,
+    };
 
-    match GLOBAL_MST.set(RwLock::new(GlobalMst::new(GlobalConfig {
-        num_of_tokens: token_num,
-        num_of_batches: batch_num,
-        batch_size: batch_size,
-        recursion_branchout_num: RECURSION_BRANCHOUT_NUM,
-    }))) {
-        Ok(_) => (),
-        Err(_) => {
-            panic!("set global mst error");
+    // a resumed run reuses the snapshot timestamp of the checkpoint, which is committed to by its recursive proofs.
+    let snapshot_timestamp = match resumed_checkpoint
+        .as_ref()
+        .map(|checkpoint| checkpoint.manifest.snapshot_timestamp)
+        .or(cfg.prover.snapshot_timestamp)
+    {
+        Some(timestamp) => timestamp,
+        None => {
+            tracing::warn!("no snapshot timestamp is configured, use the current time");
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("system time before unix epoch")
+                .as_secs()
         }
-    }
+    };
+    // verify the reserves before proving, such that an invalid reserve snapshot fails early.
+    let reserves = match &cfg.prover.reserves_path {
+        Some(path) => Some(ReserveSnapshot::load(path)?.verified_reserves(&cfg.prover.tokens)?),
+        None => None,
+    };
+
+    let account_parser = new_account_reader(&cfg.prover, batch_size);
+    account_parser.log_state();
+
+    // the accounts after the full batches are proven as a smaller last batch instead of being padded to a full batch.
+    let (batch_num, last_batch_size) =
+        GlobalConfig::split_batches(account_parser.total_num_of_users(), batch_size);
 
     let recursive_circuit_configs =
-        get_recursive_circuit_configs::<RECURSION_BRANCHOUT_NUM>(batch_num);
+        get_recursive_circuit_configs(batch_num, recursion_branchout_num);
     let recursive_level = recursive_circuit_configs.len();
 
     tracing::info!(
         "start to precompute circuits and empty proofs for {} recursive levels",
         recursive_level
     );
-    let batch_circuit_config = STANDARD_CONFIG;
-    let circuit_registry = CircuitRegistry::<RECURSION_BRANCHOUT_NUM>::init(
-        batch_size,
-        token_num,
-        batch_circuit_config.clone(),
-        recursive_circuit_configs.clone(),
-    );
-
-    tracing::info!(
-        "start to prove {} accounts with {} tokens, {} batch size, {} recursive level",
-        account_parser.total_num_of_users(),
-        token_num,
+    let circuit_registry = init_circuit_registry(
+        &cfg.prover,
         batch_size,
-        recursive_level,
+        last_batch_size,
+        recursion_branchout_num,
+        leaf_hash_mode,
+        &valuation,
+        STANDARD_CONFIG,
+        recursive_circuit_configs,
     );
 
-    let start = std::time::Instant::now();
-    let mut offset = 0;
-    let per_parse_account_num =
-        calculate_per_parse_account_num(batch_size, batch_prove_threads_num);
-
-    let mut parse_num = 0;
-    let mut batch_proofs = vec![];
-    let bar = ProgressBar::new(account_parser.total_num_of_users() as u64);
-    while offset < account_parser.total_num_of_users() {
-        parse_num += 1;
-        let mut accounts =
-            account_parser.read_n_accounts(offset, per_parse_account_num, &file_manager);
-
-        persist_account_id_to_gmst_pos(&mut database, &accounts, offset);
-
-        let account_num = accounts.len();
-        if account_num % batch_size != 0 {
-            let pad_num = batch_size - account_num % batch_size;
-            tracing::info!("in {} parse, account number {} is not a multiple of batch size {}, hence padding {} empty accounts", parse_num, account_num, batch_size,pad_num);
-            accounts.resize(account_num + pad_num, Account::get_empty_account(token_num));
+    // the batches and recursive nodes are proven by the workers if configured, otherwise in this process.
+    let mut coordinator = match &cfg.prover.worker_addrs {
+        Some(worker_addrs) => {
+            let shape =
+                RoundShape { batch_size, batch_num, last_batch_size, recursion_branchout_num };
+            Some(Coordinator::connect(
+                worker_addrs,
+                &circuit_registry,
+                shape,
+                cfg.prover.worker_secret.as_deref(),
+            )?)
         }
+        None => None,
+    };
 
-        assert_eq!(accounts.len() % batch_size, 0);
-
-        tracing::debug!(
-            "parse {} times, with number of accounts {}, number of batches {}",
-            parse_num,
-            account_num,
-            batch_num,
-        );
-
-        let msts: Vec<MerkleSumTree> = accounts
-            .par_chunks(batch_size)
-            .map(|account_batch| MerkleSumTree::new_tree_from_accounts(&account_batch.to_vec()))
-            .collect();
-
-        let global_mst = GLOBAL_MST.get().unwrap();
-        let mut _g: std::sync::RwLockWriteGuard<GlobalMst> =
-            global_mst.write().expect("unable to get a lock");
-        let batch_idx_base = batch_proofs.len();
-
-        let root_hashes: Vec<HashOut<F>> = msts
-            .into_iter()
-            .enumerate()
-            .map(|(i, mst)| {
-                let batch_idx = batch_idx_base + i;
-                mst.merkle_sum_tree.iter().enumerate().for_each(|(j, node)| {
-                    _g.set_batch_hash(batch_idx, j, node.hash);
-                });
-                mst.get_root().hash
-            })
-            .collect();
-        drop(_g);
-
-        let proofs =
-            batch_prove_accounts(&circuit_registry, accounts, batch_prove_threads_num, batch_size);
-
-        assert_eq!(proofs.len(), root_hashes.len());
+    // a resumed run reads the same accounts in every parse round as the checkpointed one, regardless of the number of threads.
+    let per_parse_account_num = match &resumed_checkpoint {
+        Some(checkpoint) => checkpoint.manifest.per_parse_account_num,
+        None => calculate_per_parse_account_num(batch_size, batch_prove_threads_num),
+    };
+    let mut checkpoint = match resumed_checkpoint {
+        Some(checkpoint) => checkpoint,
+        None => Checkpoint::create(
+            checkpoint_dir,
+            config_hash,
+            input_hash,
+            snapshot_timestamp,
+            per_parse_account_num,
+        )?,
+    };
 
-        proofs.iter().zip(root_hashes.iter()).enumerate().for_each(|(i, (proof, root_hash))|{
-            let batch_idx = batch_idx_base + i;
-            // exclude the first two pub inputs for equity and debt
-            let hash_offset = MerkleSumNodeTarget::pub_input_root_hash_offset();
-            let proof_root_hash = HashOut::<F>::from_partial(&proof.public_inputs[hash_offset]);
-            if proof_root_hash != *root_hash {
-                panic!("The root hash in proof is not equal to the one generated by merkle sum tree for batch {}", batch_idx);
-            }
+    let pipeline_cfg = PipelineConfig {
+        round_num,
+        salt_epoch,
+        snapshot_timestamp,
+        batch_size,
+        recursion_branchout_num,
+        tokens: cfg.prover.tokens.clone(),
+        leaf_hash_mode,
+        valuation,
+        per_parse_account_num,
+        user_proof_format: cfg.prover.user_proof_format.unwrap_or(DEFAULT_USER_PROOF_FORMAT),
+        compress_root_proof: cfg.prover.compress_root_proof.unwrap_or(DEFAULT_COMPRESS_ROOT_PROOF),
+    };
+    let global_cfg = pipeline_cfg.global_config(account_parser.total_num_of_users());
+    let global_mst = Arc::new(RwLock::new(match &cfg.prover.gmst_path {
+        Some(path) => GlobalMst::new_mmap(global_cfg, Path::new(path))?,
+        None => GlobalMst::new(global_cfg),
+    }));
+    let database = init_db(cfg.db, global_mst.clone());
+    let mut pipeline = ProvingPipeline::new(
+        pipeline_cfg,
+        account_parser,
+        database,
+        global_mst,
+        &circuit_registry,
+        salt_seed,
+    )?;
+
+    // the proofs of the previous round are only reused if their circuits and salt epoch are the same as the ones of this round.
+    if let Some(dir) = &cfg.prover.proof_store_dir {
+        let proof_store = ProofStore::load(
+            &PathBuf::from_str(dir).unwrap(),
+            &circuit_registry,
+            round_num,
+            salt_epoch,
+        )
+        .unwrap_or_else(|e| {
+            tracing::warn!(
+                "fail to load the proofs of the previous round, prove all batches: {:?}",
+                e
+            );
+            ProofStore::new(&circuit_registry, round_num, salt_epoch)
         });
-
-        batch_proofs.extend(proofs.into_iter());
-
-        tracing::debug!(
-            "finish {}/{} batches of accounts in {} parse, since start {:?}",
-            batch_proofs.len(),
-            batch_num,
-            parse_num,
-            start.elapsed()
-        );
-        bar.inc(account_num as u64);
-        offset += per_parse_account_num;
+        pipeline.set_proof_store(proof_store);
     }
-    bar.finish();
 
-    tracing::info!(
-        "finish batch proving {} accounts, generating {} proofs in {:?}",
-        account_parser.total_num_of_users(),
-        batch_proofs.len(),
-        start.elapsed()
-    );
-
-    let batch_proof_num = batch_proofs.len();
-
-    let (batch_circuit, _) = circuit_registry.get_batch_circuit();
-    let mut last_level_circuit_vd = batch_circuit.verifier_only.clone();
-    let mut last_level_proofs = batch_proofs;
-    let recursive_levels = circuit_registry.get_recursive_levels();
-
-    // level 0 for mst root hash
-    for level in 1..=recursive_levels {
-        let start = std::time::Instant::now();
-        let last_level_vd_digest = last_level_circuit_vd.circuit_digest;
-        let last_level_empty_proof = circuit_registry
-            .get_empty_proof(&last_level_vd_digest)
-            .expect(
-                format!("fail to find empty proof for circuit vd {:?}", last_level_vd_digest)
-                    .as_str(),
-            )
-            .clone();
-
-        let subproof_len = last_level_proofs.len();
-
-        tracing::info!(
-            "start to recursively prove {} subproofs at level {}/{}",
-            subproof_len,
-            level,
-            recursive_levels,
-        );
-
-        if subproof_len % RECURSION_BRANCHOUT_NUM != 0 {
-            let pad_num = RECURSION_BRANCHOUT_NUM - subproof_len % RECURSION_BRANCHOUT_NUM;
-            tracing::info!("At level {}, {} subproofs are not a multiple of RECURSION_BRANCHOUT_NUM {}, hence padding {} empty proofs. ", level, subproof_len, RECURSION_BRANCHOUT_NUM, pad_num);
-
-            last_level_proofs.resize(subproof_len + pad_num, last_level_empty_proof);
+    let mut local_prover = LocalProver {
+        circuit_registry: &circuit_registry,
+        batch_prove_threads_num,
+        recursive_prove_threads_num,
+    };
+    let inner_prover: &mut dyn PipelineProver = match coordinator.as_mut() {
+        Some(coordinator) => coordinator,
+        None => &mut local_prover,
+    };
+    let mut prover = CheckpointProver::new(inner_prover, &mut checkpoint, &circuit_registry);
+    pipeline.prove_batches(&mut prover)?;
+    let proof = pipeline.prove_recursion(&mut prover)?;
+
+    if let Some(reserves) = reserves {
+        let hide_liabilities = cfg.prover.hide_liabilities.unwrap_or(DEFAULT_HIDE_LIABILITIES);
+        if hide_liabilities {
+            tracing::warn!(
+                "the liabilities are only hidden by the solvency proof; the root proof in {} and the global info still expose the per-token sums, hence publish the solvency proof and a global info without the per-token sums instead of them",
+                proof_output_path.display()
+            );
         }
-
-        let global_mst = GLOBAL_MST.get().unwrap();
-        let mut _g = global_mst.write().expect("unable to get a lock");
-        last_level_proofs.iter().enumerate().for_each(|(i, proof)| {
-            let hash_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_hash_offset();
-            let proof_root_hash = HashOut::<F>::from_partial(&proof.public_inputs[hash_offset]);
-
-            _g.set_recursive_hash(level - 1, i, proof_root_hash);
-        });
-        drop(_g);
-
-        let this_level_proofs = prove_subproofs(
-            last_level_proofs,
-            last_level_circuit_vd.clone(),
-            &circuit_registry,
-            recursive_prove_threads_num,
-            level,
-        );
-
-        let recursive_circuit = circuit_registry
-            .get_recursive_circuit(&last_level_circuit_vd.circuit_digest)
-            .expect(
-                format!(
-                    "No recursive circuit found for inner circuit with vd {:?}",
-                    last_level_circuit_vd.circuit_digest
-                )
-                .as_str(),
-            )
-            .0;
-
-        last_level_circuit_vd = recursive_circuit.verifier_only.clone();
-        last_level_proofs = this_level_proofs;
-
-        tracing::debug!(
-            "finish recursive level {} with {} proofs in : {:?}",
-            level,
-            last_level_proofs.len(),
-            start.elapsed()
-        );
+        let solvency_proof = prove_solvency(&circuit_registry, &proof, reserves, hide_liabilities);
+        dump_solvency_proof(&proof_output_path, &solvency_proof)?;
     }
 
-    if last_level_proofs.len() != 1 {
-        panic!("The last level proofs should be of length 1, but got {}", last_level_proofs.len());
+    if cfg.prover.wrap_root_proof.unwrap_or(DEFAULT_WRAP_ROOT_PROOF) {
+        let wrap_artifacts = prove_wrap(&circuit_registry, &proof);
+        wrap_artifacts.dump(&proof_output_path.join(WRAP_DIRNAME))?;
     }
-    let root_proof = last_level_proofs.pop().unwrap();
-
-    // Set the root hash of the recursive circuit to the global mst
-    let hash_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_hash_offset();
-    let proof_root_hash = HashOut::<F>::from_partial(&root_proof.public_inputs[hash_offset]);
 
-    let global_mst = GLOBAL_MST.get().unwrap();
-    let mut _g = global_mst.write().expect("unable to get a lock");
-    _g.set_recursive_hash(recursive_levels, 0, proof_root_hash);
-    drop(_g);
-
-    let start = std::time::Instant::now();
-    assert!(GLOBAL_MST.get().unwrap().read().unwrap().is_integral());
-    tracing::info!("verify global mst in {:?}", start.elapsed());
-
-    circuit_registry
-        .get_root_circuit()
-        .verify(root_proof.clone())
-        .expect("fail to verify root proof");
+    if let (Some(proof_store), Some(dir)) = (pipeline.proof_store(), &cfg.prover.proof_store_dir) {
+        proof_store.save(&PathBuf::from_str(dir).unwrap())?;
+    }
 
-    tracing::info!(
-        "finish recursive proving {} subproofs in {:?}",
-        batch_proof_num,
-        start.elapsed()
-    );
+    pipeline.persist_gmst();
+    // a new account reader avoids buffering the previously loaded accounts in memory.
+    pipeline.dump_artifacts(
+        &proof,
+        &proof_output_path,
+        new_account_reader(&cfg.prover, batch_size),
+    )?;
 
-    let root_vd_digest = circuit_registry.get_root_circuit().verifier_only.circuit_digest;
+    if let Some(coordinator) = coordinator {
+        coordinator.shutdown()?;
+    }
 
-    let root_circuit_verifier_data = circuit_registry.get_root_circuit().verifier_data();
+    checkpoint.remove()?;
+    if let Some(path) = &cfg.prover.gmst_path {
+        fs::remove_file(path)?;
+    }
 
-    let root_circuit_verifier_data_bytes = root_circuit_verifier_data
-        .to_bytes(&DefaultGateSerializer)
-        .expect("fail to serialize root circuit verifier data");
-    let root_circuit_verifier_data_hex_str = hex::encode(root_circuit_verifier_data_bytes);
+    return Ok(());
+}
 
-    let proof = Proof {
-        general: General {
-            round_num: cfg.prover.round_no,
-            recursion_branchout_num: RECURSION_BRANCHOUT_NUM,
+fn new_account_reader(prover_cfg: &ConfigProver, batch_size: usize) -> FileAccountReader {
+    FileAccountReader::new(
+        FilesCfg {
+            dir: std::path::PathBuf::from_str(&prover_cfg.user_data_path).unwrap(),
             batch_size: batch_size,
-            token_num: token_num,
+            tokens: prover_cfg.tokens.clone(),
         },
-        circuits_info: Some(CircuitsInfo {
-            batch_circuit_config: batch_circuit_config,
-            recursive_circuit_configs: recursive_circuit_configs,
-            root_verifier_data_hex: root_circuit_verifier_data_hex_str,
-        }),
-        root_vd_digest: root_vd_digest,
-        proof: root_proof,
-    };
-
-    // persist gmst to database
-
-    let global_mst = GLOBAL_MST.get().unwrap();
-
-    let _g = global_mst.read().expect("unable to get a lock");
-    let root_hash = _g.get_root().expect("no root");
-    tracing::info!("root hash is {:?}", root_hash);
-    let start = std::time::Instant::now();
-    _g.persist(&mut database);
-    tracing::info!("persist gmst to db in {:?}", start.elapsed());
-
-    dump_proofs(&cfg.prover, proof_output_path, database, &proof)?;
-    tracing::info!("finish dumping global proof and user proofs in {:?}", start.elapsed());
+        &FileManager {},
+    )
+}
 
-    return Ok(());
+/// The circuit registry of the round, loaded from the circuit cache if one is configured.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn init_circuit_registry(
+    prover_cfg: &ConfigProver,
+    batch_size: usize,
+    last_batch_size: Option<usize>,
+    recursion_branchout_num: usize,
+    leaf_hash_mode: LeafHashMode,
+    valuation: &Valuation,
+    batch_circuit_config: CircuitConfig,
+    recursive_circuit_configs: Vec<CircuitConfig>,
+) -> CircuitRegistry {
+    let token_num = prover_cfg.tokens.len();
+    match &prover_cfg.circuit_cache_dir {
+        Some(dir) => CircuitRegistry::load_or_init(
+            &PathBuf::from_str(dir).unwrap(),
+            batch_size,
+            last_batch_size,
+            token_num,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            valuation,
+            batch_circuit_config,
+            recursive_circuit_configs,
+        ),
+        None => CircuitRegistry::init(
+            batch_size,
+            last_batch_size,
+            token_num,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            valuation,
+            batch_circuit_config,
+            recursive_circuit_configs,
+        ),
+    }
 }
 
-fn dump_proofs(
-    cfg: &ConfigProver,
-    proof_output_dir_path: PathBuf,
-    db: Box<dyn PoRDB>,
+/// Prove that the reserves of every token cover its liabilities, on top of the root proof.
+fn prove_solvency(
+    circuit_registry: &CircuitRegistry,
     root_proof: &Proof,
-) -> Result<(), PoRError> {
-    let user_proof_output_dir_path = proof_output_dir_path.join(USER_PROOF_DIRNAME); // directory has been checked empty before.
-
-    let global_proof_output_path = proof_output_dir_path.join(GLOBAL_PROOF_FILENAME);
-    let global_proof_file =
-        File::create(global_proof_output_path.clone()).map_err(|e| PoRError::Io(e))?;
-
-    let mut global_proof_writer = BufWriter::new(global_proof_file);
-    serde_json::to_writer(&mut global_proof_writer, &root_proof).expect(
-        format!("fail to dump global proof file to {:?}", global_proof_output_path).as_str(),
+    reserves: Vec<u64>,
+    hide_liabilities: bool,
+) -> SolvencyProof {
+    let start = std::time::Instant::now();
+    let root_circuit = circuit_registry.get_root_circuit();
+    let solvency_circuit_config = STANDARD_ZK_CONFIG;
+    let (solvency_circuit, solvency_targets) = build_solvency_circuit(
+        root_circuit,
+        root_proof.general.token_num,
+        &root_proof.general.valuation,
+        hide_liabilities,
+        solvency_circuit_config.clone(),
     );
-    global_proof_writer.flush()?;
-
-    ///////////////////////////////////////////////
-    let hash_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_hash_offset();
-    let root_hash = HashOut::<F>::from_partial(&root_proof.proof.public_inputs[hash_offset]);
-    let root_hash_bytes = root_hash
-        .elements
-        .iter()
-        .map(|x| x.to_canonical_u64().to_le_bytes())
-        .flatten()
-        .collect::<Vec<u8>>();
-    let root_hash = hex::encode(root_hash_bytes);
-
-    let equity_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_equity_offset();
-    let equity_sum = root_proof.proof.public_inputs[equity_offset].to_canonical_u64();
-
-    let debt_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_debt_offset();
-    let debt_sum = root_proof.proof.public_inputs[debt_offset].to_canonical_u64();
-    assert!(equity_sum >= debt_sum);
-    let balance_sum = equity_sum - debt_sum;
-    let info = Info {
-        root_hash: root_hash,
-        equity_sum: equity_sum,
-        debt_sum: debt_sum,
-        balance_sum: balance_sum,
+    let prover = SolvencyProver {
+        root_proof: root_proof.proof.clone(),
+        root_circuit_vd: root_circuit.verifier_only.clone(),
+        reserves,
     };
+    let proof = prover.get_proof_with_circuit_data(&solvency_targets, &solvency_circuit);
+    tracing::info!("finish solvency proving in {:?}", start.elapsed());
 
-    let global_info_output_path = proof_output_dir_path.join(GLOBAL_INFO_FILENAME);
-    let global_info_file =
-        File::create(global_info_output_path.clone()).map_err(|e| PoRError::Io(e))?;
-
-    let mut global_info_writer = BufWriter::new(global_info_file);
-    serde_json::to_writer(&mut global_info_writer, &info).expect(
-        format!("fail to dump global info file to {:?}", global_proof_output_path).as_str(),
-    );
-    global_info_writer.flush()?;
-
-    ///////////////////////////////////////////////
-    // generate and dump proof for each user
-    // create a new account reader to avoid buffering previously loaded accounts in memory
-    let file_manager = FileManager {};
-    let batch_size = cfg.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
-    let mut account_reader = FileAccountReader::new(
-        FilesCfg {
-            dir: std::path::PathBuf::from_str(&cfg.user_data_path).unwrap(),
-            batch_size: batch_size,
-            tokens: cfg.tokens.clone(),
-        },
-        &file_manager,
-    );
+    let solvency_verifier_data_bytes = solvency_circuit
+        .verifier_data()
+        .to_bytes(&DefaultGateSerializer)
+        .expect("fail to serialize solvency circuit verifier data");
+    let circuits_info = root_proof.circuits_info.as_ref().expect("no circuits info");
+    SolvencyProof {
+        general: root_proof.general.clone(),
+        hide_liabilities,
+        circuits_info: circuits_info.clone(),
+        solvency_circuit_config,
+        solvency_verifier_data_hex: hex::encode(solvency_verifier_data_bytes),
+        proof,
+    }
+}
 
-    let global_cfg = GlobalConfig {
-        num_of_tokens: cfg.tokens.len(),
-        num_of_batches: account_reader.total_num_of_batches,
-        batch_size: batch_size,
-        recursion_branchout_num: RECURSION_BRANCHOUT_NUM,
+/// Wrap the root proof by a proof with the Poseidon-BN128 hash config, which exposes the same public inputs.
+fn prove_wrap(circuit_registry: &CircuitRegistry, root_proof: &Proof) -> WrapArtifacts {
+    let start = std::time::Instant::now();
+    let root_circuit = circuit_registry.get_root_circuit();
+    let (wrap_circuit, wrap_targets) =
+        build_wrap_circuit(&root_circuit.common, &root_circuit.verifier_only, WRAP_CIRCUIT_CONFIG);
+    let prover = WrapProver {
+        root_proof: root_proof.proof.clone(),
+        root_circuit_vd: root_circuit.verifier_only.clone(),
     };
-    let user_num = account_reader.total_num_of_users();
-
-    tracing::info!("start to generate and dump merkle proof for each of {} accounts", user_num);
-
-    let bar = ProgressBar::new(user_num as u64);
-    let per_parse_account_num =
-        calculate_per_parse_account_num(batch_size, cfg.batch_prove_threads_num);
-
-    let cdb: Arc<dyn PoRDB> = Arc::from(db);
-    let mut offset = 0;
-    let chunk_size: usize = num_cpus::get();
-    while offset < account_reader.total_num_of_users() {
-        let accounts: Vec<Account> =
-            account_reader.read_n_accounts(offset, per_parse_account_num, &file_manager);
-        accounts.chunks(chunk_size).for_each(|chunk| {
-            chunk.par_iter().for_each(|account| {
-                let user_proof = MerkleProof::new_from_account(account, cdb.clone(), &global_cfg)
-                    .expect(
-                        format!("fail to generate merkle proof for account {}", account.id)
-                            .as_str(),
-                    );
-
-                let user_proof_output_path =
-                    user_proof_output_dir_path.join(format!("{}.json", account.id));
-
-                let user_proof_file = File::create(user_proof_output_path).expect(
-                    format!("fail to create user proof file for account {}", user_proof.account.id)
-                        .as_str(),
-                );
-
-                let mut user_proof_writer = BufWriter::new(user_proof_file);
-                serde_json::to_writer(&mut user_proof_writer, &user_proof).expect(
-                    format!("fail to write user proof file for account {}", user_proof.account.id)
-                        .as_str(),
-                );
-                user_proof_writer.flush().expect(
-                    format!("fail to write user proof file for account {}", user_proof.account.id)
-                        .as_str(),
-                )
-            });
+    let proof = prover.get_proof_with_circuit_data(&wrap_targets, &wrap_circuit);
+    tracing::info!("finish wrap proving in {:?}", start.elapsed());
+    WrapArtifacts::new(proof, &wrap_circuit)
+}
 
-            bar.inc(chunk.len() as u64);
-        });
-        offset += per_parse_account_num;
-    }
-    bar.finish();
+fn dump_solvency_proof(
+    proof_output_dir_path: &PathBuf,
+    solvency_proof: &SolvencyProof,
+) -> Result<(), PoRError> {
+    let solvency_proof_output_path = proof_output_dir_path.join(SOLVENCY_PROOF_FILENAME);
+    let solvency_proof_file =
+        File::create(solvency_proof_output_path.clone()).map_err(|e| PoRError::Io(e))?;
 
-    return Ok(());
+    let mut solvency_proof_writer = BufWriter::new(solvency_proof_file);
+    serde_json::to_writer(&mut solvency_proof_writer, solvency_proof).expect(
+        format!("fail to dump solvency proof file to {:?}", solvency_proof_output_path).as_str(),
+    );
+    solvency_proof_writer.flush()?;
+    Ok(())
 }
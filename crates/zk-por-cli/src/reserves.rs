@@ -0,0 +1,93 @@
+use serde_json::from_reader;
+use std::{fs::File, path::PathBuf};
+use zk_por_core::{
+    error::PoRError,
+    reserves::{ReserveSnapshot, SolvencyReport},
+    Info, Proof, TokenInfo,
+};
+
+use super::verifier::verify_global;
+
+fn open(path: &PathBuf) -> Result<std::io::BufReader<File>, PoRError> {
+    let file = File::open(path).map_err(|e| {
+        PoRError::InvalidParameter(format!("fail to open {:?} due to error {:?}", path, e))
+    })?;
+    Ok(std::io::BufReader::new(file))
+}
+
+/// Load the per-token liabilities, either from the root proof, which is verified first, or from the global info file.
+fn load_liabilities(
+    global_proof_path: Option<PathBuf>,
+    global_info_path: Option<PathBuf>,
+) -> Result<Vec<TokenInfo>, PoRError> {
+    let token_infos = match (global_proof_path, global_info_path) {
+        (Some(global_proof_path), None) => {
+            verify_global(global_proof_path.clone(), false, false)?;
+            let proof = Proof::load(&global_proof_path)?;
+            TokenInfo::from_public_inputs(&proof.general.tokens, &proof.proof.public_inputs)
+        }
+        (None, Some(global_info_path)) => {
+            println!(
+                "the liabilities are read from {:?} without verifying the root proof",
+                global_info_path
+            );
+            let info: Info = from_reader(open(&global_info_path)?).map_err(|e| {
+                PoRError::InvalidParameter(format!(
+                    "fail to parse global info due to error {:?}",
+                    e
+                ))
+            })?;
+            info.tokens
+        }
+        _ => {
+            return Err(PoRError::InvalidParameter(
+                "exactly one of the global proof and the global info is required".to_string(),
+            ))
+        }
+    };
+    if token_infos.is_empty() {
+        return Err(PoRError::InvalidParameter(
+            "the per-token liabilities are not available in this round".to_string(),
+        ));
+    }
+    Ok(token_infos)
+}
+
+fn print_solvency_report(report: &SolvencyReport) {
+    println!("the reserve addresses signed the message: {:?}", report.message);
+    println!("{:<12} {:>22} {:>22} {:>12}", "token", "reserves", "liabilities", "ratio");
+    for token in report.tokens.iter() {
+        let ratio = match token.ratio {
+            Some(ratio) => format!("{:.4}", ratio),
+            None => "-".to_string(),
+        };
+        println!(
+            "{:<12} {:>22} {:>22} {:>12}",
+            token.token, token.reserves, token.liabilities, ratio
+        );
+    }
+}
+
+/// Verify the ownership of the reserve addresses offline, and compare the reserves of each token with its liabilities.
+pub fn verify_reserves(
+    reserves_path: PathBuf,
+    global_proof_path: Option<PathBuf>,
+    global_info_path: Option<PathBuf>,
+    verbose: bool,
+) -> Result<(), PoRError> {
+    let liabilities = load_liabilities(global_proof_path, global_info_path)?;
+    let snapshot = ReserveSnapshot::load(reserves_path.to_str().unwrap())?;
+    let report = SolvencyReport::new(&snapshot, &liabilities)?;
+    if verbose {
+        println!(
+            "successfully verify the ownership of {} reserve addresses",
+            snapshot.addresses.len()
+        );
+        print_solvency_report(&report);
+    }
+
+    if let Some(token) = report.tokens.iter().find(|token| !token.is_solvent()) {
+        return Err(PoRError::Insolvent(token.token.clone()));
+    }
+    Ok(())
+}
@@ -1,22 +1,32 @@
 use indicatif::ProgressBar;
-use plonky2::{plonk::circuit_data::CircuitConfig, util::serialization::DefaultGateSerializer};
+use plonky2::{
+    hash::hash_types::HashOut,
+    plonk::{circuit_data::CircuitConfig, config::GenericHashOut},
+    util::serialization::DefaultGateSerializer,
+};
 use plonky2_field::types::PrimeField64;
-use rayon::iter::IntoParallelRefIterator;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator};
 use serde_json::from_reader;
-use std::{fs::File, path::PathBuf};
+use std::{fs, fs::File, path::PathBuf};
 // Assuming Proof is defined in lib.rs and lib.rs is in the same crate
-use super::constant::RECURSION_BRANCHOUT_NUM;
+use super::constant::{SUPPORTED_RECURSION_BRANCHOUT_NUMS, WRAP_CIRCUIT_CONFIG};
 use zk_por_core::{
     circuit_config::{STANDARD_CONFIG, STANDARD_ZK_CONFIG},
     circuit_registry::registry::CircuitRegistry,
     error::PoRError,
     merkle_proof::MerkleProof,
-    recursive_prover::recursive_circuit::RecursiveTargets,
-    types::{C, D, F},
-    Proof,
+    merkle_sum_tree::MerkleSumNode,
+    recursive_prover::recursive_circuit::{RecursiveTargets, RoundInfo},
+    reserves::ReserveSnapshot,
+    solvency_prover::solvency_circuit::{build_solvency_circuit, SolvencyTargets},
+    types::{C, D, F, HAIRCUT_LOG},
+    user_proof_archive::UserProofArchive,
+    valuation::Valuation,
+    wrap_prover::{artifacts::WrapArtifacts, wrap_circuit::build_wrap_circuit},
+    Info, Proof, SolvencyProof, TokenInfo,
 };
 
-use plonky2::{hash::hash_types::HashOut, plonk::circuit_data::VerifierCircuitData};
+use plonky2::plonk::circuit_data::VerifierCircuitData;
 use rayon::iter::ParallelIterator;
 
 use glob::glob;
@@ -43,69 +53,199 @@ fn find_matching_files(pattern: &str) -> Result<Vec<PathBuf>, io::Error> {
     Ok(matching_files)
 }
 
+/// Print the per-token liability table of the global proof.
+fn print_token_liabilities(token_infos: &[TokenInfo]) {
+    if token_infos.is_empty() {
+        return;
+    }
+    println!("{:<12} {:>22} {:>22} {:>22}", "token", "equity", "debt", "liability");
+    for info in token_infos {
+        println!(
+            "{:<12} {:>22} {:>22} {:>22}",
+            info.token, info.equity_sum, info.debt_sum, info.balance_sum
+        );
+    }
+}
+
+/// The recursion branch-out of a round must be one of the supported ones, such that one verifier checks the proofs of every round.
+pub fn check_recursion_branchout_num(recursion_branchout_num: usize) -> Result<(), PoRError> {
+    if !SUPPORTED_RECURSION_BRANCHOUT_NUMS.contains(&recursion_branchout_num) {
+        return Err(PoRError::InvalidParameter(format!(
+            "recursion branch-out {} is not one of {:?}",
+            recursion_branchout_num, SUPPORTED_RECURSION_BRANCHOUT_NUMS
+        )));
+    }
+    Ok(())
+}
+
+/// The valuation of the round, which must be the one published in the public inputs of the root proof.
+fn round_valuation(proof: &Proof) -> Result<Valuation, PoRError> {
+    let valuation = proof
+        .general
+        .valuation
+        .read_public_inputs(proof.general.token_num, &proof.proof.public_inputs)?;
+    if valuation != proof.general.valuation {
+        tracing::error!(
+            "the valuation {:?} in the proof file does not match the one {:?} in the root proof",
+            proof.general.valuation,
+            valuation
+        );
+        return Err(PoRError::InvalidProof);
+    }
+    Ok(valuation)
+}
+
+// check the round number and snapshot timestamp in the proof file against the ones bound to the root proof.
+// returns None for proof files generated before the round info is bound to the root proof.
+fn round_info(proof: &Proof, valuation: &Valuation) -> Result<Option<RoundInfo>, PoRError> {
+    let round = RecursiveTargets::round_from_public_inputs(
+        proof.general.token_num,
+        valuation,
+        &proof.proof.public_inputs,
+    );
+    let claimed = proof.general.snapshot_timestamp.map(|snapshot_timestamp| RoundInfo {
+        round_num: proof.general.round_num as u64,
+        snapshot_timestamp,
+    });
+    if round != claimed {
+        tracing::error!(
+            "the round info {:?} in the proof file does not match the one {:?} in the root proof",
+            claimed,
+            round
+        );
+        return Err(PoRError::RoundMismatch);
+    }
+    Ok(round)
+}
+
+/// Where the user proofs to verify are read from.
+pub enum UserProofSource {
+    /// The json files matching a glob pattern, one per user.
+    Files { pattern: String },
+    /// An archive dumped with the `archive` user proof format. The proofs of `user_ids` are verified if given, otherwise all proofs.
+    Archive { path: PathBuf, user_ids: Option<Vec<String>> },
+}
+
 pub fn verify_user(
     global_proof_path: PathBuf,
-    user_proof_path_pattern: &String,
+    user_proof_source: &UserProofSource,
     verbose: bool,
 ) -> Result<(), PoRError> {
-    let proof_file = File::open(&global_proof_path).map_err(|e| {
-        PoRError::InvalidParameter(format!(
-            "fail to open {:?} due to error {:?}",
-            global_proof_path, e
-        ))
-    })?;
-    let reader = std::io::BufReader::new(proof_file);
+    // the proof file is either in json or in the binary format.
+    let proof = Proof::load(&global_proof_path)?;
 
-    // Parse the JSON as Proof
-    let proof: Proof = from_reader(reader)
-        .expect(format!("fail to parse global proof from path {:?}", global_proof_path).as_str());
+    // the root hash together with the total equity and debt, against which the running sums of every user proof are checked.
+    let root_node = MerkleSumNode::new_from_public_inputs(&proof.proof.public_inputs);
+    let leaf_hash_mode = proof.general.leaf_hash_mode;
+    let valuation = round_valuation(&proof)?;
+    // the gmst shape fixes the position and the number of siblings of every user proof.
+    let global_cfg = proof.global_config()?;
+    let verify = |proof: MerkleProof| {
+        proof.verify_merkle_proof(&root_node, &global_cfg, leaf_hash_mode, &valuation)
+    };
 
-    let hash_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_hash_offset();
-    let root_hash = HashOut::<F>::from_partial(&proof.proof.public_inputs[hash_offset]);
-    let user_proof_paths =
-        find_matching_files(user_proof_path_pattern).map_err(|e| PoRError::Io(e))?;
-    let proof_file_num = user_proof_paths.len();
-    if proof_file_num == 0 {
-        return Err(PoRError::InvalidParameter(format!(
-            "fail to find any user proof files with pattern {}",
-            user_proof_path_pattern
-        )));
-    }
+    // the number of proofs to verify, and the paths or the user ids of the invalid ones.
+    let (proof_num, invalid_proofs, proof_kind) = match user_proof_source {
+        UserProofSource::Files { pattern } => {
+            let user_proof_paths = find_matching_files(pattern).map_err(|e| PoRError::Io(e))?;
+            let proof_file_num = user_proof_paths.len();
+            if proof_file_num == 0 {
+                return Err(PoRError::InvalidParameter(format!(
+                    "fail to find any user proof files with pattern {}",
+                    pattern
+                )));
+            }
 
-    if verbose {
-        println!("successfully identify {} user proof files", proof_file_num);
-    }
+            if verbose {
+                println!("successfully identify {} user proof files", proof_file_num);
+            }
 
-    let bar = ProgressBar::new(proof_file_num as u64);
-    let invalid_proof_paths = user_proof_paths
-        .par_iter()
-        .map(|user_proof_path| {
-            let merkle_path = File::open(&user_proof_path).unwrap();
-            let reader = std::io::BufReader::new(merkle_path);
-            let proof: MerkleProof = from_reader(reader).expect(
-                format!("fail to parse user proof from path {:?}", user_proof_path).as_str(),
-            );
-            let result = proof.verify_merkle_proof(root_hash);
+            let bar = ProgressBar::new(proof_file_num as u64);
+            let invalid_proof_paths = user_proof_paths
+                .par_iter()
+                .map(|user_proof_path| {
+                    let merkle_path = File::open(&user_proof_path).unwrap();
+                    let reader = std::io::BufReader::new(merkle_path);
+                    let proof: MerkleProof = from_reader(reader).expect(
+                        format!("fail to parse user proof from path {:?}", user_proof_path)
+                            .as_str(),
+                    );
+                    let result = verify(proof);
+                    if verbose {
+                        bar.inc(1);
+                    }
+                    (result, user_proof_path)
+                })
+                .filter(|(result, _)| result.is_err())
+                .map(|(_, invalid_proof_path)| invalid_proof_path.to_str().unwrap().to_owned())
+                .collect::<Vec<String>>();
             if verbose {
-                bar.inc(1);
+                bar.finish();
+            }
+            (proof_file_num, invalid_proof_paths, "proof files")
+        }
+        UserProofSource::Archive { path, user_ids } => {
+            let archive = UserProofArchive::open(path)?;
+            let proof_num = user_ids.as_ref().map_or(archive.len(), |user_ids| user_ids.len());
+            if proof_num == 0 {
+                return Err(PoRError::InvalidParameter(format!(
+                    "fail to find any user proofs to verify in {:?}",
+                    path
+                )));
+            }
+
+            if verbose {
+                println!(
+                    "successfully open the archive of {} user proofs, verify {} of them",
+                    archive.len(),
+                    proof_num
+                );
             }
-            (result, user_proof_path)
-        })
-        .filter(|(result, _)| result.is_err())
-        .map(|(_, invalid_proof_path)| invalid_proof_path.to_str().unwrap().to_owned())
-        .collect::<Vec<String>>();
-    if verbose {
-        bar.finish();
-    }
 
-    let invalid_proof_num = invalid_proof_paths.len();
-    let valid_proof_num = proof_file_num - invalid_proof_num;
+            let bar = ProgressBar::new(proof_num as u64);
+            let check = |user_id: String, proof: Result<Option<MerkleProof>, PoRError>| {
+                let result = proof.and_then(|proof| match proof {
+                    Some(proof) => verify(proof),
+                    None => Err(PoRError::InvalidParameter(format!(
+                        "user {} is not in the archive",
+                        user_id
+                    ))),
+                });
+                if verbose {
+                    bar.inc(1);
+                }
+                result.err().map(|_| user_id)
+            };
+            let invalid_user_ids = match user_ids {
+                Some(user_ids) => user_ids
+                    .par_iter()
+                    .filter_map(|user_id| check(user_id.clone(), archive.get(user_id)))
+                    .collect::<Vec<String>>(),
+                None => (0..archive.len())
+                    .into_par_iter()
+                    .filter_map(|i| check(archive.user_id_at(i), archive.proof_at(i).map(Some)))
+                    .collect::<Vec<String>>(),
+            };
+            if verbose {
+                bar.finish();
+            }
+            (proof_num, invalid_user_ids, "users")
+        }
+    };
+
+    let invalid_proof_num = invalid_proofs.len();
+    let valid_proof_num = proof_num - invalid_proof_num;
     if verbose {
         let max_to_display_valid_proof_num = 10;
 
         println!(
-            "{}/{} user proofs pass the verification. {} fail, the first {} failed proof files: {:?}",
-            valid_proof_num, proof_file_num, invalid_proof_num, std::cmp::min(invalid_proof_num, invalid_proof_num), invalid_proof_paths.iter().take(max_to_display_valid_proof_num).collect::<Vec<&String>>(),
+            "{}/{} user proofs pass the verification. {} fail, the first {} failed {}: {:?}",
+            valid_proof_num,
+            proof_num,
+            invalid_proof_num,
+            std::cmp::min(invalid_proof_num, invalid_proof_num),
+            proof_kind,
+            invalid_proofs.iter().take(max_to_display_valid_proof_num).collect::<Vec<&String>>(),
         );
     }
 
@@ -115,24 +255,19 @@ pub fn verify_user(
     Ok(())
 }
 
+/// Verify the global proof. If `check_circuit`, the circuits are rebuilt, and the root circuit must match the one of the proof. A
+/// compressed root proof is decompressed with the common data of the root verifier data in the proof file, against which it is
+/// verified.
 pub fn verify_global(
     global_proof_path: PathBuf,
     check_circuit: bool,
     verbose: bool,
 ) -> Result<(), PoRError> {
-    let proof_file = File::open(&global_proof_path).map_err(|e| {
-        PoRError::InvalidParameter(format!(
-            "fail to open {:?} due to error {:?}",
-            global_proof_path, e
-        ))
-    })?;
-    let reader = std::io::BufReader::new(proof_file);
-
-    let proof: Proof = from_reader(reader).map_err(|_| PoRError::InvalidProof)?;
+    let proof = Proof::load(&global_proof_path)?;
+    let valuation = round_valuation(&proof)?;
+    let round = round_info(&proof, &valuation)?;
 
-    if proof.general.recursion_branchout_num != RECURSION_BRANCHOUT_NUM {
-        panic!("The recursion_branchout_num is not configured to be equal to 64");
-    }
+    check_recursion_branchout_num(proof.general.recursion_branchout_num)?;
     let round_num = proof.general.round_num;
     let root_verifier_data_hex: String;
     let (mut batch_circuit_config, mut recursive_circuit_configs) =
@@ -162,9 +297,7 @@ pub fn verify_global(
 
     let round_num = proof.general.round_num;
     if check_circuit {
-        let token_num = proof.general.token_num;
         let round_num = proof.general.round_num;
-        let batch_size = proof.general.batch_size;
 
         if verbose {
             println!(
@@ -174,9 +307,13 @@ pub fn verify_global(
             );
         }
         let start = std::time::Instant::now();
-        let circuit_registry = CircuitRegistry::<RECURSION_BRANCHOUT_NUM>::init(
-            batch_size,
-            token_num,
+        let circuit_registry = CircuitRegistry::init(
+            proof.general.batch_size,
+            proof.general.last_batch_size,
+            proof.general.token_num,
+            proof.general.recursion_branchout_num,
+            proof.general.leaf_hash_mode,
+            &valuation,
             batch_circuit_config,
             recursive_circuit_configs,
         );
@@ -204,10 +341,8 @@ pub fn verify_global(
     let result = root_circuit_verifier_data.verify(proof.proof.clone());
 
     if verbose {
-        let equity = proof.proof.public_inputs
-            [RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_equity_offset()];
-        let debt = proof.proof.public_inputs
-            [RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_debt_offset()];
+        let equity = proof.proof.public_inputs[RecursiveTargets::pub_input_equity_offset()];
+        let debt = proof.proof.public_inputs[RecursiveTargets::pub_input_debt_offset()];
         if result.is_ok() {
             println!("successfully verify the global proof for round {}, total exchange users' equity is {}, debt is {}, exchange liability is {}",
             round_num, equity.to_canonical_u64(), debt.to_canonical_u64(), (equity - debt).to_canonical_u64());
@@ -215,6 +350,259 @@ pub fn verify_global(
             println!("fail to verify the global proof for round {}, total exchange users' equity is {}, debt is {}, exchange liability is {}",
             round_num, equity.to_canonical_u64(), debt.to_canonical_u64(), (equity - debt).to_canonical_u64());
         }
+
+        if let Some(user_count) = RecursiveTargets::user_count_from_public_inputs(
+            proof.general.token_num,
+            &valuation,
+            &proof.proof.public_inputs,
+        ) {
+            println!("the root proof attests {} users, excluding padding accounts", user_count);
+        }
+        if let Some(round) = &round {
+            println!(
+                "the root proof is bound to round {} with snapshot timestamp {}",
+                round.round_num, round.snapshot_timestamp
+            );
+        }
+        if let Some(prices) = &valuation.prices {
+            println!("the equity and debt are price-weighted with prices {:?}", prices);
+        }
+        if let Some(haircuts) = &valuation.haircuts {
+            println!(
+                "the equity of every user is haircut with ratios {:?} (fractions of 2^{})",
+                haircuts, HAIRCUT_LOG
+            );
+        }
+        let token_infos =
+            TokenInfo::from_public_inputs(&proof.general.tokens, &proof.proof.public_inputs);
+        print_token_liabilities(&token_infos);
+    }
+
+    result.map_err(|_| PoRError::InvalidProof)
+}
+
+/// The root hash in the hex of `Info::root_hash`, i.e., the field elements in little endian.
+fn root_hash_hex(root_hash: &[F]) -> String {
+    hex::encode(HashOut::<F>::from_partial(root_hash).to_bytes())
+}
+
+/// Verify the solvency proof of the published round. The reserves in the proof must be the ones of the reserve addresses in
+/// `reserves_path`, whose ownership is verified, and its root hash must be the one of the root proof, which is verified first, or the
+/// one of the global info if the root proof is not published.
+pub fn verify_solvency(
+    solvency_proof_path: PathBuf,
+    reserves_path: PathBuf,
+    global_proof_path: Option<PathBuf>,
+    global_info_path: Option<PathBuf>,
+    check_circuit: bool,
+    verbose: bool,
+) -> Result<(), PoRError> {
+    let proof_file = File::open(&solvency_proof_path).map_err(|e| {
+        PoRError::InvalidParameter(format!(
+            "fail to open {:?} due to error {:?}",
+            solvency_proof_path, e
+        ))
+    })?;
+    let reader = std::io::BufReader::new(proof_file);
+
+    let proof: SolvencyProof = from_reader(reader).map_err(|_| PoRError::InvalidProof)?;
+    check_recursion_branchout_num(proof.general.recursion_branchout_num)?;
+    let token_num = proof.general.token_num;
+    let round_num = proof.general.round_num;
+
+    let solvency_verifier_data_bytes = hex::decode(&proof.solvency_verifier_data_hex)
+        .expect("fail to decode solvency circuit verifier data hex string");
+    let solvency_verifier_data = VerifierCircuitData::<F, C, D>::from_bytes(
+        solvency_verifier_data_bytes,
+        &DefaultGateSerializer,
+    )
+    .expect("fail to parse solvency circuit verifier data");
+
+    if check_circuit {
+        if verbose {
+            println!("start to reconstruct the solvency circuit for round {}", round_num);
+        }
+        let start = std::time::Instant::now();
+        let circuit_registry = CircuitRegistry::init(
+            proof.general.batch_size,
+            proof.general.last_batch_size,
+            token_num,
+            proof.general.recursion_branchout_num,
+            proof.general.leaf_hash_mode,
+            &proof.general.valuation,
+            proof.circuits_info.batch_circuit_config.clone(),
+            proof.circuits_info.recursive_circuit_configs.clone(),
+        );
+        let (rebuilt_solvency_circuit, _) = build_solvency_circuit(
+            circuit_registry.get_root_circuit(),
+            token_num,
+            &proof.general.valuation,
+            proof.hide_liabilities,
+            proof.solvency_circuit_config.clone(),
+        );
+        if rebuilt_solvency_circuit.verifier_only.circuit_digest
+            != solvency_verifier_data.verifier_only.circuit_digest
+        {
+            return Err(PoRError::CircuitMismatch);
+        }
+        if verbose {
+            println!(
+                "successfully reconstruct the solvency circuit for round {} in {:?}",
+                round_num,
+                start.elapsed()
+            );
+        }
+    }
+
+    let public_inputs = &proof.proof.public_inputs;
+    let round = SolvencyTargets::round_from_public_inputs(token_num, public_inputs);
+    if Some(round.snapshot_timestamp) != proof.general.snapshot_timestamp
+        || round.round_num != round_num as u64
+    {
+        tracing::error!(
+            "the round {} at {:?} in the proof file does not match the one {:?} in the solvency proof",
+            round_num,
+            proof.general.snapshot_timestamp,
+            round
+        );
+        return Err(PoRError::RoundMismatch);
+    }
+
+    let signed_reserves = ReserveSnapshot::load(reserves_path.to_str().unwrap())?
+        .verified_reserves(&proof.general.tokens)?;
+    if SolvencyTargets::reserves_from_public_inputs(token_num, public_inputs) != signed_reserves {
+        tracing::error!(
+            "the reserves {:?} in the solvency proof are not the ones {:?} of the reserve addresses",
+            SolvencyTargets::reserves_from_public_inputs(token_num, public_inputs),
+            signed_reserves
+        );
+        return Err(PoRError::ReservesMismatch);
+    }
+
+    let published_root_hash = match (global_proof_path, global_info_path) {
+        (Some(global_proof_path), None) => {
+            verify_global(global_proof_path.clone(), check_circuit, false)?;
+            let global_proof = Proof::load(&global_proof_path)?;
+            if global_proof.general.round_num != round_num
+                || global_proof.general.snapshot_timestamp != proof.general.snapshot_timestamp
+            {
+                return Err(PoRError::RoundMismatch);
+            }
+            root_hash_hex(
+                &global_proof.proof.public_inputs[RecursiveTargets::pub_input_hash_offset()],
+            )
+        }
+        (None, Some(global_info_path)) => {
+            let info_bytes = fs::read(&global_info_path).map_err(|e| {
+                PoRError::InvalidParameter(format!(
+                    "fail to open {:?} due to error {:?}",
+                    global_info_path, e
+                ))
+            })?;
+            let info: Info = serde_json::from_slice(&info_bytes).map_err(|e| {
+                PoRError::InvalidParameter(format!(
+                    "fail to parse global info due to error {:?}",
+                    e
+                ))
+            })?;
+            info.root_hash
+        }
+        _ => {
+            return Err(PoRError::InvalidParameter(
+                "exactly one of the global proof and the global info is required".to_string(),
+            ))
+        }
+    };
+    let root_hash = root_hash_hex(&public_inputs[SolvencyTargets::pub_input_root_hash_offset()]);
+    if root_hash != published_root_hash {
+        tracing::error!(
+            "the root hash {} in the solvency proof is not the published one {}",
+            root_hash,
+            published_root_hash
+        );
+        return Err(PoRError::RootMismatch);
+    }
+
+    let result = solvency_verifier_data.verify(proof.proof.clone());
+
+    if verbose {
+        let user_count = public_inputs[SolvencyTargets::pub_input_user_count_offset(token_num)]
+            .to_canonical_u64();
+        match result.is_ok() {
+            true => println!(
+                "successfully verify the solvency proof for round {}, the reserves of every token cover the liabilities of {} users",
+                round_num, user_count
+            ),
+            false => println!("fail to verify the solvency proof for round {}", round_num),
+        }
+        let reserves = SolvencyTargets::reserves_from_public_inputs(token_num, public_inputs);
+        if proof.hide_liabilities {
+            println!("{:<12} {:>22}", "token", "reserves");
+            for (token, reserve) in proof.general.tokens.iter().zip(reserves.iter()) {
+                println!("{:<12} {:>22}", token, reserve);
+            }
+        } else {
+            let equity = &public_inputs[SolvencyTargets::pub_input_token_equity_offset(token_num)];
+            let debt = &public_inputs[SolvencyTargets::pub_input_token_debt_offset(token_num)];
+            println!("{:<12} {:>22} {:>22}", "token", "reserves", "liability");
+            for i in 0..token_num {
+                let liability =
+                    equity[i].to_canonical_u64() as i128 - debt[i].to_canonical_u64() as i128;
+                println!("{:<12} {:>22} {:>22}", proof.general.tokens[i], reserves[i], liability);
+            }
+        }
+    }
+
+    result.map_err(|_| PoRError::InvalidProof)
+}
+
+/// Verify the wrap proof under `wrap_dir` natively. The wrap circuit is rebuilt on top of the root verifier data in the global proof,
+/// and the wrap proof must expose the public inputs of the root proof. The global proof itself is verified by `verify_global`.
+pub fn verify_wrap(
+    global_proof_path: PathBuf,
+    wrap_dir: PathBuf,
+    verbose: bool,
+) -> Result<(), PoRError> {
+    let proof = Proof::load(&global_proof_path)?;
+    let circuits_info = proof.circuits_info.as_ref().ok_or(PoRError::InvalidProof)?;
+    let root_circuit_verifier_data_bytes = hex::decode(&circuits_info.root_verifier_data_hex)
+        .expect("fail to decode root circuit verifier data hex string");
+    let root_circuit_verifier_data = VerifierCircuitData::<F, C, D>::from_bytes(
+        root_circuit_verifier_data_bytes,
+        &DefaultGateSerializer,
+    )
+    .expect("fail to parse root circuit verifier data");
+    if proof.root_vd_digest != root_circuit_verifier_data.verifier_only.circuit_digest {
+        return Err(PoRError::CircuitMismatch);
+    }
+
+    let wrap_artifacts = WrapArtifacts::load(&wrap_dir)?;
+    let (wrap_circuit, _) = build_wrap_circuit(
+        &root_circuit_verifier_data.common,
+        &root_circuit_verifier_data.verifier_only,
+        WRAP_CIRCUIT_CONFIG,
+    );
+    if wrap_circuit.verifier_only.circuit_digest
+        != wrap_artifacts.verifier_data.verifier_only.circuit_digest
+    {
+        return Err(PoRError::CircuitMismatch);
+    }
+    if wrap_artifacts.proof.public_inputs != proof.proof.public_inputs {
+        tracing::error!(
+            "the public inputs of the wrap proof do not match the ones of the root proof"
+        );
+        return Err(PoRError::InvalidProof);
+    }
+
+    let result = wrap_circuit.verify(wrap_artifacts.proof);
+    if verbose {
+        match result.is_ok() {
+            true => println!(
+                "successfully verify the wrap proof for round {}, which exposes the public inputs of the root proof",
+                proof.general.round_num
+            ),
+            false => println!("fail to verify the wrap proof for round {}", proof.general.round_num),
+        }
     }
 
     result.map_err(|_| PoRError::InvalidProof)
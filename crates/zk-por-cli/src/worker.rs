@@ -0,0 +1,56 @@
+use super::{
+    constant::{DEFAULT_BATCH_SIZE, DEFAULT_LEAF_HASH_MODE, DEFAULT_RECURSION_BRANCHOUT_NUM},
+    prover::init_circuit_registry,
+    verifier::check_recursion_branchout_num,
+};
+use std::net::TcpListener;
+use zk_por_core::{
+    circuit_config::{get_recursive_circuit_configs, STANDARD_CONFIG},
+    config::ProverConfig,
+    distributed::{protocol::RoundShape, worker::serve},
+    error::PoRError,
+    valuation::Valuation,
+};
+use zk_por_tracing::{init_tracing, TraceConfig};
+
+/// Serve the batch and recursive proving jobs of the coordinator on `listen_addr`. The circuits are built like `prove` from the shape of
+/// the round sent by the coordinator, whose batch size and branch-out must be the ones of the config; the coordinator rejects the worker
+/// if its circuits differ, e.g., by the tokens or the valuation of the config.
+pub fn serve_worker(cfg: ProverConfig, listen_addr: &str) -> Result<(), PoRError> {
+    let trace_cfg: TraceConfig = cfg.log.into();
+    let _g = init_tracing(trace_cfg);
+
+    let batch_size = cfg.prover.batch_size.unwrap_or(DEFAULT_BATCH_SIZE);
+    let recursion_branchout_num =
+        cfg.prover.recursion_branchout_num.unwrap_or(DEFAULT_RECURSION_BRANCHOUT_NUM);
+    check_recursion_branchout_num(recursion_branchout_num)?;
+    let leaf_hash_mode = cfg.prover.leaf_hash_mode.unwrap_or(DEFAULT_LEAF_HASH_MODE);
+    let valuation = Valuation::from_config(&cfg.prover)?;
+
+    let listener = TcpListener::bind(listen_addr)?;
+    serve(listener, cfg.prover.worker_secret.as_deref(), |shape: &RoundShape| {
+        if shape.batch_size != batch_size
+            || shape.recursion_branchout_num != recursion_branchout_num
+        {
+            return Err(PoRError::InvalidParameter(format!(
+                "the batch size {} and branch-out {} of the coordinator are not the ones of the worker, {} and {}",
+                shape.batch_size, shape.recursion_branchout_num, batch_size, recursion_branchout_num
+            )));
+        }
+        if shape.last_batch_size.is_some_and(|last_batch_size| last_batch_size >= batch_size) {
+            return Err(PoRError::InvalidParameter(
+                "the last batch must be smaller than a full batch".to_string(),
+            ));
+        }
+        Ok(init_circuit_registry(
+            &cfg.prover,
+            batch_size,
+            shape.last_batch_size,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            &valuation,
+            STANDARD_CONFIG,
+            get_recursive_circuit_configs(shape.batch_num, recursion_branchout_num),
+        ))
+    })
+}
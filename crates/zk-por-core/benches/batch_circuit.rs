@@ -1,10 +1,11 @@
 #![feature(test)]
 use plonky2::plonk::circuit_builder::CircuitBuilder;
 use zk_por_core::{
-    account::gen_accounts_with_random_data,
+    account::{gen_accounts_with_random_data, LeafHashMode},
     circuit_config::STANDARD_CONFIG,
     merkle_sum_prover::{circuits::account_circuit::AccountTargets, prover::MerkleSumTreeProver},
     types::{C, D, F},
+    valuation::Valuation,
 };
 
 extern crate test;
@@ -15,7 +16,11 @@ fn bench(b: &mut Bencher, batch_size: usize) {
     let num_assets = 50;
     let accounts = gen_accounts_with_random_data(batch_size, num_assets);
     let prover = MerkleSumTreeProver { accounts };
-    let account_targets: Vec<AccountTargets> = prover.build_merkle_tree_targets(&mut builder);
+    let account_targets: Vec<AccountTargets> = prover.build_merkle_tree_targets(
+        &mut builder,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+    );
     let data = &builder.build::<C>();
 
     b.iter(|| _ = prover.get_proof_with_circuit_data(account_targets.as_slice(), data));
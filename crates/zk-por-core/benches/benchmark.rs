@@ -2,16 +2,19 @@ use criterion::{
     criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, Criterion, SamplingMode,
 };
 use zk_por_core::{
-    account::gen_accounts_with_random_data,
+    account::{gen_accounts_with_random_data, LeafHashMode},
     circuit_config::STANDARD_CONFIG,
     merkle_sum_prover::{
         circuits::merkle_sum_circuit::build_merkle_sum_tree_circuit, prover::MerkleSumTreeProver,
     },
-    recursive_prover::{prover::RecursiveProver, recursive_circuit::build_recursive_n_circuit},
-    types::{C, D, F},
+    recursive_prover::{
+        prover::RecursiveProver,
+        recursive_circuit::{build_recursive_n_circuit, RoundInfo},
+    },
+    types::C,
+    valuation::Valuation,
 };
 
-use plonky2::plonk::proof::ProofWithPublicInputs;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 /// Benchmark the batch proving of the accounts. There are {parallism} threads, each thread proving the batch_size accounts.
@@ -24,8 +27,13 @@ pub fn bench_batch_circuit(
     let accounts = gen_accounts_with_random_data(batch_size, num_assets);
     let bench_id =
         format!("batch_circuit_{}_token_num_{}_parallism_{}", batch_size, num_assets, parallism);
-    let (circuit_data, account_targets) =
-        build_merkle_sum_tree_circuit(batch_size, num_assets, STANDARD_CONFIG);
+    let (circuit_data, account_targets) = build_merkle_sum_tree_circuit(
+        batch_size,
+        num_assets,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+    );
     c.bench_function(bench_id.as_str(), |b| {
         b.iter(|| {
             (0..parallism).into_par_iter().for_each(|_| {
@@ -70,14 +78,20 @@ pub fn batch_circuit_for_parallism(c: &mut Criterion) {
     group.finish();
 }
 
-pub fn bench_recursive_circuit<const SUBPROOF_NUM: usize>(
+pub fn bench_recursive_circuit(
     g: &mut BenchmarkGroup<WallTime>,
+    subproof_num: usize,
     parallism: usize,
 ) {
     let batch_size = 1024;
     let token_num = 4;
-    let (merkle_sum_circuit, account_targets) =
-        build_merkle_sum_tree_circuit(batch_size, token_num, STANDARD_CONFIG);
+    let (merkle_sum_circuit, account_targets) = build_merkle_sum_tree_circuit(
+        batch_size,
+        token_num,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+    );
 
     let accounts = gen_accounts_with_random_data(batch_size, token_num);
     let prover = MerkleSumTreeProver { accounts };
@@ -85,21 +99,24 @@ pub fn bench_recursive_circuit<const SUBPROOF_NUM: usize>(
     let merkle_sum_proof =
         prover.get_proof_with_circuit_data(&account_targets, &merkle_sum_circuit);
 
-    let (recursive_circuit, recursive_targets) = build_recursive_n_circuit::<C, SUBPROOF_NUM>(
+    let (recursive_circuit, recursive_targets) = build_recursive_n_circuit::<C>(
         &merkle_sum_circuit.common,
         &merkle_sum_circuit.verifier_only,
+        subproof_num,
+        &Valuation::default(),
+        false,
         STANDARD_CONFIG,
     );
 
-    let subproofs: [ProofWithPublicInputs<F, C, D>; SUBPROOF_NUM] =
-        std::array::from_fn(|_| merkle_sum_proof.clone());
-    let bench_id = format!("recursive_circuit_{}_parallism_{}", SUBPROOF_NUM, parallism);
+    let subproofs = vec![merkle_sum_proof.clone(); subproof_num];
+    let bench_id = format!("recursive_circuit_{}_parallism_{}", subproof_num, parallism);
     g.bench_function(bench_id.as_str(), |b| {
         b.iter(|| {
             (0..parallism).into_par_iter().for_each(|_| {
                 let recursive_prover = RecursiveProver {
                     sub_proofs: subproofs.clone(),
                     sub_circuit_vd: merkle_sum_circuit.verifier_only.clone(),
+                    round: RoundInfo::default(),
                 };
                 recursive_prover
                     .get_proof_with_circuit_data(recursive_targets.clone(), &recursive_circuit);
@@ -113,11 +130,9 @@ pub fn recursive_circuit_for_branchout(c: &mut Criterion) {
     group.sample_size(10);
     group.sampling_mode(SamplingMode::Flat); // for long running benchmarks
     let parallism = 1;
-    bench_recursive_circuit::<4>(&mut group, parallism);
-    bench_recursive_circuit::<8>(&mut group, parallism);
-    bench_recursive_circuit::<16>(&mut group, parallism);
-    bench_recursive_circuit::<32>(&mut group, parallism);
-    bench_recursive_circuit::<64>(&mut group, parallism);
+    for &subproof_num in [4, 8, 16, 32, 64].iter() {
+        bench_recursive_circuit(&mut group, subproof_num, parallism);
+    }
     group.finish();
 }
 
@@ -1,5 +1,9 @@
 #![feature(test)]
-use zk_por_core::{account::gen_accounts_with_random_data, merkle_sum_tree::MerkleSumTree};
+use zk_por_core::{
+    account::{gen_accounts_with_random_data, LeafHashMode},
+    merkle_sum_tree::MerkleSumTree,
+    valuation::Valuation,
+};
 
 extern crate test;
 use test::Bencher;
@@ -8,7 +12,13 @@ fn bench(b: &mut Bencher, batch_size: usize) {
     let num_assets = 200;
     let accounts = gen_accounts_with_random_data(batch_size, num_assets);
 
-    b.iter(|| _ = MerkleSumTree::new_tree_from_accounts(&accounts));
+    b.iter(|| {
+        _ = MerkleSumTree::new_tree_from_accounts(
+            &accounts,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+        )
+    });
 }
 
 #[bench]
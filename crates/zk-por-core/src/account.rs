@@ -1,21 +1,73 @@
 use crate::{
     database::{PoRDB, UserId},
     types::F,
+    valuation::Valuation,
 };
 use plonky2::{
     hash::{hash_types::HashOut, poseidon::PoseidonHash},
-    plonk::config::Hasher,
+    plonk::config::{GenericHashOut, Hasher},
 };
-use plonky2_field::types::{Field, PrimeField64};
+use plonky2_field::types::{Field, PrimeField64, Sample};
 use rand::Rng;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// How an account is committed into its merkle sum tree leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeafHashMode {
+    /// The leaf hash commits to the user id, the total equity and the total debt.
+    #[default]
+    Sum,
+    /// The leaf hash additionally commits to the equity and debt of every token.
+    PerToken,
+}
+
+/// A secret seed from which the per-user, per-round salts of the leaf hashes are derived.
+/// It must be kept private by the prover, otherwise user ids can be brute-forced from the sibling hashes again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SaltSeed(HashOut<F>);
+
+impl SaltSeed {
+    /// Derive the seed from a secret string, e.g., configured in the prover config.
+    /// The byte length is hashed first, as the last 7-byte chunk is zero-padded, e.g., "a" and "a\0" would collide otherwise.
+    pub fn from_secret(secret: &str) -> Self {
+        let bytes = secret.as_bytes();
+        let inputs = std::iter::once(F::from_canonical_usize(bytes.len()))
+            .chain(bytes.chunks(7).map(|chunk| {
+                let mut buf = [0u8; 8];
+                buf[0..chunk.len()].copy_from_slice(chunk);
+                F::from_canonical_u64(u64::from_le_bytes(buf))
+            }))
+            .collect::<Vec<F>>();
+        Self(PoseidonHash::hash_no_pad(inputs.as_slice()))
+    }
+
+    pub fn rand() -> Self {
+        Self(HashOut::from_vec((0..4).map(|_| F::rand()).collect()))
+    }
+
+    /// The salt of a user at a salt epoch, i.e., Hash(seed, salt_epoch, user_id). The salt epoch is the round number unless configured
+    /// otherwise.
+    pub fn derive_salt(&self, salt_epoch: usize, account: &Account) -> HashOut<F> {
+        #[allow(clippy::useless_vec)]
+        let inputs = vec![
+            self.0.elements.to_vec(),
+            vec![F::from_canonical_u64(salt_epoch as u64)],
+            account.get_user_id_in_field(),
+        ]
+        .concat();
+        PoseidonHash::hash_no_pad(inputs.as_slice())
+    }
+}
+
 /// A struct representing a users account. It represents their equity and debt as a Vector of goldilocks field elements.
-#[derive(Debug, Clone)]
+/// The salt is a per-round random value mixed into the leaf hash, such that the leaf hash cannot be linked to the user id.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Account {
     pub id: String, // 256 bit hex string
     pub equity: Vec<F>,
     pub debt: Vec<F>,
+    pub salt: HashOut<F>,
 }
 
 impl Serialize for Account {
@@ -24,7 +76,7 @@ impl Serialize for Account {
         S: Serializer,
     {
         use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("Account", 3)?;
+        let mut state = serializer.serialize_struct("Account", 4)?;
         state.serialize_field("id", &self.id)?;
         // Custom serialization for equity and debt to ensure they are serialized in a specific format if needed
         let equity_as_strings: Vec<String> = self
@@ -46,6 +98,7 @@ impl Serialize for Account {
             })
             .collect();
         state.serialize_field("debt", &debt_as_strings)?;
+        state.serialize_field("salt", &hex::encode(self.salt.to_bytes()))?;
         state.end()
     }
 }
@@ -60,6 +113,8 @@ impl<'de> Deserialize<'de> for Account {
             id: String,
             equity: Vec<String>,
             debt: Vec<String>,
+            #[serde(default)]
+            salt: Option<String>,
         }
 
         let helper = InnerAccount::deserialize(deserializer)?;
@@ -74,22 +129,42 @@ impl<'de> Deserialize<'de> for Account {
             .map(|e| F::from_canonical_u64(u64::from_str_radix(e, 10).unwrap()))
             .collect();
 
-        Ok(Account { id: helper.id, equity: equity, debt: debt })
+        let salt = match helper.salt {
+            Some(salt) => {
+                let bytes = hex::decode(&salt).map_err(serde::de::Error::custom)?;
+                if bytes.len() != 32 {
+                    return Err(serde::de::Error::custom("invalid salt length"));
+                }
+                HashOut::from_bytes(&bytes)
+            }
+            None => HashOut::default(),
+        };
+
+        Ok(Account { id: helper.id, equity: equity, debt: debt, salt: salt })
     }
 }
 
 impl Account {
-    /// Gets the account hash for a given account.
-    pub fn get_hash(&self) -> HashOut<F> {
-        let sum_equity = self.equity.iter().fold(F::ZERO, |acc, x| acc + *x);
+    /// Gets the total equity and the total debt of the account under the given valuation.
+    pub fn get_sums(&self, valuation: &Valuation) -> (F, F) {
+        (valuation.value(&self.equity), valuation.value(&self.debt))
+    }
 
-        let sum_debt = self.debt.iter().fold(F::ZERO, |acc, x| acc + *x);
+    /// Gets the account hash for a given account. The hash inputs must be consistent with `AccountSumTargets::get_account_hash_targets`.
+    pub fn get_hash(&self, mode: LeafHashMode, valuation: &Valuation) -> HashOut<F> {
+        let (sum_equity, sum_debt) = self.get_sums(valuation);
 
         let id = self.get_user_id_in_field();
 
         #[allow(clippy::useless_vec)]
-        let hash =
-            PoseidonHash::hash_no_pad(vec![id, vec![sum_equity, sum_debt]].concat().as_slice());
+        let mut hash_inputs =
+            vec![id, self.salt.elements.to_vec(), vec![sum_equity, sum_debt]].concat();
+        if mode == LeafHashMode::PerToken {
+            hash_inputs.extend(self.equity.iter());
+            hash_inputs.extend(self.debt.iter());
+        }
+
+        let hash = PoseidonHash::hash_no_pad(hash_inputs.as_slice());
 
         hash
     }
@@ -99,6 +174,7 @@ impl Account {
             id: user_id,
             equity: vec![F::default(); num_of_tokens],
             debt: vec![F::default(); num_of_tokens],
+            salt: HashOut::default(),
         }
     }
 
@@ -107,9 +183,20 @@ impl Account {
             id: "0".repeat(64),
             equity: vec![F::default(); num_of_tokens],
             debt: vec![F::default(); num_of_tokens],
+            salt: HashOut::default(),
         }
     }
 
+    /// Whether this is a padding account, i.e., its user id is all zeros. Padding accounts are not counted as users.
+    pub fn is_padding(&self) -> bool {
+        self.get_user_id_in_field().iter().all(|x| x.is_zero())
+    }
+
+    /// Set the salt of this account for the salt epoch, derived from the secret seed.
+    pub fn set_salt_from_seed(&mut self, seed: &SaltSeed, salt_epoch: usize) {
+        self.salt = seed.derive_salt(salt_epoch, self);
+    }
+
     /// Gets a user id as a vec of 5 GF elements.
     pub fn get_user_id_in_field(&self) -> Vec<F> {
         assert!(self.id.len() == 64);
@@ -164,17 +251,19 @@ pub fn gen_accounts_with_random_data(num_accounts: usize, num_assets: usize) ->
         rng.fill(&mut bytes);
         #[allow(clippy::format_collect)]
         let account_id = bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
-        accounts.push(Account { id: account_id, equity: equities, debt: debts });
+        accounts.push(Account {
+            id: account_id,
+            equity: equities,
+            debt: debts,
+            salt: HashOut::default(),
+        });
     }
     accounts
 }
 
+/// Generates a batch of padding accounts, which are not counted as users.
 pub fn gen_empty_accounts(batch_size: usize, num_assets: usize) -> Vec<Account> {
-    let accounts =
-        vec![
-            Account::get_empty_account_with_user_id(UserId::rand().to_string(), num_assets);
-            batch_size
-        ];
+    let accounts = vec![Account::get_empty_account(num_assets); batch_size];
     accounts
 }
 
@@ -190,6 +279,7 @@ mod tests {
             id: "1".to_owned(), // Assuming `id` is of type that implements `Serialize` and `Deserialize`
             equity: vec![F::from_canonical_u64(0), F::from_canonical_u64(1)],
             debt: vec![F::from_canonical_u64(0), F::from_canonical_u64(2)],
+            salt: HashOut::from_vec(vec![F::rand(), F::rand(), F::rand(), F::rand()]),
         };
 
         // Step 2: Serialize the `Account` instance to a JSON string
@@ -202,5 +292,84 @@ mod tests {
         assert_eq!(original_account.id, deserialized_account.id);
         assert_eq!(original_account.equity, deserialized_account.equity);
         assert_eq!(original_account.debt, deserialized_account.debt);
+        assert_eq!(original_account.salt, deserialized_account.salt);
+    }
+
+    #[test]
+    fn test_per_token_hash_binds_balances() {
+        let account = Account {
+            id: "320b5ea99e653bc2b593db4130d10a4efd3a0b4cc2e1a6672b678d71dfbd33ad".to_string(),
+            equity: vec![F::from_canonical_u64(3), F::from_canonical_u64(1)],
+            debt: vec![F::from_canonical_u64(0), F::from_canonical_u64(2)],
+            salt: HashOut::default(),
+        };
+        // the same totals, but the balances are moved between tokens.
+        let swapped_account = Account {
+            id: account.id.clone(),
+            equity: vec![F::from_canonical_u64(1), F::from_canonical_u64(3)],
+            debt: vec![F::from_canonical_u64(2), F::from_canonical_u64(0)],
+            salt: HashOut::default(),
+        };
+
+        assert_eq!(
+            account.get_hash(LeafHashMode::Sum, &Valuation::default()),
+            swapped_account.get_hash(LeafHashMode::Sum, &Valuation::default())
+        );
+        assert_ne!(
+            account.get_hash(LeafHashMode::PerToken, &Valuation::default()),
+            swapped_account.get_hash(LeafHashMode::PerToken, &Valuation::default())
+        );
+    }
+
+    #[test]
+    fn test_price_weighted_sums() {
+        let account = Account {
+            id: "320b5ea99e653bc2b593db4130d10a4efd3a0b4cc2e1a6672b678d71dfbd33ad".to_string(),
+            equity: vec![F::from_canonical_u64(3), F::from_canonical_u64(1)],
+            debt: vec![F::from_canonical_u64(0), F::from_canonical_u64(2)],
+            salt: HashOut::default(),
+        };
+        let valuation = Valuation::new_price_weighted(vec![100, 10]).unwrap();
+        assert_eq!(
+            account.get_sums(&valuation),
+            (F::from_canonical_u64(310), F::from_canonical_u64(20))
+        );
+        assert_ne!(
+            account.get_hash(LeafHashMode::Sum, &valuation),
+            account.get_hash(LeafHashMode::Sum, &Valuation::default())
+        );
+    }
+
+    #[test]
+    fn test_salt_hides_user_id() {
+        let mut account = gen_accounts_with_random_data(1, 2).pop().unwrap();
+        let unsalted_hash = account.get_hash(LeafHashMode::PerToken, &Valuation::default());
+
+        let seed = SaltSeed::from_secret("secret seed");
+        account.set_salt_from_seed(&seed, 1);
+        let salt_round_1 = account.salt;
+        let hash_round_1 = account.get_hash(LeafHashMode::PerToken, &Valuation::default());
+        assert_ne!(unsalted_hash, hash_round_1);
+
+        // the salt is deterministic given the seed, the round and the user.
+        account.set_salt_from_seed(&seed, 1);
+        assert_eq!(salt_round_1, account.salt);
+
+        // but differs across rounds and seeds, so that the leaf hashes are not linkable.
+        account.set_salt_from_seed(&seed, 2);
+        assert_ne!(salt_round_1, account.salt);
+        assert_ne!(hash_round_1, account.get_hash(LeafHashMode::PerToken, &Valuation::default()));
+
+        account.set_salt_from_seed(&SaltSeed::from_secret("another seed"), 1);
+        assert_ne!(salt_round_1, account.salt);
+    }
+
+    #[test]
+    fn test_salt_seed_from_secret() {
+        assert_eq!(SaltSeed::from_secret("secret seed"), SaltSeed::from_secret("secret seed"));
+        // the zero padding of the last chunk does not make secrets of different lengths collide.
+        assert_ne!(SaltSeed::from_secret("a"), SaltSeed::from_secret("a\0"));
+        assert_ne!(SaltSeed::from_secret(""), SaltSeed::from_secret("\0"));
+        assert_ne!(SaltSeed::from_secret("1234567"), SaltSeed::from_secret("1234567\0"));
     }
 }
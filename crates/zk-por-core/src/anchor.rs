@@ -0,0 +1,246 @@
+use plonky2::{hash::hash_types::HashOut, plonk::config::GenericHashOut};
+use plonky2_field::types::PrimeField64;
+use sha2::Sha256;
+use sha3::{Digest, Keccak256};
+
+use crate::{
+    error::PoRError, recursive_prover::recursive_circuit::RecursiveTargets, types::F, Info, Proof,
+};
+
+/// The tag of the OP_RETURN payload, followed by the payload version.
+pub const BTC_ANCHOR_TAG: &[u8; 4] = b"ZPOR";
+pub const BTC_ANCHOR_VERSION: u8 = 1;
+/// The function of the registry contract, which takes the round number, the root hash, the root verifier data digest and the total
+/// equity and debt of the round.
+pub const ETH_ANCHOR_FUNCTION: &str = "anchor(uint64,bytes32,bytes32,uint64,uint64)";
+
+const OP_RETURN: u8 = 0x6a;
+/// The round number, the root hash, the root verifier data digest, the total equity and the total debt.
+const COMMITMENT_BYTES: usize = 8 + 32 + 32 + 8 + 8;
+const COMMITMENT_HASH_BYTES: usize = 32;
+/// The tag, the version and the sha256 hash of the commitment, which is within the 80 bytes relayed by nodes with the legacy
+/// datacarriersize and short enough to be pushed by a single opcode.
+const BTC_PAYLOAD_BYTES: usize = 4 + 1 + COMMITMENT_HASH_BYTES;
+/// The selector and 5 abi words.
+const ETH_CALLDATA_BYTES: usize = 4 + 5 * 32;
+
+/// What is anchored on-chain for a round, such that anyone can check that a published proof file is the one of the round.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnchorCommitment {
+    pub round_num: u64,
+    /// The root hash of the gmst in the bytes of `Info::root_hash`, i.e., the field elements in little endian.
+    pub root_hash: [u8; 32],
+    /// The digest of the root circuit in the same encoding as the root hash.
+    pub vd_digest: [u8; 32],
+    pub equity_sum: u64,
+    pub debt_sum: u64,
+}
+
+impl AnchorCommitment {
+    /// The commitment of the round of a root proof, which is expected to be verified, and its global info. The global info must be the
+    /// one of the root proof.
+    pub fn new(proof: &Proof, info: &Info) -> Result<Self, PoRError> {
+        let public_inputs = &proof.proof.public_inputs;
+        let root_hash: [u8; 32] =
+            HashOut::<F>::from_partial(&public_inputs[RecursiveTargets::pub_input_hash_offset()])
+                .to_bytes()
+                .try_into()
+                .unwrap();
+        let equity_sum =
+            public_inputs[RecursiveTargets::pub_input_equity_offset()].to_canonical_u64();
+        let debt_sum = public_inputs[RecursiveTargets::pub_input_debt_offset()].to_canonical_u64();
+        if hex::encode(root_hash) != info.root_hash
+            || equity_sum != info.equity_sum
+            || debt_sum != info.debt_sum
+        {
+            return Err(PoRError::InvalidAnchor(
+                "the global info does not match the root proof".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            round_num: proof.general.round_num as u64,
+            root_hash,
+            vd_digest: proof.root_vd_digest.to_bytes().try_into().unwrap(),
+            equity_sum,
+            debt_sum,
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.round_num.to_le_bytes().to_vec();
+        bytes.extend(self.root_hash);
+        bytes.extend(self.vd_digest);
+        bytes.extend(self.equity_sum.to_le_bytes());
+        bytes.extend(self.debt_sum.to_le_bytes());
+        bytes
+    }
+
+    /// The sha256 hash of the commitment with the integers in little endian, which is anchored in the OP_RETURN output. The commitment
+    /// itself is kept in the anchor file and the ethereum calldata.
+    pub fn commitment_hash(&self) -> [u8; COMMITMENT_HASH_BYTES] {
+        let bytes = self.to_bytes();
+        assert_eq!(bytes.len(), COMMITMENT_BYTES);
+        Sha256::digest(bytes).into()
+    }
+
+    /// The script of a BTC OP_RETURN output, i.e., OP_RETURN followed by a single push of the tag, the version and the commitment hash.
+    pub fn btc_op_return_script(&self) -> Vec<u8> {
+        let mut script = vec![OP_RETURN, BTC_PAYLOAD_BYTES as u8];
+        script.extend(BTC_ANCHOR_TAG);
+        script.push(BTC_ANCHOR_VERSION);
+        script.extend(self.commitment_hash());
+        script
+    }
+
+    /// Parse the commitment hash from a script built by `btc_op_return_script`.
+    pub fn commitment_hash_from_btc_op_return_script(
+        script: &[u8],
+    ) -> Result<[u8; COMMITMENT_HASH_BYTES], PoRError> {
+        if script.len() != 2 + BTC_PAYLOAD_BYTES
+            || script[0..2] != [OP_RETURN, BTC_PAYLOAD_BYTES as u8]
+        {
+            return Err(PoRError::InvalidAnchor("not an OP_RETURN anchor script".to_string()));
+        }
+        if &script[2..6] != BTC_ANCHOR_TAG || script[6] != BTC_ANCHOR_VERSION {
+            return Err(PoRError::InvalidAnchor(format!(
+                "unknown tag {} or version {}",
+                hex::encode(&script[2..6]),
+                script[6]
+            )));
+        }
+        Ok(script[7..].try_into().unwrap())
+    }
+
+    /// The calldata of `ETH_ANCHOR_FUNCTION` of the registry contract, i.e., the function selector followed by the abi encoded
+    /// arguments.
+    pub fn eth_calldata(&self) -> Vec<u8> {
+        let mut calldata = eth_function_selector().to_vec();
+        calldata.extend(abi_uint64(self.round_num));
+        calldata.extend(self.root_hash);
+        calldata.extend(self.vd_digest);
+        calldata.extend(abi_uint64(self.equity_sum));
+        calldata.extend(abi_uint64(self.debt_sum));
+        calldata
+    }
+
+    /// Parse the commitment from calldata built by `eth_calldata`, whose uint64 words must have no bits above 64.
+    pub fn from_eth_calldata(calldata: &[u8]) -> Result<Self, PoRError> {
+        if calldata.len() != ETH_CALLDATA_BYTES || calldata[0..4] != eth_function_selector() {
+            return Err(PoRError::InvalidAnchor(format!("not a call of {}", ETH_ANCHOR_FUNCTION)));
+        }
+        let words: Vec<&[u8]> = calldata[4..].chunks(32).collect();
+        let read_uint64 = |word: &[u8]| -> Result<u64, PoRError> {
+            if word[0..24].iter().any(|b| *b != 0) {
+                return Err(PoRError::InvalidAnchor("uint64 argument out of range".to_string()));
+            }
+            Ok(u64::from_be_bytes(word[24..32].try_into().unwrap()))
+        };
+        Ok(Self {
+            round_num: read_uint64(words[0])?,
+            root_hash: words[1].try_into().unwrap(),
+            vd_digest: words[2].try_into().unwrap(),
+            equity_sum: read_uint64(words[3])?,
+            debt_sum: read_uint64(words[4])?,
+        })
+    }
+
+    /// Re-parse the payloads, which must commit to this commitment.
+    pub fn verify_payloads(&self, btc_script: &[u8], eth_calldata: &[u8]) -> Result<(), PoRError> {
+        if Self::commitment_hash_from_btc_op_return_script(btc_script)? != self.commitment_hash() {
+            return Err(PoRError::InvalidAnchor(
+                "the OP_RETURN script commits to another round".to_string(),
+            ));
+        }
+        if &Self::from_eth_calldata(eth_calldata)? != self {
+            return Err(PoRError::InvalidAnchor(
+                "the ethereum calldata commits to another round".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The first 4 bytes of keccak256 of the function signature.
+fn eth_function_selector() -> [u8; 4] {
+    Keccak256::digest(ETH_ANCHOR_FUNCTION.as_bytes())[0..4].try_into().unwrap()
+}
+
+/// A uint64 as an abi word, i.e., in big endian left-padded to 32 bytes.
+fn abi_uint64(x: u64) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    word[24..].copy_from_slice(&x.to_be_bytes());
+    word
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::AnchorCommitment;
+    use crate::error::PoRError;
+
+    fn commitment() -> AnchorCommitment {
+        AnchorCommitment {
+            round_num: 508787475,
+            root_hash: [1u8; 32],
+            vd_digest: [2u8; 32],
+            equity_sum: 1 << 40,
+            debt_sum: 3,
+        }
+    }
+
+    #[test]
+    fn test_btc_op_return_script() {
+        let commitment = commitment();
+        let script = commitment.btc_op_return_script();
+        // the script of OP_RETURN and a push of 37 bytes is within the 83 bytes of the legacy datacarriersize.
+        assert_eq!(2 + 37, script.len());
+        assert_eq!([0x6au8, 37], script[0..2]);
+        assert_eq!(b"ZPOR", &script[2..6]);
+        assert_eq!(
+            commitment.commitment_hash(),
+            AnchorCommitment::commitment_hash_from_btc_op_return_script(&script).unwrap()
+        );
+        let other = AnchorCommitment { debt_sum: 4, ..commitment.clone() };
+        assert_ne!(commitment.commitment_hash(), other.commitment_hash());
+
+        let mut script = commitment.btc_op_return_script();
+        script[6] = 2;
+        assert!(matches!(
+            AnchorCommitment::commitment_hash_from_btc_op_return_script(&script),
+            Err(PoRError::InvalidAnchor(_))
+        ));
+        assert!(AnchorCommitment::commitment_hash_from_btc_op_return_script(
+            &script[..script.len() - 1]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_eth_calldata() {
+        let commitment = commitment();
+        let calldata = commitment.eth_calldata();
+        assert_eq!(4 + 5 * 32, calldata.len());
+        // the selector of anchor(uint64,bytes32,bytes32,uint64,uint64).
+        assert_eq!(hex::decode("15109078").unwrap(), calldata[0..4]);
+        assert_eq!(hex::encode(508787475u64.to_be_bytes()), hex::encode(&calldata[28..36]));
+        assert_eq!(commitment, AnchorCommitment::from_eth_calldata(&calldata).unwrap());
+
+        let mut calldata = commitment.eth_calldata();
+        calldata[4] = 1;
+        assert!(matches!(
+            AnchorCommitment::from_eth_calldata(&calldata),
+            Err(PoRError::InvalidAnchor(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_payloads() {
+        let commitment = commitment();
+        let (script, calldata) = (commitment.btc_op_return_script(), commitment.eth_calldata());
+        commitment.verify_payloads(&script, &calldata).unwrap();
+
+        let other = AnchorCommitment { round_num: 508787476, ..commitment.clone() };
+        assert!(commitment.verify_payloads(&other.btc_op_return_script(), &calldata).is_err());
+        assert!(commitment.verify_payloads(&script, &other.eth_calldata()).is_err());
+    }
+}
@@ -0,0 +1,386 @@
+use plonky2::plonk::{circuit_data::CommonCircuitData, proof::ProofWithPublicInputs};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    account::{Account, LeafHashMode},
+    circuit_registry::registry::CircuitRegistry,
+    error::PoRError,
+    merkle_sum_tree::MerkleSumNode,
+    parser::{FileManager, JsonFileManager},
+    pipeline::PipelineProver,
+    recursive_prover::recursive_circuit::RoundInfo,
+    types::{C, D, F},
+    valuation::Valuation,
+};
+
+const CHECKPOINT_MANIFEST_FILENAME: &str = "checkpoint.json";
+
+/// The parameters of a proving run that a checkpoint is only valid for. The salt seed is only included as a hash.
+#[derive(Serialize)]
+pub struct CheckpointParams<'a> {
+    pub round_no: usize,
+    pub salt_epoch: usize,
+    pub snapshot_timestamp: Option<u64>,
+    pub batch_size: usize,
+    pub recursion_branchout_num: usize,
+    pub tokens: &'a [String],
+    pub leaf_hash_mode: LeafHashMode,
+    pub valuation: &'a Valuation,
+    pub salt_seed_hash: String,
+}
+
+impl CheckpointParams<'_> {
+    pub fn hash(&self) -> String {
+        let params_bytes = serde_json::to_vec(self).expect("fail to serialize checkpoint params");
+        hex::encode(Sha256::digest(params_bytes))
+    }
+}
+
+pub fn hash_salt_seed(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+/// Hash the name and content of every user data file in the order the account parser reads them.
+pub fn hash_input_files(user_data_path: &str) -> Result<String, PoRError> {
+    let dir = Path::new(user_data_path);
+    let docs = FileManager {}.list_json_files(dir)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    for doc in docs {
+        let name = doc.strip_prefix(dir).unwrap_or(&doc);
+        hasher.update(name.to_string_lossy().as_bytes());
+        let mut file = fs::File::open(&doc)?;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// The progress of a proving run, updated after the data of every completed step is written.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointManifest {
+    pub config_hash: String,
+    pub input_hash: String,
+    pub snapshot_timestamp: u64,
+    pub per_parse_account_num: usize,
+    pub parse_rounds: usize,     // the number of completed parse rounds
+    pub last_batch: bool,        // whether the last batch is proven
+    pub recursive_levels: usize, // the number of completed recursive levels
+}
+
+/// A checkpoint directory holding the batch proofs of every parse round, the proof of the last batch, and the proofs of every
+/// recursive level, such that a crashed proving run resumes from the last completed step.
+pub struct Checkpoint {
+    dir: PathBuf,
+    pub manifest: CheckpointManifest,
+}
+
+impl Checkpoint {
+    /// Start a new checkpoint in `dir`, removing any previous one.
+    pub fn create(
+        dir: PathBuf,
+        config_hash: String,
+        input_hash: String,
+        snapshot_timestamp: u64,
+        per_parse_account_num: usize,
+    ) -> Result<Self, PoRError> {
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        let checkpoint = Self {
+            dir,
+            manifest: CheckpointManifest {
+                config_hash,
+                input_hash,
+                snapshot_timestamp,
+                per_parse_account_num,
+                parse_rounds: 0,
+                last_batch: false,
+                recursive_levels: 0,
+            },
+        };
+        checkpoint.write_manifest()?;
+        Ok(checkpoint)
+    }
+
+    /// Open the checkpoint in `dir` to resume from, which must have been created with the same config and input files.
+    pub fn resume(dir: PathBuf, config_hash: &str, input_hash: &str) -> Result<Self, PoRError> {
+        let manifest_bytes = fs::read(dir.join(CHECKPOINT_MANIFEST_FILENAME)).map_err(|e| {
+            PoRError::InvalidCheckpoint(format!("fail to read the checkpoint in {:?}: {}", dir, e))
+        })?;
+        let manifest: CheckpointManifest = serde_json::from_slice(&manifest_bytes)
+            .map_err(|e| PoRError::InvalidCheckpoint(format!("fail to parse manifest: {}", e)))?;
+        if manifest.config_hash != config_hash {
+            return Err(PoRError::InvalidCheckpoint(
+                "the config does not match the checkpoint".to_string(),
+            ));
+        }
+        if manifest.input_hash != input_hash {
+            return Err(PoRError::InvalidCheckpoint(
+                "the user data files do not match the checkpoint".to_string(),
+            ));
+        }
+        tracing::info!(
+            "resume from checkpoint with {} parse rounds, last batch proven: {}, {} recursive levels",
+            manifest.parse_rounds,
+            manifest.last_batch,
+            manifest.recursive_levels
+        );
+        Ok(Self { dir, manifest })
+    }
+
+    /// Write the manifest atomically, such that it never refers to a step whose data is not fully written.
+    fn write_manifest(&self) -> Result<(), PoRError> {
+        let tmp_path = self.dir.join(format!("{}.tmp", CHECKPOINT_MANIFEST_FILENAME));
+        let manifest_bytes =
+            serde_json::to_vec_pretty(&self.manifest).expect("fail to serialize checkpoint");
+        fs::write(&tmp_path, manifest_bytes)?;
+        fs::rename(tmp_path, self.dir.join(CHECKPOINT_MANIFEST_FILENAME))?;
+        Ok(())
+    }
+
+    /// Save the batch proofs of the parse round `parse_num` (starting from 1), which must be the next one.
+    pub fn save_parse_round(
+        &mut self,
+        parse_num: usize,
+        proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) -> Result<(), PoRError> {
+        assert_eq!(parse_num, self.manifest.parse_rounds + 1);
+        let mut bytes = Vec::new();
+        write_proofs(&mut bytes, proofs);
+        fs::write(self.dir.join(format!("parse_{}.bin", parse_num)), bytes)?;
+        self.manifest.parse_rounds = parse_num;
+        self.write_manifest()
+    }
+
+    pub fn load_parse_round(
+        &self,
+        parse_num: usize,
+        common_data: &CommonCircuitData<F, D>,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        self.load_proofs(&format!("parse_{}.bin", parse_num), common_data)
+    }
+
+    pub fn save_last_batch(
+        &mut self,
+        proof: &ProofWithPublicInputs<F, C, D>,
+    ) -> Result<(), PoRError> {
+        let mut bytes = Vec::new();
+        write_proofs(&mut bytes, std::slice::from_ref(proof));
+        fs::write(self.dir.join("last_batch.bin"), bytes)?;
+        self.manifest.last_batch = true;
+        self.write_manifest()
+    }
+
+    pub fn load_last_batch(
+        &self,
+        common_data: &CommonCircuitData<F, D>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+        let mut proofs = self.load_proofs("last_batch.bin", common_data)?;
+        if proofs.len() != 1 {
+            return Err(PoRError::InvalidCheckpoint("malformed last batch".to_string()));
+        }
+        Ok(proofs.pop().unwrap())
+    }
+
+    /// Save the proofs of the recursive level `level` (starting from 1), which must be the next one.
+    pub fn save_level(
+        &mut self,
+        level: usize,
+        proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) -> Result<(), PoRError> {
+        assert_eq!(level, self.manifest.recursive_levels + 1);
+        let mut bytes = Vec::new();
+        write_proofs(&mut bytes, proofs);
+        fs::write(self.dir.join(format!("level_{}.bin", level)), bytes)?;
+        self.manifest.recursive_levels = level;
+        self.write_manifest()
+    }
+
+    pub fn load_level(
+        &self,
+        level: usize,
+        common_data: &CommonCircuitData<F, D>,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        self.load_proofs(&format!("level_{}.bin", level), common_data)
+    }
+
+    fn load_proofs(
+        &self,
+        filename: &str,
+        common_data: &CommonCircuitData<F, D>,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        let bytes = fs::read(self.dir.join(filename))?;
+        let mut reader = ByteReader { bytes: &bytes, pos: 0 };
+        let proofs = read_proofs(&mut reader, common_data)?;
+        reader.finish()?;
+        Ok(proofs)
+    }
+
+    /// Remove the checkpoint once the proving run completes.
+    pub fn remove(self) -> Result<(), PoRError> {
+        fs::remove_dir_all(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// A prover loading the proofs of the steps completed in the checkpoint, and proving the other steps with the inner prover, saving
+/// their proofs to the checkpoint. The pipeline proves the parse rounds, the last batch and the recursive levels in this order.
+pub struct CheckpointProver<'a> {
+    prover: &'a mut dyn PipelineProver,
+    checkpoint: &'a mut Checkpoint,
+    circuit_registry: &'a CircuitRegistry,
+    parse_num: usize,
+}
+
+impl<'a> CheckpointProver<'a> {
+    pub fn new(
+        prover: &'a mut dyn PipelineProver,
+        checkpoint: &'a mut Checkpoint,
+        circuit_registry: &'a CircuitRegistry,
+    ) -> Self {
+        Self { prover, checkpoint, circuit_registry, parse_num: 0 }
+    }
+}
+
+impl PipelineProver for CheckpointProver<'_> {
+    fn prove_batches(
+        &mut self,
+        accounts: Vec<Account>,
+        batch_roots: &[MerkleSumNode],
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        self.parse_num += 1;
+        if self.parse_num <= self.checkpoint.manifest.parse_rounds {
+            tracing::debug!(
+                "load the batch proofs of {} parse from the checkpoint",
+                self.parse_num
+            );
+            let (batch_circuit, _) = self.circuit_registry.get_batch_circuit();
+            let proofs = self.checkpoint.load_parse_round(self.parse_num, &batch_circuit.common)?;
+            if proofs.len() != batch_roots.len() {
+                return Err(PoRError::InvalidCheckpoint(format!(
+                    "{} batch proofs in {} parse instead of {}",
+                    proofs.len(),
+                    self.parse_num,
+                    batch_roots.len()
+                )));
+            }
+            return Ok(proofs);
+        }
+        let proofs = self.prover.prove_batches(accounts, batch_roots)?;
+        self.checkpoint.save_parse_round(self.parse_num, &proofs)?;
+        Ok(proofs)
+    }
+
+    fn prove_last_batch(
+        &mut self,
+        accounts: Vec<Account>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+        if self.checkpoint.manifest.last_batch {
+            tracing::debug!("load the last batch proof from the checkpoint");
+            let (last_batch_circuit, _) =
+                self.circuit_registry.get_last_batch_circuit().expect("no last batch circuit");
+            return self.checkpoint.load_last_batch(&last_batch_circuit.common);
+        }
+        let proof = self.prover.prove_last_batch(accounts)?;
+        self.checkpoint.save_last_batch(&proof)?;
+        Ok(proof)
+    }
+
+    fn prove_subproofs(
+        &mut self,
+        subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
+        level: usize,
+        round: RoundInfo,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        if level <= self.checkpoint.manifest.recursive_levels {
+            tracing::info!("load the proofs at level {} from the checkpoint", level);
+            let recursive_circuit = self.circuit_registry.get_level_circuit(level);
+            let proofs = self.checkpoint.load_level(level, &recursive_circuit.common)?;
+            let expected_num =
+                subproofs.len() / self.circuit_registry.get_recursion_branchout_num();
+            if proofs.len() != expected_num {
+                return Err(PoRError::InvalidCheckpoint(format!(
+                    "{} proofs at level {} instead of {}",
+                    proofs.len(),
+                    level,
+                    expected_num
+                )));
+            }
+            return Ok(proofs);
+        }
+        let proofs = self.prover.prove_subproofs(subproofs, level, round)?;
+        self.checkpoint.save_level(level, &proofs)?;
+        Ok(proofs)
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], PoRError> {
+        if self.pos + len > self.bytes.len() {
+            return Err(PoRError::InvalidCheckpoint("truncated checkpoint file".to_string()));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize, PoRError> {
+        let bytes = self.read_bytes(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn finish(&self) -> Result<(), PoRError> {
+        if self.pos != self.bytes.len() {
+            return Err(PoRError::InvalidCheckpoint(
+                "trailing bytes in checkpoint file".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn write_len(bytes: &mut Vec<u8>, len: usize) {
+    bytes.extend((len as u64).to_le_bytes());
+}
+
+fn write_proofs(bytes: &mut Vec<u8>, proofs: &[ProofWithPublicInputs<F, C, D>]) {
+    write_len(bytes, proofs.len());
+    for proof in proofs {
+        let proof_bytes = proof.to_bytes();
+        write_len(bytes, proof_bytes.len());
+        bytes.extend(proof_bytes);
+    }
+}
+
+fn read_proofs(
+    reader: &mut ByteReader,
+    common_data: &CommonCircuitData<F, D>,
+) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+    let proof_num = reader.read_len()?;
+    (0..proof_num)
+        .map(|_| {
+            let len = reader.read_len()?;
+            let proof_bytes = reader.read_bytes(len)?.to_vec();
+            ProofWithPublicInputs::<F, C, D>::from_bytes(proof_bytes, common_data)
+                .map_err(|e| PoRError::InvalidCheckpoint(format!("fail to parse proof: {}", e)))
+        })
+        .collect()
+}
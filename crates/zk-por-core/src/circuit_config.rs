@@ -60,10 +60,11 @@ pub const STANDARD_ZK_CONFIG: CircuitConfig = CircuitConfig {
     },
 };
 
-pub fn get_recursive_circuit_configs<const RECURSION_BRANCHOUT_NUM: usize>(
+pub fn get_recursive_circuit_configs(
     batch_num: usize,
+    recursion_branchout_num: usize,
 ) -> Vec<CircuitConfig> {
-    let level = recursive_levels(batch_num, RECURSION_BRANCHOUT_NUM);
+    let level = recursive_levels(batch_num, recursion_branchout_num);
 
     let mut configs = vec![STANDARD_CONFIG; level];
 
@@ -82,11 +83,14 @@ pub mod test {
     #[test]
     pub fn test_get_recursive_circuit_config() {
         let batch_num = 1;
-        let cfgs = get_recursive_circuit_configs::<64>(batch_num);
+        let cfgs = get_recursive_circuit_configs(batch_num, 64);
         assert_eq!(vec![STANDARD_ZK_CONFIG], cfgs);
 
         let batch_num = 66;
-        let cfgs = get_recursive_circuit_configs::<64>(batch_num);
+        let cfgs = get_recursive_circuit_configs(batch_num, 64);
         assert_eq!(vec![STANDARD_CONFIG, STANDARD_ZK_CONFIG], cfgs);
+
+        let cfgs = get_recursive_circuit_configs(batch_num, 8);
+        assert_eq!(vec![STANDARD_CONFIG, STANDARD_CONFIG, STANDARD_ZK_CONFIG], cfgs);
     }
 }
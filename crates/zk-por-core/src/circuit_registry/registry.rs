@@ -4,30 +4,125 @@ use plonky2::{
         circuit_data::{CircuitConfig, CircuitData},
         proof::ProofWithPublicInputs,
     },
+    util::serialization::{
+        Buffer, DefaultGateSerializer, DefaultGeneratorSerializer, IoResult, Read, Write,
+    },
 };
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use crate::{
+    account::LeafHashMode,
+    error::PoRError,
     merkle_sum_prover::circuits::{
         account_circuit::AccountTargets, merkle_sum_circuit::build_merkle_sum_tree_circuit,
     },
-    recursive_prover::recursive_circuit::{build_recursive_n_circuit, RecursiveTargets},
+    recursive_prover::recursive_circuit::{
+        build_merge_circuit, build_recursive_n_circuit, MergeTargets, RecursiveTargets, RoundInfo,
+    },
     types::{C, D, F},
+    valuation::Valuation,
 };
 
 #[cfg(not(feature = "verifier"))]
 use crate::{
-    account::gen_empty_accounts, merkle_sum_prover::prover::MerkleSumTreeProver,
-    recursive_prover::prover::RecursiveProver,
+    account::gen_empty_accounts,
+    merkle_sum_prover::prover::MerkleSumTreeProver,
+    recursive_prover::prover::{MergeProver, RecursiveProver},
+};
+
+use std::{
+    collections::HashMap,
+    fs,
+    marker::PhantomData,
+    path::{Path, PathBuf},
 };
 
-use std::collections::HashMap;
+const REGISTRY_MANIFEST_FILENAME: &str = "registry.json";
+/// The version of the circuit logic, which must be bumped whenever a circuit changes, e.g., a new public input or constraint, such that
+/// the cached circuits of older code are not loaded.
+pub const CIRCUIT_VERSION: u32 = 1;
+
+/// The parameters the circuits of a registry are built from but the size of the last batch, which key its cache on disk together with
+/// the circuit version. The last batch size changes with the number of users from round to round, hence only the last batch and merge
+/// circuits are cached per last batch size, while the other circuits only depend on whether there is a last batch.
+#[derive(Clone, Serialize, Deserialize)]
+struct RegistryParams {
+    circuit_version: u32,
+    batch_size: usize,
+    has_last_batch: bool,
+    token_num: usize,
+    recursion_branchout_num: usize,
+    leaf_hash_mode: LeafHashMode,
+    valuation: Valuation,
+    batch_circuit_config: CircuitConfig,
+    recursive_level_configs: Vec<CircuitConfig>,
+}
+
+impl RegistryParams {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        batch_size: usize,
+        last_batch_size: Option<usize>,
+        token_num: usize,
+        recursion_branchout_num: usize,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+        batch_circuit_config: CircuitConfig,
+        recursive_level_configs: Vec<CircuitConfig>,
+    ) -> Self {
+        RegistryParams {
+            circuit_version: CIRCUIT_VERSION,
+            batch_size,
+            has_last_batch: last_batch_size.is_some(),
+            token_num,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            valuation: valuation.clone(),
+            batch_circuit_config,
+            recursive_level_configs,
+        }
+    }
+
+    fn cache_key(&self) -> String {
+        let params_bytes = serde_json::to_vec(self).expect("fail to serialize registry params");
+        hex::encode(&Sha256::digest(params_bytes)[..16])
+    }
+}
+
+/// The manifest of a registry saved to disk, against which the loaded circuits are checked. It only detects a corrupted or
+/// mismatched cache, as it is saved along with the circuits.
+#[derive(Serialize, Deserialize)]
+struct RegistryManifest {
+    params: RegistryParams,
+    // the digests of the batch circuit followed by the recursive circuits from the lowest level
+    circuit_digests: Vec<HashOut<F>>,
+    has_empty_proofs: bool,
+}
+
+/// The manifest of the last batch and merge circuits of one last batch size, saved under the cache of the other circuits.
+#[derive(Serialize, Deserialize)]
+struct LastBatchManifest {
+    last_batch_size: usize,
+    // the top recursive circuit verified by the merge circuit
+    top_circuit_digest: HashOut<F>,
+    last_batch_circuit_digest: HashOut<F>,
+    merge_circuit_digest: HashOut<F>,
+    has_empty_proofs: bool,
+}
 
 #[allow(clippy::type_complexity)]
-pub struct CircuitRegistry<const RECURSION_BRANCHOUT_NUM: usize> {
+pub struct CircuitRegistry {
+    params: RegistryParams,
     batch_circuit: (CircuitData<F, C, D>, Vec<AccountTargets>),
+    // the number of subproofs verified by every recursive circuit
+    recursion_branchout_num: usize,
     // inner_vd => the verification circuit that verify the inner circuit
-    recursive_circuits:
-        HashMap<HashOut<F>, (CircuitData<F, C, D>, RecursiveTargets<RECURSION_BRANCHOUT_NUM>)>,
+    recursive_circuits: HashMap<HashOut<F>, (CircuitData<F, C, D>, RecursiveTargets)>,
+    // the circuit of the smaller last batch, if any
+    last_batch_circuit: Option<(CircuitData<F, C, D>, Vec<AccountTargets>)>,
+    // the root circuit merging the top recursive proof with the last batch proof, if there is a last batch
+    merge_circuit: Option<(CircuitData<F, C, D>, MergeTargets)>,
     // circuit_vd -> empty proof
     #[cfg(not(feature = "verifier"))]
     empty_proofs: HashMap<HashOut<F>, ProofWithPublicInputs<F, C, D>>,
@@ -35,18 +130,47 @@ pub struct CircuitRegistry<const RECURSION_BRANCHOUT_NUM: usize> {
     last_inner_circuit_vd: HashOut<F>,
 }
 
-impl<const RECURSION_BRANCHOUT_NUM: usize> CircuitRegistry<RECURSION_BRANCHOUT_NUM> {
+impl CircuitRegistry {
+    /// If `last_batch_size` is set, the last batch is proven by a smaller batch circuit and merged with the top recursive proof by the
+    /// merge circuit, which is then the root circuit.
+    #[allow(clippy::too_many_arguments)]
     pub fn init(
         batch_size: usize,
+        last_batch_size: Option<usize>,
         token_num: usize,
+        recursion_branchout_num: usize,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
         batch_circuit_config: CircuitConfig,
         recursive_level_configs: Vec<CircuitConfig>,
     ) -> Self {
+        assert!(recursion_branchout_num > 1, "the recursion branch-out must be at least 2");
+        if let Some(last_batch_size) = last_batch_size {
+            assert!(
+                last_batch_size > 0 && last_batch_size < batch_size,
+                "the last batch must be smaller than a full batch"
+            );
+        }
         let init_start = std::time::Instant::now();
+        let params = RegistryParams::new(
+            batch_size,
+            last_batch_size,
+            token_num,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            valuation,
+            batch_circuit_config.clone(),
+            recursive_level_configs.clone(),
+        );
 
         let start = std::time::Instant::now();
-        let (batch_circuit_data, account_targets) =
-            build_merkle_sum_tree_circuit(batch_size, token_num, batch_circuit_config);
+        let (batch_circuit_data, account_targets) = build_merkle_sum_tree_circuit(
+            batch_size,
+            token_num,
+            leaf_hash_mode,
+            valuation,
+            batch_circuit_config,
+        );
         tracing::info!(
             "build merkle sum tree circuit with batch size {} in : {:?}",
             batch_size,
@@ -76,14 +200,17 @@ impl<const RECURSION_BRANCHOUT_NUM: usize> CircuitRegistry<RECURSION_BRANCHOUT_N
                 .insert(last_circuit_data.verifier_only.circuit_digest, _last_empty_proof.clone());
         }
 
+        let recursive_level_num = recursive_level_configs.len();
         for (level, circuit_config) in recursive_level_configs.into_iter().enumerate() {
             let start = std::time::Instant::now();
-            let (recursive_circuit, recursive_targets) =
-                build_recursive_n_circuit::<C, RECURSION_BRANCHOUT_NUM>(
-                    &last_circuit_data.common,
-                    &last_circuit_data.verifier_only,
-                    circuit_config,
-                );
+            let (recursive_circuit, recursive_targets) = build_recursive_n_circuit::<C>(
+                &last_circuit_data.common,
+                &last_circuit_data.verifier_only,
+                recursion_branchout_num,
+                valuation,
+                level + 1 == recursive_level_num && last_batch_size.is_none(),
+                circuit_config,
+            );
             tracing::info!(
                 "build recursive circuit at level {} in : {:?}, with vd {:?}",
                 level,
@@ -93,12 +220,12 @@ impl<const RECURSION_BRANCHOUT_NUM: usize> CircuitRegistry<RECURSION_BRANCHOUT_N
 
             #[cfg(not(feature = "verifier"))]
             {
-                let sub_proofs: [ProofWithPublicInputs<F, C, D>; RECURSION_BRANCHOUT_NUM] =
-                    std::array::from_fn(|_| _last_empty_proof.clone());
+                let sub_proofs = vec![_last_empty_proof.clone(); recursion_branchout_num];
                 let start = std::time::Instant::now();
                 let recursive_prover = RecursiveProver {
                     sub_proofs,
                     sub_circuit_vd: last_circuit_data.verifier_only.clone(),
+                    round: RoundInfo::default(),
                 };
                 let recursive_proof = recursive_prover
                     .get_proof_with_circuit_data(recursive_targets.clone(), &recursive_circuit);
@@ -122,35 +249,118 @@ impl<const RECURSION_BRANCHOUT_NUM: usize> CircuitRegistry<RECURSION_BRANCHOUT_N
             last_circuit_data = &recursive_circuits[&last_circuit_vd].0;
         }
 
+        #[cfg(feature = "verifier")]
+        let mut registry = Self {
+            params,
+            batch_circuit: (batch_circuit_data, account_targets),
+            recursion_branchout_num,
+            recursive_circuits: recursive_circuits,
+            last_batch_circuit: None,
+            merge_circuit: None,
+            last_inner_circuit_vd: last_circuit_vd,
+        };
+        #[cfg(not(feature = "verifier"))]
+        let mut registry = Self {
+            params,
+            batch_circuit: (batch_circuit_data, account_targets),
+            recursion_branchout_num,
+            empty_proofs: _empty_proofs,
+            recursive_circuits: recursive_circuits,
+            last_batch_circuit: None,
+            merge_circuit: None,
+            last_inner_circuit_vd: last_circuit_vd,
+        };
+        if let Some(last_batch_size) = last_batch_size {
+            registry.build_last_batch_circuits(last_batch_size);
+        }
+
         tracing::info!(
             "finish init circuit registry with {} recursive levels in {:?}",
-            recursive_circuits.len(),
+            registry.get_recursive_levels(),
             init_start.elapsed()
         );
+        registry
+    }
 
-        #[cfg(feature = "verifier")]
-        {
-            Self {
-                batch_circuit: (batch_circuit_data, account_targets),
-                recursive_circuits: recursive_circuits,
-                last_inner_circuit_vd: last_circuit_vd,
-            }
-        }
+    /// Build the circuit of the last batch of `last_batch_size` accounts and the merge circuit on top of the top recursive circuit.
+    fn build_last_batch_circuits(&mut self, last_batch_size: usize) {
+        let params = &self.params;
+        let start = std::time::Instant::now();
+        let (last_batch_circuit_data, last_batch_account_targets) = build_merkle_sum_tree_circuit(
+            last_batch_size,
+            params.token_num,
+            params.leaf_hash_mode,
+            &params.valuation,
+            params.batch_circuit_config.clone(),
+        );
+        tracing::info!(
+            "build merkle sum tree circuit with last batch size {} in : {:?}",
+            last_batch_size,
+            start.elapsed()
+        );
+
+        let start = std::time::Instant::now();
+        let merge_circuit_config = params
+            .recursive_level_configs
+            .last()
+            .cloned()
+            .expect("a last batch requires at least one recursive level");
+        let merge_circuit = build_merge_circuit(
+            self.get_top_recursive_circuit(),
+            &last_batch_circuit_data,
+            &params.valuation,
+            merge_circuit_config,
+        );
+        tracing::info!("build merge circuit in : {:?}", start.elapsed());
+
+        // like the other circuits, the empty proofs give the proof sizes of the circuits.
         #[cfg(not(feature = "verifier"))]
         {
-            Self {
-                batch_circuit: (batch_circuit_data, account_targets),
-                empty_proofs: _empty_proofs,
-                recursive_circuits: recursive_circuits,
-                last_inner_circuit_vd: last_circuit_vd,
-            }
+            let start = std::time::Instant::now();
+            let accounts = gen_empty_accounts(last_batch_size, params.token_num);
+            let prover = MerkleSumTreeProver { accounts };
+            let empty_last_batch_proof = prover
+                .get_proof_with_circuit_data(&last_batch_account_targets, &last_batch_circuit_data);
+            let top_circuit_vd = self.get_top_recursive_circuit().verifier_only.clone();
+            let merge_prover = MergeProver {
+                top_proof: self.empty_proofs[&top_circuit_vd.circuit_digest].clone(),
+                top_circuit_vd,
+                last_batch_proof: empty_last_batch_proof.clone(),
+                last_batch_circuit_vd: last_batch_circuit_data.verifier_only.clone(),
+                round: RoundInfo::default(),
+            };
+            let empty_merge_proof =
+                merge_prover.get_proof_with_circuit_data(&merge_circuit.1, &merge_circuit.0);
+            tracing::info!("prove empty last batch and merge proofs in : {:?}", start.elapsed());
+            self.empty_proofs.insert(
+                last_batch_circuit_data.verifier_only.circuit_digest,
+                empty_last_batch_proof,
+            );
+            self.empty_proofs
+                .insert(merge_circuit.0.verifier_only.circuit_digest, empty_merge_proof);
         }
+
+        self.last_batch_circuit = Some((last_batch_circuit_data, last_batch_account_targets));
+        self.merge_circuit = Some(merge_circuit);
     }
 
     pub fn get_batch_circuit(&self) -> (&CircuitData<F, C, D>, &[AccountTargets]) {
         (&self.batch_circuit.0, &self.batch_circuit.1)
     }
 
+    pub fn get_recursion_branchout_num(&self) -> usize {
+        self.recursion_branchout_num
+    }
+
+    pub fn get_batch_circuit_config(&self) -> &CircuitConfig {
+        &self.params.batch_circuit_config
+    }
+
+    /// The configs of the recursive circuits from the lowest level.
+    pub fn get_recursive_circuit_configs(&self) -> &[CircuitConfig] {
+        &self.params.recursive_level_configs
+    }
+
     pub fn get_empty_proof(
         &self,
         _circuit_vd: &HashOut<F>,
@@ -171,7 +381,7 @@ impl<const RECURSION_BRANCHOUT_NUM: usize> CircuitRegistry<RECURSION_BRANCHOUT_N
     pub fn get_recursive_circuit(
         &self,
         inner_circuit_vd: &HashOut<F>,
-    ) -> Option<(&CircuitData<F, C, D>, &RecursiveTargets<RECURSION_BRANCHOUT_NUM>)> {
+    ) -> Option<(&CircuitData<F, C, D>, &RecursiveTargets)> {
         let circuit_and_targets = self.recursive_circuits.get(inner_circuit_vd)?;
         Some((&circuit_and_targets.0, &circuit_and_targets.1))
     }
@@ -180,7 +390,475 @@ impl<const RECURSION_BRANCHOUT_NUM: usize> CircuitRegistry<RECURSION_BRANCHOUT_N
         self.recursive_circuits.len()
     }
 
-    pub fn get_root_circuit(&self) -> &CircuitData<F, C, D> {
+    /// The top recursive circuit, which is the root circuit unless there is a last batch.
+    pub fn get_top_recursive_circuit(&self) -> &CircuitData<F, C, D> {
         &self.recursive_circuits[&self.last_inner_circuit_vd].0
     }
+
+    /// The circuit of the proofs at `level`, i.e., the batch circuit at level 0 and the recursive circuit of that level above.
+    pub fn get_level_circuit(&self, level: usize) -> &CircuitData<F, C, D> {
+        self.circuit_chain()[level]
+    }
+
+    pub fn get_last_batch_circuit(&self) -> Option<(&CircuitData<F, C, D>, &[AccountTargets])> {
+        let circuit_and_targets = self.last_batch_circuit.as_ref()?;
+        Some((&circuit_and_targets.0, &circuit_and_targets.1))
+    }
+
+    pub fn get_merge_circuit(&self) -> Option<(&CircuitData<F, C, D>, &MergeTargets)> {
+        let circuit_and_targets = self.merge_circuit.as_ref()?;
+        Some((&circuit_and_targets.0, &circuit_and_targets.1))
+    }
+
+    pub fn get_root_circuit(&self) -> &CircuitData<F, C, D> {
+        match &self.merge_circuit {
+            Some((merge_circuit, _)) => merge_circuit,
+            None => self.get_top_recursive_circuit(),
+        }
+    }
+}
+
+impl CircuitRegistry {
+    /// The key of the cache of this registry, derived from the batch size, the token count, the branch-out, the circuit configs and
+    /// the other parameters the circuits depend on, but not from the size of the last batch.
+    pub fn cache_key(&self) -> String {
+        self.params.cache_key()
+    }
+
+    /// The circuits from the batch circuit to the top recursive circuit.
+    fn circuit_chain(&self) -> Vec<&CircuitData<F, C, D>> {
+        let mut circuits = vec![&self.batch_circuit.0];
+        for _ in 0..self.get_recursive_levels() {
+            let inner_vd_digest = circuits.last().unwrap().verifier_only.circuit_digest;
+            circuits.push(&self.recursive_circuits[&inner_vd_digest].0);
+        }
+        circuits
+    }
+
+    /// Save the circuits, their targets and the empty proofs under `dir/<cache key>`, from which `load` restores the registry
+    /// without rebuilding the circuits. The last batch and merge circuits are saved under `last_batch_<size>` of the cache directory.
+    /// Returns the cache directory.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, PoRError> {
+        let start = std::time::Instant::now();
+        let cache_dir = dir.join(self.cache_key());
+        fs::create_dir_all(&cache_dir)?;
+
+        let circuits = self.circuit_chain();
+        write_circuit(&cache_dir.join("batch_circuit.bin"), circuits[0])?;
+        write_cache_file(&cache_dir.join("batch_targets.bin"), |dst| {
+            write_account_targets(dst, &self.batch_circuit.1)
+        })?;
+        for level in 1..circuits.len() {
+            let inner_vd_digest = circuits[level - 1].verifier_only.circuit_digest;
+            let (circuit, targets) = &self.recursive_circuits[&inner_vd_digest];
+            write_circuit(&cache_dir.join(format!("recursive_circuit_{}.bin", level)), circuit)?;
+            write_cache_file(&cache_dir.join(format!("recursive_targets_{}.bin", level)), |dst| {
+                targets.serialize(dst)
+            })?;
+        }
+
+        #[cfg(not(feature = "verifier"))]
+        for (level, circuit) in circuits.iter().enumerate() {
+            let empty_proof = &self.empty_proofs[&circuit.verifier_only.circuit_digest];
+            fs::write(
+                cache_dir.join(format!("empty_proof_{}.bin", level)),
+                empty_proof.to_bytes(),
+            )?;
+        }
+
+        let manifest = RegistryManifest {
+            params: self.params.clone(),
+            circuit_digests: circuits.iter().map(|c| c.verifier_only.circuit_digest).collect(),
+            has_empty_proofs: cfg!(not(feature = "verifier")),
+        };
+        let manifest_bytes =
+            serde_json::to_vec_pretty(&manifest).expect("fail to serialize registry manifest");
+        fs::write(cache_dir.join(REGISTRY_MANIFEST_FILENAME), manifest_bytes)?;
+        self.save_last_batch_circuits(&cache_dir)?;
+
+        tracing::info!("save circuit registry to {:?} in {:?}", cache_dir, start.elapsed());
+        Ok(cache_dir)
+    }
+
+    fn save_last_batch_circuits(&self, cache_dir: &Path) -> Result<(), PoRError> {
+        let (Some((last_batch_circuit, last_batch_targets)), Some((merge_circuit, merge_targets))) =
+            (&self.last_batch_circuit, &self.merge_circuit)
+        else {
+            return Ok(());
+        };
+        let last_batch_size = last_batch_targets.len();
+        let last_batch_dir = cache_dir.join(format!("last_batch_{}", last_batch_size));
+        fs::create_dir_all(&last_batch_dir)?;
+
+        write_circuit(&last_batch_dir.join("last_batch_circuit.bin"), last_batch_circuit)?;
+        write_cache_file(&last_batch_dir.join("last_batch_targets.bin"), |dst| {
+            write_account_targets(dst, last_batch_targets)
+        })?;
+        write_circuit(&last_batch_dir.join("merge_circuit.bin"), merge_circuit)?;
+        write_cache_file(&last_batch_dir.join("merge_targets.bin"), |dst| {
+            merge_targets.serialize(dst)
+        })?;
+
+        #[cfg(not(feature = "verifier"))]
+        for (name, circuit) in [("last_batch", last_batch_circuit), ("merge", merge_circuit)] {
+            let empty_proof = &self.empty_proofs[&circuit.verifier_only.circuit_digest];
+            fs::write(
+                last_batch_dir.join(format!("empty_proof_{}.bin", name)),
+                empty_proof.to_bytes(),
+            )?;
+        }
+
+        let manifest = LastBatchManifest {
+            last_batch_size,
+            top_circuit_digest: self.get_top_recursive_circuit().verifier_only.circuit_digest,
+            last_batch_circuit_digest: last_batch_circuit.verifier_only.circuit_digest,
+            merge_circuit_digest: merge_circuit.verifier_only.circuit_digest,
+            has_empty_proofs: cfg!(not(feature = "verifier")),
+        };
+        let manifest_bytes =
+            serde_json::to_vec_pretty(&manifest).expect("fail to serialize last batch manifest");
+        fs::write(last_batch_dir.join(REGISTRY_MANIFEST_FILENAME), manifest_bytes)?;
+        Ok(())
+    }
+
+    /// Load the registry saved by `save` under `dir` for the given parameters, which are the same as the ones of `init`. A cache of other
+    /// parameters or circuit version, or whose circuits do not match the digests in its manifest, or whose empty proofs do not verify, is
+    /// rejected. As the manifest is saved along with the circuits, it only detects a corrupted cache; the batch circuit is also rebuilt
+    /// and must match the cached one, which catches a stale cache of a changed batch circuit if `CIRCUIT_VERSION` is not bumped. The other
+    /// circuits are trusted as cached, hence the cache is local state of the prover, and the verifier rebuilds all circuits instead.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
+        dir: &Path,
+        batch_size: usize,
+        last_batch_size: Option<usize>,
+        token_num: usize,
+        recursion_branchout_num: usize,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+        batch_circuit_config: CircuitConfig,
+        recursive_level_configs: Vec<CircuitConfig>,
+    ) -> Result<Self, PoRError> {
+        let start = std::time::Instant::now();
+        let params = RegistryParams::new(
+            batch_size,
+            last_batch_size,
+            token_num,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            valuation,
+            batch_circuit_config,
+            recursive_level_configs,
+        );
+        let cache_dir = dir.join(params.cache_key());
+        let mut registry = Self::load_shared_circuits(&cache_dir, params)?;
+        if let Some(last_batch_size) = last_batch_size {
+            registry.load_last_batch_circuits(&cache_dir, last_batch_size)?;
+        }
+
+        tracing::info!("load circuit registry from {:?} in {:?}", cache_dir, start.elapsed());
+        Ok(registry)
+    }
+
+    /// Load the circuits from the batch circuit to the top recursive circuit and their empty proofs from `cache_dir`.
+    fn load_shared_circuits(cache_dir: &Path, params: RegistryParams) -> Result<Self, PoRError> {
+        let manifest_bytes = fs::read(cache_dir.join(REGISTRY_MANIFEST_FILENAME))?;
+        let manifest: RegistryManifest = serde_json::from_slice(&manifest_bytes)
+            .map_err(|e| invalid_cache(format!("fail to parse the manifest: {}", e)))?;
+        if serde_json::to_value(&manifest.params).ok() != serde_json::to_value(&params).ok() {
+            return Err(invalid_cache("the parameters do not match the manifest".to_string()));
+        }
+        if manifest.circuit_digests.len() != params.recursive_level_configs.len() + 1 {
+            return Err(invalid_cache("the circuits do not match the parameters".to_string()));
+        }
+
+        let batch_circuit_data =
+            read_circuit(&cache_dir.join("batch_circuit.bin"), &manifest.circuit_digests[0])?;
+        let (rebuilt_batch_circuit_data, _) = build_merkle_sum_tree_circuit(
+            params.batch_size,
+            params.token_num,
+            params.leaf_hash_mode,
+            &params.valuation,
+            params.batch_circuit_config.clone(),
+        );
+        if rebuilt_batch_circuit_data.verifier_only.circuit_digest
+            != batch_circuit_data.verifier_only.circuit_digest
+        {
+            return Err(invalid_cache(
+                "the batch circuit does not match the rebuilt one, hence the cache is stale"
+                    .to_string(),
+            ));
+        }
+        let account_targets =
+            read_cache_file(&cache_dir.join("batch_targets.bin"), read_account_targets)?;
+        if account_targets.len() != params.batch_size {
+            return Err(invalid_cache("the batch targets do not match the batch size".to_string()));
+        }
+
+        let recursion_branchout_num = params.recursion_branchout_num;
+        let mut recursive_circuits = HashMap::new();
+        for level in 1..manifest.circuit_digests.len() {
+            let circuit = read_circuit(
+                &cache_dir.join(format!("recursive_circuit_{}.bin", level)),
+                &manifest.circuit_digests[level],
+            )?;
+            let targets = read_cache_file(
+                &cache_dir.join(format!("recursive_targets_{}.bin", level)),
+                RecursiveTargets::deserialize,
+            )?;
+            if targets.subproof_num() != recursion_branchout_num {
+                return Err(invalid_cache(format!(
+                    "the recursive targets at level {} do not match the branch-out",
+                    level
+                )));
+            }
+            recursive_circuits.insert(manifest.circuit_digests[level - 1], (circuit, targets));
+        }
+        let last_inner_circuit_vd = manifest.circuit_digests[manifest.circuit_digests.len() - 2];
+
+        #[cfg(feature = "verifier")]
+        let registry = Self {
+            params,
+            batch_circuit: (batch_circuit_data, account_targets),
+            recursion_branchout_num,
+            recursive_circuits,
+            last_batch_circuit: None,
+            merge_circuit: None,
+            last_inner_circuit_vd,
+        };
+
+        #[cfg(not(feature = "verifier"))]
+        let registry = {
+            if !manifest.has_empty_proofs {
+                return Err(invalid_cache("the cache has no empty proofs".to_string()));
+            }
+            let mut registry = Self {
+                params,
+                batch_circuit: (batch_circuit_data, account_targets),
+                recursion_branchout_num,
+                empty_proofs: HashMap::new(),
+                recursive_circuits,
+                last_batch_circuit: None,
+                merge_circuit: None,
+                last_inner_circuit_vd,
+            };
+            let mut empty_proofs = HashMap::new();
+            for (level, circuit) in registry.circuit_chain().into_iter().enumerate() {
+                let proof_bytes = fs::read(cache_dir.join(format!("empty_proof_{}.bin", level)))?;
+                let empty_proof =
+                    ProofWithPublicInputs::<F, C, D>::from_bytes(proof_bytes, &circuit.common)
+                        .map_err(|e| {
+                            invalid_cache(format!("fail to parse empty proof {}: {}", level, e))
+                        })?;
+                circuit.verify(empty_proof.clone()).map_err(|e| {
+                    invalid_cache(format!("empty proof {} does not verify: {}", level, e))
+                })?;
+                empty_proofs.insert(circuit.verifier_only.circuit_digest, empty_proof);
+            }
+            registry.empty_proofs = empty_proofs;
+            registry
+        };
+
+        Ok(registry)
+    }
+
+    /// Load the last batch and merge circuits of `last_batch_size` from `cache_dir`, which must be on top of the loaded top recursive
+    /// circuit.
+    fn load_last_batch_circuits(
+        &mut self,
+        cache_dir: &Path,
+        last_batch_size: usize,
+    ) -> Result<(), PoRError> {
+        let last_batch_dir = cache_dir.join(format!("last_batch_{}", last_batch_size));
+        let manifest_bytes = fs::read(last_batch_dir.join(REGISTRY_MANIFEST_FILENAME))?;
+        let manifest: LastBatchManifest = serde_json::from_slice(&manifest_bytes)
+            .map_err(|e| invalid_cache(format!("fail to parse the last batch manifest: {}", e)))?;
+        if manifest.last_batch_size != last_batch_size
+            || manifest.top_circuit_digest
+                != self.get_top_recursive_circuit().verifier_only.circuit_digest
+        {
+            return Err(invalid_cache(
+                "the last batch circuits do not match the parameters".to_string(),
+            ));
+        }
+
+        let circuit = read_circuit(
+            &last_batch_dir.join("last_batch_circuit.bin"),
+            &manifest.last_batch_circuit_digest,
+        )?;
+        let targets =
+            read_cache_file(&last_batch_dir.join("last_batch_targets.bin"), read_account_targets)?;
+        if targets.len() != last_batch_size {
+            return Err(invalid_cache(
+                "the last batch targets do not match the last batch size".to_string(),
+            ));
+        }
+        let last_batch_circuit = (circuit, targets);
+
+        let circuit = read_circuit(
+            &last_batch_dir.join("merge_circuit.bin"),
+            &manifest.merge_circuit_digest,
+        )?;
+        let targets =
+            read_cache_file(&last_batch_dir.join("merge_targets.bin"), MergeTargets::deserialize)?;
+        let merge_circuit = (circuit, targets);
+
+        #[cfg(not(feature = "verifier"))]
+        {
+            if !manifest.has_empty_proofs {
+                return Err(invalid_cache("the last batch cache has no empty proofs".to_string()));
+            }
+            for (name, circuit) in
+                [("last_batch", &last_batch_circuit.0), ("merge", &merge_circuit.0)]
+            {
+                let proof_bytes =
+                    fs::read(last_batch_dir.join(format!("empty_proof_{}.bin", name)))?;
+                let empty_proof =
+                    ProofWithPublicInputs::<F, C, D>::from_bytes(proof_bytes, &circuit.common)
+                        .map_err(|e| {
+                            invalid_cache(format!("fail to parse empty {} proof: {}", name, e))
+                        })?;
+                circuit.verify(empty_proof.clone()).map_err(|e| {
+                    invalid_cache(format!("empty {} proof does not verify: {}", name, e))
+                })?;
+                self.empty_proofs.insert(circuit.verifier_only.circuit_digest, empty_proof);
+            }
+        }
+
+        self.last_batch_circuit = Some(last_batch_circuit);
+        self.merge_circuit = Some(merge_circuit);
+        Ok(())
+    }
+
+    /// Load the registry from the cache under `dir` if it is valid, otherwise init the registry and save it to the cache. If only the
+    /// last batch and merge circuits of the last batch size are not cached, only they are built and saved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load_or_init(
+        dir: &Path,
+        batch_size: usize,
+        last_batch_size: Option<usize>,
+        token_num: usize,
+        recursion_branchout_num: usize,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+        batch_circuit_config: CircuitConfig,
+        recursive_level_configs: Vec<CircuitConfig>,
+    ) -> Self {
+        let params = RegistryParams::new(
+            batch_size,
+            last_batch_size,
+            token_num,
+            recursion_branchout_num,
+            leaf_hash_mode,
+            valuation,
+            batch_circuit_config.clone(),
+            recursive_level_configs.clone(),
+        );
+        let cache_dir = dir.join(params.cache_key());
+        match Self::load_shared_circuits(&cache_dir, params) {
+            Ok(mut registry) => {
+                tracing::info!("load circuit registry from {:?}", cache_dir);
+                let Some(last_batch_size) = last_batch_size else {
+                    return registry;
+                };
+                if let Err(e) = registry.load_last_batch_circuits(&cache_dir, last_batch_size) {
+                    tracing::info!(
+                        "no valid last batch circuits of size {} in {:?} ({}), hence rebuild them",
+                        last_batch_size,
+                        cache_dir,
+                        e
+                    );
+                    registry.build_last_batch_circuits(last_batch_size);
+                    if let Err(e) = registry.save_last_batch_circuits(&cache_dir) {
+                        tracing::warn!(
+                            "fail to save last batch circuits to {:?}: {}",
+                            cache_dir,
+                            e
+                        );
+                    }
+                }
+                registry
+            }
+            Err(e) => {
+                tracing::info!(
+                    "no valid circuit registry cache in {:?} ({}), hence rebuild it",
+                    dir,
+                    e
+                );
+                let registry = Self::init(
+                    batch_size,
+                    last_batch_size,
+                    token_num,
+                    recursion_branchout_num,
+                    leaf_hash_mode,
+                    valuation,
+                    batch_circuit_config,
+                    recursive_level_configs,
+                );
+                if let Err(e) = registry.save(dir) {
+                    tracing::warn!("fail to save circuit registry to {:?}: {}", dir, e);
+                }
+                registry
+            }
+        }
+    }
+}
+
+fn invalid_cache(reason: String) -> PoRError {
+    PoRError::InvalidCircuitCache(reason)
+}
+
+fn write_cache_file(
+    path: &Path,
+    write: impl FnOnce(&mut Vec<u8>) -> IoResult<()>,
+) -> Result<(), PoRError> {
+    let mut bytes = Vec::new();
+    write(&mut bytes).map_err(|_| invalid_cache(format!("fail to serialize {:?}", path)))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+fn read_cache_file<T>(
+    path: &Path,
+    read: impl FnOnce(&mut Buffer) -> IoResult<T>,
+) -> Result<T, PoRError> {
+    let bytes = fs::read(path)?;
+    let mut buffer = Buffer::new(&bytes);
+    let value =
+        read(&mut buffer).map_err(|_| invalid_cache(format!("fail to parse {:?}", path)))?;
+    if buffer.remaining() != 0 {
+        return Err(invalid_cache(format!("trailing bytes in {:?}", path)));
+    }
+    Ok(value)
+}
+
+fn write_circuit(path: &Path, circuit: &CircuitData<F, C, D>) -> Result<(), PoRError> {
+    let generator_serializer = DefaultGeneratorSerializer::<C, D> { _phantom: PhantomData };
+    let bytes = circuit
+        .to_bytes(&DefaultGateSerializer, &generator_serializer)
+        .map_err(|_| invalid_cache(format!("fail to serialize {:?}", path)))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+/// Read a circuit and check that its digest is the one in the manifest.
+fn read_circuit(path: &Path, digest: &HashOut<F>) -> Result<CircuitData<F, C, D>, PoRError> {
+    let generator_serializer = DefaultGeneratorSerializer::<C, D> { _phantom: PhantomData };
+    let bytes = fs::read(path)?;
+    let circuit =
+        CircuitData::<F, C, D>::from_bytes(&bytes, &DefaultGateSerializer, &generator_serializer)
+            .map_err(|_| invalid_cache(format!("fail to parse {:?}", path)))?;
+    if circuit.verifier_only.circuit_digest != *digest {
+        return Err(invalid_cache(format!("the digest of {:?} does not match the manifest", path)));
+    }
+    Ok(circuit)
+}
+
+fn write_account_targets(dst: &mut Vec<u8>, targets: &[AccountTargets]) -> IoResult<()> {
+    dst.write_usize(targets.len())?;
+    targets.iter().try_for_each(|target| target.serialize(dst))
+}
+
+fn read_account_targets(src: &mut Buffer) -> IoResult<Vec<AccountTargets>> {
+    let len = src.read_usize()?;
+    (0..len).map(|_| AccountTargets::deserialize(src)).collect()
 }
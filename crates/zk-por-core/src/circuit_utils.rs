@@ -8,12 +8,12 @@ use plonky2::{
     },
     util::timing::TimingTree,
 };
-use plonky2_field::extension::Extendable;
+use plonky2_field::{extension::Extendable, types::Field};
 use std::panic;
 
 use crate::{
     circuit_config::STANDARD_CONFIG,
-    types::{C, D, F, MAX_POSITIVE_AMOUNT_LOG},
+    types::{C, D, F, HAIRCUT_LOG, MAX_POSITIVE_AMOUNT_LOG},
 };
 
 pub fn prove_timing() -> TimingTree {
@@ -56,13 +56,72 @@ pub fn assert_non_negative_unsigned(builder: &mut CircuitBuilder<F, D>, x: Targe
     builder.range_check(x, MAX_POSITIVE_AMOUNT_LOG);
 }
 
+/// Sum up `xs` while asserting that every element and every running sum lies in [0, MAX_POSITIVE_AMOUNT].
+/// As the sum of two such values is smaller than the field order, the sum can never wrap around the field.
+pub fn sum_non_negative_unsigned(builder: &mut CircuitBuilder<F, D>, xs: &[Target]) -> Target {
+    let mut sum = builder.zero();
+    for (i, x) in xs.iter().enumerate() {
+        assert_non_negative_unsigned(builder, *x);
+        sum = builder.add(sum, *x);
+        if i > 0 {
+            assert_non_negative_unsigned(builder, sum);
+        }
+    }
+    sum
+}
+
+/// Multiply `x` in [0, MAX_POSITIVE_AMOUNT] by a constant `price` in [0, 2^MAX_PRICE_LOG) without wrapping around the field.
+///
+/// `x` is split into 31-bit halves, such that `x * price = high * price * 2^31 + low * price`. Both `low * price` and
+/// `high * price` are smaller than 2^63, and `high * price` is constrained to 31 bits, such that the sum is smaller
+/// than the field order. The caller still has to range-check the product, e.g., with `sum_non_negative_unsigned`.
+pub fn mul_non_negative_unsigned_by_price(
+    builder: &mut CircuitBuilder<F, D>,
+    x: Target,
+    price: F,
+) -> Target {
+    let half_bits = MAX_POSITIVE_AMOUNT_LOG / 2;
+    let (low, high) = builder.split_low_high(x, half_bits, MAX_POSITIVE_AMOUNT_LOG);
+    let high_product = builder.mul_const(price, high);
+    builder.range_check(high_product, half_bits);
+    let low_product = builder.mul_const(price, low);
+    builder.mul_const_add(F::from_canonical_u64(1 << half_bits), high_product, low_product)
+}
+
+/// Multiply `x` in [0, MAX_POSITIVE_AMOUNT] by the fraction `haircut / 2^HAIRCUT_LOG` of a constant `haircut` in [0, 2^HAIRCUT_LOG],
+/// rounded down.
+///
+/// With `x = high * 2^31 + low`, the result is `high * haircut * 2^(31 - HAIRCUT_LOG) + floor(low * haircut / 2^HAIRCUT_LOG)`,
+/// where the rounded quotient is obtained by splitting `low * haircut` < 2^(31 + HAIRCUT_LOG) at HAIRCUT_LOG bits. No term wraps
+/// around the field, and the result is at most `x`.
+pub fn mul_non_negative_unsigned_by_haircut(
+    builder: &mut CircuitBuilder<F, D>,
+    x: Target,
+    haircut: F,
+) -> Target {
+    let half_bits = MAX_POSITIVE_AMOUNT_LOG / 2;
+    let (low, high) = builder.split_low_high(x, half_bits, MAX_POSITIVE_AMOUNT_LOG);
+    let low_product = builder.mul_const(haircut, low);
+    let (_, low_quotient) =
+        builder.split_low_high(low_product, HAIRCUT_LOG, half_bits + HAIRCUT_LOG);
+    let high_product = builder.mul_const(haircut, high);
+    builder.mul_const_add(
+        F::from_canonical_u64(1 << (half_bits - HAIRCUT_LOG)),
+        high_product,
+        low_quotient,
+    )
+}
+
 #[cfg(test)]
 pub mod test {
-    use crate::types::F;
+    use crate::types::{F, MAX_POSITIVE_AMOUNT_LOG};
 
     use plonky2_field::types::{Field, Field64};
 
-    use super::{assert_non_negative_unsigned, run_circuit_test};
+    use super::{
+        assert_non_negative_unsigned, mul_non_negative_unsigned_by_haircut,
+        mul_non_negative_unsigned_by_price, run_circuit_test, sum_non_negative_unsigned,
+    };
 
     #[test]
     fn test_assert_non_negative_unsigned() {
@@ -80,4 +139,79 @@ pub mod test {
             assert_non_negative_unsigned(builder, x);
         });
     }
+
+    #[test]
+    fn test_sum_non_negative_unsigned() {
+        run_circuit_test(|builder, _pw| {
+            let max = F::from_canonical_u64((1 << MAX_POSITIVE_AMOUNT_LOG) - 1);
+            let xs = vec![builder.constant(max - F::ONE), builder.constant(F::ONE)];
+            let sum = sum_non_negative_unsigned(builder, &xs);
+            let expected = builder.constant(max);
+            builder.connect(sum, expected);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_sum_non_negative_unsigned_wrapped_element_panic() {
+        // -1 + 5 = 4 in the field, which is a valid amount, but -1 is not.
+        run_circuit_test(|builder, _pw| {
+            let xs = vec![
+                builder.constant(F::from_canonical_u64(F::ORDER - 1)),
+                builder.constant(F::from_canonical_u64(5)),
+            ];
+            sum_non_negative_unsigned(builder, &xs);
+        });
+    }
+
+    #[test]
+    fn test_mul_non_negative_unsigned_by_price() {
+        run_circuit_test(|builder, _pw| {
+            let x = builder.constant(F::from_canonical_u64((1 << 40) + 3));
+            let product =
+                mul_non_negative_unsigned_by_price(builder, x, F::from_canonical_u64(1 << 20));
+            let expected = builder.constant(F::from_canonical_u64((1 << 60) + (3 << 20)));
+            builder.connect(product, expected);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_mul_non_negative_unsigned_by_price_overflow_panic() {
+        // the product wraps around the field without the bound on the high half.
+        run_circuit_test(|builder, _pw| {
+            let x = builder.constant(F::from_canonical_u64((1 << MAX_POSITIVE_AMOUNT_LOG) - 1));
+            mul_non_negative_unsigned_by_price(builder, x, F::from_canonical_u64((1 << 32) - 1));
+        });
+    }
+
+    #[test]
+    fn test_mul_non_negative_unsigned_by_haircut() {
+        run_circuit_test(|builder, _pw| {
+            let max = (1 << MAX_POSITIVE_AMOUNT_LOG) - 1;
+            // 0.75 of the max amount, rounded down.
+            let x = builder.constant(F::from_canonical_u64(max));
+            let product =
+                mul_non_negative_unsigned_by_haircut(builder, x, F::from_canonical_u64(3 << 14));
+            let expected = builder.constant(F::from_canonical_u64((max as u128 * 3 / 4) as u64));
+            builder.connect(product, expected);
+
+            // a full haircut ratio keeps the amount.
+            let x = builder.constant(F::from_canonical_u64(12345));
+            let product =
+                mul_non_negative_unsigned_by_haircut(builder, x, F::from_canonical_u64(1 << 16));
+            builder.connect(product, x);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_sum_non_negative_unsigned_overflow_panic() {
+        // every element is a valid amount, but the running sum exceeds the max amount.
+        run_circuit_test(|builder, _pw| {
+            let max = F::from_canonical_u64((1 << MAX_POSITIVE_AMOUNT_LOG) - 1);
+            let xs = vec![builder.constant(max); 4];
+            sum_non_negative_unsigned(builder, &xs);
+        });
+    }
 }
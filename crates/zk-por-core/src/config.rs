@@ -1,8 +1,10 @@
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
 use tracing::Level;
+
+use crate::{account::LeafHashMode, pipeline::UserProofFormat};
 use zk_por_tracing::TraceConfig;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -29,8 +31,49 @@ impl From<ConfigLog> for TraceConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct ConfigProver {
     pub round_no: usize,
+    /// The unix timestamp (in seconds) of the user data snapshot of this round. The current time is used if absent.
+    pub snapshot_timestamp: Option<u64>,
     pub batch_size: Option<usize>,
+    /// The number of subproofs verified by every recursive circuit, one of 8, 16, 32 and 64.
+    pub recursion_branchout_num: Option<usize>,
     pub tokens: Vec<String>,
+    pub leaf_hash_mode: Option<LeafHashMode>,
+    /// The secret from which the per-user salts of the leaf hashes are derived. A random one is used if absent.
+    pub salt_seed: Option<String>,
+    /// The epoch from which the salts are derived instead of the round number. The rounds of an epoch reuse the proofs of unchanged
+    /// batches from the proof store, but the leaf hashes of unchanged users are linkable across these rounds.
+    pub salt_epoch: Option<usize>,
+    /// A json file of the fixed-point price of every token. The user balances are price-weighted if present.
+    pub price_snapshot_path: Option<String>,
+    /// The haircut ratio in [0, 1] of the equity of a token, e.g., `BTC = 0.95`. Tokens without a ratio are not haircut.
+    pub haircuts: Option<HashMap<String, f64>>,
+    /// The json file of the signed reserve addresses. If present, a solvency proof is generated on top of the root proof.
+    pub reserves_path: Option<String>,
+    /// Whether the solvency proof hides the per-token liabilities. The root proof is not zero-knowledge and still exposes them, as does
+    /// the global info, hence neither may be published for the liabilities to stay hidden.
+    pub hide_liabilities: Option<bool>,
+    /// The directory of the circuit cache. If present, the circuits and empty proofs are loaded from it instead of being rebuilt, and
+    /// saved to it if there is no valid cache for the batch size, token count, branch-out and circuit configs of the round.
+    pub circuit_cache_dir: Option<String>,
+    /// The directory of the batch and recursive proofs of the previous round. If present, the proofs of unchanged batches and of the
+    /// recursive nodes above them are reused from it, and the proofs of this round are saved to it. Requires `salt_seed` and
+    /// `salt_epoch`, without which the salts change every round.
+    pub proof_store_dir: Option<String>,
+    /// The addresses of the workers started by the `worker` command. If present, the batches and recursive nodes are proven by the
+    /// workers instead of this process.
+    pub worker_addrs: Option<Vec<String>>,
+    /// The secret shared by the prover and its workers, with which they authenticate each other before any job. Without it, the workers
+    /// and their addresses must be on loopback addresses.
+    pub worker_secret: Option<String>,
+    /// The file of the gmst. If present, the nodes of the gmst are stored in a memory-mapped file at the path instead of the memory,
+    /// for a gmst larger than the memory. The file is removed once the user proofs are dumped.
+    pub gmst_path: Option<String>,
+    /// Whether the merkle proofs of the users are dumped as a json file per user, or as a single archive of all users.
+    pub user_proof_format: Option<UserProofFormat>,
+    /// Whether the root proof in the global proof file is compressed.
+    pub compress_root_proof: Option<bool>,
+    /// Whether the root proof is wrapped by a proof with the Poseidon-BN128 hash config, for an external Groth16 wrapper over BN254.
+    pub wrap_root_proof: Option<bool>,
     pub user_data_path: String,
     pub batch_prove_threads_num: usize,
     pub recursive_prove_threads_num: usize,
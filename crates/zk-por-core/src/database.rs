@@ -1,9 +1,6 @@
 use hex::ToHex;
-use plonky2::hash::hash_types::HashOut;
 use rand::Rng;
 
-#[cfg(feature = "zk-por-db")]
-use plonky2::plonk::config::GenericHashOut;
 #[cfg(feature = "zk-por-db")]
 use std::str::FromStr;
 #[cfg(feature = "zk-por-db")]
@@ -11,8 +8,11 @@ use zk_por_db::LevelDb;
 
 use super::config::ConfigDb;
 
-use crate::{error::PoRError, global::GLOBAL_MST, types::F};
-use std::{collections::HashMap, sync::RwLock};
+use crate::{error::PoRError, global::GlobalMst, merkle_sum_tree::MerkleSumNode};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 #[derive(Debug, Clone, Copy, Eq, Hash, PartialEq)]
 pub struct UserId(pub [u8; 32]);
 
@@ -67,8 +67,8 @@ impl db_key::Key for UserId {
 pub trait PoRDB: Sync + Send {
     fn add_batch_users(&mut self, batches: Vec<(UserId, u32)>);
     fn get_user_index(&self, user_id: UserId) -> Option<u32>;
-    fn add_batch_gmst_nodes(&mut self, batches: Vec<(i32, HashOut<F>)>);
-    fn get_gmst_node_hash(&self, node_idx: i32) -> Option<HashOut<F>>;
+    fn add_batch_gmst_nodes(&mut self, batches: Vec<(i32, MerkleSumNode)>);
+    fn get_gmst_node(&self, node_idx: i32) -> Option<MerkleSumNode>;
 }
 
 pub struct PoRLevelDBOption {
@@ -112,28 +112,29 @@ impl PoRDB for PoRLevelDB {
     }
 
     /// 0: the index of the gmst
-    /// 1: the hash value at that index
-    fn add_batch_gmst_nodes(&mut self, batches: Vec<(i32, HashOut<F>)>) {
+    /// 1: the node (hash and sums) at that index
+    fn add_batch_gmst_nodes(&mut self, batches: Vec<(i32, MerkleSumNode)>) {
         let batches = batches
             .into_iter()
-            .map(|(id, hash)| {
-                let ret = (id, hash.to_bytes());
+            .map(|(id, node)| {
+                let ret = (id, node.to_bytes());
                 ret
             })
             .collect::<Vec<(i32, Vec<u8>)>>();
         self.gmst_db.batch_put(batches);
     }
 
-    fn get_gmst_node_hash(&self, node_idx: i32) -> Option<HashOut<F>> {
+    fn get_gmst_node(&self, node_idx: i32) -> Option<MerkleSumNode> {
         let ret = self.gmst_db.get(node_idx).map(|x| {
-            let ret = HashOut::<F>::from_bytes(&x);
+            let ret = MerkleSumNode::from_bytes(&x);
             ret
         });
         ret
     }
 }
 
-pub fn init_db(db_config: Option<ConfigDb>) -> Box<dyn PoRDB> {
+/// Init the leveldb of the config if any, otherwise a memory db reading the gmst nodes from `global_mst`.
+pub fn init_db(db_config: Option<ConfigDb>, global_mst: Arc<RwLock<GlobalMst>>) -> Box<dyn PoRDB> {
     let database: Box<dyn PoRDB>;
     if let Some(level_db_config) = db_config {
         #[cfg(feature = "zk-por-db")]
@@ -146,17 +147,17 @@ pub fn init_db(db_config: Option<ConfigDb>) -> Box<dyn PoRDB> {
 
         #[cfg(not(feature = "zk-por-db"))]
         {
-            _ = level_db_config;
+            _ = (level_db_config, global_mst);
             panic!("leveldb feature is not enabled");
         }
     } else {
-        database = Box::new(PoRGMSTMemoryDB::new());
+        database = Box::new(PoRGMSTMemoryDB::new(global_mst));
     }
     database
 }
 pub struct PoRMemoryDB {
     user_map: HashMap<UserId, u32>,
-    gmst_map: HashMap<i32, HashOut<F>>,
+    gmst_map: HashMap<i32, MerkleSumNode>,
 }
 
 impl PoRMemoryDB {
@@ -176,26 +177,27 @@ impl PoRDB for RwLock<PoRMemoryDB> {
         self.read().unwrap().user_map.get(&user_id).map(|x| *x)
     }
 
-    fn add_batch_gmst_nodes(&mut self, batches: Vec<(i32, HashOut<F>)>) {
-        for (id, hash) in batches {
-            self.write().unwrap().gmst_map.insert(id, hash);
+    fn add_batch_gmst_nodes(&mut self, batches: Vec<(i32, MerkleSumNode)>) {
+        for (id, node) in batches {
+            self.write().unwrap().gmst_map.insert(id, node);
         }
     }
 
-    fn get_gmst_node_hash(&self, node_idx: i32) -> Option<HashOut<F>> {
+    fn get_gmst_node(&self, node_idx: i32) -> Option<MerkleSumNode> {
         self.read().unwrap().gmst_map.get(&node_idx).map(|x| *x)
     }
 }
 
-/// PoRGMSTMemoryDB delegates the query on gmst node to the direct access of global GMST. For user_db, the query is delegated to PoRMemoryDB.
-/// This is to save memory fingerprint.
+/// PoRGMSTMemoryDB delegates the query on gmst node to the direct access of the GMST it is built with. For user_db, the query is
+/// delegated to PoRMemoryDB. This is to save memory fingerprint.
 pub struct PoRGMSTMemoryDB {
     user_db: RwLock<PoRMemoryDB>,
+    global_mst: Arc<RwLock<GlobalMst>>,
 }
-///
+
 impl PoRGMSTMemoryDB {
-    pub fn new() -> Self {
-        Self { user_db: RwLock::new(PoRMemoryDB::new()) }
+    pub fn new(global_mst: Arc<RwLock<GlobalMst>>) -> Self {
+        Self { user_db: RwLock::new(PoRMemoryDB::new()), global_mst }
     }
 }
 
@@ -209,19 +211,22 @@ impl PoRDB for PoRGMSTMemoryDB {
     }
 
     #[inline(always)]
-    fn add_batch_gmst_nodes(&mut self, _batches: Vec<(i32, HashOut<F>)>) {
+    fn add_batch_gmst_nodes(&mut self, _batches: Vec<(i32, MerkleSumNode)>) {
         // do nothing as we assume GMST is already built.
         return;
     }
 
-    fn get_gmst_node_hash(&self, node_idx: i32) -> Option<HashOut<F>> {
-        GLOBAL_MST.get().unwrap().read().unwrap().inner.get(node_idx as usize).map(|x| *x)
+    fn get_gmst_node(&self, node_idx: i32) -> Option<MerkleSumNode> {
+        self.global_mst.read().unwrap().get_node(node_idx as usize)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use plonky2::{field::types::Sample, hash::hash_types::HashOut};
+    use plonky2::{
+        field::types::{Field, Sample},
+        hash::hash_types::HashOut,
+    };
 
     #[cfg(feature = "zk-por-db")]
     use tempdir::TempDir;
@@ -229,10 +234,12 @@ mod test {
     #[cfg(feature = "zk-por-db")]
     use crate::database::{PoRLevelDB, PoRLevelDBOption};
     use crate::{
-        database::{PoRDB, PoRMemoryDB, UserId},
+        database::{PoRDB, PoRGMSTMemoryDB, PoRMemoryDB, UserId},
+        global::{GlobalConfig, GlobalMst},
+        merkle_sum_tree::MerkleSumNode,
         types::F,
     };
-    use std::sync::RwLock;
+    use std::sync::{Arc, RwLock};
 
     fn test_database(mut db: Box<dyn PoRDB>) {
         let batches_user = (0..4)
@@ -247,16 +254,23 @@ mod test {
         assert_eq!(db.get_user_index(batches_user[0].0), Some(0));
         assert_eq!(db.get_user_index(batches_user[3].0), Some(3));
 
-        let batches_hash = (0..4)
+        let batches_node = (0..4)
             .into_iter()
-            .map(|i| (i, HashOut::<F>::from_vec(vec![F::rand(), F::rand(), F::rand(), F::rand()])))
-            .collect::<Vec<(i32, HashOut<F>)>>();
-        db.add_batch_gmst_nodes(batches_hash.clone());
-
-        assert_eq!(db.get_gmst_node_hash(0), Some(batches_hash[0].1));
-        assert_eq!(db.get_gmst_node_hash(1), Some(batches_hash[1].1));
-        assert_eq!(db.get_gmst_node_hash(2), Some(batches_hash[2].1));
-        assert_eq!(db.get_gmst_node_hash(3), Some(batches_hash[3].1));
+            .map(|i| {
+                let node = MerkleSumNode {
+                    sum_equity: F::from_canonical_u64(i as u64 * 100),
+                    sum_debt: F::from_canonical_u64(i as u64),
+                    hash: HashOut::<F>::from_vec(vec![F::rand(), F::rand(), F::rand(), F::rand()]),
+                };
+                (i, node)
+            })
+            .collect::<Vec<(i32, MerkleSumNode)>>();
+        db.add_batch_gmst_nodes(batches_node.clone());
+
+        assert_eq!(db.get_gmst_node(0), Some(batches_node[0].1));
+        assert_eq!(db.get_gmst_node(1), Some(batches_node[1].1));
+        assert_eq!(db.get_gmst_node(2), Some(batches_node[2].1));
+        assert_eq!(db.get_gmst_node(3), Some(batches_node[3].1));
     }
 
     #[test]
@@ -276,4 +290,39 @@ mod test {
         let db = PoRMemoryDB::new();
         test_database(Box::new(RwLock::new(db)));
     }
+
+    #[test]
+    fn test_gmst_memorydb() {
+        let cfg = GlobalConfig {
+            num_of_tokens: 2,
+            num_of_batches: 2,
+            batch_size: 2,
+            recursion_branchout_num: 2,
+            last_batch_size: None,
+        };
+        let leaf_idx = |i: usize| GlobalMst::get_batch_tree_global_index(&cfg, 0, i) as i32;
+        let new_gmst = |sum_equity: u64| {
+            let mut gmst = GlobalMst::new(cfg);
+            let node = MerkleSumNode {
+                sum_equity: F::from_canonical_u64(sum_equity),
+                sum_debt: F::ZERO,
+                hash: HashOut::<F>::from_vec(vec![F::rand(), F::rand(), F::rand(), F::rand()]),
+            };
+            gmst.set_batch_node(0, 0, node);
+            (Arc::new(RwLock::new(gmst)), node)
+        };
+
+        // the dbs of two gmsts side by side read the nodes of their own gmst.
+        let (gmst_a, node_a) = new_gmst(100);
+        let (gmst_b, node_b) = new_gmst(200);
+        let db_a = PoRGMSTMemoryDB::new(gmst_a.clone());
+        let db_b = PoRGMSTMemoryDB::new(gmst_b);
+        assert_eq!(db_a.get_gmst_node(leaf_idx(0)), Some(node_a));
+        assert_eq!(db_b.get_gmst_node(leaf_idx(0)), Some(node_b));
+
+        // the nodes set after the db is built are visible to it.
+        gmst_a.write().unwrap().set_batch_node(0, 1, node_b);
+        assert_eq!(db_a.get_gmst_node(leaf_idx(1)), Some(node_b));
+        assert_eq!(db_b.get_gmst_node(leaf_idx(1)), Some(MerkleSumNode::default()));
+    }
 }
@@ -0,0 +1,287 @@
+use plonky2::plonk::{circuit_data::CircuitData, proof::ProofWithPublicInputs};
+use plonky2_field::types::Field;
+use std::{net::TcpStream, sync::Mutex};
+
+use super::protocol::{
+    check_tag, check_unauthenticated_addr, circuit_digests, coordinator_tag, max_message_bytes,
+    new_nonce, read_message, worker_tag, write_message, Request, Response, RoundShape,
+};
+use crate::{
+    account::Account,
+    circuit_registry::registry::CircuitRegistry,
+    e2e::prove_last_batch,
+    error::PoRError,
+    global::GlobalMst,
+    merkle_sum_tree::MerkleSumNode,
+    pipeline::PipelineProver,
+    recursive_prover::recursive_circuit::RoundInfo,
+    types::{C, D, F},
+};
+
+/// A coordinator splitting the batches and the recursive nodes of every level into jobs, which are sent to workers over TCP. Every
+/// worker works on one job at a time, and every returned proof is verified with the circuits of the coordinator and checked to prove
+/// the expected node before it is accepted.
+pub struct Coordinator<'a> {
+    circuit_registry: &'a CircuitRegistry,
+    workers: Vec<TcpStream>,
+    max_message_bytes: usize,
+}
+
+impl<'a> Coordinator<'a> {
+    /// Connect to the workers, which build their circuits from the shape of the round of the registry, and whose circuits must have the
+    /// same digests as the ones of the registry. With a worker secret, every worker must prove the knowledge of it in the handshake, and
+    /// proves it to the worker in turn; without one, the workers must be on loopback addresses.
+    pub fn connect(
+        worker_addrs: &[String],
+        circuit_registry: &'a CircuitRegistry,
+        shape: RoundShape,
+        worker_secret: Option<&str>,
+    ) -> Result<Self, PoRError> {
+        if worker_addrs.is_empty() {
+            return Err(PoRError::InvalidParameter("no worker address".to_string()));
+        }
+        let expected_digests = circuit_digests(circuit_registry);
+        let max_message_bytes = max_message_bytes(circuit_registry);
+        let mut workers = Vec::new();
+        for addr in worker_addrs {
+            let mut stream = TcpStream::connect(addr.as_str())?;
+            check_unauthenticated_addr(worker_secret, &stream.peer_addr()?)?;
+            stream.set_nodelay(true)?;
+
+            let coordinator_nonce = new_nonce();
+            let hello = Request::Hello { nonce: coordinator_nonce };
+            let (worker_nonce, tag) = match request(&mut stream, &hello, max_message_bytes)? {
+                Response::Hello { nonce, tag } => (nonce, tag),
+                response => return Err(unexpected_response(addr, &response)),
+            };
+            let expected_tag = worker_tag(worker_secret, &coordinator_nonce, &worker_nonce);
+            if !check_tag(worker_secret, &expected_tag, &tag) {
+                tracing::error!("worker {} fails to authenticate", addr);
+                return Err(PoRError::Distributed(format!(
+                    "worker {} fails to authenticate",
+                    addr
+                )));
+            }
+
+            // the worker builds its circuits before answering the setup.
+            let tag = coordinator_tag(worker_secret, &coordinator_nonce, &worker_nonce, &shape);
+            match request(&mut stream, &Request::Setup { shape, tag }, max_message_bytes)? {
+                Response::Setup { circuit_digests } if circuit_digests == expected_digests => {}
+                Response::Setup { .. } => {
+                    tracing::error!("the circuits of worker {} do not match", addr);
+                    return Err(PoRError::CircuitMismatch);
+                }
+                response => return Err(unexpected_response(addr, &response)),
+            }
+            tracing::info!("connected to worker {}", addr);
+            workers.push(stream);
+        }
+        Ok(Self { circuit_registry, workers, max_message_bytes })
+    }
+
+    /// Prove the batches of accounts on the workers like `batch_prove_accounts`. Every batch proof must verify against the batch circuit
+    /// and prove the root in `batch_roots`.
+    pub fn batch_prove_accounts(
+        &mut self,
+        accounts: &[Account],
+        batch_roots: &[MerkleSumNode],
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        let (batch_circuit, account_targets) = self.circuit_registry.get_batch_circuit();
+        let batch_size = account_targets.len();
+        assert_eq!(accounts.len(), batch_roots.len() * batch_size);
+        let jobs = accounts
+            .chunks(batch_size)
+            .map(|accounts| Request::Batch { accounts: accounts.to_vec() })
+            .collect();
+        self.run_jobs(jobs, batch_circuit, |i, proof| check_node(i, proof, &batch_roots[i]))
+    }
+
+    /// Prove the subproofs at `level` on the workers like `prove_subproofs`. Every recursive proof must verify against the recursive
+    /// circuit of the level, prove the parent of the roots of its subproofs, and expose the round if it is the root circuit.
+    pub fn prove_subproofs(
+        &mut self,
+        subproofs: &[ProofWithPublicInputs<F, C, D>],
+        level: usize,
+        round: RoundInfo,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        let recursion_branchout_num = self.circuit_registry.get_recursion_branchout_num();
+        assert_eq!(subproofs.len() % recursion_branchout_num, 0);
+        let inner_circuit_vd =
+            self.circuit_registry.get_level_circuit(level - 1).verifier_only.clone();
+        let (recursive_circuit, recursive_targets) = self
+            .circuit_registry
+            .get_recursive_circuit(&inner_circuit_vd.circuit_digest)
+            .unwrap_or_else(|| panic!("No recursive circuit found at level {}", level));
+        let exposes_round = recursive_targets.round_targets.is_some();
+
+        let jobs = subproofs
+            .chunks(recursion_branchout_num)
+            .map(|subproofs| Request::Recursive {
+                level,
+                round,
+                subproofs: subproofs.iter().map(|proof| proof.to_bytes()).collect(),
+            })
+            .collect();
+        self.run_jobs(jobs, recursive_circuit, |i, proof| {
+            let children: Vec<MerkleSumNode> = subproofs
+                [i * recursion_branchout_num..(i + 1) * recursion_branchout_num]
+                .iter()
+                .map(|subproof| MerkleSumNode::new_from_public_inputs(&subproof.public_inputs))
+                .collect();
+            check_node(i, proof, &GlobalMst::get_recursive_parent(&children))?;
+            let public_inputs = &proof.public_inputs;
+            if exposes_round
+                && public_inputs[public_inputs.len() - 2..]
+                    != [
+                        F::from_canonical_u64(round.round_num),
+                        F::from_canonical_u64(round.snapshot_timestamp),
+                    ]
+            {
+                return Err(PoRError::RoundMismatch);
+            }
+            Ok(())
+        })
+    }
+
+    /// Close the connections to the workers.
+    pub fn shutdown(mut self) -> Result<(), PoRError> {
+        for worker in self.workers.iter_mut() {
+            write_message(worker, &Request::Shutdown.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Send the jobs to the workers, each of which takes the next job once it returns the proof of its last one. Every proof is verified
+    /// against `circuit` before `check`, and the proofs are returned in the order of the jobs.
+    fn run_jobs(
+        &mut self,
+        jobs: Vec<Request>,
+        circuit: &CircuitData<F, C, D>,
+        check: impl Fn(usize, &ProofWithPublicInputs<F, C, D>) -> Result<(), PoRError> + Sync,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        let job_num = jobs.len();
+        let jobs = Mutex::new(jobs.into_iter().enumerate());
+        let proofs: Mutex<Vec<Option<ProofWithPublicInputs<F, C, D>>>> =
+            Mutex::new(vec![None; job_num]);
+        let (jobs, proofs, check) = (&jobs, &proofs, &check);
+        let max_message_bytes = self.max_message_bytes;
+
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self
+                .workers
+                .iter_mut()
+                .map(|worker| {
+                    s.spawn(move || -> Result<(), PoRError> {
+                        loop {
+                            let next_job = jobs.lock().unwrap().next();
+                            let Some((i, job)) = next_job else {
+                                return Ok(());
+                            };
+                            let proof_bytes = match request(worker, &job, max_message_bytes)? {
+                                Response::Proof { proof } => proof,
+                                Response::Error { message } => {
+                                    return Err(PoRError::Distributed(format!(
+                                        "job {} failed: {}",
+                                        i, message
+                                    )))
+                                }
+                                response => {
+                                    return Err(unexpected_response(
+                                        &worker_addr(worker),
+                                        &response,
+                                    ))
+                                }
+                            };
+                            let proof = ProofWithPublicInputs::<F, C, D>::from_bytes(
+                                proof_bytes,
+                                &circuit.common,
+                            )
+                            .map_err(|e| {
+                                PoRError::Distributed(format!(
+                                    "fail to parse proof of job {}: {}",
+                                    i, e
+                                ))
+                            })?;
+                            // the verifier data of the coordinator binds the proof to the expected circuit digest.
+                            circuit.verify(proof.clone()).map_err(|_| PoRError::InvalidProof)?;
+                            check(i, &proof)?;
+                            proofs.lock().unwrap()[i] = Some(proof);
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("coordinator thread panicked"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+
+        Ok(proofs.lock().unwrap().drain(..).map(|proof| proof.unwrap()).collect())
+    }
+}
+
+impl PipelineProver for Coordinator<'_> {
+    fn prove_batches(
+        &mut self,
+        accounts: Vec<Account>,
+        batch_roots: &[MerkleSumNode],
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        self.batch_prove_accounts(&accounts, batch_roots)
+    }
+
+    /// The last batch is proven by the coordinator, as the workers only prove full batches.
+    fn prove_last_batch(
+        &mut self,
+        accounts: Vec<Account>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+        Ok(prove_last_batch(self.circuit_registry, accounts))
+    }
+
+    fn prove_subproofs(
+        &mut self,
+        subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
+        level: usize,
+        round: RoundInfo,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        Coordinator::prove_subproofs(self, &subproofs, level, round)
+    }
+}
+
+fn request(
+    stream: &mut TcpStream,
+    request: &Request,
+    max_message_bytes: usize,
+) -> Result<Response, PoRError> {
+    write_message(stream, &request.to_bytes())?;
+    Response::from_bytes(&read_message(stream, max_message_bytes)?)
+}
+
+fn worker_addr(stream: &TcpStream) -> String {
+    stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default()
+}
+
+fn unexpected_response(addr: &str, response: &Response) -> PoRError {
+    let kind = match response {
+        Response::Hello { .. } => "hello",
+        Response::Setup { .. } => "setup",
+        Response::Proof { .. } => "proof",
+        Response::Error { message } => {
+            return PoRError::Distributed(format!("worker {} failed: {}", addr, message))
+        }
+    };
+    PoRError::Distributed(format!("unexpected {} response from worker {}", kind, addr))
+}
+
+fn check_node(
+    job: usize,
+    proof: &ProofWithPublicInputs<F, C, D>,
+    expected_node: &MerkleSumNode,
+) -> Result<(), PoRError> {
+    if MerkleSumNode::new_from_public_inputs(&proof.public_inputs) != *expected_node {
+        return Err(PoRError::Distributed(format!(
+            "the proof of job {} does not prove the expected node",
+            job
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,506 @@
+use plonky2::{
+    hash::hash_types::{HashOut, NUM_HASH_OUT_ELTS},
+    util::serialization::{Buffer, IoResult, Read, Write},
+};
+use sha2::{Digest, Sha256};
+use std::{io, net::SocketAddr};
+
+use crate::{
+    account::Account, circuit_registry::registry::CircuitRegistry, error::PoRError,
+    recursive_prover::recursive_circuit::RoundInfo, types::F,
+};
+
+/// The length of a user id in hex.
+const USER_ID_BYTES: usize = 64;
+/// The maximum size of the message of an error response, and of the fixed fields of any message.
+const MAX_ERROR_MESSAGE_BYTES: usize = 1 << 16;
+/// The maximum size of a message of the handshake, which is read before the worker has built the circuits that bound the other messages.
+pub const MAX_HANDSHAKE_MESSAGE_BYTES: usize = MAX_ERROR_MESSAGE_BYTES;
+/// The block size of SHA-256, i.e., the key size of HMAC-SHA256.
+const HMAC_BLOCK_BYTES: usize = 64;
+
+pub const NONCE_BYTES: usize = 32;
+pub const AUTH_TAG_BYTES: usize = 32;
+pub type Nonce = [u8; NONCE_BYTES];
+pub type AuthTag = [u8; AUTH_TAG_BYTES];
+
+const REQUEST_HELLO: u8 = 0;
+const REQUEST_BATCH: u8 = 1;
+const REQUEST_RECURSIVE: u8 = 2;
+const REQUEST_SHUTDOWN: u8 = 3;
+const REQUEST_SETUP: u8 = 4;
+
+const RESPONSE_HELLO: u8 = 0;
+const RESPONSE_PROOF: u8 = 1;
+const RESPONSE_ERROR: u8 = 2;
+const RESPONSE_SETUP: u8 = 3;
+
+/// The shape of a round, from which a worker builds the same circuits as the coordinator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundShape {
+    pub batch_size: usize,
+    /// The number of full batches.
+    pub batch_num: usize,
+    pub last_batch_size: Option<usize>,
+    pub recursion_branchout_num: usize,
+}
+
+/// A message from the coordinator to a worker. Proofs are encoded with `ProofWithPublicInputs::to_bytes`.
+///
+/// A connection starts with the handshake: the coordinator sends `Hello` with its nonce, the worker answers with its nonce and its tag
+/// over both nonces, and the coordinator sends `Setup` with the shape of the round and its tag over both nonces and the shape. The worker
+/// answers `Setup` with the digests of the circuits it built from the shape, before which it accepts no job.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    /// Start the handshake with a fresh nonce of the coordinator.
+    Hello { nonce: Nonce },
+    /// Ask the worker to build the circuits of the round of `shape`, whose digests must be the ones of the coordinator.
+    Setup { shape: RoundShape, tag: AuthTag },
+    /// Prove a batch of accounts with the batch circuit.
+    Batch { accounts: Vec<Account> },
+    /// Prove the subproofs of a recursive node at `level` with the recursive circuit of the level.
+    Recursive { level: usize, round: RoundInfo, subproofs: Vec<Vec<u8>> },
+    /// Close the connection.
+    Shutdown,
+}
+
+/// A message from a worker to the coordinator, answering a request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Response {
+    /// A fresh nonce of the worker and its tag over the nonces of the handshake.
+    Hello {
+        nonce: Nonce,
+        tag: AuthTag,
+    },
+    /// The digests of the batch circuit followed by the recursive circuits from the lowest level.
+    Setup {
+        circuit_digests: Vec<HashOut<F>>,
+    },
+    Proof {
+        proof: Vec<u8>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// The digests of the circuits of the registry, which workers and the coordinator compare before any job.
+pub fn circuit_digests(circuit_registry: &CircuitRegistry) -> Vec<HashOut<F>> {
+    (0..=circuit_registry.get_recursive_levels())
+        .map(|level| circuit_registry.get_level_circuit(level).verifier_only.circuit_digest)
+        .collect()
+}
+
+/// The size of the largest message of the circuits of the registry, i.e., a batch request, a recursive request of the largest subproofs,
+/// or a proof response, which bounds the memory a peer can make the other side allocate. Proofs of a circuit have a fixed size, which
+/// is the one of its empty proof, and every circuit of the registry is counted, including the last batch and merge circuits.
+pub fn max_message_bytes(circuit_registry: &CircuitRegistry) -> usize {
+    let (_, account_targets) = circuit_registry.get_batch_circuit();
+    let token_num = account_targets[0].equity.len();
+    let account_bytes = 8 + USER_ID_BYTES + 2 * (8 + token_num * 8) + NUM_HASH_OUT_ELTS * 8;
+    let batch_request_bytes = account_targets.len() * account_bytes;
+    let max_proof_bytes = (0..=circuit_registry.get_recursive_levels())
+        .map(|level| circuit_registry.get_level_circuit(level))
+        .chain(circuit_registry.get_last_batch_circuit().map(|(circuit, _)| circuit))
+        .chain(circuit_registry.get_merge_circuit().map(|(circuit, _)| circuit))
+        .map(|circuit| {
+            let digest = &circuit.verifier_only.circuit_digest;
+            circuit_registry
+                .get_empty_proof(digest)
+                .unwrap_or_else(|| panic!("fail to find the empty proof of circuit {:?}", digest))
+                .to_bytes()
+                .len()
+        })
+        .max()
+        .unwrap();
+    let recursive_request_bytes =
+        circuit_registry.get_recursion_branchout_num() * (8 + max_proof_bytes);
+    MAX_ERROR_MESSAGE_BYTES + std::cmp::max(batch_request_bytes, recursive_request_bytes)
+}
+
+/// The tag with which a worker proves the knowledge of the worker secret, over the nonce of the coordinator and its own.
+pub fn worker_tag(
+    worker_secret: Option<&str>,
+    coordinator_nonce: &Nonce,
+    worker_nonce: &Nonce,
+) -> AuthTag {
+    auth_tag(
+        worker_secret,
+        &[b"worker".as_slice(), coordinator_nonce.as_slice(), worker_nonce.as_slice()].concat(),
+    )
+}
+
+/// The tag with which the coordinator proves the knowledge of the worker secret, over both nonces and the shape it sends.
+pub fn coordinator_tag(
+    worker_secret: Option<&str>,
+    coordinator_nonce: &Nonce,
+    worker_nonce: &Nonce,
+    shape: &RoundShape,
+) -> AuthTag {
+    let mut shape_bytes = Vec::new();
+    shape.write(&mut shape_bytes).expect("fail to serialize round shape");
+    auth_tag(
+        worker_secret,
+        &[
+            b"coordinator".as_slice(),
+            coordinator_nonce.as_slice(),
+            worker_nonce.as_slice(),
+            shape_bytes.as_slice(),
+        ]
+        .concat(),
+    )
+}
+
+/// Whether a received tag is the expected one, compared in constant time. Any tag is accepted without a worker secret, in which case
+/// the peers are only allowed on loopback addresses by `check_unauthenticated_addr`.
+pub fn check_tag(worker_secret: Option<&str>, expected: &AuthTag, tag: &AuthTag) -> bool {
+    worker_secret.is_none() || expected.iter().zip(tag).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Reject a non-loopback address if there is no worker secret, as the connections would accept any peer. The connections are not
+/// encrypted either way.
+pub fn check_unauthenticated_addr(
+    worker_secret: Option<&str>,
+    addr: &SocketAddr,
+) -> Result<(), PoRError> {
+    if worker_secret.is_none() && !addr.ip().is_loopback() {
+        return Err(PoRError::InvalidParameter(format!(
+            "{} is not a loopback address, which requires a worker_secret to authenticate the prover and the workers",
+            addr
+        )));
+    }
+    Ok(())
+}
+
+pub fn new_nonce() -> Nonce {
+    rand::random()
+}
+
+/// HMAC-SHA256 of `message` keyed by the worker secret, or all zeros without a secret.
+fn auth_tag(worker_secret: Option<&str>, message: &[u8]) -> AuthTag {
+    let Some(secret) = worker_secret else {
+        return [0u8; AUTH_TAG_BYTES];
+    };
+    let mut key = [0u8; HMAC_BLOCK_BYTES];
+    if secret.len() > HMAC_BLOCK_BYTES {
+        key[..AUTH_TAG_BYTES].copy_from_slice(&Sha256::digest(secret.as_bytes()));
+    } else {
+        key[..secret.len()].copy_from_slice(secret.as_bytes());
+    }
+    let inner = Sha256::new().chain_update(key.map(|b| b ^ 0x36)).chain_update(message).finalize();
+    Sha256::new().chain_update(key.map(|b| b ^ 0x5c)).chain_update(inner).finalize().into()
+}
+
+impl RoundShape {
+    fn write(&self, dst: &mut Vec<u8>) -> IoResult<()> {
+        dst.write_usize(self.batch_size)?;
+        dst.write_usize(self.batch_num)?;
+        // a last batch is never empty, hence 0 encodes no last batch.
+        dst.write_usize(self.last_batch_size.unwrap_or(0))?;
+        dst.write_usize(self.recursion_branchout_num)
+    }
+
+    fn read(src: &mut Buffer) -> IoResult<Self> {
+        let batch_size = src.read_usize()?;
+        let batch_num = src.read_usize()?;
+        let last_batch_size = match src.read_usize()? {
+            0 => None,
+            last_batch_size => Some(last_batch_size),
+        };
+        let recursion_branchout_num = src.read_usize()?;
+        Ok(Self { batch_size, batch_num, last_batch_size, recursion_branchout_num })
+    }
+}
+
+/// Write a message prefixed by its length.
+pub fn write_message(stream: &mut impl io::Write, bytes: &[u8]) -> io::Result<()> {
+    stream.write_all(&(bytes.len() as u64).to_le_bytes())?;
+    stream.write_all(bytes)?;
+    stream.flush()
+}
+
+/// Read a message written by `write_message`, which is rejected if longer than `max_len`, e.g., `max_message_bytes`.
+pub fn read_message(stream: &mut impl io::Read, max_len: usize) -> io::Result<Vec<u8>> {
+    let mut len_bytes = [0u8; 8];
+    stream.read_exact(&mut len_bytes)?;
+    let len = u64::from_le_bytes(len_bytes) as usize;
+    if len > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes exceeds the limit", len),
+        ));
+    }
+    let mut bytes = vec![0u8; len];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+impl Request {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let write = |dst: &mut Vec<u8>| -> IoResult<()> {
+            match self {
+                Request::Hello { nonce } => {
+                    dst.write_u8(REQUEST_HELLO)?;
+                    dst.write_all(nonce)
+                }
+                Request::Setup { shape, tag } => {
+                    dst.write_u8(REQUEST_SETUP)?;
+                    shape.write(dst)?;
+                    dst.write_all(tag)
+                }
+                Request::Batch { accounts } => {
+                    dst.write_u8(REQUEST_BATCH)?;
+                    dst.write_usize(accounts.len())?;
+                    accounts.iter().try_for_each(|account| write_account(dst, account))
+                }
+                Request::Recursive { level, round, subproofs } => {
+                    dst.write_u8(REQUEST_RECURSIVE)?;
+                    dst.write_usize(*level)?;
+                    dst.write_all(&round.round_num.to_le_bytes())?;
+                    dst.write_all(&round.snapshot_timestamp.to_le_bytes())?;
+                    dst.write_usize(subproofs.len())?;
+                    subproofs.iter().try_for_each(|proof| write_bytes(dst, proof))
+                }
+                Request::Shutdown => dst.write_u8(REQUEST_SHUTDOWN),
+            }
+        };
+        write(&mut bytes).expect("fail to serialize request");
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PoRError> {
+        let mut src = Buffer::new(bytes);
+        let read = |src: &mut Buffer| -> IoResult<Self> {
+            match src.read_u8()? {
+                REQUEST_HELLO => Ok(Request::Hello { nonce: read_array(src)? }),
+                REQUEST_SETUP => {
+                    let shape = RoundShape::read(src)?;
+                    Ok(Request::Setup { shape, tag: read_array(src)? })
+                }
+                REQUEST_BATCH => {
+                    let len = src.read_usize()?;
+                    let accounts = (0..len).map(|_| read_account(src)).collect::<IoResult<_>>()?;
+                    Ok(Request::Batch { accounts })
+                }
+                REQUEST_RECURSIVE => {
+                    let level = src.read_usize()?;
+                    let round =
+                        RoundInfo { round_num: read_u64(src)?, snapshot_timestamp: read_u64(src)? };
+                    let len = src.read_usize()?;
+                    let subproofs = (0..len).map(|_| read_bytes(src)).collect::<IoResult<_>>()?;
+                    Ok(Request::Recursive { level, round, subproofs })
+                }
+                REQUEST_SHUTDOWN => Ok(Request::Shutdown),
+                _ => Err(plonky2::util::serialization::IoError),
+            }
+        };
+        let request =
+            read(&mut src).map_err(|_| PoRError::Distributed("malformed request".to_string()))?;
+        if src.remaining() != 0 {
+            return Err(PoRError::Distributed("trailing bytes in request".to_string()));
+        }
+        Ok(request)
+    }
+}
+
+impl Response {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let write = |dst: &mut Vec<u8>| -> IoResult<()> {
+            match self {
+                Response::Hello { nonce, tag } => {
+                    dst.write_u8(RESPONSE_HELLO)?;
+                    dst.write_all(nonce)?;
+                    dst.write_all(tag)
+                }
+                Response::Setup { circuit_digests } => {
+                    dst.write_u8(RESPONSE_SETUP)?;
+                    dst.write_usize(circuit_digests.len())?;
+                    circuit_digests
+                        .iter()
+                        .try_for_each(|digest| dst.write_field_vec(&digest.elements))
+                }
+                Response::Proof { proof } => {
+                    dst.write_u8(RESPONSE_PROOF)?;
+                    write_bytes(dst, proof)
+                }
+                Response::Error { message } => {
+                    dst.write_u8(RESPONSE_ERROR)?;
+                    write_bytes(dst, message.as_bytes())
+                }
+            }
+        };
+        write(&mut bytes).expect("fail to serialize response");
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PoRError> {
+        let mut src = Buffer::new(bytes);
+        let read = |src: &mut Buffer| -> IoResult<Self> {
+            match src.read_u8()? {
+                RESPONSE_HELLO => {
+                    let nonce = read_array(src)?;
+                    Ok(Response::Hello { nonce, tag: read_array(src)? })
+                }
+                RESPONSE_SETUP => {
+                    let len = src.read_usize()?;
+                    let circuit_digests = (0..len)
+                        .map(|_| Ok(HashOut::from_vec(src.read_field_vec(NUM_HASH_OUT_ELTS)?)))
+                        .collect::<IoResult<_>>()?;
+                    Ok(Response::Setup { circuit_digests })
+                }
+                RESPONSE_PROOF => Ok(Response::Proof { proof: read_bytes(src)? }),
+                RESPONSE_ERROR => Ok(Response::Error {
+                    message: String::from_utf8_lossy(&read_bytes(src)?).to_string(),
+                }),
+                _ => Err(plonky2::util::serialization::IoError),
+            }
+        };
+        let response =
+            read(&mut src).map_err(|_| PoRError::Distributed("malformed response".to_string()))?;
+        if src.remaining() != 0 {
+            return Err(PoRError::Distributed("trailing bytes in response".to_string()));
+        }
+        Ok(response)
+    }
+}
+
+fn write_bytes(dst: &mut Vec<u8>, bytes: &[u8]) -> IoResult<()> {
+    dst.write_usize(bytes.len())?;
+    dst.write_all(bytes)
+}
+
+fn read_bytes(src: &mut Buffer) -> IoResult<Vec<u8>> {
+    let len = src.read_usize()?;
+    if len > src.remaining() {
+        return Err(plonky2::util::serialization::IoError);
+    }
+    let mut bytes = vec![0u8; len];
+    src.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_array<const N: usize>(src: &mut Buffer) -> IoResult<[u8; N]> {
+    let mut bytes = [0u8; N];
+    src.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u64(src: &mut Buffer) -> IoResult<u64> {
+    let mut bytes = [0u8; 8];
+    src.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_account(dst: &mut Vec<u8>, account: &Account) -> IoResult<()> {
+    write_bytes(dst, account.id.as_bytes())?;
+    dst.write_usize(account.equity.len())?;
+    dst.write_field_vec(&account.equity)?;
+    dst.write_usize(account.debt.len())?;
+    dst.write_field_vec(&account.debt)?;
+    dst.write_field_vec(&account.salt.elements)
+}
+
+fn read_account(src: &mut Buffer) -> IoResult<Account> {
+    let id =
+        String::from_utf8(read_bytes(src)?).map_err(|_| plonky2::util::serialization::IoError)?;
+    let equity_len = src.read_usize()?;
+    let equity = src.read_field_vec(equity_len)?;
+    let debt_len = src.read_usize()?;
+    let debt = src.read_field_vec(debt_len)?;
+    let salt = HashOut::from_vec(src.read_field_vec(NUM_HASH_OUT_ELTS)?);
+    Ok(Account { id, equity, debt, salt })
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{
+        check_tag, check_unauthenticated_addr, coordinator_tag, new_nonce, worker_tag, Request,
+        Response, RoundShape,
+    };
+    use crate::{
+        account::gen_accounts_with_random_data, recursive_prover::recursive_circuit::RoundInfo,
+    };
+    use plonky2::hash::hash_types::HashOut;
+
+    const SHAPE: RoundShape = RoundShape {
+        batch_size: 1024,
+        batch_num: 5,
+        last_batch_size: Some(3),
+        recursion_branchout_num: 16,
+    };
+
+    #[test]
+    fn test_message_round_trip() {
+        let requests = vec![
+            Request::Hello { nonce: new_nonce() },
+            Request::Setup { shape: SHAPE, tag: [7u8; 32] },
+            Request::Setup { shape: RoundShape { last_batch_size: None, ..SHAPE }, tag: [0u8; 32] },
+            Request::Batch { accounts: gen_accounts_with_random_data(3, 2) },
+            Request::Recursive {
+                level: 2,
+                round: RoundInfo { round_num: 7, snapshot_timestamp: 1726444800 },
+                subproofs: vec![vec![1, 2, 3], vec![]],
+            },
+            Request::Shutdown,
+        ];
+        requests.into_iter().for_each(|request| {
+            assert_eq!(request, Request::from_bytes(&request.to_bytes()).unwrap());
+        });
+
+        let responses = vec![
+            Response::Hello { nonce: new_nonce(), tag: [9u8; 32] },
+            Response::Setup { circuit_digests: vec![HashOut::default(); 3] },
+            Response::Proof { proof: vec![4, 5, 6] },
+            Response::Error { message: "no circuit".to_string() },
+        ];
+        responses.into_iter().for_each(|response| {
+            assert_eq!(response, Response::from_bytes(&response.to_bytes()).unwrap());
+        });
+
+        let mut bytes = Request::Shutdown.to_bytes();
+        bytes.push(0);
+        assert!(Request::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_auth_tags() {
+        let (coordinator_nonce, worker_nonce) = (new_nonce(), new_nonce());
+        let secret = Some("worker secret");
+        let tag = coordinator_tag(secret, &coordinator_nonce, &worker_nonce, &SHAPE);
+        assert!(check_tag(secret, &tag, &tag));
+
+        // the tag depends on the secret, the nonces, the role and the shape.
+        let other_tags = [
+            coordinator_tag(Some("other secret"), &coordinator_nonce, &worker_nonce, &SHAPE),
+            coordinator_tag(secret, &worker_nonce, &coordinator_nonce, &SHAPE),
+            coordinator_tag(secret, &coordinator_nonce, &new_nonce(), &SHAPE),
+            coordinator_tag(
+                secret,
+                &coordinator_nonce,
+                &worker_nonce,
+                &RoundShape { batch_num: 6, ..SHAPE },
+            ),
+            worker_tag(secret, &coordinator_nonce, &worker_nonce),
+        ];
+        other_tags.iter().for_each(|other_tag| assert!(!check_tag(secret, &tag, other_tag)));
+
+        // a secret longer than the hmac block is hashed first.
+        let long_secret = "s".repeat(100);
+        let long_tag = worker_tag(Some(&long_secret), &coordinator_nonce, &worker_nonce);
+        assert!(check_tag(Some(&long_secret), &long_tag, &long_tag));
+        assert!(!check_tag(Some(&long_secret), &long_tag, &[0u8; 32]));
+    }
+
+    #[test]
+    fn test_check_unauthenticated_addr() {
+        let loopback = "127.0.0.1:9000".parse().unwrap();
+        let remote = "10.0.0.1:9000".parse().unwrap();
+        let unspecified = "0.0.0.0:9000".parse().unwrap();
+        assert!(check_unauthenticated_addr(None, &loopback).is_ok());
+        assert!(check_unauthenticated_addr(None, &remote).is_err());
+        assert!(check_unauthenticated_addr(None, &unspecified).is_err());
+        assert!(check_unauthenticated_addr(Some("secret"), &remote).is_ok());
+    }
+}
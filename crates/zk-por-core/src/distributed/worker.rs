@@ -0,0 +1,191 @@
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use std::{
+    io,
+    net::{TcpListener, TcpStream},
+    panic::{catch_unwind, AssertUnwindSafe},
+    time::Duration,
+};
+
+use super::protocol::{
+    check_tag, check_unauthenticated_addr, circuit_digests, coordinator_tag, max_message_bytes,
+    new_nonce, read_message, worker_tag, write_message, Request, Response, RoundShape,
+    MAX_HANDSHAKE_MESSAGE_BYTES,
+};
+use crate::{
+    account::Account,
+    circuit_registry::registry::CircuitRegistry,
+    error::PoRError,
+    merkle_sum_prover::prover::MerkleSumTreeProver,
+    recursive_prover::{prover::RecursiveProver, recursive_circuit::RoundInfo},
+    types::{C, D, F},
+};
+
+/// The time a coordinator has for each message of the handshake, such that a peer that never completes it does not block the worker.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Accept coordinators on the listener one after another, and serve the jobs of each. The circuits are built by `init_registry` from
+/// the shape of the round sent by the coordinator, and kept for the next coordinator of the same shape. Without a worker secret, the
+/// listener must be on a loopback address, as any peer could send jobs and read the returned proofs.
+pub fn serve(
+    listener: TcpListener,
+    worker_secret: Option<&str>,
+    mut init_registry: impl FnMut(&RoundShape) -> Result<CircuitRegistry, PoRError>,
+) -> Result<(), PoRError> {
+    let local_addr = listener.local_addr()?;
+    check_unauthenticated_addr(worker_secret, &local_addr)?;
+    tracing::info!("worker listening on {:?}", local_addr);
+    let mut circuits: Option<(RoundShape, CircuitRegistry)> = None;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer_addr = stream.peer_addr()?;
+        tracing::info!("serve coordinator {:?}", peer_addr);
+        if let Err(e) = serve_coordinator(stream, worker_secret, &mut circuits, &mut init_registry)
+        {
+            tracing::warn!("connection to coordinator {:?} failed: {:?}", peer_addr, e);
+        }
+    }
+    Ok(())
+}
+
+fn serve_coordinator(
+    mut stream: TcpStream,
+    worker_secret: Option<&str>,
+    circuits: &mut Option<(RoundShape, CircuitRegistry)>,
+    init_registry: &mut impl FnMut(&RoundShape) -> Result<CircuitRegistry, PoRError>,
+) -> Result<(), PoRError> {
+    let shape = accept_coordinator(&mut stream, worker_secret)?;
+    if circuits.as_ref().map(|(cached_shape, _)| cached_shape) != Some(&shape) {
+        // drop the circuits of the previous shape before building the new ones.
+        *circuits = None;
+        tracing::info!("build the circuits of {:?}", shape);
+        match init_registry(&shape) {
+            Ok(circuit_registry) => *circuits = Some((shape, circuit_registry)),
+            Err(e) => return Err(reject(&mut stream, &e.to_string())),
+        }
+    }
+    let (_, circuit_registry) = circuits.as_ref().unwrap();
+    run_worker(stream, circuit_registry)
+}
+
+/// Run the handshake of a coordinator up to its setup request: answer its hello with the nonce and the tag of the worker, and check the
+/// tag of the coordinator if there is a worker secret. Returns the shape of the round, from which the circuits of `run_worker` must be
+/// built.
+pub fn accept_coordinator(
+    stream: &mut TcpStream,
+    worker_secret: Option<&str>,
+) -> Result<RoundShape, PoRError> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let Request::Hello { nonce: coordinator_nonce } =
+        Request::from_bytes(&read_message(stream, MAX_HANDSHAKE_MESSAGE_BYTES)?)?
+    else {
+        return Err(reject(stream, "the handshake must start with a hello"));
+    };
+    let worker_nonce = new_nonce();
+    let tag = worker_tag(worker_secret, &coordinator_nonce, &worker_nonce);
+    write_message(stream, &Response::Hello { nonce: worker_nonce, tag }.to_bytes())?;
+
+    let Request::Setup { shape, tag } =
+        Request::from_bytes(&read_message(stream, MAX_HANDSHAKE_MESSAGE_BYTES)?)?
+    else {
+        return Err(reject(stream, "the hello must be followed by a setup"));
+    };
+    let expected_tag = coordinator_tag(worker_secret, &coordinator_nonce, &worker_nonce, &shape);
+    if !check_tag(worker_secret, &expected_tag, &tag) {
+        return Err(reject(stream, "the coordinator fails to authenticate"));
+    }
+    stream.set_read_timeout(None)?;
+    Ok(shape)
+}
+
+/// Answer the setup request of `accept_coordinator` with the circuit digests of the registry, and serve the jobs of the coordinator
+/// until it shuts the worker down or disconnects. A job that fails is answered with an error, such that the worker keeps serving.
+pub fn run_worker(
+    mut stream: TcpStream,
+    circuit_registry: &CircuitRegistry,
+) -> Result<(), PoRError> {
+    let setup = Response::Setup { circuit_digests: circuit_digests(circuit_registry) };
+    write_message(&mut stream, &setup.to_bytes())?;
+    let max_len = max_message_bytes(circuit_registry);
+    loop {
+        let bytes = match read_message(&mut stream, max_len) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let response = match Request::from_bytes(&bytes)? {
+            Request::Hello { .. } | Request::Setup { .. } => {
+                Response::Error { message: "the handshake is already done".to_string() }
+            }
+            Request::Batch { accounts } => to_response(catch_unwind(AssertUnwindSafe(|| {
+                prove_batch(circuit_registry, accounts)
+            }))),
+            Request::Recursive { level, round, subproofs } => {
+                to_response(catch_unwind(AssertUnwindSafe(|| {
+                    prove_recursive(circuit_registry, level, round, &subproofs)
+                })))
+            }
+            Request::Shutdown => return Ok(()),
+        };
+        write_message(&mut stream, &response.to_bytes())?;
+    }
+}
+
+/// Answer the coordinator with an error before closing the connection. As it may have disconnected already, the answer is best effort.
+fn reject(stream: &mut TcpStream, message: &str) -> PoRError {
+    let _ = write_message(stream, &Response::Error { message: message.to_string() }.to_bytes());
+    PoRError::Distributed(message.to_string())
+}
+
+fn to_response(
+    result: std::thread::Result<Result<ProofWithPublicInputs<F, C, D>, String>>,
+) -> Response {
+    match result {
+        Ok(Ok(proof)) => Response::Proof { proof: proof.to_bytes() },
+        Ok(Err(message)) => Response::Error { message },
+        Err(_) => Response::Error { message: "the prover panicked".to_string() },
+    }
+}
+
+fn prove_batch(
+    circuit_registry: &CircuitRegistry,
+    accounts: Vec<Account>,
+) -> Result<ProofWithPublicInputs<F, C, D>, String> {
+    let (batch_circuit, account_targets) = circuit_registry.get_batch_circuit();
+    if accounts.len() != account_targets.len() {
+        return Err(format!(
+            "the batch has {} accounts instead of {}",
+            accounts.len(),
+            account_targets.len()
+        ));
+    }
+    let prover = MerkleSumTreeProver { accounts };
+    Ok(prover.get_proof_with_circuit_data(account_targets, batch_circuit))
+}
+
+fn prove_recursive(
+    circuit_registry: &CircuitRegistry,
+    level: usize,
+    round: RoundInfo,
+    subproofs: &[Vec<u8>],
+) -> Result<ProofWithPublicInputs<F, C, D>, String> {
+    if level == 0 || level > circuit_registry.get_recursive_levels() {
+        return Err(format!("no recursive circuit at level {}", level));
+    }
+    if subproofs.len() != circuit_registry.get_recursion_branchout_num() {
+        return Err(format!("{} subproofs do not match the branch-out", subproofs.len()));
+    }
+    let inner_circuit = circuit_registry.get_level_circuit(level - 1);
+    let sub_proofs = subproofs
+        .iter()
+        .map(|proof| {
+            ProofWithPublicInputs::<F, C, D>::from_bytes(proof.clone(), &inner_circuit.common)
+                .map_err(|e| format!("fail to parse subproof: {}", e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (recursive_circuit, recursive_targets) = circuit_registry
+        .get_recursive_circuit(&inner_circuit.verifier_only.circuit_digest)
+        .expect("no recursive circuit for an inner circuit of the registry");
+    let recursive_prover =
+        RecursiveProver { sub_proofs, sub_circuit_vd: inner_circuit.verifier_only.clone(), round };
+    Ok(recursive_prover.get_proof_with_circuit_data(recursive_targets.clone(), recursive_circuit))
+}
@@ -7,12 +7,16 @@ use crate::{
     account::Account,
     circuit_registry::registry::CircuitRegistry,
     merkle_sum_prover::prover::MerkleSumTreeProver,
-    recursive_prover::prover::RecursiveProver,
+    pipeline::{prove_recursive_levels, LocalProver},
+    recursive_prover::{
+        prover::{MergeProver, RecursiveProver},
+        recursive_circuit::RoundInfo,
+    },
     types::{C, D, F},
 };
 
-pub fn batch_prove_accounts<const RECURSION_BRANCHOUT_NUM: usize>(
-    circuit_registry: &CircuitRegistry<RECURSION_BRANCHOUT_NUM>,
+pub fn batch_prove_accounts(
+    circuit_registry: &CircuitRegistry,
     account_batches: Vec<Account>,
     parallism: usize,
     batch_size: usize,
@@ -38,15 +42,49 @@ pub fn batch_prove_accounts<const RECURSION_BRANCHOUT_NUM: usize>(
     batch_proofs
 }
 
-pub fn prove_subproofs<const RECURSION_BRANCHOUT_NUM: usize>(
+/// Prove the smaller last batch with the last batch circuit of the registry.
+pub fn prove_last_batch(
+    circuit_registry: &CircuitRegistry,
+    accounts: Vec<Account>,
+) -> ProofWithPublicInputs<F, C, D> {
+    let (last_batch_circuit, account_targets) =
+        circuit_registry.get_last_batch_circuit().expect("no last batch circuit in the registry");
+    assert_eq!(accounts.len(), account_targets.len());
+    let prover = MerkleSumTreeProver { accounts };
+    prover.get_proof_with_circuit_data(account_targets, last_batch_circuit)
+}
+
+/// Merge the top recursive proof with the last batch proof into the root proof.
+pub fn merge_last_batch(
+    circuit_registry: &CircuitRegistry,
+    top_proof: ProofWithPublicInputs<F, C, D>,
+    last_batch_proof: ProofWithPublicInputs<F, C, D>,
+    round: RoundInfo,
+) -> ProofWithPublicInputs<F, C, D> {
+    let (merge_circuit, merge_targets) =
+        circuit_registry.get_merge_circuit().expect("no merge circuit in the registry");
+    let (last_batch_circuit, _) = circuit_registry.get_last_batch_circuit().unwrap();
+    let prover = MergeProver {
+        top_proof,
+        top_circuit_vd: circuit_registry.get_top_recursive_circuit().verifier_only.clone(),
+        last_batch_proof,
+        last_batch_circuit_vd: last_batch_circuit.verifier_only.clone(),
+        round,
+    };
+    prover.get_proof_with_circuit_data(merge_targets, merge_circuit)
+}
+
+pub fn prove_subproofs(
     subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
     last_level_circuit_vd: VerifierOnlyCircuitData<C, D>,
-    circuit_registry: &CircuitRegistry<RECURSION_BRANCHOUT_NUM>,
+    circuit_registry: &CircuitRegistry,
     parallism: usize,
     level: usize,
+    round: RoundInfo,
 ) -> Vec<ProofWithPublicInputs<F, C, D>> {
     let bar = ProgressBar::new(subproofs.len() as u64);
-    assert_eq!(subproofs.len() % RECURSION_BRANCHOUT_NUM, 0);
+    let recursion_branchout_num = circuit_registry.get_recursion_branchout_num();
+    assert_eq!(subproofs.len() % recursion_branchout_num, 0);
     let last_level_vd_digest = last_level_circuit_vd.circuit_digest;
 
     let (recursive_circuit, recursive_targets) =
@@ -58,21 +96,19 @@ pub fn prove_subproofs<const RECURSION_BRANCHOUT_NUM: usize>(
             .as_str(),
         );
 
-    let expected_this_level_proof_num = subproofs.len() / RECURSION_BRANCHOUT_NUM;
+    let expected_this_level_proof_num = subproofs.len() / recursion_branchout_num;
     let mut this_level_proofs = vec![];
 
     let _ = subproofs
-        .chunks(parallism * RECURSION_BRANCHOUT_NUM)
+        .chunks(parallism * recursion_branchout_num)
         .map(|chunk| {
             let proofs: Vec<ProofWithPublicInputs<F, C, D>> = chunk
-                .par_chunks(RECURSION_BRANCHOUT_NUM)
+                .par_chunks(recursion_branchout_num)
                 .map(|subproofs| {
                     let recursive_prover = RecursiveProver {
-                        sub_proofs: subproofs
-                            .to_owned()
-                            .try_into()
-                            .expect("subproofs length not equal to RECURSION_BRANCHOUT_NUM"),
+                        sub_proofs: subproofs.to_owned(),
                         sub_circuit_vd: last_level_circuit_vd.clone(),
+                        round,
                     };
                     let proof = recursive_prover
                         .get_proof_with_circuit_data(recursive_targets.clone(), &recursive_circuit);
@@ -80,7 +116,7 @@ pub fn prove_subproofs<const RECURSION_BRANCHOUT_NUM: usize>(
                 })
                 .collect();
             this_level_proofs.extend(proofs.into_iter());
-            bar.inc((this_level_proofs.len() * RECURSION_BRANCHOUT_NUM) as u64);
+            bar.inc((this_level_proofs.len() * recursion_branchout_num) as u64);
             tracing::debug!(
                 "finish {}/{} proofs in level {}/{}",
                 this_level_proofs.len(),
@@ -95,74 +131,27 @@ pub fn prove_subproofs<const RECURSION_BRANCHOUT_NUM: usize>(
     this_level_proofs
 }
 
-pub fn recursive_prove_subproofs<const RECURSION_BRANCHOUT_NUM: usize>(
+/// Recursively prove the batch proofs up to the root proof, merging the top recursive proof with `last_batch_proof` if any.
+pub fn recursive_prove_subproofs(
     subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
-    circuit_registry: &CircuitRegistry<RECURSION_BRANCHOUT_NUM>,
+    circuit_registry: &CircuitRegistry,
     parallism: usize,
+    round: RoundInfo,
+    last_batch_proof: Option<ProofWithPublicInputs<F, C, D>>,
 ) -> ProofWithPublicInputs<F, C, D> {
-    let (batch_circuit, _) = circuit_registry.get_batch_circuit();
-    let mut last_level_circuit_vd = batch_circuit.verifier_only.clone();
-    let mut last_level_proofs = subproofs;
-    let recursive_levels = circuit_registry.get_recursive_levels();
-    tracing::info!("total recursive levels: {:?}", recursive_levels);
-
-    for level in 1..=recursive_levels {
-        let start = std::time::Instant::now();
-
-        let last_level_vd_digest = last_level_circuit_vd.circuit_digest;
-        let last_level_empty_proof = circuit_registry
-            .get_empty_proof(&last_level_vd_digest)
-            .expect(
-                format!("fail to find empty proof for circuit vd {:?}", last_level_vd_digest)
-                    .as_str(),
-            )
-            .clone();
-
-        let subproof_len = last_level_proofs.len();
-
-        if subproof_len % RECURSION_BRANCHOUT_NUM != 0 {
-            let pad_num = RECURSION_BRANCHOUT_NUM - subproof_len % RECURSION_BRANCHOUT_NUM;
-            tracing::info!("At level {}, {} subproofs are not a multiple of RECURSION_BRANCHOUT_NUM {}, hence padding {} empty proofs. ", level, subproof_len, RECURSION_BRANCHOUT_NUM, pad_num);
-
-            last_level_proofs.resize(subproof_len + pad_num, last_level_empty_proof);
-        }
-
-        last_level_proofs = prove_subproofs(
-            last_level_proofs,
-            last_level_circuit_vd.clone(),
-            circuit_registry,
-            parallism,
-            level,
-        );
-
-        let recursive_circuit = circuit_registry
-            .get_recursive_circuit(&last_level_circuit_vd.circuit_digest)
-            .expect(
-                format!(
-                    "No recursive circuit found for inner circuit with vd {:?}",
-                    last_level_circuit_vd.circuit_digest
-                )
-                .as_str(),
-            )
-            .0;
-
-        last_level_circuit_vd = recursive_circuit.verifier_only.clone();
-
-        tracing::info!(
-            "finish recursive level {} with {} proofs in : {:?}",
-            level,
-            last_level_proofs.len(),
-            start.elapsed()
-        );
-    }
-
-    if last_level_proofs.len() != 1 {
-        panic!("The last level proofs should be of length 1, but got {}", last_level_proofs.len());
-    }
-    let root_proof = last_level_proofs.pop().unwrap();
-    circuit_registry
-        .get_root_circuit()
-        .verify(root_proof.clone())
-        .expect("fail to verify root proof");
-    root_proof
+    let mut prover = LocalProver {
+        circuit_registry,
+        batch_prove_threads_num: parallism,
+        recursive_prove_threads_num: parallism,
+    };
+    prove_recursive_levels(
+        circuit_registry,
+        subproofs,
+        last_batch_proof,
+        round,
+        &mut prover,
+        None,
+        None,
+    )
+    .expect("fail to recursively prove subproofs")
 }
@@ -24,6 +24,45 @@ pub enum PoRError {
     #[error("The verification circuit digest does not match the prover. ")]
     CircuitMismatch,
 
+    #[error("The round info in the proof file does not match the root proof")]
+    RoundMismatch,
+
     #[error("User is not valid")]
     InvalidUser,
+
+    #[error("Reserve ownership is not valid for address {0}")]
+    InvalidReserveOwnership(String),
+
+    #[error("Reserves do not cover the liabilities of token {0}")]
+    Insolvent(String),
+
+    #[error("The reserves in the solvency proof do not match the signed reserve addresses")]
+    ReservesMismatch,
+
+    #[error("The root hash in the solvency proof does not match the published root")]
+    RootMismatch,
+
+    #[error("Circuit cache is invalid: {0}")]
+    InvalidCircuitCache(String),
+
+    #[error("Checkpoint is invalid: {0}")]
+    InvalidCheckpoint(String),
+
+    #[error("Proof store is invalid: {0}")]
+    InvalidProofStore(String),
+
+    #[error("Distributed proving error: {0}")]
+    Distributed(String),
+
+    #[error("User proof archive is invalid: {0}")]
+    InvalidUserProofArchive(String),
+
+    #[error("Proof file is invalid: {0}")]
+    InvalidProofFile(String),
+
+    #[error("Wrap artifacts are invalid: {0}")]
+    InvalidWrapArtifacts(String),
+
+    #[error("Anchor payload is invalid: {0}")]
+    InvalidAnchor(String),
 }
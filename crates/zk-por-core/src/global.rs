@@ -1,59 +1,97 @@
 use crate::{
     circuit_utils::recursive_levels,
     database::PoRDB,
-    merkle_sum_prover::utils::hash_2_subhashes,
-    recursive_prover::prover::hash_n_subhashes,
-    types::{D, F},
-    util::{get_node_level, pad_to_multiple_of},
+    error::PoRError,
+    gmst_storage::{GmstStorage, MmapGmstStorage},
+    merkle_sum_tree::MerkleSumNode,
+    recursive_prover::prover::hash_n_subnodes,
+    types::F,
+    util::{locate_node, pad_to_multiple_of, tree_level_sizes, tree_node_num},
 };
-use once_cell::sync::OnceCell;
-use plonky2::{hash::hash_types::HashOut, util::log2_strict};
-use std::{ops::Div, sync::RwLock};
+use plonky2::field::types::Field;
+use std::path::Path;
 use tracing::debug;
 
-#[derive(Debug, Clone, Copy)]
+/// The shape of the gmst of a round: `num_of_batches` batches of `batch_size` accounts, whose roots form the recursive tree, and
+/// optionally a smaller last batch of `last_batch_size` accounts, whose root is merged with the root of the recursive tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct GlobalConfig {
     pub num_of_tokens: usize,
     pub num_of_batches: usize,
     pub batch_size: usize, // num of accounts witin one batch
     pub recursion_branchout_num: usize,
+    pub last_batch_size: Option<usize>,
 }
 
-pub static GLOBAL_MST: OnceCell<RwLock<GlobalMst>> = OnceCell::new();
+impl GlobalConfig {
+    /// Split `num_of_users` accounts into full batches and a smaller last batch, returning the number of full batches and the size of
+    /// the last batch. If the accounts are a multiple of the batch size, there is no last batch. Fewer accounts than the batch size
+    /// are a last batch after no full batch, whose recursive tree has only empty batches.
+    pub fn split_batches(num_of_users: usize, batch_size: usize) -> (usize, Option<usize>) {
+        let last_batch_size = num_of_users % batch_size;
+        if last_batch_size == 0 {
+            (num_of_users / batch_size, None)
+        } else {
+            (num_of_users / batch_size, Some(last_batch_size))
+        }
+    }
+
+    /// The number of nodes at recursive level 0, i.e., the batch roots padded with empty batches to a multiple of the branch-out.
+    /// Without any full batch, the level still has one group of empty batches below the recursive root.
+    pub fn get_padded_batch_num(&self) -> usize {
+        pad_to_multiple_of(self.num_of_batches.max(1), self.recursion_branchout_num)
+    }
+}
 
 pub struct GlobalMst {
-    pub inner: Vec<HashOut<F>>,
+    storage: Box<dyn GmstStorage>,
     top_recursion_level: usize,
     pub cfg: GlobalConfig,
 }
 
 impl GlobalMst {
+    /// A gmst whose nodes are stored in memory.
     pub fn new(cfg: GlobalConfig) -> Self {
+        let tree_size = GlobalMst::get_tree_length_of(&cfg);
+        GlobalMst::new_with_storage(cfg, Box::new(vec![MerkleSumNode::default(); tree_size]))
+    }
+
+    /// A gmst whose nodes are stored in a memory-mapped file created at `path`, for a tree larger than the memory.
+    pub fn new_mmap(cfg: GlobalConfig, path: &Path) -> Result<Self, PoRError> {
+        let storage = MmapGmstStorage::create(path, GlobalMst::get_tree_length_of(&cfg))?;
+        Ok(GlobalMst::new_with_storage(cfg, Box::new(storage)))
+    }
+
+    /// `storage` must hold `get_tree_length_of(&cfg)` empty nodes.
+    pub fn new_with_storage(cfg: GlobalConfig, storage: Box<dyn GmstStorage>) -> Self {
+        assert_eq!(storage.len(), GlobalMst::get_tree_length_of(&cfg));
         let top_level = recursive_levels(cfg.num_of_batches, cfg.recursion_branchout_num);
+        Self { storage, top_recursion_level: top_level, cfg }
+    }
 
-        let mst_vec = vec![HashOut::default(); 0]; // will resize later
-        let mut mst = Self { inner: mst_vec, top_recursion_level: top_level, cfg: cfg };
-        // the number of hash is one smaller to the index of the root node of the last recursion level.
-        let root_node_idx = GlobalMst::get_recursive_global_index(&cfg, top_level, 0);
-        let tree_size = root_node_idx + 1;
-        mst.inner.resize(tree_size, HashOut::default());
-        mst
+    /// The number of nodes of the gmst, the root node being the last one.
+    pub fn get_tree_length_of(cfg: &GlobalConfig) -> usize {
+        GlobalMst::get_root_global_index(cfg) + 1
     }
 
     pub fn get_tree_length(&self) -> usize {
-        self.inner.len()
+        self.storage.len()
     }
 
     pub fn get_num_of_leaves(cfg: &GlobalConfig) -> usize {
-        cfg.batch_size * cfg.num_of_batches
+        cfg.batch_size * cfg.num_of_batches + cfg.last_batch_size.unwrap_or(0)
+    }
+
+    pub fn get_node(&self, idx: usize) -> Option<MerkleSumNode> {
+        (idx < self.storage.len()).then(|| self.storage.get(idx))
     }
 
-    pub fn get_nodes(&self, range: std::ops::Range<usize>) -> &[HashOut<F>] {
-        &self.inner[range]
+    pub fn get_nodes(&self, range: std::ops::Range<usize>) -> Vec<MerkleSumNode> {
+        range.map(|idx| self.storage.get(idx)).collect()
     }
 
-    pub fn get_root(&self) -> Option<&HashOut<F>> {
-        self.inner.last()
+    pub fn get_root(&self) -> Option<MerkleSumNode> {
+        self.get_node(GlobalMst::get_root_global_index(&self.cfg))
     }
 
     /// convert a mst node inner index to global index in gmst.
@@ -62,25 +100,21 @@ impl GlobalMst {
     ///   12      13
     ///  8-9,   10-11
     /// 0 - 3,  4 - 7
+    /// A batch size of other than a power of 2 pads the levels with an odd number of nodes, see `tree_level_sizes`.
     pub fn get_batch_tree_global_index(
         cfg: &GlobalConfig,
         batch_idx: usize,
         inner_tree_idx: usize,
     ) -> usize {
-        let batch_size = cfg.batch_size;
-        let tree_depth = log2_strict(batch_size);
-        let batch_tree_level = get_node_level(batch_size, inner_tree_idx);
+        let level_sizes = tree_level_sizes(cfg.batch_size);
+        let (level_from_bottom, inner_level_start) = locate_node(&level_sizes, inner_tree_idx);
 
-        let level_from_bottom = tree_depth - batch_tree_level;
+        let global_tree_vertical_offset = inner_level_start * cfg.num_of_batches; // the gmst idx of the first node at {level_from_bottom} level
 
-        let numeritor = 2 * batch_size * cfg.num_of_batches;
-        let global_tree_vertical_offset = numeritor - numeritor.div(1 << level_from_bottom); // the gmst idx of the first node at {level_from_bottom} level
-
-        let level_node_counts = batch_size.div(1 << level_from_bottom);
+        let level_node_counts = level_sizes[level_from_bottom];
         let global_inter_tree_horizontal_offset = level_node_counts * (batch_idx); // the number of preceding nodes at {level_from_bottom} level in the preceding mst.
 
-        let intra_tree_horizontal_offset =
-            inner_tree_idx - (2 * batch_size - 2 * batch_size.div(1 << level_from_bottom));
+        let intra_tree_horizontal_offset = inner_tree_idx - inner_level_start;
         // the number of preceding nodes at {level_from_bottom} level in the current mst.
 
         let index = global_tree_vertical_offset
@@ -89,13 +123,37 @@ impl GlobalMst {
         index
     }
 
+    /// The nodes of the last batch tree follow the root of the recursive tree, in the same level-by-level order as its inner index.
+    pub fn get_last_batch_global_index(cfg: &GlobalConfig, inner_tree_idx: usize) -> usize {
+        let recursive_root_idx = GlobalMst::get_recursive_global_index(
+            cfg,
+            recursive_levels(cfg.num_of_batches, cfg.recursion_branchout_num),
+            0,
+        );
+        recursive_root_idx + 1 + inner_tree_idx
+    }
+
+    /// The root of the gmst, i.e., the root of the recursive tree, or the parent of it and the root of the last batch if any.
+    pub fn get_root_global_index(cfg: &GlobalConfig) -> usize {
+        match cfg.last_batch_size {
+            Some(last_batch_size) => {
+                GlobalMst::get_last_batch_global_index(cfg, tree_node_num(last_batch_size))
+            }
+            None => GlobalMst::get_recursive_global_index(
+                cfg,
+                recursive_levels(cfg.num_of_batches, cfg.recursion_branchout_num),
+                0,
+            ),
+        }
+    }
+
     // mst root node at level 0,
     pub fn get_recursive_global_index(
         cfg: &GlobalConfig,
         recursive_level: usize,
         inner_level_idx: usize,
     ) -> usize {
-        let mst_node_num = 2 * cfg.batch_size - 1;
+        let mst_node_num = tree_node_num(cfg.batch_size);
         let batch_num = cfg.num_of_batches;
         let branchout_num = cfg.recursion_branchout_num;
         if recursive_level == 0 {
@@ -110,11 +168,7 @@ impl GlobalMst {
         }
 
         // pad num_of_batches to be multiple of recursion_branchout_num.
-        let pad_num = if batch_num % branchout_num == 0 {
-            0
-        } else {
-            branchout_num - batch_num % branchout_num
-        };
+        let pad_num = cfg.get_padded_batch_num() - batch_num;
 
         let mut last_level_node_num = batch_num + pad_num;
         assert_eq!(0, last_level_node_num % branchout_num);
@@ -138,68 +192,123 @@ impl GlobalMst {
     }
 
     /// `batch_idx`: index indicating the batch index
-    /// `i`: the sub batch tree index; e.g the batch tree is of size 1<<10; i \in [0, 2*batch_size)
-    pub fn set_batch_hash(&mut self, batch_idx: usize, i: usize, hash: HashOut<F>) {
+    /// `i`: the sub batch tree index; e.g the batch tree is of size 1<<10; i \in [0, tree_node_num(batch_size))
+    pub fn set_batch_node(&mut self, batch_idx: usize, i: usize, node: MerkleSumNode) {
         let global_mst_idx = GlobalMst::get_batch_tree_global_index(&self.cfg, batch_idx, i);
-        self.inner[global_mst_idx] = hash;
+        self.storage.set(global_mst_idx, node);
     }
 
-    pub fn get_batch_root_hash(&self, batch_idx: usize) -> HashOut<F> {
-        debug!("get batch root hash, batch_idx: {:?}", batch_idx);
+    pub fn get_batch_root_node(&self, batch_idx: usize) -> MerkleSumNode {
+        debug!("get batch root node, batch_idx: {:?}", batch_idx);
         assert!(batch_idx < self.cfg.num_of_batches);
         let root_idx = GlobalMst::get_batch_tree_global_index(
             &self.cfg,
             batch_idx,
-            2 * self.cfg.batch_size - 2,
+            tree_node_num(self.cfg.batch_size) - 1,
         );
-        self.inner[root_idx]
+        self.storage.get(root_idx)
+    }
+
+    /// `i`: the index of the node in the last batch tree.
+    pub fn set_last_batch_node(&mut self, i: usize, node: MerkleSumNode) {
+        assert!(self.cfg.last_batch_size.is_some(), "there is no last batch");
+        let global_mst_idx = GlobalMst::get_last_batch_global_index(&self.cfg, i);
+        self.storage.set(global_mst_idx, node);
+    }
+
+    /// Set the root of the gmst, which merges the root of the recursive tree and the root of the last batch.
+    pub fn set_root_node(&mut self, node: MerkleSumNode) {
+        assert!(self.cfg.last_batch_size.is_some(), "the root is the top recursive node");
+        let global_mst_idx = GlobalMst::get_root_global_index(&self.cfg);
+        self.storage.set(global_mst_idx, node);
     }
 
     /// `recursive_level` count from bottom to top; recursive_level = 1 means the bottom layer; increase whilve moving to the top.
-    pub fn set_recursive_hash(&mut self, recursive_level: usize, index: usize, hash: HashOut<F>) {
+    pub fn set_recursive_node(
+        &mut self,
+        recursive_level: usize,
+        index: usize,
+        node: MerkleSumNode,
+    ) {
         let idx = GlobalMst::get_recursive_global_index(&self.cfg, recursive_level, index);
-        self.inner[idx] = hash;
+        self.storage.set(idx, node);
     }
 
-    pub fn is_integral(&self) -> bool {
-        // we check all nodes are examined to ensure global_index-related functions are correct.
-        let mut visited_global_idx = vec![false; self.inner.len()];
-        let batch_num = self.cfg.num_of_batches;
-        for tree_idx in 0..self.cfg.num_of_batches {
-            let leaf_size = self.cfg.batch_size;
-            let mst_size = 2 * leaf_size - 1;
-            for inner_tree_idx in leaf_size..mst_size {
-                let inner_left_child_idx = 2 * (inner_tree_idx - leaf_size);
-                let inner_right_child_idx = 2 * (inner_tree_idx - leaf_size) + 1;
-
-                let global_parent_idx =
-                    GlobalMst::get_batch_tree_global_index(&self.cfg, tree_idx, inner_tree_idx);
-                let global_left_child_idx = GlobalMst::get_batch_tree_global_index(
-                    &self.cfg,
-                    tree_idx,
-                    inner_left_child_idx,
-                );
-                let global_right_child_idx = GlobalMst::get_batch_tree_global_index(
-                    &self.cfg,
-                    tree_idx,
-                    inner_right_child_idx,
-                );
+    /// The parent node of a recursive proof, whose hash commits to the hash and sums of every child.
+    pub fn get_recursive_parent(children: &[MerkleSumNode]) -> MerkleSumNode {
+        let hash = hash_n_subnodes(children);
+        let sum_equity = children.iter().fold(F::ZERO, |acc, x| acc + x.sum_equity);
+        let sum_debt = children.iter().fold(F::ZERO, |acc, x| acc + x.sum_debt);
+        MerkleSumNode { hash, sum_equity, sum_debt }
+    }
+
+    /// Check the nodes of a batch tree with `num_of_leaves` leaves, whose node at inner index `i` is at `global_index(i)` in gmst, and
+    /// mark its nodes but the root as visited. The padded nodes must be empty.
+    fn is_batch_tree_integral(
+        &self,
+        tree_idx: usize,
+        num_of_leaves: usize,
+        global_index: impl Fn(usize) -> usize,
+        visited_global_idx: &mut [bool],
+    ) -> bool {
+        let level_sizes = tree_level_sizes(num_of_leaves);
+        let mut level_start = 0;
+        let mut level_node_num = num_of_leaves; // the number of nodes at this level that are not padded
+        for &level_size in level_sizes.iter().take(level_sizes.len() - 1) {
+            for inner_tree_idx in level_start..level_start + level_size {
+                let global_idx = global_index(inner_tree_idx);
+                visited_global_idx[global_idx] = true;
+                if inner_tree_idx - level_start >= level_node_num
+                    && self.storage.get(global_idx) != MerkleSumNode::default()
+                {
+                    tracing::error!("Non-empty padded node at mst tree {}, global index {:?}, inner index {:?}: {:?}", tree_idx, global_idx, inner_tree_idx, self.storage.get(global_idx));
+                    return false;
+                }
+            }
 
-                visited_global_idx[global_left_child_idx] = true;
-                visited_global_idx[global_right_child_idx] = true;
+            for inner_left_child_idx in (level_start..level_start + level_size).step_by(2) {
+                let inner_right_child_idx = inner_left_child_idx + 1;
+                let inner_tree_idx =
+                    level_start + level_size + (inner_left_child_idx - level_start) / 2;
 
-                let expected_parent_hash = hash_2_subhashes::<F, D>(
-                    &self.inner[global_left_child_idx],
-                    &self.inner[global_right_child_idx],
+                let global_parent_idx = global_index(inner_tree_idx);
+                let global_left_child_idx = global_index(inner_left_child_idx);
+                let global_right_child_idx = global_index(inner_right_child_idx);
+
+                let expected_parent = MerkleSumNode::new_from_children_nodes(
+                    &self.storage.get(global_left_child_idx),
+                    &self.storage.get(global_right_child_idx),
                 );
-                if expected_parent_hash != self.inner[global_parent_idx] {
-                    tracing::error!("Inconsistent hash at mst tree {}, global index (parent: {:?}, left child: {:?}, right child: {:?}), inner index (parent: {:?}, left child: {:?}, right child: {:?}), expected parent hash: {:?}, actual parent hash: {:?}", tree_idx, global_parent_idx, global_left_child_idx, global_right_child_idx,  inner_tree_idx, inner_left_child_idx, inner_right_child_idx, expected_parent_hash, self.inner[global_parent_idx]);
+                if expected_parent != self.storage.get(global_parent_idx) {
+                    tracing::error!("Inconsistent node at mst tree {}, global index (parent: {:?}, left child: {:?}, right child: {:?}), inner index (parent: {:?}, left child: {:?}, right child: {:?}), expected parent: {:?}, actual parent: {:?}", tree_idx, global_parent_idx, global_left_child_idx, global_right_child_idx,  inner_tree_idx, inner_left_child_idx, inner_right_child_idx, expected_parent, self.storage.get(global_parent_idx));
                     return false;
                 }
             }
+            level_start += level_size;
+            level_node_num = level_size / 2;
+        }
+        true
+    }
+
+    pub fn is_integral(&self) -> bool {
+        // we check all nodes are examined to ensure global_index-related functions are correct.
+        let mut visited_global_idx = vec![false; self.storage.len()];
+        let batch_num = self.cfg.num_of_batches;
+        for tree_idx in 0..self.cfg.num_of_batches {
+            let global_index = |inner_tree_idx| {
+                GlobalMst::get_batch_tree_global_index(&self.cfg, tree_idx, inner_tree_idx)
+            };
+            if !self.is_batch_tree_integral(
+                tree_idx,
+                self.cfg.batch_size,
+                global_index,
+                &mut visited_global_idx,
+            ) {
+                return false;
+            }
         }
         let branchout_num = self.cfg.recursion_branchout_num;
-        let mut last_level_node_count = pad_to_multiple_of(batch_num, branchout_num);
+        let mut last_level_node_count = self.cfg.get_padded_batch_num();
         for level in 1..=self.top_recursion_level {
             let this_level_node_count = last_level_node_count / branchout_num;
             for inner_idx in 0..this_level_node_count {
@@ -217,23 +326,49 @@ impl GlobalMst {
                     })
                     .collect::<Vec<usize>>();
 
-                let children_hashes = global_child_indexes
+                let children = global_child_indexes
                     .iter()
-                    .map(|&i| self.inner[i])
-                    .collect::<Vec<HashOut<F>>>();
+                    .map(|&i| self.storage.get(i))
+                    .collect::<Vec<MerkleSumNode>>();
 
-                let expected_parent_hash = hash_n_subhashes::<F, D>(&children_hashes);
+                let expected_parent = GlobalMst::get_recursive_parent(&children);
 
-                if expected_parent_hash != self.inner[global_idx] {
-                    tracing::error!("Inconsistent hash at recursive level {}, Global index: {:?}, global child indexes: {:?}, inner index: {:?}, child indexes {:?}, expected parent hash: {:?}, actual parent hash: {:?}. ", level, global_idx, global_child_indexes, inner_idx, inner_child_indexes, expected_parent_hash, self.inner[global_idx]);
+                if expected_parent != self.storage.get(global_idx) {
+                    tracing::error!("Inconsistent node at recursive level {}, Global index: {:?}, global child indexes: {:?}, inner index: {:?}, child indexes {:?}, expected parent: {:?}, actual parent: {:?}. ", level, global_idx, global_child_indexes, inner_idx, inner_child_indexes, expected_parent, self.storage.get(global_idx));
                     return false;
                 }
                 last_level_node_count = pad_to_multiple_of(this_level_node_count, branchout_num);
             }
         }
-        let global_root_idx =
+        let recursive_root_idx =
             GlobalMst::get_recursive_global_index(&self.cfg, self.top_recursion_level, 0);
-        visited_global_idx[global_root_idx] = true;
+        visited_global_idx[recursive_root_idx] = true;
+
+        if let Some(last_batch_size) = self.cfg.last_batch_size {
+            let global_index =
+                |inner_tree_idx| GlobalMst::get_last_batch_global_index(&self.cfg, inner_tree_idx);
+            if !self.is_batch_tree_integral(
+                batch_num,
+                last_batch_size,
+                global_index,
+                &mut visited_global_idx,
+            ) {
+                return false;
+            }
+
+            let last_batch_root_idx = global_index(tree_node_num(last_batch_size) - 1);
+            visited_global_idx[last_batch_root_idx] = true;
+            let global_root_idx = GlobalMst::get_root_global_index(&self.cfg);
+            let expected_root = GlobalMst::get_recursive_parent(&[
+                self.storage.get(recursive_root_idx),
+                self.storage.get(last_batch_root_idx),
+            ]);
+            if expected_root != self.storage.get(global_root_idx) {
+                tracing::error!("Inconsistent root merging the last batch, global index: {:?}, expected root: {:?}, actual root: {:?}", global_root_idx, expected_root, self.storage.get(global_root_idx));
+                return false;
+            }
+            visited_global_idx[global_root_idx] = true;
+        }
 
         visited_global_idx.iter().all(|&v| v)
     }
@@ -250,7 +385,7 @@ impl GlobalMst {
                 .into_iter()
                 .enumerate()
                 .map(|(chunk_idx, j)| ((j).try_into().unwrap(), nodes[chunk_idx]))
-                .collect::<Vec<(i32, HashOut<F>)>>();
+                .collect::<Vec<(i32, MerkleSumNode)>>();
             db.add_batch_gmst_nodes(batches);
             i += chunk_size;
         }
@@ -259,15 +394,16 @@ impl GlobalMst {
 
 #[cfg(test)]
 mod test {
-    use super::GlobalMst;
+    use super::{GlobalConfig, GlobalMst};
     use crate::{
-        account::gen_accounts_with_random_data,
-        merkle_sum_tree::MerkleSumTree,
-        recursive_prover::prover::hash_n_subhashes,
-        types::{D, F},
+        account::{gen_accounts_with_random_data, LeafHashMode},
+        merkle_sum_tree::{MerkleSumNode, MerkleSumTree},
+        types::F,
         util::pad_to_multiple_of,
+        valuation::Valuation,
     };
-    use plonky2::hash::hash_types::HashOut;
+    use plonky2::field::types::Field;
+    use tempdir::TempDir;
     use zk_por_tracing::{init_tracing, TraceConfig};
 
     #[test]
@@ -277,6 +413,7 @@ mod test {
             num_of_batches: 6,
             batch_size: 8,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
         let total_len = gmst.get_tree_length();
 
@@ -308,61 +445,243 @@ mod test {
     }
 
     #[test]
-    fn test_integrity() {
-        let cfg = TraceConfig {
-            prefix: "zkpor".to_string(),
-            dir: "logs".to_string(),
-            level: tracing::Level::DEBUG,
-            console: true,
-            flame: false,
-        };
+    fn test_index_last_batch() {
+        let gmst = GlobalMst::new(super::GlobalConfig {
+            num_of_tokens: 22,
+            num_of_batches: 5,
+            batch_size: 3,
+            recursion_branchout_num: 4,
+            last_batch_size: Some(2),
+        });
 
-        {
-            init_tracing(cfg)
-        };
+        /*
+        root:                                    46
+        last batch:                              45
+                                               43-44
+        L2:                            42
+        L1:               38,    39,    40e,    41e
+        L0:   30,  31,  32,  33,  34,  35e,  36e,  37e
+              20-21, 22-23, 24-25, 26-27, 28-29
+              0-3e, 4-7e, 8-11e, 12-15e, 16-19e
+        */
+        assert_eq!(gmst.get_tree_length(), 47);
+        assert_eq!(GlobalMst::get_num_of_leaves(&gmst.cfg), 17);
 
-        let mut gmst = GlobalMst::new(super::GlobalConfig {
+        assert_eq!(GlobalMst::get_batch_tree_global_index(&gmst.cfg, 0, 3), 3);
+        assert_eq!(GlobalMst::get_batch_tree_global_index(&gmst.cfg, 1, 4), 22);
+        assert_eq!(GlobalMst::get_batch_tree_global_index(&gmst.cfg, 4, 5), 29);
+        assert_eq!(GlobalMst::get_batch_tree_global_index(&gmst.cfg, 4, 6), 34);
+
+        assert_eq!(GlobalMst::get_recursive_global_index(&gmst.cfg, 0, 5), 35);
+        assert_eq!(GlobalMst::get_recursive_global_index(&gmst.cfg, 1, 1), 39);
+        assert_eq!(GlobalMst::get_recursive_global_index(&gmst.cfg, 2, 0), 42);
+
+        assert_eq!(GlobalMst::get_last_batch_global_index(&gmst.cfg, 0), 43);
+        assert_eq!(GlobalMst::get_last_batch_global_index(&gmst.cfg, 2), 45);
+        assert_eq!(GlobalMst::get_root_global_index(&gmst.cfg), 46);
+    }
+
+    #[test]
+    fn test_index_no_full_batch() {
+        let gmst = GlobalMst::new(super::GlobalConfig {
             num_of_tokens: 22,
-            num_of_batches: 6,
-            batch_size: 8,
+            num_of_batches: 0,
+            batch_size: 4,
             recursion_branchout_num: 4,
+            last_batch_size: Some(3),
         });
 
-        assert!(!gmst.is_integral());
+        /*
+        root:                12
+        last batch:          11
+                            9-10
+                            5-8
+        L1:                  4
+        L0:           0e, 1e, 2e, 3e
+        */
+        assert_eq!(gmst.get_tree_length(), 13);
+        assert_eq!(gmst.top_recursion_level, 1);
+        assert_eq!(GlobalMst::get_num_of_leaves(&gmst.cfg), 3);
+
+        assert_eq!(GlobalMst::get_recursive_global_index(&gmst.cfg, 0, 3), 3);
+        assert_eq!(GlobalMst::get_recursive_global_index(&gmst.cfg, 1, 0), 4);
+        assert_eq!(GlobalMst::get_last_batch_global_index(&gmst.cfg, 0), 5);
+        assert_eq!(GlobalMst::get_last_batch_global_index(&gmst.cfg, 6), 11);
+        assert_eq!(GlobalMst::get_root_global_index(&gmst.cfg), 12);
+    }
+
+    #[test]
+    fn test_split_batches() {
+        assert_eq!(GlobalConfig::split_batches(3, 4), (0, Some(3)));
+        assert_eq!(GlobalConfig::split_batches(8, 4), (2, None));
+        assert_eq!(GlobalConfig::split_batches(9, 4), (2, Some(1)));
+        assert_eq!(GlobalConfig::split_batches(1_000_001, 1024), (976, Some(577)));
+    }
+
+    fn update_node(gmst: &mut GlobalMst, idx: usize, update: impl FnOnce(&mut MerkleSumNode)) {
+        let mut node = gmst.storage.get(idx);
+        update(&mut node);
+        gmst.storage.set(idx, node);
+    }
+
+    /// Fill the gmst with the trees of random accounts and the recursive nodes on top of them.
+    fn fill_gmst(gmst: &mut GlobalMst) {
         let batch_num = gmst.cfg.num_of_batches;
         let batch_size = gmst.cfg.batch_size;
         let branchout_num = gmst.cfg.recursion_branchout_num;
 
         for batch_idx in 0..batch_num {
             let accounts = gen_accounts_with_random_data(batch_size, 1);
-            let mst = MerkleSumTree::new_tree_from_accounts(&accounts);
-
-            for i in 0..batch_size * 2 - 1 {
-                gmst.set_batch_hash(batch_idx, i, mst.merkle_sum_tree[i].hash);
+            let mst = MerkleSumTree::new_tree_from_accounts(
+                &accounts,
+                LeafHashMode::PerToken,
+                &Valuation::default(),
+            );
+
+            for i in 0..mst.merkle_sum_tree.len() {
+                gmst.set_batch_node(batch_idx, i, mst.merkle_sum_tree[i]);
             }
         }
 
-        let mut last_level_node_num = pad_to_multiple_of(batch_num, branchout_num);
+        let mut last_level_node_num = gmst.cfg.get_padded_batch_num();
         for level in 1..=gmst.top_recursion_level {
             let this_level_node_count = last_level_node_num / branchout_num;
             for inner_idx in 0..this_level_node_count {
-                let children_hashes = (0..branchout_num)
+                let children = (0..branchout_num)
                     .map(|i| {
                         let child_global_idx = GlobalMst::get_recursive_global_index(
                             &gmst.cfg,
                             level - 1,
                             inner_idx * branchout_num + i,
                         );
-                        gmst.inner[child_global_idx]
+                        gmst.get_node(child_global_idx).unwrap()
                     })
-                    .collect::<Vec<HashOut<F>>>();
+                    .collect::<Vec<MerkleSumNode>>();
 
-                let expected_parent_hash = hash_n_subhashes::<F, D>(&children_hashes);
-                gmst.set_recursive_hash(level, inner_idx, expected_parent_hash);
+                let expected_parent = GlobalMst::get_recursive_parent(&children);
+                gmst.set_recursive_node(level, inner_idx, expected_parent);
             }
 
             last_level_node_num = pad_to_multiple_of(this_level_node_count, branchout_num);
         }
+
+        if let Some(last_batch_size) = gmst.cfg.last_batch_size {
+            let accounts = gen_accounts_with_random_data(last_batch_size, 1);
+            let mst = MerkleSumTree::new_tree_from_accounts(
+                &accounts,
+                LeafHashMode::PerToken,
+                &Valuation::default(),
+            );
+            for i in 0..mst.merkle_sum_tree.len() {
+                gmst.set_last_batch_node(i, mst.merkle_sum_tree[i]);
+            }
+            let recursive_root_idx =
+                GlobalMst::get_recursive_global_index(&gmst.cfg, gmst.top_recursion_level, 0);
+            let root = GlobalMst::get_recursive_parent(&[
+                gmst.get_node(recursive_root_idx).unwrap(),
+                mst.get_root(),
+            ]);
+            gmst.set_root_node(root);
+        }
+    }
+
+    #[test]
+    fn test_integrity() {
+        let cfg = TraceConfig {
+            prefix: "zkpor".to_string(),
+            dir: "logs".to_string(),
+            level: tracing::Level::DEBUG,
+            console: true,
+            flame: false,
+        };
+
+        {
+            init_tracing(cfg)
+        };
+
+        let mut gmst = GlobalMst::new(super::GlobalConfig {
+            num_of_tokens: 22,
+            num_of_batches: 6,
+            batch_size: 8,
+            recursion_branchout_num: 4,
+            last_batch_size: None,
+        });
+
+        assert!(!gmst.is_integral());
+        fill_gmst(&mut gmst);
         assert!(gmst.is_integral());
+
+        // tampering with the sums of a node breaks the integrity even if the hash is unchanged.
+        let tampered_idx = GlobalMst::get_batch_tree_global_index(&gmst.cfg, 0, 0);
+        update_node(&mut gmst, tampered_idx, |node| node.sum_equity += F::ONE);
+        assert!(!gmst.is_integral());
+    }
+
+    #[test]
+    fn test_integrity_last_batch() {
+        let mut gmst = GlobalMst::new(super::GlobalConfig {
+            num_of_tokens: 22,
+            num_of_batches: 5,
+            batch_size: 3,
+            recursion_branchout_num: 4,
+            last_batch_size: Some(2),
+        });
+
+        assert!(!gmst.is_integral());
+        fill_gmst(&mut gmst);
+        assert!(gmst.is_integral());
+
+        // the padded nodes must be empty.
+        let padded_idx = GlobalMst::get_batch_tree_global_index(&gmst.cfg, 0, 3);
+        update_node(&mut gmst, padded_idx, |node| node.sum_equity += F::ONE);
+        assert!(!gmst.is_integral());
+        update_node(&mut gmst, padded_idx, |node| node.sum_equity -= F::ONE);
+
+        let tampered_idx = GlobalMst::get_last_batch_global_index(&gmst.cfg, 0);
+        update_node(&mut gmst, tampered_idx, |node| node.sum_debt += F::ONE);
+        assert!(!gmst.is_integral());
+    }
+
+    #[test]
+    fn test_integrity_no_full_batch() {
+        let mut gmst = GlobalMst::new(super::GlobalConfig {
+            num_of_tokens: 22,
+            num_of_batches: 0,
+            batch_size: 4,
+            recursion_branchout_num: 4,
+            last_batch_size: Some(3),
+        });
+
+        assert!(!gmst.is_integral());
+        fill_gmst(&mut gmst);
+        assert!(gmst.is_integral());
+
+        // the recursive root over the empty batches is a child of the root.
+        let recursive_root_idx = GlobalMst::get_recursive_global_index(&gmst.cfg, 1, 0);
+        update_node(&mut gmst, recursive_root_idx, |node| node.sum_equity += F::ONE);
+        assert!(!gmst.is_integral());
+    }
+
+    #[test]
+    fn test_integrity_mmap() {
+        let cfg = GlobalConfig {
+            num_of_tokens: 22,
+            num_of_batches: 5,
+            batch_size: 3,
+            recursion_branchout_num: 4,
+            last_batch_size: Some(2),
+        };
+        let dir = TempDir::new("gmst").unwrap();
+        let mut gmst = GlobalMst::new_mmap(cfg, &dir.path().join("gmst")).unwrap();
+        assert_eq!(GlobalMst::new(cfg).get_tree_length(), gmst.get_tree_length());
+
+        assert!(!gmst.is_integral());
+        fill_gmst(&mut gmst);
+        assert!(gmst.is_integral());
+        assert_eq!(gmst.get_node(GlobalMst::get_root_global_index(&cfg)), gmst.get_root());
+
+        let tampered_idx = GlobalMst::get_recursive_global_index(&cfg, 1, 0);
+        update_node(&mut gmst, tampered_idx, |node| node.sum_equity += F::ONE);
+        assert!(!gmst.is_integral());
     }
 }
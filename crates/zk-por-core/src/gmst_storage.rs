@@ -0,0 +1,109 @@
+use memmap2::MmapMut;
+use std::{fs::OpenOptions, path::Path};
+
+use crate::{
+    error::PoRError,
+    merkle_sum_tree::{MerkleSumNode, MERKLE_SUM_NODE_BYTES},
+};
+
+/// The storage of the nodes of a gmst, indexed by their global index. A storage of `len` nodes is created with empty nodes.
+pub trait GmstStorage: Send + Sync {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// `idx` must be less than `len`.
+    fn get(&self, idx: usize) -> MerkleSumNode;
+
+    /// `idx` must be less than `len`.
+    fn set(&mut self, idx: usize, node: MerkleSumNode);
+}
+
+impl GmstStorage for Vec<MerkleSumNode> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn get(&self, idx: usize) -> MerkleSumNode {
+        self[idx]
+    }
+
+    fn set(&mut self, idx: usize, node: MerkleSumNode) {
+        self[idx] = node;
+    }
+}
+
+/// A storage of the nodes in a memory-mapped file, each node in `MERKLE_SUM_NODE_BYTES` bytes at the offset of its index, such that a
+/// gmst larger than the memory is paged to the file by the OS.
+pub struct MmapGmstStorage {
+    mmap: MmapMut,
+    len: usize,
+}
+
+impl MmapGmstStorage {
+    /// Create a file of `len` empty nodes at `path`, truncating any existing one, and map it to memory. As the bytes of an empty node
+    /// are all zero, the file is sparse until the nodes are set.
+    pub fn create(path: &Path, len: usize) -> Result<Self, PoRError> {
+        let file =
+            OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)?;
+        file.set_len((len * MERKLE_SUM_NODE_BYTES) as u64)?;
+        // safety: the file is created for this storage, and is not supposed to be modified by others while mapped.
+        let mmap = unsafe { MmapMut::map_mut(&file)? };
+        Ok(Self { mmap, len })
+    }
+
+    /// Flush the nodes set to the file.
+    pub fn flush(&self) -> Result<(), PoRError> {
+        Ok(self.mmap.flush()?)
+    }
+}
+
+impl GmstStorage for MmapGmstStorage {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, idx: usize) -> MerkleSumNode {
+        assert!(idx < self.len);
+        MerkleSumNode::from_bytes(
+            &self.mmap[idx * MERKLE_SUM_NODE_BYTES..(idx + 1) * MERKLE_SUM_NODE_BYTES],
+        )
+    }
+
+    fn set(&mut self, idx: usize, node: MerkleSumNode) {
+        assert!(idx < self.len);
+        self.mmap[idx * MERKLE_SUM_NODE_BYTES..(idx + 1) * MERKLE_SUM_NODE_BYTES]
+            .copy_from_slice(&node.to_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GmstStorage, MmapGmstStorage};
+    use crate::{merkle_sum_tree::MerkleSumNode, types::F};
+    use plonky2::{
+        field::types::{Field, Sample},
+        hash::hash_types::HashOut,
+    };
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_mmap_storage() {
+        let dir = TempDir::new("gmst").unwrap();
+        let mut storage = MmapGmstStorage::create(&dir.path().join("gmst"), 5).unwrap();
+        assert_eq!(storage.len(), 5);
+        (0..5).for_each(|i| assert_eq!(storage.get(i), MerkleSumNode::default()));
+
+        let node = MerkleSumNode {
+            sum_equity: F::from_canonical_u64(100),
+            sum_debt: F::from_canonical_u64(1),
+            hash: HashOut::<F>::from_vec(vec![F::rand(), F::rand(), F::rand(), F::rand()]),
+        };
+        storage.set(4, node);
+        assert_eq!(storage.get(4), node);
+        assert_eq!(storage.get(3), MerkleSumNode::default());
+        storage.flush().unwrap();
+    }
+}
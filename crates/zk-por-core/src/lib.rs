@@ -1,33 +1,64 @@
+use account::LeafHashMode;
+use error::PoRError;
+use global::GlobalConfig;
+use merkle_sum_prover::circuits::merkle_sum_circuit::MerkleSumNodeTarget;
 use plonky2::{
     hash::hash_types::HashOut,
     plonk::{circuit_data::CircuitConfig, proof::ProofWithPublicInputs},
 };
+use plonky2_field::types::PrimeField64;
+use recursive_prover::recursive_circuit::RecursiveTargets;
 use serde::*;
 use types::{C, D, F};
+use valuation::Valuation;
 
 pub mod account;
+pub mod anchor;
+pub mod checkpoint;
 pub mod circuit_config;
 pub mod circuit_registry;
 pub mod circuit_utils;
 pub mod config;
 pub mod database;
+pub mod distributed;
 pub mod e2e;
 pub mod error;
 pub mod global;
+pub mod gmst_storage;
 pub mod merkle_proof;
 pub mod merkle_sum_prover;
 pub mod merkle_sum_tree;
 pub mod parser;
+pub mod pipeline;
+pub mod proof_file;
+pub mod proof_store;
 pub mod recursive_prover;
+pub mod reserves;
+pub mod solvency_prover;
 pub mod types;
+pub mod user_proof_archive;
 pub mod util;
+pub mod valuation;
+pub mod wrap_prover;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct General {
     pub round_num: usize,
     pub recursion_branchout_num: usize,
     pub batch_size: usize,
     pub token_num: usize,
+    #[serde(default)] // proof files before per-token sums do not have this field.
+    pub tokens: Vec<String>,
+    #[serde(default)] // proof files before per-token leaf hashes only hash the sums.
+    pub leaf_hash_mode: LeafHashMode,
+    #[serde(default)] // proof files before price-weighted sums sum up the raw token amounts.
+    pub valuation: Valuation,
+    #[serde(default)]
+    // proof files before the round info is bound to the root proof do not have this field.
+    pub snapshot_timestamp: Option<u64>,
+    #[serde(default)]
+    // proof files before the smaller last batch pad the last batch to a full batch.
+    pub last_batch_size: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,17 +70,130 @@ pub struct Proof {
     pub proof: ProofWithPublicInputs<F, C, D>,
 }
 
-#[derive(Serialize, Deserialize)]
+impl Proof {
+    /// The shape of the gmst of the round, from the general section and the user count attested by the root proof, which locates the
+    /// user proofs in the gmst. Proof files without the smaller last batch pad the users to full batches.
+    pub fn global_config(&self) -> Result<GlobalConfig, PoRError> {
+        let general = &self.general;
+        let user_count = RecursiveTargets::user_count_from_public_inputs(
+            general.token_num,
+            &general.valuation,
+            &self.proof.public_inputs,
+        )
+        .ok_or_else(|| {
+            tracing::error!("the root proof does not attest the user count to locate the users");
+            PoRError::InvalidProof
+        })? as usize;
+        let num_of_batches = match general.last_batch_size {
+            Some(last_batch_size) => {
+                if user_count % general.batch_size != last_batch_size || last_batch_size == 0 {
+                    tracing::error!(
+                        "the last batch size {} does not match the user count {} of batch size {}",
+                        last_batch_size,
+                        user_count,
+                        general.batch_size
+                    );
+                    return Err(PoRError::InvalidProof);
+                }
+                user_count / general.batch_size
+            }
+            None => user_count.div_ceil(general.batch_size),
+        };
+        Ok(GlobalConfig {
+            num_of_tokens: general.token_num,
+            num_of_batches,
+            batch_size: general.batch_size,
+            recursion_branchout_num: general.recursion_branchout_num,
+            last_batch_size: general.last_batch_size,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct CircuitsInfo {
     pub batch_circuit_config: CircuitConfig,
     pub recursive_circuit_configs: Vec<CircuitConfig>,
     pub root_verifier_data_hex: String,
 }
 
+/// The solvency proof wrapping the root proof, which proves that the reserves of every token cover its liabilities.
+#[derive(Serialize, Deserialize)]
+pub struct SolvencyProof {
+    pub general: General,
+    pub hide_liabilities: bool,
+    pub circuits_info: CircuitsInfo,
+    pub solvency_circuit_config: CircuitConfig,
+    pub solvency_verifier_data_hex: String,
+    pub proof: ProofWithPublicInputs<F, C, D>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Info {
     pub root_hash: String,
     pub equity_sum: u64,
     pub debt_sum: u64,
     pub balance_sum: u64,
+    #[serde(default)]
+    // the number of real users attested by the root proof, excluding padding accounts.
+    pub user_count: u64,
+    #[serde(default)]
+    pub tokens: Vec<TokenInfo>,
+}
+
+/// The total equity and debt of all users for one token. The balance is negative if users owe more of the token than they hold.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenInfo {
+    pub token: String,
+    pub equity_sum: u64,
+    pub debt_sum: u64,
+    pub balance_sum: i128,
+}
+
+impl TokenInfo {
+    /// Read the per-token sums from the public inputs of a root proof. Returns an empty vec if the proof does not expose per-token sums.
+    pub fn from_public_inputs(tokens: &[String], public_inputs: &[F]) -> Vec<TokenInfo> {
+        let token_num = tokens.len();
+        let equity_offset = MerkleSumNodeTarget::pub_input_token_equity_offset(token_num);
+        let debt_offset = MerkleSumNodeTarget::pub_input_token_debt_offset(token_num);
+        if public_inputs.len() < debt_offset.end {
+            return vec![];
+        }
+
+        tokens
+            .iter()
+            .zip(public_inputs[equity_offset].iter().zip(public_inputs[debt_offset].iter()))
+            .map(|(token, (equity, debt))| {
+                let equity_sum = equity.to_canonical_u64();
+                let debt_sum = debt.to_canonical_u64();
+                TokenInfo {
+                    token: token.clone(),
+                    equity_sum,
+                    debt_sum,
+                    balance_sum: i128::from(equity_sum) - i128::from(debt_sum),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use plonky2_field::types::{Field, Field64};
+
+    use super::TokenInfo;
+    use crate::types::F;
+
+    #[test]
+    fn test_token_info_balance_does_not_wrap() {
+        let tokens = vec!["BTC".to_owned(), "ETH".to_owned()];
+        // sum equity, sum debt, root hash, per-token equity, per-token debt and the user count.
+        let mut public_inputs = vec![F::ZERO; 6];
+        public_inputs.extend([F::from_canonical_u64(F::ORDER - 1), F::ZERO]);
+        public_inputs.extend([F::ZERO, F::from_canonical_u64(F::ORDER - 1)]);
+        public_inputs.push(F::ONE);
+
+        let infos = TokenInfo::from_public_inputs(&tokens, &public_inputs);
+        assert_eq!(i128::from(F::ORDER - 1), infos[0].balance_sum);
+        assert_eq!(-i128::from(F::ORDER - 1), infos[1].balance_sum);
+    }
 }
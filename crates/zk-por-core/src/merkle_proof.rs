@@ -1,19 +1,19 @@
 use itertools::Itertools;
-use plonky2::{
-    hash::{hash_types::HashOut, poseidon::PoseidonHash},
-    plonk::config::{GenericHashOut, Hasher},
-};
+use plonky2::hash::hash_types::HashOut;
+use plonky2_field::types::{Field, Field64, PrimeField64};
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use serde::{self, Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    account::Account,
+    account::{Account, LeafHashMode},
     circuit_utils::recursive_levels,
     database::{PoRDB, UserId},
     error::PoRError,
     global::{GlobalConfig, GlobalMst},
-    merkle_sum_prover::utils::hash_2_subhashes,
-    types::{D, F},
+    merkle_sum_tree::MerkleSumNode,
+    types::F,
+    util::{tree_level_sizes, tree_node_num},
+    valuation::Valuation,
 };
 
 use std::sync::Arc;
@@ -42,35 +42,48 @@ impl MerkleProofIndex {
     }
 }
 
+/// Get the inner indexes of the siblings of a leaf in a binary merkle sum tree with `num_of_leaves` leaves, from the leaf to the root.
+/// The nodes are indexed level by level, hence the parent of the node at position `i` of a level is at position `i / 2` of the level above.
+fn get_batch_tree_siblings_index(num_of_leaves: usize, local_index: usize) -> Vec<usize> {
+    let level_sizes = tree_level_sizes(num_of_leaves);
+    let mut siblings = Vec::new();
+    let mut level_start = 0;
+    let mut level_index = local_index;
+    for level_size in level_sizes.iter().take(level_sizes.len() - 1) {
+        siblings.push(level_start + (level_index ^ 1));
+        level_start += level_size;
+        level_index /= 2;
+    }
+    siblings
+}
+
 /// Get the siblings index for the merkle proof of inclusion given a leaf index of a binary merkle sum tree.
-/// We get the parent index of a leaf using the formula: parent = index / 2 + num_leaves
+/// The leaf is either in one of the full batches or in the last batch.
 pub fn get_mst_siblings_index(global_leaf_index: usize, cfg: &GlobalConfig) -> Vec<usize> {
     // Make sure our global index is within the number of leaves
     assert!(global_leaf_index < GlobalMst::get_num_of_leaves(cfg));
 
     let batch_id = global_leaf_index / cfg.batch_size;
-    let mut siblings = Vec::new();
 
     // This is the index in the local mst tree
-    let mut local_index = global_leaf_index % cfg.batch_size;
-
-    while local_index < (cfg.batch_size * 2 - 2) {
-        if local_index % 2 == 1 {
-            let sibling_index = local_index - 1;
-            siblings.push(sibling_index);
-        } else {
-            let sibling_index = local_index + 1;
-            siblings.push(sibling_index);
-        }
+    let local_index = global_leaf_index % cfg.batch_size;
 
-        let local_parent_index = local_index / 2 + cfg.batch_size;
-        local_index = local_parent_index;
+    if batch_id == cfg.num_of_batches {
+        let last_batch_size = cfg.last_batch_size.unwrap();
+        return get_batch_tree_siblings_index(last_batch_size, local_index)
+            .par_iter()
+            .map(|x| GlobalMst::get_last_batch_global_index(cfg, *x))
+            .collect();
     }
 
-    siblings.par_iter().map(|x| GlobalMst::get_batch_tree_global_index(cfg, batch_id, *x)).collect()
+    get_batch_tree_siblings_index(cfg.batch_size, local_index)
+        .par_iter()
+        .map(|x| GlobalMst::get_batch_tree_global_index(cfg, batch_id, *x))
+        .collect()
 }
 
 /// Gets the recursive siblings indexes (recursive tree is n-ary tree) as a Vec of vecs, each inner vec is one layer of siblings.
+/// If there is a last batch, the root of the recursive tree and the root of the last batch are siblings at the top layer.
 pub fn get_recursive_siblings_index(
     global_index: usize,
     cfg: &GlobalConfig,
@@ -80,11 +93,17 @@ pub fn get_recursive_siblings_index(
 
     let mut siblings = Vec::new();
     let mst_batch_idx = global_index / cfg.batch_size;
+    let recursive_level_num = recursive_levels(cfg.num_of_batches, cfg.recursion_branchout_num);
+    if mst_batch_idx == cfg.num_of_batches {
+        let recursive_root_idx = GlobalMst::get_recursive_global_index(cfg, recursive_level_num, 0);
+        siblings
+            .push(RecursiveIndex { left_indexes: vec![recursive_root_idx], right_indexes: vec![] });
+        return siblings;
+    }
+
     let mut recursive_idx = mst_batch_idx / cfg.recursion_branchout_num;
     let mut recursive_offset = mst_batch_idx % cfg.recursion_branchout_num;
 
-    let recursive_level_num = recursive_levels(cfg.num_of_batches, cfg.recursion_branchout_num);
-
     for i in 0..recursive_level_num {
         let mut left_layer = Vec::new();
         let mut right_layer = Vec::new();
@@ -106,173 +125,60 @@ pub fn get_recursive_siblings_index(
         recursive_idx = recursive_idx / cfg.recursion_branchout_num;
     }
 
-    siblings
-}
-
-/// We use this wrapper struct for the left and right hashes of our recursive siblings. This is needed so a user knows the position of
-/// their own hash when hashing.
-#[derive(Debug, Clone, PartialEq)]
-pub struct RecursiveHashes {
-    left_hashes: Vec<HashOut<F>>,
-    right_hashes: Vec<HashOut<F>>,
-}
-
-impl Serialize for RecursiveHashes {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("RecursiveHashes", 2)?;
-
-        let left_hashes: Vec<String> = self
-            .left_hashes
-            .iter()
-            .map(|e| {
-                let bytes = e.to_bytes();
-                hex::encode(&bytes)
-            })
-            .collect();
-        state.serialize_field("left_hashes", &left_hashes)?;
-
-        let right_hashes: Vec<String> = self
-            .right_hashes
-            .iter()
-            .map(|e| {
-                let bytes = e.to_bytes();
-                hex::encode(&bytes)
-            })
-            .collect();
-        state.serialize_field("right_hashes", &right_hashes)?;
-        state.end()
+    if let Some(last_batch_size) = cfg.last_batch_size {
+        let last_batch_root_idx =
+            GlobalMst::get_last_batch_global_index(cfg, tree_node_num(last_batch_size) - 1);
+        siblings.push(RecursiveIndex {
+            left_indexes: vec![],
+            right_indexes: vec![last_batch_root_idx],
+        });
     }
-}
 
-impl<'de> Deserialize<'de> for RecursiveHashes {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        struct Inner {
-            left_hashes: Vec<String>,
-            right_hashes: Vec<String>,
-        }
-
-        let helper = Inner::deserialize(deserializer)?;
-        let left_hashes = helper
-            .left_hashes
-            .iter()
-            .map(|e| {
-                let bytes = hex::decode(e).unwrap();
-                HashOut::from_bytes(&bytes)
-            })
-            .collect();
-        let right_hashes = helper
-            .right_hashes
-            .iter()
-            .map(|e| {
-                let bytes = hex::decode(e).unwrap();
-                HashOut::from_bytes(&bytes)
-            })
-            .collect();
+    siblings
+}
 
-        Ok(RecursiveHashes { left_hashes: left_hashes, right_hashes: right_hashes })
-    }
+/// We use this wrapper struct for the left and right nodes of our recursive siblings. This is needed so a user knows the position of
+/// their own node when hashing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecursiveNodes {
+    left_nodes: Vec<MerkleSumNode>,
+    right_nodes: Vec<MerkleSumNode>,
 }
 
-impl RecursiveHashes {
+impl RecursiveNodes {
     pub fn new_from_index(indexes: &RecursiveIndex, db: Arc<dyn PoRDB>) -> Self {
-        let left_hashes = indexes
-            .left_indexes
-            .iter()
-            .map(|y| db.get_gmst_node_hash(*y as i32).unwrap())
-            .collect_vec();
-        let right_hashes = indexes
+        let left_nodes =
+            indexes.left_indexes.iter().map(|y| db.get_gmst_node(*y as i32).unwrap()).collect_vec();
+        let right_nodes = indexes
             .right_indexes
             .iter()
-            .map(|y| db.get_gmst_node_hash(*y as i32).unwrap())
+            .map(|y| db.get_gmst_node(*y as i32).unwrap())
             .collect_vec();
-        RecursiveHashes { left_hashes, right_hashes }
+        RecursiveNodes { left_nodes, right_nodes }
     }
 
-    /// Calculated Hash = Left hashes || own hash || Right hashes
-    pub fn get_calculated_hash(self, own_hash: HashOut<F>) -> HashOut<F> {
-        let mut hash_inputs = self.left_hashes;
-        hash_inputs.push(own_hash);
-        hash_inputs.extend(self.right_hashes);
+    pub fn siblings(&self) -> impl Iterator<Item = &MerkleSumNode> {
+        self.left_nodes.iter().chain(self.right_nodes.iter())
+    }
 
-        let inputs: Vec<F> = hash_inputs.iter().map(|x| x.elements).flatten().collect();
+    /// Calculated Node = Hash(Left nodes || own node || Right nodes), with the sums of all of them.
+    pub fn get_calculated_node(&self, own_node: MerkleSumNode) -> MerkleSumNode {
+        let mut children = self.left_nodes.clone();
+        children.push(own_node);
+        children.extend(self.right_nodes.iter());
 
-        PoseidonHash::hash_no_pad(inputs.as_slice())
+        GlobalMst::get_recursive_parent(&children)
     }
 }
 
-/// Hashes for a given users merkle proof of inclusion siblings in the Global Merkle Sum Tree, also includes account data as it is needed for the verification
-/// of the merkle proof (needed to calculate own hash)
-#[derive(Debug, Clone)]
+/// Nodes for a given users merkle proof of inclusion siblings in the Global Merkle Sum Tree, also includes account data as it is needed for the verification
+/// of the merkle proof (needed to calculate own hash). Each sibling carries its hash as well as its sum of equity and debt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MerkleProof {
     pub account: Account,
     pub index: usize,
-    pub sum_tree_siblings: Vec<HashOut<F>>,
-    pub recursive_tree_siblings: Vec<RecursiveHashes>,
-}
-
-impl Serialize for MerkleProof {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("MerkleProof", 4)?;
-        state.serialize_field("account", &self.account)?;
-        state.serialize_field("index", &self.index)?;
-
-        let sum_tree_siblings: Vec<String> = self
-            .sum_tree_siblings
-            .iter()
-            .map(|e| {
-                let bytes = e.to_bytes();
-                hex::encode(&bytes)
-            })
-            .collect();
-
-        state.serialize_field("sum_tree_siblings", &sum_tree_siblings)?;
-        state.serialize_field("recursive_tree_siblings", &self.recursive_tree_siblings)?;
-        state.end()
-    }
-}
-
-impl<'de> Deserialize<'de> for MerkleProof {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        #[derive(Deserialize)]
-        struct InnerMerkleProof {
-            account: Account,
-            index: usize,
-            sum_tree_siblings: Vec<String>,
-            recursive_tree_siblings: Vec<RecursiveHashes>,
-        }
-
-        let helper = InnerMerkleProof::deserialize(deserializer)?;
-        let sum_tree_siblings = helper
-            .sum_tree_siblings
-            .iter()
-            .map(|e| {
-                let bytes = hex::decode(e).unwrap();
-                HashOut::from_bytes(&bytes)
-            })
-            .collect();
-
-        Ok(MerkleProof {
-            account: helper.account,
-            index: helper.index,
-            sum_tree_siblings: sum_tree_siblings,
-            recursive_tree_siblings: helper.recursive_tree_siblings,
-        })
-    }
+    pub sum_tree_siblings: Vec<MerkleSumNode>,
+    pub recursive_tree_siblings: Vec<RecursiveNodes>,
 }
 
 impl MerkleProof {
@@ -296,7 +202,7 @@ impl MerkleProof {
 
         let merkle_proof_indexes =
             MerkleProofIndex::new_from_user_index(user_index.unwrap() as usize, cfg);
-        let merkle_proof = get_merkle_proof_hashes_from_indexes(
+        let merkle_proof = get_merkle_proof_nodes_from_indexes(
             account,
             &merkle_proof_indexes,
             user_index.unwrap() as usize,
@@ -305,75 +211,276 @@ impl MerkleProof {
         Ok(merkle_proof)
     }
 
-    pub fn verify_merkle_proof(&self, gmst_root: HashOut<F>) -> Result<(), PoRError> {
-        let account_hash = self.account.get_hash();
+    /// Verify the merkle proof against the gmst root, i.e., the root hash and the total equity and debt published in the root proof.
+    /// `cfg` must be the gmst shape of the round, which locates the account in its batch tree and fixes the number of siblings at
+    /// every level of the path, such that a path to an inner node of the gmst is not accepted as a path to the root.
+    /// `leaf_hash_mode` must be the mode of the round, in which case `LeafHashMode::PerToken` ensures every token balance of the account is included.
+    /// `valuation` must be the valuation of the round, i.e., the prices published in the root proof if the sums are price-weighted.
+    ///
+    /// Besides the hashes, the sums are checked on the way up: every sibling must have non-negative net balance and the running totals
+    /// (computed over integers, hence without wrapping around the field) must end up exactly at the root sums.
+    pub fn verify_merkle_proof(
+        &self,
+        gmst_root: &MerkleSumNode,
+        cfg: &GlobalConfig,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+    ) -> Result<(), PoRError> {
+        let invalid = || PoRError::InvalidMerkleProof(self.account.id.clone());
+
+        if self.index >= GlobalMst::get_num_of_leaves(cfg) {
+            tracing::error!("user index {} is out of the gmst of {:?}", self.index, cfg);
+            return Err(invalid());
+        }
+        let expected_indexes = MerkleProofIndex::new_from_user_index(self.index, cfg);
+        let is_path_shape_expected = self.sum_tree_siblings.len()
+            == expected_indexes.sum_tree_siblings.len()
+            && self.recursive_tree_siblings.len() == expected_indexes.recursive_tree_siblings.len()
+            && self
+                .recursive_tree_siblings
+                .iter()
+                .zip(expected_indexes.recursive_tree_siblings.iter())
+                .all(|(siblings, indexes)| {
+                    siblings.left_nodes.len() == indexes.left_indexes.len()
+                        && siblings.right_nodes.len() == indexes.right_indexes.len()
+                });
+        if !is_path_shape_expected {
+            tracing::error!(
+                "the siblings of user index {} do not match the gmst of {:?}",
+                self.index,
+                cfg
+            );
+            return Err(invalid());
+        }
 
-        let mut index = self.index;
+        let account_node =
+            MerkleSumNode::new_from_account(&self.account, leaf_hash_mode, valuation);
+        let mut running_equity = account_node.sum_equity.to_canonical_u64();
+        let mut running_debt = account_node.sum_debt.to_canonical_u64();
+
+        let mut add_sibling = |sibling: &MerkleSumNode| -> Result<(), PoRError> {
+            let equity = sibling.sum_equity.to_canonical_u64();
+            let debt = sibling.sum_debt.to_canonical_u64();
+            if equity < debt {
+                tracing::error!("sibling {:?} has a negative net balance", sibling);
+                return Err(invalid());
+            }
+            running_equity = running_equity.checked_add(equity).ok_or_else(invalid)?;
+            running_debt = running_debt.checked_add(debt).ok_or_else(invalid)?;
+            Ok(())
+        };
 
-        let calculated_mst_hash = self.sum_tree_siblings.iter().fold(account_hash, |acc, x| {
-            if index % 2 == 0 {
-                index /= 2;
-                hash_2_subhashes::<F, D>(&acc, x)
+        // the last batch starts at a multiple of the batch size as well.
+        let mut index = self.index % cfg.batch_size;
+        let mut calculated_node = account_node;
+        for sibling in self.sum_tree_siblings.iter() {
+            add_sibling(sibling)?;
+            calculated_node = if index % 2 == 0 {
+                MerkleSumNode::new_from_children_nodes(&calculated_node, sibling)
             } else {
-                index /= 2;
-                hash_2_subhashes::<F, D>(x, &acc)
+                MerkleSumNode::new_from_children_nodes(sibling, &calculated_node)
+            };
+            index /= 2;
+        }
+
+        for recursive_siblings in self.recursive_tree_siblings.iter() {
+            for sibling in recursive_siblings.siblings() {
+                add_sibling(sibling)?;
             }
-        });
+            calculated_node = recursive_siblings.get_calculated_node(calculated_node);
+        }
 
-        let calculated_hash = self
-            .recursive_tree_siblings
-            .iter()
-            .fold(calculated_mst_hash, |acc, x| x.clone().get_calculated_hash(acc));
+        if calculated_node.hash != gmst_root.hash {
+            tracing::error!(
+                "calculated root hash {:?} does not match the gmst root hash {:?}",
+                calculated_node.hash,
+                gmst_root.hash
+            );
+            return Err(invalid());
+        }
 
-        if calculated_hash == gmst_root {
-            Ok(())
-        } else {
-            Err(PoRError::InvalidMerkleProof(self.account.id.clone()))
+        if running_equity != gmst_root.sum_equity.to_canonical_u64()
+            || running_debt != gmst_root.sum_debt.to_canonical_u64()
+        {
+            tracing::error!(
+                "calculated root sums (equity: {}, debt: {}) do not match the gmst root sums (equity: {}, debt: {})",
+                running_equity,
+                running_debt,
+                gmst_root.sum_equity.to_canonical_u64(),
+                gmst_root.sum_debt.to_canonical_u64()
+            );
+            return Err(invalid());
+        }
+
+        Ok(())
+    }
+
+    /// The compact binary encoding of the proof in a user proof archive, all integers in little endian:
+    /// - the user id in 32 bytes and the index of the user in u64,
+    /// - the token num in u32, followed by the equity and the debt of every token and the salt of the account,
+    /// - the sum tree siblings in u32 count and `MERKLE_SUM_NODE_BYTES` bytes each,
+    /// - the recursive levels in u32 count, each with the left and the right siblings like the sum tree siblings.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let user_id = UserId::from_hex_string(self.account.id.clone())
+            .expect(format!("invalid user id {}", self.account.id).as_str());
+        let mut bytes = user_id.0.to_vec();
+        bytes.extend((self.index as u64).to_le_bytes());
+        bytes.extend((self.account.equity.len() as u32).to_le_bytes());
+        for amount in self.account.equity.iter().chain(self.account.debt.iter()) {
+            bytes.extend(amount.to_canonical_u64().to_le_bytes());
+        }
+        bytes.extend(
+            self.account.salt.elements.iter().flat_map(|x| x.to_canonical_u64().to_le_bytes()),
+        );
+
+        let extend_nodes = |bytes: &mut Vec<u8>, nodes: &[MerkleSumNode]| {
+            bytes.extend((nodes.len() as u32).to_le_bytes());
+            nodes.iter().for_each(|node| bytes.extend(node.to_bytes()));
+        };
+        extend_nodes(&mut bytes, &self.sum_tree_siblings);
+        bytes.extend((self.recursive_tree_siblings.len() as u32).to_le_bytes());
+        for siblings in self.recursive_tree_siblings.iter() {
+            extend_nodes(&mut bytes, &siblings.left_nodes);
+            extend_nodes(&mut bytes, &siblings.right_nodes);
         }
+        bytes
+    }
+
+    /// Decode a proof encoded by `to_bytes`, which must span all of `bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<MerkleProof, PoRError> {
+        let mut reader = ProofBytesReader { bytes };
+        let id = UserId(reader.read(32)?.try_into().unwrap()).to_string();
+        let index = reader.read_u64()? as usize;
+        let token_num = reader.read_u32()? as usize;
+        let equity = (0..token_num).map(|_| reader.read_field()).collect::<Result<_, _>>()?;
+        let debt = (0..token_num).map(|_| reader.read_field()).collect::<Result<_, _>>()?;
+        let salt = reader.read_hash()?;
+
+        let sum_tree_siblings = reader.read_nodes()?;
+        let level_num = reader.read_u32()? as usize;
+        let recursive_tree_siblings = (0..level_num)
+            .map(|_| {
+                Ok(RecursiveNodes {
+                    left_nodes: reader.read_nodes()?,
+                    right_nodes: reader.read_nodes()?,
+                })
+            })
+            .collect::<Result<_, PoRError>>()?;
+        if !reader.bytes.is_empty() {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "{} trailing bytes in the proof of user {}",
+                reader.bytes.len(),
+                id
+            )));
+        }
+
+        Ok(MerkleProof {
+            account: Account { id, equity, debt, salt },
+            index,
+            sum_tree_siblings,
+            recursive_tree_siblings,
+        })
+    }
+}
+
+/// Reads the fields of a proof encoded by `MerkleProof::to_bytes`, rejecting truncated bytes and non-canonical field elements.
+struct ProofBytesReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ProofBytesReader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], PoRError> {
+        if self.bytes.len() < len {
+            return Err(PoRError::InvalidUserProofArchive("truncated user proof".to_string()));
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, PoRError> {
+        Ok(u32::from_le_bytes(self.read(4)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, PoRError> {
+        Ok(u64::from_le_bytes(self.read(8)?.try_into().unwrap()))
+    }
+
+    fn read_field(&mut self) -> Result<F, PoRError> {
+        let value = self.read_u64()?;
+        if value >= F::ORDER {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "non-canonical field element {} in user proof",
+                value
+            )));
+        }
+        Ok(F::from_canonical_u64(value))
+    }
+
+    fn read_hash(&mut self) -> Result<HashOut<F>, PoRError> {
+        let elements = (0..4).map(|_| self.read_field()).collect::<Result<Vec<F>, _>>()?;
+        Ok(HashOut::from_vec(elements))
+    }
+
+    /// The layout of `MerkleSumNode::to_bytes`, i.e., the hash followed by the equity and the debt.
+    fn read_nodes(&mut self) -> Result<Vec<MerkleSumNode>, PoRError> {
+        let node_num = self.read_u32()? as usize;
+        (0..node_num)
+            .map(|_| {
+                let hash = self.read_hash()?;
+                let sum_equity = self.read_field()?;
+                let sum_debt = self.read_field()?;
+                Ok(MerkleSumNode { sum_equity, sum_debt, hash })
+            })
+            .collect()
     }
 }
 
-/// Given the indexes for the MST siblings, get the hashes from the database for the merkle proof of inclusion.
-pub fn get_merkle_proof_hashes_from_indexes(
+/// Given the indexes for the MST siblings, get the nodes from the database for the merkle proof of inclusion.
+pub fn get_merkle_proof_nodes_from_indexes(
     account: &Account,
     indexes: &MerkleProofIndex,
     user_index: usize,
     db: Arc<dyn PoRDB>,
 ) -> MerkleProof {
-    let mst_hashes: Vec<HashOut<F>> = indexes
-        .sum_tree_siblings
-        .iter()
-        .map(|x| db.get_gmst_node_hash(*x as i32).unwrap())
-        .collect();
+    let mst_nodes: Vec<MerkleSumNode> =
+        indexes.sum_tree_siblings.iter().map(|x| db.get_gmst_node(*x as i32).unwrap()).collect();
 
-    let recursive_hashes: Vec<RecursiveHashes> = indexes
+    let recursive_nodes: Vec<RecursiveNodes> = indexes
         .recursive_tree_siblings
         .iter()
-        .map(|x| RecursiveHashes::new_from_index(x, db.clone()))
+        .map(|x| RecursiveNodes::new_from_index(x, db.clone()))
         .collect();
 
     MerkleProof {
         account: account.clone(),
-        sum_tree_siblings: mst_hashes,
-        recursive_tree_siblings: recursive_hashes,
+        sum_tree_siblings: mst_nodes,
+        recursive_tree_siblings: recursive_nodes,
         index: user_index,
     }
 }
 
 #[cfg(test)]
 pub mod test {
-    use itertools::Itertools;
-    use plonky2::hash::hash_types::HashOut;
+    use std::sync::{Arc, RwLock};
 
     use crate::{
-        account::Account,
+        account::{
+            gen_accounts_with_random_data, persist_account_id_to_gmst_pos, Account, LeafHashMode,
+            SaltSeed,
+        },
+        circuit_utils::recursive_levels,
+        database::{PoRDB, PoRMemoryDB},
         global::{GlobalConfig, GlobalMst},
         merkle_proof::{get_recursive_siblings_index, MerkleProofIndex, RecursiveIndex},
+        merkle_sum_tree::{MerkleSumNode, MerkleSumTree},
         types::F,
+        util::{pad_to_multiple_of, tree_node_num},
+        valuation::Valuation,
     };
     use plonky2_field::types::Field;
 
-    use super::{get_mst_siblings_index, MerkleProof, RecursiveHashes};
+    use super::{get_mst_siblings_index, MerkleProof};
 
     #[test]
     pub fn test_get_siblings_index() {
@@ -382,6 +489,7 @@ pub mod test {
             num_of_batches: 4,
             batch_size: 8,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 0;
@@ -394,6 +502,7 @@ pub mod test {
             num_of_batches: 8,
             batch_size: 8,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 0;
@@ -406,6 +515,7 @@ pub mod test {
             num_of_batches: 6,
             batch_size: 8,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 0;
@@ -421,6 +531,7 @@ pub mod test {
             num_of_batches: 15,
             batch_size: 4,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 0;
@@ -440,6 +551,7 @@ pub mod test {
             num_of_batches: 30,
             batch_size: 8,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 163;
@@ -459,6 +571,7 @@ pub mod test {
             num_of_batches: 6,
             batch_size: 4,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 20;
@@ -480,6 +593,7 @@ pub mod test {
             num_of_batches: 15,
             batch_size: 4,
             recursion_branchout_num: 4,
+            last_batch_size: None,
         });
 
         let global_index = 0;
@@ -498,172 +612,291 @@ pub mod test {
         );
     }
 
-    #[test]
-    pub fn test_verify_merkle_proof() {
-        let _gmst = GlobalMst::new(GlobalConfig {
-            num_of_tokens: 3,
-            num_of_batches: 4,
-            batch_size: 2,
-            recursion_branchout_num: 4,
-        });
+    /// Build a gmst over random accounts, persist it into a memory db and return the db together with the accounts.
+    pub fn build_gmst_db(
+        cfg: GlobalConfig,
+        valuation: &Valuation,
+    ) -> (GlobalMst, Arc<dyn PoRDB>, Vec<Account>) {
+        let mut gmst = GlobalMst::new(cfg);
+        let mut db: Box<dyn PoRDB> = Box::new(RwLock::new(PoRMemoryDB::new()));
+        let mut all_accounts = Vec::new();
+        let seed = SaltSeed::from_secret("test seed");
+
+        for batch_idx in 0..cfg.num_of_batches {
+            let mut accounts = gen_accounts_with_random_data(cfg.batch_size, cfg.num_of_tokens);
+            accounts.iter_mut().for_each(|acct| acct.set_salt_from_seed(&seed, 1));
+            let mst =
+                MerkleSumTree::new_tree_from_accounts(&accounts, LeafHashMode::PerToken, valuation);
+            for (i, node) in mst.merkle_sum_tree.iter().enumerate() {
+                gmst.set_batch_node(batch_idx, i, *node);
+            }
+            persist_account_id_to_gmst_pos(&mut db, &accounts, batch_idx * cfg.batch_size);
+            all_accounts.extend(accounts);
+        }
 
-        let equity = vec![3, 3, 3].iter().map(|x| F::from_canonical_u32(*x)).collect_vec();
-        let debt = vec![1, 1, 1].iter().map(|x| F::from_canonical_u32(*x)).collect_vec();
+        if let Some(last_batch_size) = cfg.last_batch_size {
+            let mut accounts = gen_accounts_with_random_data(last_batch_size, cfg.num_of_tokens);
+            accounts.iter_mut().for_each(|acct| acct.set_salt_from_seed(&seed, 1));
+            let mst =
+                MerkleSumTree::new_tree_from_accounts(&accounts, LeafHashMode::PerToken, valuation);
+            for (i, node) in mst.merkle_sum_tree.iter().enumerate() {
+                gmst.set_last_batch_node(i, *node);
+            }
+            persist_account_id_to_gmst_pos(&mut db, &accounts, cfg.num_of_batches * cfg.batch_size);
+            all_accounts.extend(accounts);
+        }
 
-        let sum_tree_siblings = vec![HashOut::from_vec(
-            vec![
-                7609058119952049295,
-                8895839458156070742,
-                1052773619972611009,
-                6038312163525827182,
-            ]
-            .iter()
-            .map(|x| F::from_canonical_u64(*x))
-            .collect::<Vec<F>>(),
-        )];
-
-        let recursive_tree_siblings = vec![RecursiveHashes {
-            left_hashes: vec![],
-            right_hashes: vec![
-                HashOut::from_vec(
-                    vec![
-                        15026394135096265436,
-                        13313300609834454638,
-                        10151802728958521275,
-                        6200471959130767555,
-                    ]
-                    .iter()
-                    .map(|x| F::from_canonical_u64(*x))
-                    .collect::<Vec<F>>(),
-                ),
-                HashOut::from_vec(
-                    vec![
-                        2010803994799996791,
-                        568450490466247075,
-                        18209684900543488748,
-                        7678193912819861368,
-                    ]
-                    .iter()
-                    .map(|x| F::from_canonical_u64(*x))
-                    .collect::<Vec<F>>(),
-                ),
-                HashOut::from_vec(
-                    vec![
-                        13089029781628355232,
-                        10704046654659337561,
-                        15794212269117984095,
-                        15948192230150472783,
-                    ]
-                    .iter()
-                    .map(|x| F::from_canonical_u64(*x))
-                    .collect::<Vec<F>>(),
-                ),
-            ],
-        }];
-
-        let account = Account {
-            id: "320b5ea99e653bc2b593db4130d10a4efd3a0b4cc2e1a6672b678d71dfbd33ad".to_string(),
-            equity: equity.clone(),
-            debt: debt.clone(),
-        };
+        let branchout_num = cfg.recursion_branchout_num;
+        let top_level = recursive_levels(cfg.num_of_batches, branchout_num);
+        let mut last_level_node_num = cfg.get_padded_batch_num();
+        for level in 1..=top_level {
+            let this_level_node_count = last_level_node_num / branchout_num;
+            for inner_idx in 0..this_level_node_count {
+                let children = (0..branchout_num)
+                    .map(|i| {
+                        gmst.get_node(GlobalMst::get_recursive_global_index(
+                            &gmst.cfg,
+                            level - 1,
+                            inner_idx * branchout_num + i,
+                        ))
+                        .unwrap()
+                    })
+                    .collect::<Vec<MerkleSumNode>>();
+                let parent = GlobalMst::get_recursive_parent(&children);
+                gmst.set_recursive_node(level, inner_idx, parent);
+            }
+            last_level_node_num = pad_to_multiple_of(this_level_node_count, branchout_num);
+        }
+        if let Some(last_batch_size) = cfg.last_batch_size {
+            let recursive_root =
+                gmst.get_node(GlobalMst::get_recursive_global_index(&cfg, top_level, 0)).unwrap();
+            let last_batch_root = gmst
+                .get_node(GlobalMst::get_last_batch_global_index(
+                    &cfg,
+                    tree_node_num(last_batch_size) - 1,
+                ))
+                .unwrap();
+            gmst.set_root_node(GlobalMst::get_recursive_parent(&[recursive_root, last_batch_root]));
+        }
+        assert!(gmst.is_integral());
+        gmst.persist(&mut db);
 
-        let merkle_proof =
-            MerkleProof { account, sum_tree_siblings, recursive_tree_siblings, index: 0 };
+        (gmst, Arc::from(db), all_accounts)
+    }
 
-        let root = HashOut::from_vec(
-            vec![
-                10628303359772907103,
-                7478459528589413745,
-                12007196562137971174,
-                2652030368197917032,
-            ]
-            .iter()
-            .map(|x| F::from_canonical_u64(*x))
-            .collect::<Vec<F>>(),
+    #[test]
+    pub fn test_verify_merkle_proof() {
+        let price_weighted = Valuation::new_price_weighted(vec![6_000_000, 250_000, 100]).unwrap();
+        for valuation in [Valuation::default(), price_weighted] {
+            let (gmst, db, accounts) = build_gmst_db(
+                GlobalConfig {
+                    num_of_tokens: 3,
+                    num_of_batches: 6,
+                    batch_size: 4,
+                    recursion_branchout_num: 4,
+                    last_batch_size: None,
+                },
+                &valuation,
+            );
+            let root = gmst.get_root().unwrap();
+
+            for account in accounts.iter() {
+                let merkle_proof =
+                    MerkleProof::new_from_account(account, db.clone(), &gmst.cfg).unwrap();
+                merkle_proof
+                    .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_verify_merkle_proof_last_batch() {
+        let valuation = Valuation::default();
+        // 5 batches of 3 accounts and a last batch of 2 accounts.
+        let (gmst, db, accounts) = build_gmst_db(
+            GlobalConfig {
+                num_of_tokens: 3,
+                num_of_batches: 5,
+                batch_size: 3,
+                recursion_branchout_num: 4,
+                last_batch_size: Some(2),
+            },
+            &valuation,
         );
+        assert_eq!(17, accounts.len());
 
-        let res = merkle_proof.verify_merkle_proof(root);
+        assert_eq!(get_mst_siblings_index(0, &gmst.cfg), vec![1, 21]);
+        assert_eq!(
+            get_recursive_siblings_index(0, &gmst.cfg).last().unwrap(),
+            &RecursiveIndex { left_indexes: vec![], right_indexes: vec![45] }
+        );
+        assert_eq!(get_mst_siblings_index(15, &gmst.cfg), vec![44]);
+        assert_eq!(
+            get_recursive_siblings_index(15, &gmst.cfg),
+            vec![RecursiveIndex { left_indexes: vec![42], right_indexes: vec![] }]
+        );
 
-        res.unwrap();
+        let root = gmst.get_root().unwrap();
+        for account in accounts.iter() {
+            let merkle_proof =
+                MerkleProof::new_from_account(account, db.clone(), &gmst.cfg).unwrap();
+            merkle_proof
+                .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+                .unwrap();
+        }
     }
 
     #[test]
-    pub fn test_json_merkle_proof() {
-        let _gmst = GlobalMst::new(GlobalConfig {
-            num_of_tokens: 3,
-            num_of_batches: 4,
-            batch_size: 2,
-            recursion_branchout_num: 4,
-        });
-
-        let equity = vec![0, 3, 3].iter().map(|x| F::from_canonical_u32(*x)).collect_vec();
-        let debt = vec![1, 1, 1].iter().map(|x| F::from_canonical_u32(*x)).collect_vec();
+    pub fn test_verify_merkle_proof_rejects_tampered_sums() {
+        let valuation = Valuation::default();
+        let (gmst, db, accounts) = build_gmst_db(
+            GlobalConfig {
+                num_of_tokens: 3,
+                num_of_batches: 6,
+                batch_size: 4,
+                recursion_branchout_num: 4,
+                last_batch_size: None,
+            },
+            &valuation,
+        );
+        let root = gmst.get_root().unwrap();
+        let merkle_proof = MerkleProof::new_from_account(&accounts[5], db, &gmst.cfg).unwrap();
+
+        // a root with a different total debt is rejected even though the hash matches.
+        let mut wrong_root = root;
+        wrong_root.sum_debt += F::ONE;
+        assert!(merkle_proof
+            .verify_merkle_proof(&wrong_root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+            .is_err());
+
+        // a sibling with modified sums no longer hashes to the root.
+        let mut tampered = merkle_proof.clone();
+        tampered.sum_tree_siblings[0].sum_debt += F::ONE;
+        assert!(tampered
+            .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+            .is_err());
+
+        // a sibling with a negative net balance is rejected.
+        let mut tampered = merkle_proof.clone();
+        tampered.recursive_tree_siblings[0].right_nodes[0].sum_debt =
+            tampered.recursive_tree_siblings[0].right_nodes[0].sum_equity + F::ONE;
+        assert!(tampered
+            .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+            .is_err());
+
+        // the account hash must be computed in the mode of the round.
+        assert!(merkle_proof
+            .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::Sum, &valuation)
+            .is_err());
+
+        // and with the valuation of the round.
+        let price_weighted = Valuation::new_price_weighted(vec![1, 2, 3]).unwrap();
+        assert!(merkle_proof
+            .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &price_weighted)
+            .is_err());
+    }
 
-        let sum_tree_siblings = vec![HashOut::from_vec(
-            vec![
-                7609058119952049295,
-                8895839458156070742,
-                1052773619972611009,
-                6038312163525827182,
-            ]
-            .iter()
-            .map(|x| F::from_canonical_u64(*x))
-            .collect::<Vec<F>>(),
-        )];
-
-        let recursive_tree_siblings = vec![RecursiveHashes {
-            left_hashes: vec![],
-            right_hashes: vec![
-                HashOut::from_vec(
-                    vec![
-                        15026394135096265436,
-                        13313300609834454638,
-                        10151802728958521275,
-                        6200471959130767555,
-                    ]
-                    .iter()
-                    .map(|x| F::from_canonical_u64(*x))
-                    .collect::<Vec<F>>(),
-                ),
-                HashOut::from_vec(
-                    vec![
-                        2010803994799996791,
-                        568450490466247075,
-                        18209684900543488748,
-                        7678193912819861368,
-                    ]
-                    .iter()
-                    .map(|x| F::from_canonical_u64(*x))
-                    .collect::<Vec<F>>(),
-                ),
-                HashOut::from_vec(
-                    vec![
-                        13089029781628355232,
-                        10704046654659337561,
-                        15794212269117984095,
-                        15948192230150472783,
-                    ]
-                    .iter()
-                    .map(|x| F::from_canonical_u64(*x))
-                    .collect::<Vec<F>>(),
-                ),
-            ],
-        }];
-
-        let account = Account {
-            id: "320b5ea99e653bc2b593db4130d10a4efd3a0b4cc2e1a6672b678d71dfbd33ad".to_string(),
-            equity: equity.clone(),
-            debt: debt.clone(),
-        };
+    #[test]
+    pub fn test_verify_merkle_proof_rejects_unexpected_path() {
+        let valuation = Valuation::default();
+        let (gmst, db, accounts) = build_gmst_db(
+            GlobalConfig {
+                num_of_tokens: 3,
+                num_of_batches: 5,
+                batch_size: 3,
+                recursion_branchout_num: 4,
+                last_batch_size: Some(2),
+            },
+            &valuation,
+        );
+        let merkle_proof = MerkleProof::new_from_account(&accounts[0], db, &gmst.cfg).unwrap();
+
+        // the path without the last batch root ends at the recursive root, whose hash and sums are consistent with the path.
+        let mut truncated = merkle_proof.clone();
+        truncated.recursive_tree_siblings.pop();
+        let recursive_root = gmst.get_node(42).unwrap();
+        assert!(truncated
+            .verify_merkle_proof(&recursive_root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+            .is_err());
+
+        // nor is the path of a full batch user accepted for a gmst of another shape.
+        let root = gmst.get_root().unwrap();
+        let cfg = GlobalConfig { num_of_batches: 4, ..gmst.cfg };
+        assert!(merkle_proof
+            .verify_merkle_proof(&root, &cfg, LeafHashMode::PerToken, &valuation)
+            .is_err());
+
+        // the user index must be within the gmst.
+        let mut out_of_range = merkle_proof.clone();
+        out_of_range.index = GlobalMst::get_num_of_leaves(&gmst.cfg);
+        assert!(out_of_range
+            .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+            .is_err());
+        merkle_proof
+            .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+            .unwrap();
+    }
 
-        let merkle_proof =
-            MerkleProof { account, sum_tree_siblings, recursive_tree_siblings, index: 0 };
+    #[test]
+    pub fn test_json_merkle_proof() {
+        let (gmst, db, accounts) = build_gmst_db(
+            GlobalConfig {
+                num_of_tokens: 3,
+                num_of_batches: 4,
+                batch_size: 2,
+                recursion_branchout_num: 4,
+                last_batch_size: None,
+            },
+            &Valuation::default(),
+        );
+        let merkle_proof = MerkleProof::new_from_account(&accounts[0], db, &gmst.cfg).unwrap();
 
         let json_string = serde_json::to_string(&merkle_proof).unwrap();
 
-        // Step 3: Deserialize the JSON string back into an `Account` instance
         let deserialized_merkle_proof: MerkleProof = serde_json::from_str(&json_string).unwrap();
         assert_eq!(merkle_proof.index, deserialized_merkle_proof.index);
         assert_eq!(merkle_proof.sum_tree_siblings, deserialized_merkle_proof.sum_tree_siblings);
+        assert_eq!(
+            merkle_proof.recursive_tree_siblings,
+            deserialized_merkle_proof.recursive_tree_siblings
+        );
+    }
+
+    #[test]
+    pub fn test_binary_merkle_proof() {
+        let valuation = Valuation::default();
+        let (gmst, db, accounts) = build_gmst_db(
+            GlobalConfig {
+                num_of_tokens: 3,
+                num_of_batches: 5,
+                batch_size: 3,
+                recursion_branchout_num: 4,
+                last_batch_size: Some(2),
+            },
+            &valuation,
+        );
+        let root = gmst.get_root().unwrap();
+
+        for account in accounts.iter() {
+            let merkle_proof =
+                MerkleProof::new_from_account(account, db.clone(), &gmst.cfg).unwrap();
+            let bytes = merkle_proof.to_bytes();
+            let decoded_merkle_proof = MerkleProof::from_bytes(&bytes).unwrap();
+            assert_eq!(merkle_proof.account, decoded_merkle_proof.account);
+            assert_eq!(merkle_proof.index, decoded_merkle_proof.index);
+            assert_eq!(merkle_proof.sum_tree_siblings, decoded_merkle_proof.sum_tree_siblings);
+            assert_eq!(
+                merkle_proof.recursive_tree_siblings,
+                decoded_merkle_proof.recursive_tree_siblings
+            );
+            decoded_merkle_proof
+                .verify_merkle_proof(&root, &gmst.cfg, LeafHashMode::PerToken, &valuation)
+                .unwrap();
+
+            // truncated or trailing bytes are rejected.
+            assert!(MerkleProof::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+            assert!(MerkleProof::from_bytes(&[bytes.as_slice(), &[0]].concat()).is_err());
+        }
     }
 }
@@ -5,18 +5,21 @@ use plonky2::{
         witness::{PartialWitness, WitnessWrite},
     },
     plonk::circuit_builder::CircuitBuilder,
+    util::serialization::{Buffer, IoError, IoResult, Read, Write},
 };
 
 use crate::{
-    account::Account,
-    circuit_utils::assert_non_negative_unsigned,
+    account::{Account, LeafHashMode},
+    circuit_utils::{assert_non_negative_unsigned, sum_non_negative_unsigned},
     types::{D, F},
+    valuation::Valuation,
 };
 
 #[derive(Debug, Clone)]
 /// Targets representing a users account, where their equity and debt are split into individual tokens.
 pub struct AccountTargets {
     pub id: [Target; 5],
+    pub salt: HashOutTarget,
     pub equity: Vec<Target>,
     pub debt: Vec<Target>,
 }
@@ -27,10 +30,11 @@ impl AccountTargets {
         builder: &mut CircuitBuilder<F, D>,
     ) -> AccountTargets {
         let id: [Target; 5] = std::array::from_fn(|_| builder.add_virtual_target());
+        let salt = builder.add_virtual_hash();
         let equity = builder.add_virtual_targets(account.equity.len());
         let debt = builder.add_virtual_targets(account.debt.len());
 
-        AccountTargets { id, equity, debt }
+        AccountTargets { id, salt, equity, debt }
     }
 
     pub fn set_account_targets(&self, account_info: &Account, pw: &mut PartialWitness<F>) {
@@ -40,48 +44,115 @@ impl AccountTargets {
         pw.set_target_arr(self.equity.as_slice(), account_info.equity.as_slice());
         pw.set_target_arr(self.debt.as_slice(), account_info.debt.as_slice());
         pw.set_target_arr(self.id.as_slice(), account_info.get_user_id_in_field().as_slice());
+        pw.set_hash_target(self.salt, account_info.salt);
+    }
+
+    pub fn serialize(&self, dst: &mut Vec<u8>) -> IoResult<()> {
+        dst.write_target_vec(&self.id)?;
+        dst.write_target_hash(&self.salt)?;
+        dst.write_target_vec(&self.equity)?;
+        dst.write_target_vec(&self.debt)
+    }
+
+    pub fn deserialize(src: &mut Buffer) -> IoResult<Self> {
+        let id: [Target; 5] = src.read_target_vec()?.try_into().map_err(|_| IoError)?;
+        let salt = src.read_target_hash()?;
+        let equity = src.read_target_vec()?;
+        let debt = src.read_target_vec()?;
+        Ok(AccountTargets { id, salt, equity, debt })
     }
 }
 
 #[derive(Debug, Clone)]
 /// Targets representing a users account, where their equity and liabilities are summed into 2 summed values.
+/// The sums are valued according to the `Valuation` of the round, e.g., in USD with a price snapshot.
+/// The per-token equity and debt are kept so that they can be summed per token in the merkle sum tree.
 pub struct AccountSumTargets {
     pub id: [Target; 5],
+    pub salt: HashOutTarget,
     pub sum_equity: Target,
     pub sum_debt: Target,
+    pub equity: Vec<Target>,
+    pub debt: Vec<Target>,
 }
 
 impl AccountSumTargets {
     /// Given Account Targets, sum the account equity and liabilities and return a AccountSumTargets.
     pub fn from_account_target(
         account: &AccountTargets,
+        valuation: &Valuation,
         builder: &mut CircuitBuilder<F, D>,
     ) -> AccountSumTargets {
-        let sum_equity = account.equity.iter().fold(builder.zero(), |x, y| builder.add(x, *y));
+        // Every token value, every valued token value and every running sum is constrained to 62 bits, such that neither sum
+        // can wrap around the field.
+        let valued_equity = valuation.value_token_targets(builder, &account.equity);
+        let sum_equity = sum_non_negative_unsigned(builder, &valued_equity);
 
-        let sum_debt = account.debt.iter().fold(builder.zero(), |x, y| builder.add(x, *y));
+        let sum_debt = valuation.value_targets(builder, &account.debt);
 
-        let diff_between_equity_debt = builder.sub(sum_equity, sum_debt);
+        // With haircuts, only the haircut equity counts as collateral against the debt.
+        let collateral =
+            valuation.collateral_targets(builder, &valued_equity).unwrap_or(sum_equity);
 
-        // Ensure the equity is greater than the debt. This works as long as we constrict our equity to 62 bits.
-        assert_non_negative_unsigned(builder, diff_between_equity_debt);
+        let diff_between_collateral_debt = builder.sub(collateral, sum_debt);
 
-        AccountSumTargets { id: account.id, sum_equity, sum_debt }
+        // Ensure the collateral is greater than the debt. This works as we constrict our collateral and debt to 62 bits.
+        assert_non_negative_unsigned(builder, diff_between_collateral_debt);
+
+        AccountSumTargets {
+            id: account.id,
+            salt: account.salt,
+            sum_equity,
+            sum_debt,
+            equity: account.equity.clone(),
+            debt: account.debt.clone(),
+        }
     }
 
-    /// Get account hash targets
-    pub fn get_account_hash_targets(&self, builder: &mut CircuitBuilder<F, D>) -> HashOutTarget {
+    /// Get account hash targets. The hash inputs must be consistent with `Account::get_hash`.
+    pub fn get_account_hash_targets(
+        &self,
+        builder: &mut CircuitBuilder<F, D>,
+        mode: LeafHashMode,
+    ) -> HashOutTarget {
         #[allow(clippy::useless_vec)]
-        let hash_inputs = vec![self.id.to_vec(), vec![self.sum_equity, self.sum_debt]].concat();
+        let mut hash_inputs = vec![
+            self.id.to_vec(),
+            self.salt.elements.to_vec(),
+            vec![self.sum_equity, self.sum_debt],
+        ]
+        .concat();
+        if mode == LeafHashMode::PerToken {
+            hash_inputs.extend(self.equity.iter());
+            hash_inputs.extend(self.debt.iter());
+        }
         builder.hash_n_to_hash_no_pad::<PoseidonHash>(hash_inputs)
     }
+
+    /// Get the user count of this account, i.e., 0 for a padding account whose user id is all zeros and 1 otherwise.
+    /// It must be consistent with `Account::is_padding`.
+    pub fn get_user_count_target(&self, builder: &mut CircuitBuilder<F, D>) -> Target {
+        let zero = builder.zero();
+        let mut is_padding = builder._true();
+        for id in self.id.iter() {
+            let is_zero = builder.is_equal(*id, zero);
+            is_padding = builder.and(is_padding, is_zero);
+        }
+        builder.not(is_padding).target
+    }
 }
 
 #[cfg(test)]
 pub mod test {
+    use plonky2::hash::hash_types::HashOut;
+    use plonky2_field::types::{Field, Field64};
+
     use crate::{
+        account::Account,
         circuit_utils::run_circuit_test,
         parser::{FileManager, JsonFileManager},
+        types::{F, MAX_POSITIVE_AMOUNT_LOG},
+        valuation::Valuation,
     };
 
     use super::{AccountSumTargets, AccountTargets};
@@ -111,8 +182,11 @@ pub mod test {
             let account_target =
                 AccountTargets::new_from_account(accounts.get(0).unwrap(), builder);
 
-            let account_sum_target =
-                AccountSumTargets::from_account_target(&account_target, builder);
+            let account_sum_target = AccountSumTargets::from_account_target(
+                &account_target,
+                &Valuation::default(),
+                builder,
+            );
 
             let total_equity =
                 account_target.equity.iter().fold(builder.zero(), |x, y| builder.add(x, *y));
@@ -126,6 +200,111 @@ pub mod test {
         });
     }
 
+    /// Build the account sum targets for an account with the given balances; the circuit must reject any wrapped balance.
+    fn run_account_sum_test(equity: Vec<u64>, debt: Vec<u64>, valuation: Valuation) {
+        run_circuit_test(|builder, pw| {
+            let account = Account {
+                id: "320b5ea99e653bc2b593db4130d10a4efd3a0b4cc2e1a6672b678d71dfbd33ad".to_string(),
+                equity: equity.iter().map(|x| F::from_canonical_u64(*x)).collect(),
+                debt: debt.iter().map(|x| F::from_canonical_u64(*x)).collect(),
+                salt: HashOut::default(),
+            };
+            let account_target = AccountTargets::new_from_account(&account, builder);
+            AccountSumTargets::from_account_target(&account_target, &valuation, builder);
+            account_target.set_account_targets(&account, pw);
+        });
+    }
+
+    #[test]
+    fn test_account_sum_target_max_amount() {
+        let max = (1 << MAX_POSITIVE_AMOUNT_LOG) - 1;
+        run_account_sum_test(vec![max - 1, 1], vec![max, 0], Valuation::default());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_account_sum_target_wrapped_equity_panic() {
+        // -1 + 10 = 9 equity against 5 debt would pass the non-negative check on the difference alone.
+        run_account_sum_test(vec![F::ORDER - 1, 10], vec![0, 5], Valuation::default());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_account_sum_target_wrapped_debt_panic() {
+        // a debt of -100 turns into a larger net balance.
+        run_account_sum_test(vec![1, 1], vec![F::ORDER - 100, 0], Valuation::default());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_account_sum_target_overflow_panic() {
+        // each token is within 62 bits, but the total equity is not.
+        let max = (1 << MAX_POSITIVE_AMOUNT_LOG) - 1;
+        run_account_sum_test(vec![max, max, max, max], vec![0, 0, 0, 0], Valuation::default());
+    }
+
+    #[test]
+    fn test_account_sum_target_price_weighted() {
+        // 1 BTC at 100 USD covers a debt of 50 ETH at 1 USD, although the raw amounts do not.
+        let valuation = Valuation::new_price_weighted(vec![100, 1]).unwrap();
+        run_account_sum_test(vec![1, 0], vec![0, 50], valuation);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_account_sum_target_price_weighted_negative_panic() {
+        // the same balances are negative in USD if ETH is the more valuable token.
+        let valuation = Valuation::new_price_weighted(vec![1, 100]).unwrap();
+        run_account_sum_test(vec![1, 0], vec![0, 50], valuation);
+    }
+
+    #[test]
+    fn test_account_sum_target_haircut() {
+        // half of the 100 USD of BTC covers a debt of 50 USD.
+        let valuation = Valuation::new_price_weighted(vec![100, 1])
+            .unwrap()
+            .with_haircuts(vec![1 << 15, 0])
+            .unwrap();
+        run_account_sum_test(vec![1, 0], vec![0, 50], valuation);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_account_sum_target_haircut_negative_panic() {
+        // the raw equity of 100 USD covers the debt, but the haircut equity of 50 USD does not.
+        let valuation = Valuation::new_price_weighted(vec![100, 1])
+            .unwrap()
+            .with_haircuts(vec![1 << 15, 0])
+            .unwrap();
+        run_account_sum_test(vec![1, 0], vec![0, 51], valuation);
+    }
+
+    #[test]
+    fn test_user_count_target() {
+        run_circuit_test(|builder, pw| {
+            let fm = FileManager {};
+            let path = "../../test-data/batch0.json";
+            let tokens = vec!["BTC".to_owned(), "ETH".to_owned()];
+            let user = fm.read_json_into_accounts_vec(path, &tokens).remove(0);
+            let padding = Account::get_empty_account(tokens.len());
+            assert!(!user.is_padding());
+            assert!(padding.is_padding());
+
+            for (account, count) in [(user, F::ONE), (padding, F::ZERO)] {
+                let account_target = AccountTargets::new_from_account(&account, builder);
+                let account_sum_target = AccountSumTargets::from_account_target(
+                    &account_target,
+                    &Valuation::default(),
+                    builder,
+                );
+                let user_count = account_sum_target.get_user_count_target(builder);
+                let expected = builder.constant(count);
+                builder.connect(user_count, expected);
+                account_target.set_account_targets(&account, pw);
+            }
+        });
+    }
+
     #[test]
     fn test_theory() {}
 }
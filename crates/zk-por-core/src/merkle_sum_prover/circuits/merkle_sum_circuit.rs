@@ -13,35 +13,66 @@ use plonky2::{
 use plonky2_field::types::Field;
 
 use crate::{
+    account::LeafHashMode,
     circuit_utils::assert_non_negative_unsigned,
     types::{C, D, F},
+    util::tree_level_sizes,
+    valuation::Valuation,
 };
 
 use super::account_circuit::{AccountSumTargets, AccountTargets};
 
 /// A node in the merkle sum tree, contains the total amount of equity (in usd) and the total amount of debt (in usd) and the hash.
 ///
-/// The hash is Hash(hash_left, hash_right).
+/// The hash is Hash(hash_left, sum_equity_left, sum_debt_left, hash_right, sum_equity_right, sum_debt_right), such that
+/// the sums of the children are bound to the hash a user recomputes in the merkle proof.
 ///
 /// The amount of equity and amount of debt is the sum of the equity and debt of the children.
-#[derive(Debug, Copy, Clone)]
+///
+/// `token_equity` and `token_debt` carry the same sums broken down per token, so that the root proof can expose the liabilities of each asset.
+///
+/// `user_count` is the number of real users under this node, where padding accounts are counted as zero.
+#[derive(Debug, Clone)]
 pub struct MerkleSumNodeTarget {
     pub sum_equity: Target,
     pub sum_debt: Target,
     pub hash: HashOutTarget,
+    pub token_equity: Vec<Target>,
+    pub token_debt: Vec<Target>,
+    pub user_count: Target,
 }
 
 impl MerkleSumNodeTarget {
+    /// The empty node padding a level with an odd number of nodes, i.e., zero hash, zero sums and no user.
+    /// It must be consistent with `MerkleSumNode::default()`.
+    pub fn new_empty(builder: &mut CircuitBuilder<F, D>, token_num: usize) -> MerkleSumNodeTarget {
+        let zero = builder.zero();
+        MerkleSumNodeTarget {
+            sum_equity: zero,
+            sum_debt: zero,
+            hash: HashOutTarget::from_vec(vec![zero; NUM_HASH_OUT_ELTS]),
+            token_equity: vec![zero; token_num],
+            token_debt: vec![zero; token_num],
+            user_count: zero,
+        }
+    }
+
     /// Given children nodes, generate the MerkleSumNodeTarget
-    pub fn get_parent_from_children<const N: usize>(
+    pub fn get_parent_from_children(
         builder: &mut CircuitBuilder<F, D>,
         children: &[MerkleSumNodeTarget],
     ) -> MerkleSumNodeTarget {
-        assert_eq!(N, children.len());
+        assert!(!children.is_empty());
+        let token_num = children[0].token_equity.len();
         let mut sum_equity = builder.constant(F::ZERO);
         let mut sum_debt = builder.constant(F::ZERO);
+        let mut token_equity = vec![builder.zero(); token_num];
+        let mut token_debt = vec![builder.zero(); token_num];
+        let mut user_count = builder.zero();
         let mut hash_inputs = Vec::new();
         children.iter().for_each(|child| {
+            assert_eq!(token_num, child.token_equity.len());
+            assert_eq!(token_num, child.token_debt.len());
             sum_equity = builder.add(sum_equity, child.sum_equity);
             sum_debt = builder.add(sum_debt, child.sum_debt);
 
@@ -56,22 +87,43 @@ impl MerkleSumNodeTarget {
             let diff_between_debt_child_and_sum = builder.sub(sum_debt, child.sum_debt);
             assert_non_negative_unsigned(builder, diff_between_debt_child_and_sum);
 
+            // The per-token sums are not range-checked, as they cannot wrap around the field either: every token amount of a leaf
+            // is range-checked to be non-negative, and is valued into `sum_equity` and `sum_debt` at a price of at least 1, i.e.,
+            // the raw amount without a price snapshot. Haircuts only apply to the collateral of a user, not to these sums. Hence the
+            // per-token sums of a node are at most its `sum_equity` and `sum_debt`, which are range-checked above. They add up to
+            // `sum_equity` and `sum_debt` only without a price snapshot.
+            for i in 0..token_num {
+                token_equity[i] = builder.add(token_equity[i], child.token_equity[i]);
+                token_debt[i] = builder.add(token_debt[i], child.token_debt[i]);
+            }
+
+            // The user count is bounded by the number of leaves, hence it cannot wrap around the field.
+            user_count = builder.add(user_count, child.user_count);
+
+            // must be consistent with `MerkleSumNode::get_hash_inputs`
             hash_inputs.extend(child.hash.elements.iter());
+            hash_inputs.push(child.sum_equity);
+            hash_inputs.push(child.sum_debt);
         });
         let hash = builder.hash_n_to_hash_no_pad::<PoseidonHash>(hash_inputs);
-        MerkleSumNodeTarget { sum_equity, sum_debt, hash }
+        MerkleSumNodeTarget { sum_equity, sum_debt, hash, token_equity, token_debt, user_count }
     }
 
     /// Get a merkle sum node target from account sum targets.
     pub fn get_node_from_account_targets(
         builder: &mut CircuitBuilder<F, D>,
         account_targets: &AccountSumTargets,
+        leaf_hash_mode: LeafHashMode,
     ) -> MerkleSumNodeTarget {
-        let hash = account_targets.get_account_hash_targets(builder);
+        let hash = account_targets.get_account_hash_targets(builder, leaf_hash_mode);
+        let user_count = account_targets.get_user_count_target(builder);
         MerkleSumNodeTarget {
             sum_equity: account_targets.sum_equity,
             sum_debt: account_targets.sum_debt,
             hash,
+            token_equity: account_targets.equity.clone(),
+            token_debt: account_targets.debt.clone(),
+            user_count,
         }
     }
 
@@ -90,18 +142,50 @@ impl MerkleSumNodeTarget {
         2..6
     }
 
+    /// the per-token equity sums follow the root hash.
+    pub fn pub_input_token_equity_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = Self::pub_input_root_hash_offset().end;
+        start..start + token_num
+    }
+
+    /// the per-token debt sums follow the per-token equity sums.
+    pub fn pub_input_token_debt_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = Self::pub_input_token_equity_offset(token_num).end;
+        start..start + token_num
+    }
+
+    /// the user count follows the per-token debt sums.
+    pub fn pub_input_user_count_offset(token_num: usize) -> usize {
+        Self::pub_input_token_debt_offset(token_num).end
+    }
+
+    /// the number of public inputs registered by a node.
+    pub fn num_public_inputs(token_num: usize) -> usize {
+        Self::pub_input_user_count_offset(token_num) + 1
+    }
+
     /// Register this node targets as a public input
     pub fn register_as_public_input(&self, builder: &mut CircuitBuilder<F, D>) {
         builder.register_public_input(self.sum_equity);
         builder.register_public_input(self.sum_debt);
         builder.register_public_inputs(&self.hash.elements);
+        builder.register_public_inputs(&self.token_equity);
+        builder.register_public_inputs(&self.token_debt);
+        builder.register_public_input(self.user_count);
     }
 }
 
 impl From<MerkleSumNodeTarget> for Vec<Target> {
     fn from(node: MerkleSumNodeTarget) -> Vec<Target> {
         #[allow(clippy::useless_vec)]
-        vec![vec![node.sum_equity, node.sum_debt], node.hash.elements.to_vec()].concat()
+        vec![
+            vec![node.sum_equity, node.sum_debt],
+            node.hash.elements.to_vec(),
+            node.token_equity,
+            node.token_debt,
+            vec![node.user_count],
+        ]
+        .concat()
     }
 }
 
@@ -113,10 +197,19 @@ impl From<Vec<Target>> for MerkleSumNodeTarget {
         let sum_debt_target = iter.next().unwrap();
         let hash_target = HashOutTarget::from_vec(iter.by_ref().take(NUM_HASH_OUT_ELTS).collect());
 
+        // the remaining targets are the per-token equity sums followed by the per-token debt sums, and the user count.
+        let mut token_equity: Vec<Target> = iter.collect();
+        let user_count_target = token_equity.pop().unwrap();
+        assert_eq!(token_equity.len() % 2, 0, "per-token sums must come in equity/debt pairs");
+        let token_debt = token_equity.split_off(token_equity.len() / 2);
+
         MerkleSumNodeTarget {
             sum_equity: sum_equity_target,
             sum_debt: sum_debt_target,
             hash: hash_target,
+            token_equity,
+            token_debt,
+            user_count: user_count_target,
         }
     }
 }
@@ -130,29 +223,46 @@ impl MerkleSumTreeTarget {
         self.sum_tree.last().unwrap()
     }
 
-    /// Register the root hash, sum_equity and sum_debt as public inputs to be used in recursive proving.
-    pub fn register_public_inputs(&self, builder: &mut CircuitBuilder<F, D>) {
+    /// Register the root hash, sum_equity and sum_debt as public inputs to be used in recursive proving, followed by the valuation
+    /// of the round.
+    pub fn register_public_inputs(
+        &self,
+        builder: &mut CircuitBuilder<F, D>,
+        valuation: &Valuation,
+    ) {
         let root = self.get_root();
         root.register_as_public_input(builder);
+        valuation.register_public_inputs(builder);
     }
 
     /// Builds a merkle sum tree of a given size (based on the number of leaves). It will build the merkle sum tree on top of the leaves vector
     /// in order to do the task in place. There is no return value as the input leaves vector is mutated.
+    ///
+    /// The number of leaves needs not be a power of 2, in which case a level with an odd number of nodes is padded with an empty node,
+    /// consistent with `MerkleSumTree::new_tree_from_accounts`.
     pub fn build_merkle_sum_tree(
         builder: &mut CircuitBuilder<F, D>,
         leaves: &mut Vec<MerkleSumNodeTarget>,
     ) {
-        let num_leaves = leaves.len();
-
-        for i in num_leaves..(num_leaves * 2 - 1) {
-            let left_child_index = 2 * (i - num_leaves);
-            let right_child_index = 2 * (i - num_leaves) + 1;
-            let left_child = leaves.get(left_child_index).unwrap();
-            let right_child = leaves.get(right_child_index).unwrap();
-            leaves.push(MerkleSumNodeTarget::get_parent_from_children::<2>(
-                builder,
-                &vec![*left_child, *right_child],
-            ));
+        let token_num = leaves[0].token_equity.len();
+        let mut level_start = 0;
+        for level_size in tree_level_sizes(leaves.len()) {
+            if leaves.len() < level_start + level_size {
+                let empty_node = MerkleSumNodeTarget::new_empty(builder, token_num);
+                leaves.resize(level_start + level_size, empty_node);
+            }
+            if level_size == 1 {
+                break;
+            }
+            for i in (level_start..level_start + level_size).step_by(2) {
+                let left_child = leaves.get(i).unwrap().clone();
+                let right_child = leaves.get(i + 1).unwrap().clone();
+                leaves.push(MerkleSumNodeTarget::get_parent_from_children(
+                    builder,
+                    &vec![left_child, right_child],
+                ));
+            }
+            level_start += level_size;
         }
     }
 
@@ -160,17 +270,19 @@ impl MerkleSumTreeTarget {
     pub fn build_new_from_account_targets(
         builder: &mut CircuitBuilder<F, D>,
         accounts: &mut [AccountSumTargets],
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
     ) -> MerkleSumTreeTarget {
         let mut leaves: Vec<MerkleSumNodeTarget> = accounts
             .iter()
-            .map(|x| MerkleSumNodeTarget::get_node_from_account_targets(builder, x))
+            .map(|x| MerkleSumNodeTarget::get_node_from_account_targets(builder, x, leaf_hash_mode))
             .collect();
 
         MerkleSumTreeTarget::build_merkle_sum_tree(builder, &mut leaves);
 
         let tree = MerkleSumTreeTarget { sum_tree: leaves };
 
-        tree.register_public_inputs(builder);
+        tree.register_public_inputs(builder, valuation);
 
         tree
     }
@@ -179,26 +291,34 @@ impl MerkleSumTreeTarget {
 pub fn build_merkle_sum_tree_circuit(
     num_of_leaves: usize,
     token_num: usize,
+    leaf_hash_mode: LeafHashMode,
+    valuation: &Valuation,
     config: CircuitConfig,
 ) -> (CircuitData<F, C, D>, Vec<AccountTargets>) {
-    // assert num_of_leaves is a power of 2
-    assert!(num_of_leaves.is_power_of_two(), "num_of_leaves must be a power of 2.");
+    assert!(num_of_leaves > 0, "num_of_leaves must be positive.");
 
     let mut builder = CircuitBuilder::<F, D>::new(config);
     let mut account_targets: Vec<AccountTargets> = Vec::new();
     (0..num_of_leaves).for_each(|_| {
         let id: [Target; 5] = std::array::from_fn(|_| builder.add_virtual_target());
+        let salt = builder.add_virtual_hash();
         let equity_targets = builder.add_virtual_targets(token_num);
         let debt_targets = builder.add_virtual_targets(token_num);
-        let account_target = AccountTargets { id, equity: equity_targets, debt: debt_targets };
+        let account_target =
+            AccountTargets { id, salt, equity: equity_targets, debt: debt_targets };
         account_targets.push(account_target);
     });
     let mut account_sum_targets: Vec<AccountSumTargets> = account_targets
         .iter()
-        .map(|x| AccountSumTargets::from_account_target(x, &mut builder))
+        .map(|x| AccountSumTargets::from_account_target(x, valuation, &mut builder))
         .collect();
 
-    _ = MerkleSumTreeTarget::build_new_from_account_targets(&mut builder, &mut account_sum_targets);
+    _ = MerkleSumTreeTarget::build_new_from_account_targets(
+        &mut builder,
+        &mut account_sum_targets,
+        leaf_hash_mode,
+        valuation,
+    );
     let circuit_data = builder.build::<C>();
     (circuit_data, account_targets)
 }
@@ -206,9 +326,11 @@ pub fn build_merkle_sum_tree_circuit(
 #[cfg(test)]
 pub mod test {
     use crate::{
+        account::LeafHashMode,
         circuit_utils::run_circuit_test,
         merkle_sum_prover::circuits::account_circuit::{AccountSumTargets, AccountTargets},
         parser::{FileManager, JsonFileManager},
+        valuation::Valuation,
     };
 
     use super::MerkleSumNodeTarget;
@@ -226,17 +348,29 @@ pub mod test {
             let account_target_2 =
                 AccountTargets::new_from_account(accounts.get(1).unwrap(), builder);
 
-            let account_sum_target_1 =
-                AccountSumTargets::from_account_target(&account_target_1, builder);
-            let account_sum_target_2 =
-                AccountSumTargets::from_account_target(&account_target_2, builder);
+            let account_sum_target_1 = AccountSumTargets::from_account_target(
+                &account_target_1,
+                &Valuation::default(),
+                builder,
+            );
+            let account_sum_target_2 = AccountSumTargets::from_account_target(
+                &account_target_2,
+                &Valuation::default(),
+                builder,
+            );
 
-            let merkle_sum_node_target_1 =
-                MerkleSumNodeTarget::get_node_from_account_targets(builder, &account_sum_target_1);
-            let merkle_sum_node_target_2 =
-                MerkleSumNodeTarget::get_node_from_account_targets(builder, &account_sum_target_2);
+            let merkle_sum_node_target_1 = MerkleSumNodeTarget::get_node_from_account_targets(
+                builder,
+                &account_sum_target_1,
+                LeafHashMode::PerToken,
+            );
+            let merkle_sum_node_target_2 = MerkleSumNodeTarget::get_node_from_account_targets(
+                builder,
+                &account_sum_target_2,
+                LeafHashMode::PerToken,
+            );
 
-            let merkle_sum_node_target_3 = MerkleSumNodeTarget::get_parent_from_children::<2>(
+            let merkle_sum_node_target_3 = MerkleSumNodeTarget::get_parent_from_children(
                 builder,
                 &vec![merkle_sum_node_target_1, merkle_sum_node_target_2],
             );
@@ -249,6 +383,14 @@ pub mod test {
             builder.connect(merkle_sum_node_target_3.sum_equity, sum_equity);
             builder.connect(merkle_sum_node_target_3.sum_debt, sum_debt);
 
+            for i in 0..tokens.len() {
+                let token_equity =
+                    builder.add(account_target_1.equity[i], account_target_2.equity[i]);
+                let token_debt = builder.add(account_target_1.debt[i], account_target_2.debt[i]);
+                builder.connect(merkle_sum_node_target_3.token_equity[i], token_equity);
+                builder.connect(merkle_sum_node_target_3.token_debt[i], token_debt);
+            }
+
             account_target_1.set_account_targets(accounts.get(0).unwrap(), pw);
             account_target_2.set_account_targets(accounts.get(1).unwrap(), pw);
         });
@@ -1,9 +1,10 @@
 use crate::{
-    account::Account,
+    account::{Account, LeafHashMode},
     circuit_config::STANDARD_CONFIG,
     circuit_utils::prove_timing,
     merkle_sum_prover::circuits::account_circuit::{AccountSumTargets, AccountTargets},
     types::{C, D, F},
+    valuation::Valuation,
 };
 use plonky2::{
     iop::witness::PartialWitness,
@@ -41,6 +42,8 @@ impl MerkleSumTreeProver {
     pub fn build_merkle_tree_targets(
         &self,
         builder: &mut CircuitBuilder<F, D>,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
     ) -> Vec<AccountTargets> {
         let mut account_targets: Vec<AccountTargets> = Vec::new();
 
@@ -54,23 +57,32 @@ impl MerkleSumTreeProver {
 
         let mut account_sum_targets: Vec<AccountSumTargets> = account_targets
             .iter()
-            .map(|x| AccountSumTargets::from_account_target(x, builder))
+            .map(|x| AccountSumTargets::from_account_target(x, valuation, builder))
             .collect();
 
         // build merkle sum tree
-        let _merkle_tree_targets =
-            MerkleSumTreeTarget::build_new_from_account_targets(builder, &mut account_sum_targets);
+        let _merkle_tree_targets = MerkleSumTreeTarget::build_new_from_account_targets(
+            builder,
+            &mut account_sum_targets,
+            leaf_hash_mode,
+            valuation,
+        );
 
         account_targets
     }
 
     /// Get the merkle sum tree proof of this batch of accounts.
-    pub fn get_proof(&self) -> ProofWithPublicInputs<F, C, D> {
+    pub fn get_proof(
+        &self,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+    ) -> ProofWithPublicInputs<F, C, D> {
         let mut builder = CircuitBuilder::<F, D>::new(STANDARD_CONFIG);
         let mut pw = PartialWitness::<F>::new();
 
         // Build and set merkle tree targets
-        let account_targets = self.build_merkle_tree_targets(&mut builder);
+        let account_targets =
+            self.build_merkle_tree_targets(&mut builder, leaf_hash_mode, valuation);
         self.set_merkle_tree_targets(&mut pw, &account_targets);
 
         builder.print_gate_counts(0);
@@ -142,12 +154,15 @@ impl MerkleSumTreeProver {
     /// Get the merkle sum tree proof of this batch of accounts and the circuit data of the corresponding proof.
     pub fn get_proof_and_circuit_data(
         &self,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
     ) -> (ProofWithPublicInputs<F, C, D>, CircuitData<F, C, D>) {
         let mut builder = CircuitBuilder::<F, D>::new(STANDARD_CONFIG);
         let mut pw = PartialWitness::<F>::new();
 
         // Build and set merkle tree targets
-        let account_targets = self.build_merkle_tree_targets(&mut builder);
+        let account_targets =
+            self.build_merkle_tree_targets(&mut builder, leaf_hash_mode, valuation);
         self.set_merkle_tree_targets(&mut pw, &account_targets);
 
         builder.print_gate_counts(0);
@@ -183,8 +198,10 @@ impl MerkleSumTreeProver {
 #[cfg(test)]
 pub mod test {
     use crate::{
+        account::LeafHashMode,
         circuit_utils::run_circuit_test,
         parser::{FileManager, JsonFileManager},
+        valuation::Valuation,
     };
 
     use super::MerkleSumTreeProver;
@@ -203,7 +220,11 @@ pub mod test {
             };
 
             // Build and set merkle tree targets
-            let account_targets = prover.build_merkle_tree_targets(builder);
+            let account_targets = prover.build_merkle_tree_targets(
+                builder,
+                LeafHashMode::PerToken,
+                &Valuation::default(),
+            );
             prover.set_merkle_tree_targets(pw, &account_targets);
         });
     }
@@ -219,6 +240,6 @@ pub mod test {
             accounts,
         };
 
-        let _proof = prover.get_proof();
+        let _proof = prover.get_proof(LeafHashMode::PerToken, &Valuation::default());
     }
 }
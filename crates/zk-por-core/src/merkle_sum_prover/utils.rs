@@ -5,14 +5,13 @@ use plonky2::{
     },
     plonk::config::Hasher,
 };
-use plonky2_field::extension::Extendable;
 
-pub fn hash_2_subhashes<F: RichField + Extendable<D>, const D: usize>(
-    hash_1: &HashOut<F>,
-    hash_2: &HashOut<F>,
-) -> HashOut<F> {
+use crate::{merkle_sum_tree::MerkleSumNode, types::F};
+
+/// Hash of a parent node in the batch tree, i.e., H(left.hash, left.sum_equity, left.sum_debt, right.hash, right.sum_equity, right.sum_debt).
+pub fn hash_2_subnodes(left: &MerkleSumNode, right: &MerkleSumNode) -> HashOut<F> {
     #[allow(clippy::useless_vec)]
-    let inputs = vec![hash_1.elements.to_vec(), hash_2.elements.to_vec()].concat();
+    let inputs = vec![left.get_hash_inputs(), right.get_hash_inputs()].concat();
     hash_inputs(inputs)
 }
 
@@ -1,14 +1,22 @@
-use plonky2::{hash::hash_types::HashOut, util::log2_strict};
+use plonky2::{hash::hash_types::HashOut, plonk::config::GenericHashOut};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use crate::{
-    account::Account,
-    merkle_sum_prover::utils::hash_2_subhashes,
-    types::{D, F},
+    account::{Account, LeafHashMode},
+    merkle_sum_prover::{
+        circuits::merkle_sum_circuit::MerkleSumNodeTarget, utils::hash_2_subnodes,
+    },
+    types::F,
+    util::tree_level_sizes,
+    valuation::Valuation,
 };
 
-use plonky2_field::types::Field;
+use plonky2_field::types::{Field, PrimeField64};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// The byte length of a serialized merkle sum node, i.e., 32 bytes of hash followed by the equity and debt as u64.
+pub const MERKLE_SUM_NODE_BYTES: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct MerkleSumNode {
     pub sum_equity: F,
     pub sum_debt: F,
@@ -17,25 +25,103 @@ pub struct MerkleSumNode {
 
 impl MerkleSumNode {
     /// Get a new merkle sum node given a account.
-    pub fn new_from_account(account: &Account) -> MerkleSumNode {
-        let sum_equity = account.equity.iter().fold(F::ZERO, |acc, x| acc + *x);
-
-        let sum_debt = account.debt.iter().fold(F::ZERO, |acc, x| acc + *x);
+    pub fn new_from_account(
+        account: &Account,
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+    ) -> MerkleSumNode {
+        let (sum_equity, sum_debt) = account.get_sums(valuation);
 
-        let hash = account.get_hash();
+        let hash = account.get_hash(leaf_hash_mode, valuation);
         MerkleSumNode { hash, sum_equity, sum_debt }
     }
 
     /// Get a new MerkleSumNode given its 2 child nodes.
     pub fn new_from_children_nodes(node1: &MerkleSumNode, node2: &MerkleSumNode) -> MerkleSumNode {
-        let hash = hash_2_subhashes::<F, D>(&node1.hash, &node2.hash);
+        let hash = hash_2_subnodes(node1, node2);
         let sum_equity = node1.sum_equity + node2.sum_equity;
         let sum_debt = node1.sum_debt + node2.sum_debt;
         MerkleSumNode { hash, sum_equity, sum_debt }
     }
+
+    /// Get the merkle sum node exposed in the public inputs of a batch or recursive proof.
+    pub fn new_from_public_inputs(public_inputs: &[F]) -> MerkleSumNode {
+        let hash_offset = MerkleSumNodeTarget::pub_input_root_hash_offset();
+        MerkleSumNode {
+            sum_equity: public_inputs[MerkleSumNodeTarget::pub_input_equity_offset()],
+            sum_debt: public_inputs[MerkleSumNodeTarget::pub_input_debt_offset()],
+            hash: HashOut::<F>::from_partial(&public_inputs[hash_offset]),
+        }
+    }
+
+    /// The elements of this node that are hashed into its parent, i.e., hash || sum_equity || sum_debt.
+    /// It must be consistent with `MerkleSumNodeTarget::get_parent_from_children`.
+    pub fn get_hash_inputs(&self) -> Vec<F> {
+        #[allow(clippy::useless_vec)]
+        vec![self.hash.elements.to_vec(), vec![self.sum_equity, self.sum_debt]].concat()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.hash.to_bytes();
+        bytes.extend(self.sum_equity.to_canonical_u64().to_le_bytes());
+        bytes.extend(self.sum_debt.to_canonical_u64().to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> MerkleSumNode {
+        assert_eq!(bytes.len(), MERKLE_SUM_NODE_BYTES);
+        let hash = HashOut::<F>::from_bytes(&bytes[0..32]);
+        let sum_equity =
+            F::from_canonical_u64(u64::from_le_bytes(bytes[32..40].try_into().unwrap()));
+        let sum_debt = F::from_canonical_u64(u64::from_le_bytes(bytes[40..48].try_into().unwrap()));
+        MerkleSumNode { sum_equity, sum_debt, hash }
+    }
 }
 
-/// Struct representing a merkle sum tree, it is represented as a vector of Merkle Sum Nodes.
+impl Serialize for MerkleSumNode {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("MerkleSumNode", 3)?;
+        state.serialize_field("hash", &hex::encode(self.hash.to_bytes()))?;
+        state.serialize_field("sum_equity", &self.sum_equity.to_canonical_u64().to_string())?;
+        state.serialize_field("sum_debt", &self.sum_debt.to_canonical_u64().to_string())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for MerkleSumNode {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct InnerMerkleSumNode {
+            hash: String,
+            sum_equity: String,
+            sum_debt: String,
+        }
+
+        let helper = InnerMerkleSumNode::deserialize(deserializer)?;
+        let hash_bytes = hex::decode(&helper.hash).map_err(serde::de::Error::custom)?;
+        if hash_bytes.len() != 32 {
+            return Err(serde::de::Error::custom("invalid hash length"));
+        }
+        let sum_equity = helper.sum_equity.parse::<u64>().map_err(serde::de::Error::custom)?;
+        let sum_debt = helper.sum_debt.parse::<u64>().map_err(serde::de::Error::custom)?;
+
+        Ok(MerkleSumNode {
+            hash: HashOut::from_bytes(&hash_bytes),
+            sum_equity: F::from_canonical_u64(sum_equity),
+            sum_debt: F::from_canonical_u64(sum_debt),
+        })
+    }
+}
+
+/// Struct representing a merkle sum tree, it is represented as a vector of Merkle Sum Nodes, level by level from the leaves.
+/// The number of leaves needs not be a power of 2, see `tree_level_sizes` for the padding of each level.
 #[derive(Debug, Clone)]
 pub struct MerkleSumTree {
     pub merkle_sum_tree: Vec<MerkleSumNode>,
@@ -43,23 +129,33 @@ pub struct MerkleSumTree {
 }
 
 impl MerkleSumTree {
-    pub fn new_tree_from_accounts(accounts: &[Account]) -> MerkleSumTree {
-        let num_leaves = accounts.len();
-        let tree_depth = log2_strict(num_leaves);
-        let mut merkle_sum_tree: Vec<MerkleSumNode> = Vec::new();
-
-        for i in 0..num_leaves * 2 - 1 {
-            if i < num_leaves {
-                let acct = accounts.get(i).unwrap();
-                merkle_sum_tree.push(MerkleSumNode::new_from_account(acct));
-            } else {
-                let left_child_index = 2 * (i - num_leaves);
-                let right_child_index = 2 * (i - num_leaves) + 1;
-                let left_child = merkle_sum_tree.get(left_child_index).unwrap();
-                let right_child = merkle_sum_tree.get(right_child_index).unwrap();
-                let node = MerkleSumNode::new_from_children_nodes(left_child, right_child);
+    pub fn new_tree_from_accounts(
+        accounts: &[Account],
+        leaf_hash_mode: LeafHashMode,
+        valuation: &Valuation,
+    ) -> MerkleSumTree {
+        let level_sizes = tree_level_sizes(accounts.len());
+        let tree_depth = level_sizes.len() - 1;
+        let mut merkle_sum_tree: Vec<MerkleSumNode> = accounts
+            .iter()
+            .map(|acct| MerkleSumNode::new_from_account(acct, leaf_hash_mode, valuation))
+            .collect();
+
+        let mut level_start = 0;
+        for level_size in level_sizes {
+            // pad a level with an odd number of nodes with an empty node.
+            merkle_sum_tree.resize(level_start + level_size, MerkleSumNode::default());
+            if level_size == 1 {
+                break;
+            }
+            for i in (level_start..level_start + level_size).step_by(2) {
+                let node = MerkleSumNode::new_from_children_nodes(
+                    &merkle_sum_tree[i],
+                    &merkle_sum_tree[i + 1],
+                );
                 merkle_sum_tree.push(node);
             }
+            level_start += level_size;
         }
 
         MerkleSumTree { merkle_sum_tree, tree_depth }
@@ -77,16 +173,16 @@ impl MerkleSumTree {
 #[cfg(test)]
 pub mod test {
     use crate::{
-        account::gen_accounts_with_random_data,
+        account::{gen_accounts_with_random_data, LeafHashMode},
         circuit_config::STANDARD_CONFIG,
         merkle_sum_prover::{
-            circuits::merkle_sum_circuit::{build_merkle_sum_tree_circuit, MerkleSumNodeTarget},
+            circuits::merkle_sum_circuit::build_merkle_sum_tree_circuit,
             prover::MerkleSumTreeProver,
         },
         parser::{FileManager, JsonFileManager},
         types::F,
+        valuation::Valuation,
     };
-    use plonky2::hash::hash_types::HashOut;
     use plonky2_field::types::Field;
 
     use super::{MerkleSumNode, MerkleSumTree};
@@ -99,7 +195,8 @@ pub mod test {
         let accounts = fm.read_json_into_accounts_vec(path, &tokens);
 
         let account = accounts.get(0).unwrap();
-        let node = MerkleSumNode::new_from_account(account);
+        let node =
+            MerkleSumNode::new_from_account(account, LeafHashMode::PerToken, &Valuation::default());
         let btc_amount = 9195990;
         let eth_amount = 0;
         assert_eq!(node.sum_equity, F::from_canonical_u64(btc_amount + eth_amount));
@@ -114,14 +211,22 @@ pub mod test {
         let accounts = fm.read_json_into_accounts_vec(path, &tokens);
 
         let account1 = accounts.get(0).unwrap();
-        let node1 = MerkleSumNode::new_from_account(account1);
+        let node1 = MerkleSumNode::new_from_account(
+            account1,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+        );
         let acc1_btc_amount = 9195990;
         let acc1_eth_amount = 0;
         let acc2_btc_amount = 1729750;
         let acc2_eth_amount = 0;
 
         let account2 = accounts.get(1).unwrap();
-        let node2 = MerkleSumNode::new_from_account(account2);
+        let node2 = MerkleSumNode::new_from_account(
+            account2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+        );
         let node3 = MerkleSumNode::new_from_children_nodes(&node1, &node2);
         assert_eq!(
             node3.sum_equity,
@@ -152,7 +257,11 @@ pub mod test {
             });
         }
 
-        let tree = MerkleSumTree::new_tree_from_accounts(&accounts);
+        let tree = MerkleSumTree::new_tree_from_accounts(
+            &accounts,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+        );
 
         let root = tree.get_root();
         assert_eq!(root.sum_equity, sum_equity);
@@ -164,17 +273,75 @@ pub mod test {
         let batch_num = 4;
         let num_assets = 4;
         let accounts = gen_accounts_with_random_data(4, num_assets);
+        let price_weighted =
+            Valuation::new_price_weighted(vec![6_000_000, 250_000, 100, 1]).unwrap();
+
+        for (leaf_hash_mode, valuation) in [
+            (LeafHashMode::Sum, Valuation::default()),
+            (LeafHashMode::PerToken, Valuation::default()),
+            (LeafHashMode::PerToken, price_weighted.clone()),
+        ] {
+            let merkle_sum_tree =
+                MerkleSumTree::new_tree_from_accounts(&accounts, leaf_hash_mode, &valuation);
+
+            let (batch_circuit, account_targets) = build_merkle_sum_tree_circuit(
+                batch_num,
+                num_assets,
+                leaf_hash_mode,
+                &valuation,
+                STANDARD_CONFIG,
+            );
 
-        let merkle_sum_tree = MerkleSumTree::new_tree_from_accounts(&accounts);
+            let prover = MerkleSumTreeProver { accounts: accounts.clone() };
+            let proof = prover.get_proof_with_circuit_data(&account_targets, &batch_circuit);
+
+            let proof_root = MerkleSumNode::new_from_public_inputs(&proof.public_inputs);
+            assert_eq!(proof_root, merkle_sum_tree.get_root());
+        }
 
-        let (batch_circuit, account_targets) =
-            build_merkle_sum_tree_circuit(batch_num, num_assets, STANDARD_CONFIG);
+        // a non-power-of-2 number of leaves, where both the leaves and the level above are padded.
+        let accounts = gen_accounts_with_random_data(5, num_assets);
+        let merkle_sum_tree = MerkleSumTree::new_tree_from_accounts(
+            &accounts,
+            LeafHashMode::PerToken,
+            &price_weighted,
+        );
+        assert_eq!(merkle_sum_tree.merkle_sum_tree.len(), 13);
+        assert_eq!(merkle_sum_tree.tree_depth, 3);
+        assert_eq!(merkle_sum_tree.merkle_sum_tree[5], MerkleSumNode::default());
 
+        let (batch_circuit, account_targets) = build_merkle_sum_tree_circuit(
+            accounts.len(),
+            num_assets,
+            LeafHashMode::PerToken,
+            &price_weighted,
+            STANDARD_CONFIG,
+        );
         let prover = MerkleSumTreeProver { accounts };
         let proof = prover.get_proof_with_circuit_data(&account_targets, &batch_circuit);
+        let proof_root = MerkleSumNode::new_from_public_inputs(&proof.public_inputs);
+        assert_eq!(proof_root, merkle_sum_tree.get_root());
+    }
 
-        let hash_offset = MerkleSumNodeTarget::pub_input_root_hash_offset();
-        let proof_root_hash = HashOut::<F>::from_partial(&proof.public_inputs[hash_offset]);
-        assert_eq!(proof_root_hash, merkle_sum_tree.get_root().hash);
+    #[test]
+    fn test_node_encoding() {
+        let accounts = gen_accounts_with_random_data(2, 3);
+        let node = MerkleSumNode::new_from_children_nodes(
+            &MerkleSumNode::new_from_account(
+                &accounts[0],
+                LeafHashMode::PerToken,
+                &Valuation::default(),
+            ),
+            &MerkleSumNode::new_from_account(
+                &accounts[1],
+                LeafHashMode::PerToken,
+                &Valuation::default(),
+            ),
+        );
+
+        assert_eq!(MerkleSumNode::from_bytes(&node.to_bytes()), node);
+
+        let json_string = serde_json::to_string(&node).unwrap();
+        assert_eq!(serde_json::from_str::<MerkleSumNode>(&json_string).unwrap(), node);
     }
 }
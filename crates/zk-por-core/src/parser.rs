@@ -1,5 +1,6 @@
 use super::account::{gen_accounts_with_random_data, Account};
 use crate::types::{ASSETS_KEY, F};
+use plonky2::hash::hash_types::HashOut;
 use plonky2_field::types::Field;
 use serde_json::{Map, Value};
 use std::{
@@ -281,7 +282,12 @@ pub fn parse_account_state(parsed_data: &Map<String, Value>, tokens: &Vec<String
         parsed_debts.push(F::from_canonical_u64(parsed_debt));
     }
 
-    Account { id: account_id.into(), equity: parsed_equities, debt: parsed_debts }
+    Account {
+        id: account_id.into(),
+        equity: parsed_equities,
+        debt: parsed_debts,
+        salt: HashOut::default(),
+    }
 }
 
 pub struct RandomAccountParser {
@@ -0,0 +1,748 @@
+use indicatif::ProgressBar;
+use plonky2::{
+    hash::hash_types::HashOut, plonk::proof::ProofWithPublicInputs,
+    util::serialization::DefaultGateSerializer,
+};
+use plonky2_field::types::PrimeField64;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    account::{persist_account_id_to_gmst_pos, Account, LeafHashMode, SaltSeed},
+    circuit_registry::registry::CircuitRegistry,
+    circuit_utils::recursive_levels,
+    database::PoRDB,
+    e2e::{batch_prove_accounts, merge_last_batch, prove_last_batch, prove_subproofs},
+    error::PoRError,
+    global::{GlobalConfig, GlobalMst},
+    merkle_proof::MerkleProof,
+    merkle_sum_tree::{MerkleSumNode, MerkleSumTree},
+    parser::{AccountParser, FileManager},
+    proof_file::ProofFormat,
+    proof_store::ProofStore,
+    recursive_prover::recursive_circuit::{RecursiveTargets, RoundInfo},
+    types::{C, D, F},
+    user_proof_archive::UserProofArchiveWriter,
+    util::pad_to_multiple_of,
+    valuation::Valuation,
+    CircuitsInfo, General, Info, Proof, TokenInfo,
+};
+
+pub const GLOBAL_PROOF_FILENAME: &str = "sum_proof_data.json";
+pub const GLOBAL_INFO_FILENAME: &str = "global_info.json";
+pub const USER_PROOF_DIRNAME: &str = "user_proofs";
+pub const USER_PROOF_ARCHIVE_FILENAME: &str = "user_proofs.bin";
+
+/// How the merkle proofs of the users are dumped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserProofFormat {
+    /// A json file per user in `USER_PROOF_DIRNAME`.
+    #[default]
+    Json,
+    /// A single archive of the proofs of all users in `USER_PROOF_ARCHIVE_FILENAME`, see `UserProofArchiveWriter`.
+    Archive,
+}
+
+/// The parameters of a proving run, with the defaults of the prover config resolved.
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    pub round_num: usize,
+    /// The epoch the salts of the leaf hashes are derived from, i.e., the round number unless configured otherwise. The leaf hashes of
+    /// unchanged users are the same in the rounds of an epoch, such that the proofs of their batches are reused from a `ProofStore`,
+    /// but they are also linkable across these rounds.
+    pub salt_epoch: usize,
+    /// The unix timestamp (in seconds) of the user data snapshot, which the root proof commits to.
+    pub snapshot_timestamp: u64,
+    pub batch_size: usize,
+    pub recursion_branchout_num: usize,
+    pub tokens: Vec<String>,
+    pub leaf_hash_mode: LeafHashMode,
+    pub valuation: Valuation,
+    /// The number of accounts read and proven in every parse round, a multiple of the batch size.
+    pub per_parse_account_num: usize,
+    pub user_proof_format: UserProofFormat,
+    /// Whether the root proof in the global proof file is compressed, which makes the file downloaded by every verifier smaller.
+    pub compress_root_proof: bool,
+}
+
+impl PipelineConfig {
+    pub fn round(&self) -> RoundInfo {
+        RoundInfo { round_num: self.round_num as u64, snapshot_timestamp: self.snapshot_timestamp }
+    }
+
+    /// The shape of the gmst of `num_of_users` accounts, to build the gmst of a pipeline with. The accounts after the full batches are
+    /// proven as a smaller last batch instead of being padded to a full batch.
+    pub fn global_config(&self, num_of_users: usize) -> GlobalConfig {
+        let (num_of_batches, last_batch_size) =
+            GlobalConfig::split_batches(num_of_users, self.batch_size);
+        GlobalConfig {
+            num_of_tokens: self.tokens.len(),
+            num_of_batches,
+            batch_size: self.batch_size,
+            recursion_branchout_num: self.recursion_branchout_num,
+            last_batch_size,
+        }
+    }
+}
+
+/// The prover of the batches and recursive nodes of a pipeline, which proves them in this process like `LocalProver`, or elsewhere,
+/// e.g., on the workers of a `Coordinator`.
+pub trait PipelineProver {
+    /// Prove every `batch_size` accounts, whose merkle sum tree root is the one in `batch_roots`, with the batch circuit.
+    fn prove_batches(
+        &mut self,
+        accounts: Vec<Account>,
+        batch_roots: &[MerkleSumNode],
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError>;
+
+    /// Prove the smaller last batch with the last batch circuit.
+    fn prove_last_batch(
+        &mut self,
+        accounts: Vec<Account>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PoRError>;
+
+    /// Prove every `recursion_branchout_num` subproofs at `level` with the recursive circuit of the level.
+    fn prove_subproofs(
+        &mut self,
+        subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
+        level: usize,
+        round: RoundInfo,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError>;
+}
+
+/// Prove in this process, with one thread for every batch or recursive node.
+pub struct LocalProver<'a> {
+    pub circuit_registry: &'a CircuitRegistry,
+    pub batch_prove_threads_num: usize,
+    pub recursive_prove_threads_num: usize,
+}
+
+impl PipelineProver for LocalProver<'_> {
+    fn prove_batches(
+        &mut self,
+        accounts: Vec<Account>,
+        _batch_roots: &[MerkleSumNode],
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        let batch_size = self.circuit_registry.get_batch_circuit().1.len();
+        Ok(batch_prove_accounts(
+            self.circuit_registry,
+            accounts,
+            self.batch_prove_threads_num,
+            batch_size,
+        ))
+    }
+
+    fn prove_last_batch(
+        &mut self,
+        accounts: Vec<Account>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+        Ok(prove_last_batch(self.circuit_registry, accounts))
+    }
+
+    fn prove_subproofs(
+        &mut self,
+        subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
+        level: usize,
+        round: RoundInfo,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        let inner_circuit_vd =
+            self.circuit_registry.get_level_circuit(level - 1).verifier_only.clone();
+        Ok(prove_subproofs(
+            subproofs,
+            inner_circuit_vd,
+            self.circuit_registry,
+            self.recursive_prove_threads_num,
+            level,
+            round,
+        ))
+    }
+}
+
+/// A proving run over the accounts of an account parser, in stages:
+/// 1. `prove_batches` reads the accounts, records their gmst positions in the database and proves the batches,
+/// 2. `prove_recursion` proves the recursive levels up to the root proof,
+/// 3. `persist_gmst` persists the gmst to the database,
+/// 4. `dump_artifacts` dumps the root proof, the global info and the merkle proof of every user.
+///
+/// The nodes of the run are set to the gmst of the pipeline, which is shared with the database (e.g., `PoRGMSTMemoryDB`), such that
+/// several pipelines with their own gmsts run side by side in one process.
+pub struct ProvingPipeline<'a, A: AccountParser> {
+    cfg: PipelineConfig,
+    account_parser: A,
+    database: Box<dyn PoRDB>,
+    global_mst: Arc<RwLock<GlobalMst>>,
+    circuit_registry: &'a CircuitRegistry,
+    salt_seed: SaltSeed,
+    global_cfg: GlobalConfig,
+    proof_store: Option<ProofStore>,
+    batch_proofs: Vec<ProofWithPublicInputs<F, C, D>>,
+    last_batch_proof: Option<ProofWithPublicInputs<F, C, D>>,
+}
+
+impl<'a, A: AccountParser> ProvingPipeline<'a, A> {
+    /// The gmst must be built with `cfg.global_config` of the number of users, and the circuits of the registry for the batch size,
+    /// branch-out and number of users of the run.
+    pub fn new(
+        cfg: PipelineConfig,
+        account_parser: A,
+        database: Box<dyn PoRDB>,
+        global_mst: Arc<RwLock<GlobalMst>>,
+        circuit_registry: &'a CircuitRegistry,
+        salt_seed: SaltSeed,
+    ) -> Result<Self, PoRError> {
+        if cfg.batch_size == 0
+            || cfg.per_parse_account_num == 0
+            || cfg.per_parse_account_num % cfg.batch_size != 0
+        {
+            return Err(PoRError::InvalidParameter(format!(
+                "the accounts of a parse round {} are not a positive multiple of the batch size {}",
+                cfg.per_parse_account_num, cfg.batch_size
+            )));
+        }
+        let global_cfg = cfg.global_config(account_parser.total_num_of_users());
+        if global_mst.read().expect("unable to get a lock").cfg != global_cfg {
+            return Err(PoRError::InvalidParameter(
+                "the gmst is not built for the accounts of the run".to_string(),
+            ));
+        }
+        let GlobalConfig { num_of_batches, last_batch_size, .. } = global_cfg;
+        if circuit_registry.get_batch_circuit().1.len() != cfg.batch_size
+            || circuit_registry.get_recursion_branchout_num() != cfg.recursion_branchout_num
+            || circuit_registry.get_recursive_levels()
+                != recursive_levels(num_of_batches, cfg.recursion_branchout_num)
+            || circuit_registry.get_last_batch_circuit().map(|(_, targets)| targets.len())
+                != last_batch_size
+        {
+            tracing::error!(
+                "the circuits of the registry are not built for the batches of the run"
+            );
+            return Err(PoRError::CircuitMismatch);
+        }
+
+        Ok(Self {
+            cfg,
+            account_parser,
+            database,
+            global_mst,
+            circuit_registry,
+            salt_seed,
+            global_cfg,
+            proof_store: None,
+            batch_proofs: vec![],
+            last_batch_proof: None,
+        })
+    }
+
+    /// Reuse the proofs of the previous round in the store, which also collects the proofs of this run.
+    pub fn set_proof_store(&mut self, proof_store: ProofStore) {
+        self.proof_store = Some(proof_store);
+    }
+
+    pub fn proof_store(&self) -> Option<&ProofStore> {
+        self.proof_store.as_ref()
+    }
+
+    pub fn global_config(&self) -> &GlobalConfig {
+        &self.global_cfg
+    }
+
+    /// Read the accounts in parse rounds of `per_parse_account_num`, record the gmst position of every account in the database, and
+    /// prove the full batches, padding the last parse round to a multiple of the batch size, and the smaller last batch if any. The
+    /// nodes of every batch tree are set to the gmst.
+    pub fn prove_batches(&mut self, prover: &mut dyn PipelineProver) -> Result<(), PoRError> {
+        let start = std::time::Instant::now();
+        let file_manager = FileManager {};
+        let total_num_of_users = self.account_parser.total_num_of_users();
+        let batch_size = self.cfg.batch_size;
+        let token_num = self.cfg.tokens.len();
+        let leaf_hash_mode = self.cfg.leaf_hash_mode;
+        let valuation = &self.cfg.valuation;
+        let batch_num = self.global_cfg.num_of_batches;
+        let full_batch_account_num = batch_num * batch_size;
+        let global_mst = self.global_mst.clone();
+
+        tracing::info!(
+            "start to prove {} accounts with {} tokens, {} batch size, {} recursive level",
+            total_num_of_users,
+            token_num,
+            batch_size,
+            self.circuit_registry.get_recursive_levels(),
+        );
+
+        let mut offset = 0;
+        let mut parse_num = 0;
+        let mut last_batch_accounts = vec![];
+        let bar = ProgressBar::new(total_num_of_users as u64);
+        while offset < total_num_of_users {
+            parse_num += 1;
+            let mut accounts = self.account_parser.read_n_accounts(
+                offset,
+                self.cfg.per_parse_account_num,
+                &file_manager,
+            );
+
+            persist_account_id_to_gmst_pos(&mut self.database, &accounts, offset);
+            accounts
+                .iter_mut()
+                .for_each(|acct| acct.set_salt_from_seed(&self.salt_seed, self.cfg.salt_epoch));
+
+            let account_num = accounts.len();
+            if self.global_cfg.last_batch_size.is_some()
+                && offset + account_num > full_batch_account_num
+            {
+                last_batch_accounts = accounts.split_off(full_batch_account_num - offset);
+            }
+            if accounts.is_empty() {
+                bar.inc(account_num as u64);
+                offset += self.cfg.per_parse_account_num;
+                continue;
+            }
+
+            let full_account_num = accounts.len();
+            if full_account_num % batch_size != 0 {
+                let pad_num = batch_size - full_account_num % batch_size;
+                tracing::info!("in {} parse, account number {} is not a multiple of batch size {}, hence padding {} empty accounts", parse_num, full_account_num, batch_size,pad_num);
+                accounts.resize(full_account_num + pad_num, Account::get_empty_account(token_num));
+            }
+
+            tracing::debug!(
+                "parse {} times, with number of accounts {}, number of batches {}",
+                parse_num,
+                account_num,
+                batch_num,
+            );
+
+            let batch_trees: Vec<Vec<MerkleSumNode>> = accounts
+                .par_chunks(batch_size)
+                .map(|account_batch| {
+                    MerkleSumTree::new_tree_from_accounts(
+                        &account_batch.to_vec(),
+                        leaf_hash_mode,
+                        valuation,
+                    )
+                    .merkle_sum_tree
+                })
+                .collect();
+            let batch_roots: Vec<MerkleSumNode> =
+                batch_trees.iter().map(|batch_tree| *batch_tree.last().unwrap()).collect();
+
+            let proofs = match self.proof_store.as_mut() {
+                Some(proof_store) => proof_store.prove_changed_batches(
+                    accounts,
+                    batch_size,
+                    &batch_roots,
+                    |accounts, batch_roots| prover.prove_batches(accounts, &batch_roots),
+                )?,
+                None => prover.prove_batches(accounts, &batch_roots)?,
+            };
+            assert_eq!(proofs.len(), batch_trees.len());
+
+            let batch_idx_base = self.batch_proofs.len();
+            for (i, (proof, batch_root)) in proofs.iter().zip(batch_roots.iter()).enumerate() {
+                if MerkleSumNode::new_from_public_inputs(&proof.public_inputs) != *batch_root {
+                    tracing::error!("The root node in proof is not equal to the one generated by merkle sum tree for batch {}", batch_idx_base + i);
+                    return Err(PoRError::InvalidProof);
+                }
+            }
+
+            let mut _g = global_mst.write().expect("unable to get a lock");
+            batch_trees.iter().enumerate().for_each(|(i, batch_tree)| {
+                let batch_idx = batch_idx_base + i;
+                batch_tree.iter().enumerate().for_each(|(j, node)| {
+                    _g.set_batch_node(batch_idx, j, *node);
+                });
+            });
+            drop(_g);
+
+            self.batch_proofs.extend(proofs.into_iter());
+
+            tracing::debug!(
+                "finish {}/{} batches of accounts in {} parse, since start {:?}",
+                self.batch_proofs.len(),
+                batch_num,
+                parse_num,
+                start.elapsed()
+            );
+            bar.inc(account_num as u64);
+            offset += self.cfg.per_parse_account_num;
+        }
+        bar.finish();
+
+        if let Some(last_batch_size) = self.global_cfg.last_batch_size {
+            assert_eq!(last_batch_accounts.len(), last_batch_size);
+            let last_batch_tree = MerkleSumTree::new_tree_from_accounts(
+                &last_batch_accounts,
+                leaf_hash_mode,
+                valuation,
+            );
+            let proof = prover.prove_last_batch(last_batch_accounts)?;
+            if MerkleSumNode::new_from_public_inputs(&proof.public_inputs)
+                != last_batch_tree.get_root()
+            {
+                tracing::error!("The root node in proof is not equal to the one generated by merkle sum tree for the last batch");
+                return Err(PoRError::InvalidProof);
+            }
+
+            let mut _g = global_mst.write().expect("unable to get a lock");
+            last_batch_tree.merkle_sum_tree.iter().enumerate().for_each(|(i, node)| {
+                _g.set_last_batch_node(i, *node);
+            });
+            drop(_g);
+            self.last_batch_proof = Some(proof);
+        }
+
+        tracing::info!(
+            "finish batch proving {} accounts, generating {} proofs in {:?}",
+            total_num_of_users,
+            self.batch_proofs.len() + self.last_batch_proof.iter().count(),
+            start.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Recursively prove the batch proofs of `prove_batches` up to the root proof, setting the recursive nodes and the root to the gmst,
+    /// which is then checked to be integral. Returns the root proof with the parameters and circuits to verify it.
+    pub fn prove_recursion(&mut self, prover: &mut dyn PipelineProver) -> Result<Proof, PoRError> {
+        let start = std::time::Instant::now();
+        let batch_proof_num = self.batch_proofs.len();
+        let global_mst = self.global_mst.clone();
+        let mut _g = global_mst.write().expect("unable to get a lock");
+        let root_proof = prove_recursive_levels(
+            self.circuit_registry,
+            std::mem::take(&mut self.batch_proofs),
+            self.last_batch_proof.take(),
+            self.cfg.round(),
+            prover,
+            self.proof_store.as_mut(),
+            Some(&mut _g),
+        )?;
+        drop(_g);
+        tracing::info!(
+            "finish recursive proving {} subproofs in {:?}",
+            batch_proof_num,
+            start.elapsed()
+        );
+
+        let user_count = RecursiveTargets::user_count_from_public_inputs(
+            self.cfg.tokens.len(),
+            &self.cfg.valuation,
+            &root_proof.public_inputs,
+        );
+        if user_count != Some(self.account_parser.total_num_of_users() as u64) {
+            tracing::error!(
+                "the root proof attests {:?} users instead of {}",
+                user_count,
+                self.account_parser.total_num_of_users()
+            );
+            return Err(PoRError::InvalidProof);
+        }
+
+        let start = std::time::Instant::now();
+        if !global_mst.read().expect("unable to get a lock").is_integral() {
+            return Err(PoRError::InvalidProof);
+        }
+        tracing::info!("verify global mst in {:?}", start.elapsed());
+
+        let root_circuit = self.circuit_registry.get_root_circuit();
+        let root_circuit_verifier_data_bytes = root_circuit
+            .verifier_data()
+            .to_bytes(&DefaultGateSerializer)
+            .expect("fail to serialize root circuit verifier data");
+
+        Ok(Proof {
+            general: General {
+                round_num: self.cfg.round_num,
+                recursion_branchout_num: self.cfg.recursion_branchout_num,
+                batch_size: self.cfg.batch_size,
+                token_num: self.cfg.tokens.len(),
+                tokens: self.cfg.tokens.clone(),
+                leaf_hash_mode: self.cfg.leaf_hash_mode,
+                valuation: self.cfg.valuation.clone(),
+                snapshot_timestamp: Some(self.cfg.snapshot_timestamp),
+                last_batch_size: self.global_cfg.last_batch_size,
+            },
+            circuits_info: Some(CircuitsInfo {
+                batch_circuit_config: self.circuit_registry.get_batch_circuit_config().clone(),
+                recursive_circuit_configs: self
+                    .circuit_registry
+                    .get_recursive_circuit_configs()
+                    .to_vec(),
+                root_verifier_data_hex: hex::encode(root_circuit_verifier_data_bytes),
+            }),
+            root_vd_digest: root_circuit.verifier_only.circuit_digest,
+            proof: root_proof,
+        })
+    }
+
+    /// Persist the gmst to the database, from which the merkle proofs of the users are generated.
+    pub fn persist_gmst(&mut self) {
+        let _g = self.global_mst.read().expect("unable to get a lock");
+        let root_hash = _g.get_root().expect("no root");
+        tracing::info!("root hash is {:?}", root_hash);
+        let start = std::time::Instant::now();
+        _g.persist(&mut self.database);
+        tracing::info!("persist gmst to db in {:?}", start.elapsed());
+    }
+
+    /// Dump the root proof, the global info and the merkle proof of every user under `output_dir`, in the user proof format of the
+    /// config. As an account parser reads the accounts only once, the accounts of the users are read again by `account_parser`, which
+    /// must read the same accounts.
+    pub fn dump_artifacts(
+        self,
+        root_proof: &Proof,
+        output_dir: &Path,
+        mut account_parser: impl AccountParser,
+    ) -> Result<(), PoRError> {
+        let start = std::time::Instant::now();
+        let global_proof_output_path = output_dir.join(GLOBAL_PROOF_FILENAME);
+        let global_proof_bytes = if self.cfg.compress_root_proof {
+            let compressed_proof = self
+                .circuit_registry
+                .get_root_circuit()
+                .compress(root_proof.proof.clone())
+                .expect("fail to compress root proof");
+            root_proof.to_compressed_bytes(ProofFormat::Json, &compressed_proof)?
+        } else {
+            root_proof.to_bytes(ProofFormat::Json)?
+        };
+        fs::write(&global_proof_output_path, global_proof_bytes)?;
+
+        let info = global_info(&self.cfg.tokens, root_proof);
+        let global_info_output_path = output_dir.join(GLOBAL_INFO_FILENAME);
+        let global_info_file = File::create(global_info_output_path.clone())?;
+        let mut global_info_writer = BufWriter::new(global_info_file);
+        serde_json::to_writer(&mut global_info_writer, &info).expect(
+            format!("fail to dump global info file to {:?}", global_info_output_path).as_str(),
+        );
+        global_info_writer.flush()?;
+
+        ///////////////////////////////////////////////
+        // generate and dump proof for each user
+        let user_num = account_parser.total_num_of_users();
+        assert_eq!(user_num, self.account_parser.total_num_of_users());
+        tracing::info!("start to generate and dump merkle proof for each of {} accounts", user_num);
+        let user_proof_output_dir_path = output_dir.join(USER_PROOF_DIRNAME);
+        let mut archive_writer = match self.cfg.user_proof_format {
+            UserProofFormat::Json => {
+                fs::create_dir_all(&user_proof_output_dir_path)?;
+                None
+            }
+            UserProofFormat::Archive => Some(UserProofArchiveWriter::create(
+                &output_dir.join(USER_PROOF_ARCHIVE_FILENAME),
+                user_num,
+            )?),
+        };
+
+        let file_manager = FileManager {};
+        let bar = ProgressBar::new(user_num as u64);
+        let cdb: Arc<dyn PoRDB> = Arc::from(self.database);
+        let new_user_proof = |account: &Account| {
+            MerkleProof::new_from_account(account, cdb.clone(), &self.global_cfg).expect(
+                format!("fail to generate merkle proof for account {}", account.id).as_str(),
+            )
+        };
+        let mut offset = 0;
+        let chunk_size: usize = num_cpus::get();
+        while offset < user_num {
+            let mut accounts: Vec<Account> = account_parser.read_n_accounts(
+                offset,
+                self.cfg.per_parse_account_num,
+                &file_manager,
+            );
+            // the salts are embedded only in the proof of each user.
+            accounts
+                .iter_mut()
+                .for_each(|acct| acct.set_salt_from_seed(&self.salt_seed, self.cfg.salt_epoch));
+            for chunk in accounts.chunks(chunk_size) {
+                match archive_writer.as_mut() {
+                    // the proofs are appended in the order of the users.
+                    Some(writer) => {
+                        let user_proofs: Vec<MerkleProof> =
+                            chunk.par_iter().map(&new_user_proof).collect();
+                        for user_proof in user_proofs.iter() {
+                            writer.append(user_proof)?;
+                        }
+                    }
+                    None => chunk.par_iter().for_each(|account| {
+                        let user_proof = new_user_proof(account);
+
+                        let user_proof_output_path =
+                            user_proof_output_dir_path.join(format!("{}.json", account.id));
+
+                        let user_proof_file = File::create(user_proof_output_path).expect(
+                            format!(
+                                "fail to create user proof file for account {}",
+                                user_proof.account.id
+                            )
+                            .as_str(),
+                        );
+
+                        let mut user_proof_writer = BufWriter::new(user_proof_file);
+                        serde_json::to_writer(&mut user_proof_writer, &user_proof).expect(
+                            format!(
+                                "fail to write user proof file for account {}",
+                                user_proof.account.id
+                            )
+                            .as_str(),
+                        );
+                        user_proof_writer.flush().expect(
+                            format!(
+                                "fail to write user proof file for account {}",
+                                user_proof.account.id
+                            )
+                            .as_str(),
+                        )
+                    }),
+                }
+
+                bar.inc(chunk.len() as u64);
+            }
+            offset += self.cfg.per_parse_account_num;
+        }
+        if let Some(writer) = archive_writer {
+            writer.finish()?;
+        }
+        bar.finish();
+        tracing::info!("finish dumping global proof and user proofs in {:?}", start.elapsed());
+
+        Ok(())
+    }
+}
+
+/// The root hash and the total and per-token sums attested by the root proof.
+fn global_info(tokens: &[String], root_proof: &Proof) -> Info {
+    let public_inputs = &root_proof.proof.public_inputs;
+    let hash_offset = RecursiveTargets::pub_input_hash_offset();
+    let root_hash = HashOut::<F>::from_partial(&public_inputs[hash_offset]);
+    let root_hash_bytes = root_hash
+        .elements
+        .iter()
+        .flat_map(|x| x.to_canonical_u64().to_le_bytes())
+        .collect::<Vec<u8>>();
+
+    let equity_sum = public_inputs[RecursiveTargets::pub_input_equity_offset()].to_canonical_u64();
+    let debt_sum = public_inputs[RecursiveTargets::pub_input_debt_offset()].to_canonical_u64();
+    assert!(equity_sum >= debt_sum);
+    let user_count = RecursiveTargets::user_count_from_public_inputs(
+        tokens.len(),
+        &root_proof.general.valuation,
+        public_inputs,
+    )
+    .expect("the root proof must expose the user count");
+    Info {
+        root_hash: hex::encode(root_hash_bytes),
+        equity_sum,
+        debt_sum,
+        balance_sum: equity_sum - debt_sum,
+        user_count,
+        tokens: TokenInfo::from_public_inputs(tokens, public_inputs),
+    }
+}
+
+/// Recursively prove the batch proofs with `prover` level by level up to the top recursive proof, padding the subproofs of every level
+/// with empty proofs to a multiple of the branch-out, and merge it with `last_batch_proof` if any into the root proof, which is
+/// verified. The proofs of unchanged nodes are reused from `proof_store` if any, and the nodes of all levels are set to `global_mst` if
+/// any.
+pub(crate) fn prove_recursive_levels(
+    circuit_registry: &CircuitRegistry,
+    batch_proofs: Vec<ProofWithPublicInputs<F, C, D>>,
+    last_batch_proof: Option<ProofWithPublicInputs<F, C, D>>,
+    round: RoundInfo,
+    prover: &mut dyn PipelineProver,
+    mut proof_store: Option<&mut ProofStore>,
+    mut global_mst: Option<&mut GlobalMst>,
+) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+    let recursive_levels = circuit_registry.get_recursive_levels();
+    let recursion_branchout_num = circuit_registry.get_recursion_branchout_num();
+    tracing::info!("total recursive levels: {:?}", recursive_levels);
+
+    let mut last_level_proofs = batch_proofs;
+    // level 0 for mst root hash
+    for level in 1..=recursive_levels {
+        let start = std::time::Instant::now();
+        let subproof_len = last_level_proofs.len();
+        tracing::info!(
+            "start to recursively prove {} subproofs at level {}/{}",
+            subproof_len,
+            level,
+            recursive_levels,
+        );
+
+        // without any full batch, the recursive tree has a single group of empty batches.
+        let padded_len = pad_to_multiple_of(subproof_len.max(1), recursion_branchout_num);
+        if padded_len != subproof_len {
+            let last_level_vd_digest =
+                circuit_registry.get_level_circuit(level - 1).verifier_only.circuit_digest;
+            let last_level_empty_proof = circuit_registry
+                .get_empty_proof(&last_level_vd_digest)
+                .expect(
+                    format!("fail to find empty proof for circuit vd {:?}", last_level_vd_digest)
+                        .as_str(),
+                )
+                .clone();
+            let pad_num = padded_len - subproof_len;
+            tracing::info!("At level {}, {} subproofs are not a multiple of the recursion branch-out {}, hence padding {} empty proofs. ", level, subproof_len, recursion_branchout_num, pad_num);
+
+            last_level_proofs.resize(subproof_len + pad_num, last_level_empty_proof);
+        }
+
+        if let Some(global_mst) = global_mst.as_deref_mut() {
+            last_level_proofs.iter().enumerate().for_each(|(i, proof)| {
+                let proof_root_node = MerkleSumNode::new_from_public_inputs(&proof.public_inputs);
+                global_mst.set_recursive_node(level - 1, i, proof_root_node);
+            });
+        }
+
+        last_level_proofs = match proof_store.as_deref_mut() {
+            Some(proof_store) => proof_store.prove_changed_subproofs(
+                level,
+                last_level_proofs,
+                recursion_branchout_num,
+                |subproofs| prover.prove_subproofs(subproofs, level, round),
+            )?,
+            None => prover.prove_subproofs(last_level_proofs, level, round)?,
+        };
+
+        tracing::info!(
+            "finish recursive level {} with {} proofs in : {:?}",
+            level,
+            last_level_proofs.len(),
+            start.elapsed()
+        );
+    }
+
+    if last_level_proofs.len() != 1 {
+        panic!("The last level proofs should be of length 1, but got {}", last_level_proofs.len());
+    }
+    let top_proof = last_level_proofs.pop().unwrap();
+    if let Some(global_mst) = global_mst.as_deref_mut() {
+        let top_root_node = MerkleSumNode::new_from_public_inputs(&top_proof.public_inputs);
+        global_mst.set_recursive_node(recursive_levels, 0, top_root_node);
+    }
+
+    let root_proof = match last_batch_proof {
+        Some(last_batch_proof) => {
+            let root_proof = merge_last_batch(circuit_registry, top_proof, last_batch_proof, round);
+            if let Some(global_mst) = global_mst.as_deref_mut() {
+                global_mst.set_root_node(MerkleSumNode::new_from_public_inputs(
+                    &root_proof.public_inputs,
+                ));
+            }
+            root_proof
+        }
+        None => top_proof,
+    };
+    circuit_registry
+        .get_root_circuit()
+        .verify(root_proof.clone())
+        .map_err(|_| PoRError::InvalidProof)?;
+    Ok(root_proof)
+}
@@ -0,0 +1,366 @@
+use plonky2::{
+    hash::hash_types::{HashOut, NUM_HASH_OUT_ELTS},
+    plonk::{
+        circuit_data::{CircuitConfig, VerifierCircuitData},
+        proof::{CompressedProofWithPublicInputs, ProofWithPublicInputs},
+    },
+    util::serialization::{Buffer, DefaultGateSerializer, IoError, IoResult, Read, Write},
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path, str::FromStr};
+
+use crate::{
+    error::PoRError,
+    types::{C, D, F},
+    CircuitsInfo, General, Proof,
+};
+
+pub const PROOF_BINARY_MAGIC: &[u8; 8] = b"ZKPORPRF";
+/// Version 2 adds the compressed root proof.
+pub const PROOF_BINARY_VERSION: u32 = 2;
+/// The magic and the version in u32.
+const HEADER_BYTES: usize = 8 + 4;
+
+/// How the global proof is encoded in a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofFormat {
+    /// The serde json encoding of `Proof`.
+    #[default]
+    Json,
+    /// The versioned binary encoding, which keeps field elements and the root verifier data as raw bytes:
+    /// - a header of the magic and the version,
+    /// - the general info in json, prefixed by its length,
+    /// - the root verifier data digest as field elements,
+    /// - the batch and recursive circuit configs in json and the raw root verifier data bytes, each prefixed by its length,
+    /// - whether the root proof is compressed as a byte, which version 1 does not have as its root proof is never compressed,
+    /// - the root proof encoded by plonky2, which is decoded with the common data of the root verifier data.
+    Binary,
+}
+
+impl ProofFormat {
+    /// The format of an encoded proof, which is binary if it starts with the magic of the binary encoding.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(PROOF_BINARY_MAGIC) {
+            ProofFormat::Binary
+        } else {
+            ProofFormat::Json
+        }
+    }
+}
+
+impl FromStr for ProofFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ProofFormat::Json),
+            "binary" => Ok(ProofFormat::Binary),
+            _ => Err(format!("unknown proof format {}, expected json or binary", s)),
+        }
+    }
+}
+
+/// The root proof to encode, which is either the full proof or its compressed form.
+#[derive(Clone, Copy)]
+enum RootProof<'a> {
+    Full(&'a ProofWithPublicInputs<F, C, D>),
+    Compressed(&'a CompressedProofWithPublicInputs<F, C, D>),
+}
+
+/// The json encoding of a proof with a compressed root proof, which has `compressed_proof` instead of `proof`.
+#[derive(Serialize)]
+struct CompressedProofJson<'a> {
+    general: &'a General,
+    root_vd_digest: &'a HashOut<F>,
+    circuits_info: &'a Option<CircuitsInfo>,
+    compressed_proof: &'a CompressedProofWithPublicInputs<F, C, D>,
+}
+
+/// The json encoding of a proof with either a full or a compressed root proof.
+#[derive(Deserialize)]
+struct ProofJson {
+    general: General,
+    root_vd_digest: HashOut<F>,
+    #[serde(default)]
+    circuits_info: Option<CircuitsInfo>,
+    proof: Option<ProofWithPublicInputs<F, C, D>>,
+    compressed_proof: Option<CompressedProofWithPublicInputs<F, C, D>>,
+}
+
+/// The root verifier data in the circuits info, which the binary format and the compressed root proof require.
+fn root_verifier_data(
+    circuits_info: Option<&CircuitsInfo>,
+) -> Result<(Vec<u8>, VerifierCircuitData<F, C, D>), PoRError> {
+    let circuits_info = circuits_info.ok_or_else(|| {
+        PoRError::InvalidProofFile("the circuits info of the proof is required".to_string())
+    })?;
+    let bytes = hex::decode(&circuits_info.root_verifier_data_hex).map_err(|e| {
+        PoRError::InvalidProofFile(format!("fail to decode root verifier data hex: {}", e))
+    })?;
+    let verifier_data =
+        VerifierCircuitData::<F, C, D>::from_bytes(bytes.clone(), &DefaultGateSerializer).map_err(
+            |_| PoRError::InvalidProofFile("fail to parse root verifier data".to_string()),
+        )?;
+    Ok((bytes, verifier_data))
+}
+
+fn decompress(
+    compressed_proof: CompressedProofWithPublicInputs<F, C, D>,
+    root_verifier_data: &VerifierCircuitData<F, C, D>,
+) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+    compressed_proof
+        .decompress(&root_verifier_data.verifier_only.circuit_digest, &root_verifier_data.common)
+        .map_err(|e| PoRError::InvalidProofFile(format!("fail to decompress root proof: {}", e)))
+}
+
+impl Proof {
+    /// Encode the proof in the format.
+    pub fn to_bytes(&self, format: ProofFormat) -> Result<Vec<u8>, PoRError> {
+        self.encode(format, RootProof::Full(&self.proof))
+    }
+
+    /// Encode the proof in the format, with the compressed root proof instead of the full one, which must be the compression of
+    /// `self.proof`, e.g., by `CircuitData::compress` of the root circuit. The compressed proof omits the merkle paths shared by the
+    /// FRI query rounds, and is decompressed by `from_bytes` with the common data of the root verifier data in the circuits info.
+    pub fn to_compressed_bytes(
+        &self,
+        format: ProofFormat,
+        compressed_proof: &CompressedProofWithPublicInputs<F, C, D>,
+    ) -> Result<Vec<u8>, PoRError> {
+        self.encode(format, RootProof::Compressed(compressed_proof))
+    }
+
+    /// Compress the root proof with the root verifier data in the circuits info.
+    pub fn compress(&self) -> Result<CompressedProofWithPublicInputs<F, C, D>, PoRError> {
+        let (_, root_verifier_data) = root_verifier_data(self.circuits_info.as_ref())?;
+        self.proof
+            .clone()
+            .compress(&root_verifier_data.verifier_only.circuit_digest, &root_verifier_data.common)
+            .map_err(|e| PoRError::InvalidProofFile(format!("fail to compress root proof: {}", e)))
+    }
+
+    fn encode(&self, format: ProofFormat, root_proof: RootProof) -> Result<Vec<u8>, PoRError> {
+        match (format, root_proof) {
+            (ProofFormat::Json, RootProof::Full(_)) => {
+                Ok(serde_json::to_vec(self).expect("fail to serialize proof"))
+            }
+            (ProofFormat::Json, RootProof::Compressed(compressed_proof)) => {
+                // the root verifier data is checked here, as it is required to decompress the proof.
+                root_verifier_data(self.circuits_info.as_ref())?;
+                Ok(serde_json::to_vec(&CompressedProofJson {
+                    general: &self.general,
+                    root_vd_digest: &self.root_vd_digest,
+                    circuits_info: &self.circuits_info,
+                    compressed_proof,
+                })
+                .expect("fail to serialize proof"))
+            }
+            (ProofFormat::Binary, root_proof) => self.encode_binary(root_proof),
+        }
+    }
+
+    /// The proof must have its circuits info, from which the root proof is decoded.
+    fn encode_binary(&self, root_proof: RootProof) -> Result<Vec<u8>, PoRError> {
+        let (root_verifier_data_bytes, _) = root_verifier_data(self.circuits_info.as_ref())?;
+        let circuits_info = self.circuits_info.as_ref().unwrap();
+        let general_bytes =
+            serde_json::to_vec(&self.general).expect("fail to serialize general info");
+        let circuit_configs_bytes = serde_json::to_vec(&(
+            &circuits_info.batch_circuit_config,
+            &circuits_info.recursive_circuit_configs,
+        ))
+        .expect("fail to serialize circuit configs");
+
+        let mut bytes = PROOF_BINARY_MAGIC.to_vec();
+        bytes.extend(PROOF_BINARY_VERSION.to_le_bytes());
+        let write = |dst: &mut Vec<u8>| -> IoResult<()> {
+            write_bytes(dst, &general_bytes)?;
+            dst.write_field_vec(&self.root_vd_digest.elements)?;
+            write_bytes(dst, &circuit_configs_bytes)?;
+            write_bytes(dst, &root_verifier_data_bytes)?;
+            match root_proof {
+                RootProof::Full(proof) => {
+                    dst.write_bool(false)?;
+                    dst.write_proof_with_public_inputs(proof)
+                }
+                RootProof::Compressed(compressed_proof) => {
+                    dst.write_bool(true)?;
+                    dst.write_compressed_proof_with_public_inputs(compressed_proof)
+                }
+            }
+        };
+        write(&mut bytes).expect("fail to serialize proof");
+        Ok(bytes)
+    }
+
+    /// Decode a proof in the binary format, decompressing a compressed root proof.
+    pub fn from_binary_bytes(bytes: &[u8]) -> Result<Self, PoRError> {
+        Self::decode_binary(bytes).map(|(proof, _)| proof)
+    }
+
+    fn decode_binary(bytes: &[u8]) -> Result<(Self, bool), PoRError> {
+        if bytes.len() < HEADER_BYTES || !bytes.starts_with(PROOF_BINARY_MAGIC) {
+            return Err(PoRError::InvalidProofFile("not a binary proof".to_string()));
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version == 0 || version > PROOF_BINARY_VERSION {
+            return Err(PoRError::InvalidProofFile(format!(
+                "unsupported version {}, expected at most {}",
+                version, PROOF_BINARY_VERSION
+            )));
+        }
+
+        let mut src = Buffer::new(&bytes[HEADER_BYTES..]);
+        let malformed = |what: &str| PoRError::InvalidProofFile(format!("fail to parse {}", what));
+        let general: General =
+            serde_json::from_slice(&read_bytes(&mut src).map_err(|_| malformed("general info"))?)
+                .map_err(|_| malformed("general info"))?;
+        let root_vd_digest = HashOut::from_vec(
+            src.read_field_vec(NUM_HASH_OUT_ELTS).map_err(|_| malformed("root vd digest"))?,
+        );
+        let (batch_circuit_config, recursive_circuit_configs): (CircuitConfig, Vec<CircuitConfig>) =
+            serde_json::from_slice(&read_bytes(&mut src).map_err(|_| malformed("circuit configs"))?)
+                .map_err(|_| malformed("circuit configs"))?;
+        let root_verifier_data_bytes =
+            read_bytes(&mut src).map_err(|_| malformed("root verifier data"))?;
+        let root_verifier_data = VerifierCircuitData::<F, C, D>::from_bytes(
+            root_verifier_data_bytes.clone(),
+            &DefaultGateSerializer,
+        )
+        .map_err(|_| malformed("root verifier data"))?;
+        let compressed = version > 1 && src.read_bool().map_err(|_| malformed("root proof"))?;
+        let proof: ProofWithPublicInputs<F, C, D> = if compressed {
+            let compressed_proof: CompressedProofWithPublicInputs<F, C, D> = src
+                .read_compressed_proof_with_public_inputs(&root_verifier_data.common)
+                .map_err(|_| malformed("compressed root proof"))?;
+            decompress(compressed_proof, &root_verifier_data)?
+        } else {
+            src.read_proof_with_public_inputs(&root_verifier_data.common)
+                .map_err(|_| malformed("root proof"))?
+        };
+        if src.remaining() != 0 {
+            return Err(PoRError::InvalidProofFile("trailing bytes after root proof".to_string()));
+        }
+
+        let proof = Proof {
+            general,
+            root_vd_digest,
+            circuits_info: Some(CircuitsInfo {
+                batch_circuit_config,
+                recursive_circuit_configs,
+                root_verifier_data_hex: hex::encode(root_verifier_data_bytes),
+            }),
+            proof,
+        };
+        Ok((proof, compressed))
+    }
+
+    fn decode_json(bytes: &[u8]) -> Result<(Self, bool), PoRError> {
+        let proof_json: ProofJson = serde_json::from_slice(bytes)
+            .map_err(|e| PoRError::InvalidProofFile(format!("fail to parse json proof: {}", e)))?;
+        let (proof, compressed) = match (proof_json.proof, proof_json.compressed_proof) {
+            (Some(proof), None) => (proof, false),
+            (None, Some(compressed_proof)) => {
+                let (_, root_verifier_data) =
+                    root_verifier_data(proof_json.circuits_info.as_ref())?;
+                (decompress(compressed_proof, &root_verifier_data)?, true)
+            }
+            _ => {
+                return Err(PoRError::InvalidProofFile(
+                    "exactly one of proof and compressed_proof is required".to_string(),
+                ))
+            }
+        };
+        let proof = Proof {
+            general: proof_json.general,
+            root_vd_digest: proof_json.root_vd_digest,
+            circuits_info: proof_json.circuits_info,
+            proof,
+        };
+        Ok((proof, compressed))
+    }
+
+    /// Decode a proof in either format, which is detected by `ProofFormat::detect`, and whether its root proof is compressed. A
+    /// compressed root proof is decompressed.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, bool), PoRError> {
+        match ProofFormat::detect(bytes) {
+            ProofFormat::Json => Self::decode_json(bytes),
+            ProofFormat::Binary => Self::decode_binary(bytes),
+        }
+    }
+
+    /// Decode a proof in either format, which is detected by `ProofFormat::detect`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PoRError> {
+        Self::decode(bytes).map(|(proof, _)| proof)
+    }
+
+    /// Load the proof file at `path` in either format.
+    pub fn load(path: &Path) -> Result<Self, PoRError> {
+        let bytes = fs::read(path).map_err(|e| {
+            PoRError::InvalidParameter(format!("fail to open {:?} due to error {:?}", path, e))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Save the proof to `path` in the format, with the compressed root proof if `compress`.
+    pub fn save(&self, path: &Path, format: ProofFormat, compress: bool) -> Result<(), PoRError> {
+        let bytes = match compress {
+            true => self.to_compressed_bytes(format, &self.compress()?)?,
+            false => self.to_bytes(format)?,
+        };
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+}
+
+fn write_bytes(dst: &mut Vec<u8>, bytes: &[u8]) -> IoResult<()> {
+    dst.write_usize(bytes.len())?;
+    dst.write_all(bytes)
+}
+
+fn read_bytes(src: &mut Buffer) -> IoResult<Vec<u8>> {
+    let len = src.read_usize()?;
+    if len > src.remaining() {
+        return Err(IoError);
+    }
+    let mut bytes = vec![0u8; len];
+    src.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{ProofFormat, PROOF_BINARY_MAGIC, PROOF_BINARY_VERSION};
+    use crate::{error::PoRError, Proof};
+
+    #[test]
+    fn test_proof_format() {
+        assert_eq!(ProofFormat::Binary, ProofFormat::detect(b"ZKPORPRF\x01\x00\x00\x00"));
+        assert_eq!(ProofFormat::Json, ProofFormat::detect(b"{\"general\":{}}"));
+        assert_eq!(ProofFormat::Json, ProofFormat::detect(b""));
+        assert_eq!(Ok(ProofFormat::Binary), "binary".parse());
+        assert_eq!(Ok(ProofFormat::Json), "json".parse());
+        assert!("bin".parse::<ProofFormat>().is_err());
+    }
+
+    #[test]
+    fn test_invalid_binary_proof() {
+        // a header of another version.
+        let mut bytes = PROOF_BINARY_MAGIC.to_vec();
+        bytes.extend((PROOF_BINARY_VERSION + 1).to_le_bytes());
+        assert!(matches!(Proof::from_bytes(&bytes), Err(PoRError::InvalidProofFile(_))));
+
+        // a header without any content.
+        let mut bytes = PROOF_BINARY_MAGIC.to_vec();
+        bytes.extend(PROOF_BINARY_VERSION.to_le_bytes());
+        assert!(matches!(Proof::from_bytes(&bytes), Err(PoRError::InvalidProofFile(_))));
+
+        // a truncated header.
+        assert!(matches!(
+            Proof::from_binary_bytes(&PROOF_BINARY_MAGIC[..]),
+            Err(PoRError::InvalidProofFile(_))
+        ));
+        assert!(matches!(Proof::from_bytes(b"{}"), Err(PoRError::InvalidProofFile(_))));
+    }
+}
@@ -0,0 +1,324 @@
+use plonky2::{
+    hash::hash_types::HashOut,
+    plonk::proof::ProofWithPublicInputs,
+    util::serialization::{Buffer, IoResult, Read, Write},
+};
+use plonky2_field::types::PrimeField64;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::{
+    account::Account,
+    circuit_registry::registry::CircuitRegistry,
+    error::PoRError,
+    merkle_sum_tree::MerkleSumNode,
+    types::{C, D, F},
+};
+
+const PROOF_STORE_MANIFEST_FILENAME: &str = "proof_store.json";
+
+/// The key of a stored proof, which determines the public inputs of the proof.
+pub type ProofKey = [u8; 32];
+
+#[derive(Serialize, Deserialize)]
+struct ProofStoreManifest {
+    round_num: usize,
+    // the epoch of the salts of the leaf hashes, on which every stored proof depends
+    salt_epoch: usize,
+    // the digests of the circuits of the stored levels, from the batch circuit
+    circuit_digests: Vec<HashOut<F>>,
+}
+
+/// The batch proofs and recursive proofs of a round, such that the next round reuses the proof of every unchanged batch, and of every
+/// recursive node whose subproofs are unchanged, instead of proving it again.
+///
+/// A store holds the proofs of the previous round, which are looked up, and the proofs of this round, which are saved. Only the levels
+/// whose circuit is the same in both rounds are reused, and the proofs of the root level, which expose the round, are never stored.
+/// As the leaf hashes commit to the salts, the proofs of the previous round are only reused if its salts are derived from the same
+/// salt epoch, see `PipelineConfig::salt_epoch`.
+pub struct ProofStore {
+    round_num: usize,
+    salt_epoch: usize,
+    circuit_digests: Vec<HashOut<F>>,
+    previous: Vec<HashMap<ProofKey, ProofWithPublicInputs<F, C, D>>>,
+    current: Vec<HashMap<ProofKey, ProofWithPublicInputs<F, C, D>>>,
+    // the number of proofs of the previous round reused at every level
+    reused_proof_nums: Vec<usize>,
+}
+
+impl ProofStore {
+    /// An empty store of a round, whose salts are derived from `salt_epoch`, for the circuits of the registry.
+    pub fn new(circuit_registry: &CircuitRegistry, round_num: usize, salt_epoch: usize) -> Self {
+        let mut level_num = circuit_registry.get_recursive_levels() + 1;
+        // without a last batch, the top recursive circuit is the root circuit.
+        if circuit_registry.get_merge_circuit().is_none() {
+            level_num -= 1;
+        }
+        let circuit_digests = (0..level_num)
+            .map(|level| circuit_registry.get_level_circuit(level).verifier_only.circuit_digest)
+            .collect();
+        Self {
+            round_num,
+            salt_epoch,
+            circuit_digests,
+            previous: vec![HashMap::new(); level_num],
+            current: vec![HashMap::new(); level_num],
+            reused_proof_nums: vec![0; level_num],
+        }
+    }
+
+    /// Load the proofs saved by `save` under `dir` as the ones of the previous round. The levels from the first one whose circuit
+    /// differs from the one of the registry are not loaded, and all proofs are proven again if the batch circuit differs, or if the
+    /// salts of the previous round are derived from another salt epoch. If `dir` has no saved proofs, the store is empty.
+    pub fn load(
+        dir: &Path,
+        circuit_registry: &CircuitRegistry,
+        round_num: usize,
+        salt_epoch: usize,
+    ) -> Result<Self, PoRError> {
+        let start = std::time::Instant::now();
+        let mut store = Self::new(circuit_registry, round_num, salt_epoch);
+        let manifest_path = dir.join(PROOF_STORE_MANIFEST_FILENAME);
+        if !manifest_path.exists() {
+            tracing::info!("no proofs of a previous round in {:?}", dir);
+            return Ok(store);
+        }
+        let manifest_bytes = fs::read(manifest_path)?;
+        let manifest: ProofStoreManifest =
+            serde_json::from_slice(&manifest_bytes).map_err(|e| {
+                PoRError::InvalidProofStore(format!("fail to parse the manifest: {}", e))
+            })?;
+        if manifest.salt_epoch != salt_epoch {
+            tracing::warn!(
+                "the proofs of round {} in {:?} are salted with epoch {} instead of {}, hence no proof can be reused; configure the same salt_epoch for both rounds to reuse them",
+                manifest.round_num,
+                dir,
+                manifest.salt_epoch,
+                salt_epoch
+            );
+            return Ok(store);
+        }
+
+        for level in 0..store.circuit_digests.len() {
+            if manifest.circuit_digests.get(level) != Some(&store.circuit_digests[level]) {
+                tracing::warn!(
+                    "the circuit at level {} differs from the previous round, prove all nodes from this level",
+                    level
+                );
+                break;
+            }
+            let path = dir.join(format!("level_{}.bin", level));
+            let bytes = fs::read(&path)?;
+            let mut buffer = Buffer::new(&bytes);
+            let common_data = &circuit_registry.get_level_circuit(level).common;
+            let read_proofs = |src: &mut Buffer| -> IoResult<_> {
+                let len = src.read_usize()?;
+                (0..len)
+                    .map(|_| {
+                        let mut key = [0u8; 32];
+                        src.read_exact(&mut key)?;
+                        Ok((key, src.read_proof_with_public_inputs(common_data)?))
+                    })
+                    .collect::<IoResult<HashMap<_, _>>>()
+            };
+            store.previous[level] = read_proofs(&mut buffer)
+                .map_err(|_| PoRError::InvalidProofStore(format!("fail to parse {:?}", path)))?;
+            if buffer.remaining() != 0 {
+                return Err(PoRError::InvalidProofStore(format!("trailing bytes in {:?}", path)));
+            }
+        }
+
+        tracing::info!(
+            "load {} proofs of the previous round from {:?} in {:?}",
+            store.previous.iter().map(|proofs| proofs.len()).sum::<usize>(),
+            dir,
+            start.elapsed()
+        );
+        Ok(store)
+    }
+
+    /// Save the proofs of this round under `dir`, replacing the ones of the previous round.
+    pub fn save(&self, dir: &Path) -> Result<(), PoRError> {
+        let start = std::time::Instant::now();
+        fs::create_dir_all(dir)?;
+        for (level, proofs) in self.current.iter().enumerate() {
+            let path = dir.join(format!("level_{}.bin", level));
+            let mut bytes = Vec::new();
+            let write_proofs = |dst: &mut Vec<u8>| -> IoResult<()> {
+                dst.write_usize(proofs.len())?;
+                proofs.iter().try_for_each(|(key, proof)| {
+                    dst.write_all(key)?;
+                    dst.write_proof_with_public_inputs(proof)
+                })
+            };
+            write_proofs(&mut bytes).map_err(|_| {
+                PoRError::InvalidProofStore(format!("fail to serialize {:?}", path))
+            })?;
+            fs::write(path, bytes)?;
+        }
+
+        let manifest = ProofStoreManifest {
+            round_num: self.round_num,
+            salt_epoch: self.salt_epoch,
+            circuit_digests: self.circuit_digests.clone(),
+        };
+        let manifest_bytes =
+            serde_json::to_vec_pretty(&manifest).expect("fail to serialize proof store manifest");
+        fs::write(dir.join(PROOF_STORE_MANIFEST_FILENAME), manifest_bytes)?;
+        tracing::info!("save proofs to {:?} in {:?}", dir, start.elapsed());
+        Ok(())
+    }
+
+    /// The key of a batch proof, i.e., the root of its merkle sum tree together with the token amounts of its accounts, to which the
+    /// root does not commit if the leaf hashes only commit to the sums.
+    pub fn batch_key(root: &MerkleSumNode, accounts: &[Account]) -> ProofKey {
+        let mut hasher = Sha256::new();
+        hasher.update(root.to_bytes());
+        accounts.iter().for_each(|account| {
+            account.equity.iter().chain(account.debt.iter()).for_each(|amount| {
+                hasher.update(amount.to_canonical_u64().to_le_bytes());
+            })
+        });
+        hasher.finalize().into()
+    }
+
+    /// The key of a recursive proof, i.e., the public inputs of its subproofs, from which its own public inputs follow.
+    pub fn recursive_key(subproofs: &[ProofWithPublicInputs<F, C, D>]) -> ProofKey {
+        let mut hasher = Sha256::new();
+        subproofs.iter().flat_map(|proof| proof.public_inputs.iter()).for_each(|x| {
+            hasher.update(x.to_canonical_u64().to_le_bytes());
+        });
+        hasher.finalize().into()
+    }
+
+    /// The number of proofs of the previous round reused at `level` so far.
+    pub fn reused_proof_num(&self, level: usize) -> usize {
+        self.reused_proof_nums.get(level).copied().unwrap_or(0)
+    }
+
+    /// The proof of the previous round at `level` with the key.
+    pub fn get(&self, level: usize, key: &ProofKey) -> Option<&ProofWithPublicInputs<F, C, D>> {
+        self.previous.get(level)?.get(key)
+    }
+
+    /// Store a proof of this round at `level`, which is ignored at the root level.
+    pub fn insert(&mut self, level: usize, key: ProofKey, proof: &ProofWithPublicInputs<F, C, D>) {
+        if let Some(proofs) = self.current.get_mut(level) {
+            proofs.insert(key, proof.clone());
+        }
+    }
+
+    /// Prove the batches of accounts with `prove`, but reuse the proof of the previous round of every batch whose merkle sum tree root in
+    /// `batch_roots` and token amounts are unchanged, such that `prove` only gets the changed batches and their roots. The proofs of this
+    /// round are stored.
+    pub fn prove_changed_batches(
+        &mut self,
+        accounts: Vec<Account>,
+        batch_size: usize,
+        batch_roots: &[MerkleSumNode],
+        prove: impl FnOnce(
+            Vec<Account>,
+            Vec<MerkleSumNode>,
+        ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError>,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        assert_eq!(accounts.len(), batch_roots.len() * batch_size);
+        let reused_proofs: Vec<Option<ProofWithPublicInputs<F, C, D>>> = accounts
+            .chunks(batch_size)
+            .zip(batch_roots.iter())
+            .map(|(accounts, root)| self.get(0, &Self::batch_key(root, accounts)).cloned())
+            .collect();
+        let (changed_accounts, changed_roots): (Vec<Vec<Account>>, Vec<MerkleSumNode>) = accounts
+            .chunks(batch_size)
+            .zip(batch_roots.iter())
+            .zip(reused_proofs.iter())
+            .filter(|(_, reused_proof)| reused_proof.is_none())
+            .map(|((accounts, root), _)| (accounts.to_vec(), *root))
+            .unzip();
+        tracing::info!(
+            "reuse {} of {} batch proofs of the previous round",
+            batch_roots.len() - changed_roots.len(),
+            batch_roots.len()
+        );
+        self.reused_proof_nums[0] += batch_roots.len() - changed_roots.len();
+
+        let mut changed_proofs = prove(changed_accounts.concat(), changed_roots)?.into_iter();
+        let proofs: Vec<ProofWithPublicInputs<F, C, D>> = reused_proofs
+            .into_iter()
+            .map(|reused_proof| reused_proof.unwrap_or_else(|| changed_proofs.next().unwrap()))
+            .collect();
+        self.insert_batches(&accounts, batch_size, batch_roots, &proofs);
+        Ok(proofs)
+    }
+
+    /// Prove the subproofs at `level` with `prove`, but reuse the proof of the previous round of every recursive node whose subproofs
+    /// have unchanged public inputs, such that `prove` only gets the subproofs of the changed nodes. The proofs of this round are stored.
+    pub fn prove_changed_subproofs(
+        &mut self,
+        level: usize,
+        subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
+        recursion_branchout_num: usize,
+        prove: impl FnOnce(
+            Vec<ProofWithPublicInputs<F, C, D>>,
+        ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError>,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        assert_eq!(subproofs.len() % recursion_branchout_num, 0);
+        let reused_proofs: Vec<Option<ProofWithPublicInputs<F, C, D>>> = subproofs
+            .chunks(recursion_branchout_num)
+            .map(|subproofs| self.get(level, &Self::recursive_key(subproofs)).cloned())
+            .collect();
+        let changed_subproofs: Vec<ProofWithPublicInputs<F, C, D>> = subproofs
+            .chunks(recursion_branchout_num)
+            .zip(reused_proofs.iter())
+            .filter(|(_, reused_proof)| reused_proof.is_none())
+            .flat_map(|(subproofs, _)| subproofs.to_vec())
+            .collect();
+        let reused_proof_num =
+            reused_proofs.len() - changed_subproofs.len() / recursion_branchout_num;
+        tracing::info!(
+            "reuse {} of {} recursive proofs of the previous round at level {}",
+            reused_proof_num,
+            reused_proofs.len(),
+            level
+        );
+        if let Some(num) = self.reused_proof_nums.get_mut(level) {
+            *num += reused_proof_num;
+        }
+
+        let mut changed_proofs = prove(changed_subproofs)?.into_iter();
+        let proofs: Vec<ProofWithPublicInputs<F, C, D>> = reused_proofs
+            .into_iter()
+            .map(|reused_proof| reused_proof.unwrap_or_else(|| changed_proofs.next().unwrap()))
+            .collect();
+        self.insert_recursive(level, &subproofs, recursion_branchout_num, &proofs);
+        Ok(proofs)
+    }
+
+    /// Store the batch proofs of this round, one for every `batch_size` accounts with the root of its merkle sum tree.
+    pub fn insert_batches(
+        &mut self,
+        accounts: &[Account],
+        batch_size: usize,
+        batch_roots: &[MerkleSumNode],
+        proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) {
+        assert_eq!(batch_roots.len(), proofs.len());
+        accounts.chunks(batch_size).zip(batch_roots.iter()).zip(proofs.iter()).for_each(
+            |((accounts, root), proof)| self.insert(0, Self::batch_key(root, accounts), proof),
+        );
+    }
+
+    /// Store the recursive proofs of this round at `level`, one for every `recursion_branchout_num` subproofs.
+    pub fn insert_recursive(
+        &mut self,
+        level: usize,
+        subproofs: &[ProofWithPublicInputs<F, C, D>],
+        recursion_branchout_num: usize,
+        proofs: &[ProofWithPublicInputs<F, C, D>],
+    ) {
+        assert_eq!(subproofs.len(), proofs.len() * recursion_branchout_num);
+        subproofs.chunks(recursion_branchout_num).zip(proofs.iter()).for_each(
+            |(subproofs, proof)| self.insert(level, Self::recursive_key(subproofs), proof),
+        );
+    }
+}
@@ -1,8 +1,5 @@
 use plonky2::{
-    hash::{
-        hash_types::{HashOut, RichField},
-        poseidon::PoseidonHash,
-    },
+    hash::{hash_types::HashOut, poseidon::PoseidonHash},
     iop::witness::PartialWitness,
     plonk::{
         circuit_data::{CircuitData, VerifierOnlyCircuitData},
@@ -11,28 +8,28 @@ use plonky2::{
         prover::prove,
     },
 };
-use plonky2_field::extension::Extendable;
-
 use tracing::error;
 
 use crate::{
     circuit_utils::prove_timing,
-    types::{D, F},
+    merkle_sum_tree::MerkleSumNode,
+    types::{C, D, F},
 };
 
-use super::recursive_circuit::RecursiveTargets;
+use super::recursive_circuit::{MergeTargets, RecursiveTargets, RoundInfo};
 
-pub struct RecursiveProver<C: GenericConfig<D, F = F>, const N: usize> {
+pub struct RecursiveProver<C: GenericConfig<D, F = F>> {
     // pub batch_id: usize,
-    pub sub_proofs: [ProofWithPublicInputs<F, C, D>; N],
+    pub sub_proofs: Vec<ProofWithPublicInputs<F, C, D>>, // as many as the subproofs of the recursive targets
     pub sub_circuit_vd: VerifierOnlyCircuitData<C, D>,
+    pub round: RoundInfo, // only used by the root circuit
 }
 
-impl<C: GenericConfig<D, F = F>, const N: usize> RecursiveProver<C, N> {
+impl<C: GenericConfig<D, F = F>> RecursiveProver<C> {
     /// Get proof with a pre-compiled merkle sum circuit and recursive targets. In this method we do not need to build the circuit as we use a pre-built circuit.
     pub fn get_proof_with_circuit_data(
         &self,
-        recursive_targets: RecursiveTargets<N>,
+        recursive_targets: RecursiveTargets,
         cd: &CircuitData<F, C, D>,
     ) -> ProofWithPublicInputs<F, C, D>
     where
@@ -41,7 +38,61 @@ impl<C: GenericConfig<D, F = F>, const N: usize> RecursiveProver<C, N> {
         let mut pw = PartialWitness::<F>::new();
         let CircuitData { prover_only, common, .. } = &cd;
 
-        recursive_targets.set_targets(&mut pw, self.sub_proofs.to_vec(), &self.sub_circuit_vd);
+        recursive_targets.set_targets(
+            &mut pw,
+            self.sub_proofs.clone(),
+            &self.sub_circuit_vd,
+            &self.round,
+        );
+
+        let mut t = prove_timing();
+        let proof_res = prove(prover_only, common, pw, &mut t);
+
+        match proof_res {
+            Ok(proof) => {
+                let proof_verification_res = cd.verify(proof.clone());
+                match proof_verification_res {
+                    Ok(_) => proof,
+                    Err(e) => {
+                        error!("Proof verification failed: {:?}", e);
+                        panic!("Proof verification failed!");
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Proof generation failed: {:?}", e);
+                panic!("Proof generation failed!");
+            }
+        }
+    }
+}
+
+pub struct MergeProver {
+    pub top_proof: ProofWithPublicInputs<F, C, D>,
+    pub top_circuit_vd: VerifierOnlyCircuitData<C, D>,
+    pub last_batch_proof: ProofWithPublicInputs<F, C, D>,
+    pub last_batch_circuit_vd: VerifierOnlyCircuitData<C, D>,
+    pub round: RoundInfo,
+}
+
+impl MergeProver {
+    /// Get proof with a pre-compiled merge circuit and merge targets.
+    pub fn get_proof_with_circuit_data(
+        &self,
+        merge_targets: &MergeTargets,
+        cd: &CircuitData<F, C, D>,
+    ) -> ProofWithPublicInputs<F, C, D> {
+        let mut pw = PartialWitness::<F>::new();
+        let CircuitData { prover_only, common, .. } = &cd;
+
+        merge_targets.set_targets(
+            &mut pw,
+            &self.top_proof,
+            &self.top_circuit_vd,
+            &self.last_batch_proof,
+            &self.last_batch_circuit_vd,
+            &self.round,
+        );
 
         let mut t = prove_timing();
         let proof_res = prove(prover_only, common, pw, &mut t);
@@ -65,10 +116,9 @@ impl<C: GenericConfig<D, F = F>, const N: usize> RecursiveProver<C, N> {
     }
 }
 
-pub fn hash_n_subhashes<F: RichField + Extendable<D>, const D: usize>(
-    hashes: &Vec<HashOut<F>>,
-) -> HashOut<F> {
-    let inputs: Vec<F> = hashes.iter().map(|h| h.elements.to_vec()).flatten().collect();
+/// Hash of a parent node in the recursive tree, i.e., H(child_0.hash, child_0.sum_equity, child_0.sum_debt, child_1.hash, ...).
+pub fn hash_n_subnodes(nodes: &[MerkleSumNode]) -> HashOut<F> {
+    let inputs: Vec<F> = nodes.iter().map(|node| node.get_hash_inputs()).flatten().collect();
     let hash = PoseidonHash::hash_no_pad(inputs.as_slice());
     hash
 }
@@ -12,21 +12,66 @@ use plonky2::{
         config::{AlgebraicHasher, GenericConfig},
         proof::{ProofWithPublicInputs, ProofWithPublicInputsTarget},
     },
+    util::serialization::{Buffer, IoResult, Read, Write},
 };
 
+use plonky2_field::types::{Field, PrimeField64};
+
 use crate::merkle_sum_prover::circuits::merkle_sum_circuit::MerkleSumNodeTarget;
 
-use crate::types::{C, D, F};
+use crate::{
+    types::{C, D, F},
+    valuation::Valuation,
+};
+
+/// The round of a proof of reserves, i.e., the round number and the unix timestamp of the user data snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoundInfo {
+    pub round_num: u64,
+    pub snapshot_timestamp: u64,
+}
+
+/// Targets of the round info, which are public inputs of the root circuit only, such that a root proof cannot be relabelled as another round.
+#[derive(Debug, Clone, Copy)]
+pub struct RoundTargets {
+    pub round_num: Target,
+    pub snapshot_timestamp: Target,
+}
+
+impl RoundTargets {
+    pub fn new_public_inputs(builder: &mut CircuitBuilder<F, D>) -> Self {
+        let round_num = builder.add_virtual_public_input();
+        let snapshot_timestamp = builder.add_virtual_public_input();
+        Self { round_num, snapshot_timestamp }
+    }
+
+    pub fn set_targets(&self, pw: &mut PartialWitness<F>, round: &RoundInfo) {
+        pw.set_target(self.round_num, F::from_canonical_u64(round.round_num));
+        pw.set_target(self.snapshot_timestamp, F::from_canonical_u64(round.snapshot_timestamp));
+    }
+
+    pub fn serialize(&self, dst: &mut Vec<u8>) -> IoResult<()> {
+        dst.write_target(self.round_num)?;
+        dst.write_target(self.snapshot_timestamp)
+    }
+
+    pub fn deserialize(src: &mut Buffer) -> IoResult<Self> {
+        let round_num = src.read_target()?;
+        let snapshot_timestamp = src.read_target()?;
+        Ok(Self { round_num, snapshot_timestamp })
+    }
+}
 
 /// Struct representing the targets of a recusive circuit. Since we have the same type of subproofs, we only need one type of verifier circuit as
-/// we can verify all the targets using the same circuit.
+/// we can verify all the targets using the same circuit. The number of subproofs, i.e., the recursion branch-out, is the length of `proof_with_pub_input_targets`.
 #[derive(Clone)]
-pub struct RecursiveTargets<const N: usize> {
+pub struct RecursiveTargets {
     pub proof_with_pub_input_targets: Vec<ProofWithPublicInputsTarget<D>>,
-    pub verifier_circuit_target: VerifierCircuitTarget, // Only one needed instead of N
+    pub verifier_circuit_target: VerifierCircuitTarget, // Only one needed instead of one per subproof
+    pub round_targets: Option<RoundTargets>, // only the root circuit exposes the round info
 }
 
-impl<const N: usize> RecursiveTargets<N> {
+impl RecursiveTargets {
     pub fn pub_input_hash_offset() -> std::ops::Range<usize> {
         // the hash offset of recursive pub input is the same as the merkle sum tree node target
         MerkleSumNodeTarget::pub_input_root_hash_offset()
@@ -42,56 +87,159 @@ impl<const N: usize> RecursiveTargets<N> {
         MerkleSumNodeTarget::pub_input_debt_offset()
     }
 
+    pub fn pub_input_token_equity_offset(token_num: usize) -> std::ops::Range<usize> {
+        MerkleSumNodeTarget::pub_input_token_equity_offset(token_num)
+    }
+
+    pub fn pub_input_token_debt_offset(token_num: usize) -> std::ops::Range<usize> {
+        MerkleSumNodeTarget::pub_input_token_debt_offset(token_num)
+    }
+
+    pub fn pub_input_user_count_offset(token_num: usize) -> usize {
+        MerkleSumNodeTarget::pub_input_user_count_offset(token_num)
+    }
+
+    pub fn pub_input_price_offset(token_num: usize) -> std::ops::Range<usize> {
+        Valuation::pub_input_price_offset(token_num)
+    }
+
+    /// The round number and the snapshot timestamp are the last public inputs of the root circuit.
+    pub fn pub_input_round_offset(
+        token_num: usize,
+        valuation: &Valuation,
+    ) -> std::ops::Range<usize> {
+        let start =
+            MerkleSumNodeTarget::num_public_inputs(token_num) + valuation.num_public_inputs();
+        start..start + 2
+    }
+
+    /// Read the round info from the public inputs of a root proof, if it exposes the round info.
+    pub fn round_from_public_inputs(
+        token_num: usize,
+        valuation: &Valuation,
+        public_inputs: &[F],
+    ) -> Option<RoundInfo> {
+        let offset = Self::pub_input_round_offset(token_num, valuation);
+        if public_inputs.len() != offset.end {
+            return None;
+        }
+        Some(RoundInfo {
+            round_num: public_inputs[offset.start].to_canonical_u64(),
+            snapshot_timestamp: public_inputs[offset.start + 1].to_canonical_u64(),
+        })
+    }
+
+    /// Read the number of real users from the public inputs of a root proof, if it exposes the user count.
+    pub fn user_count_from_public_inputs(
+        token_num: usize,
+        valuation: &Valuation,
+        public_inputs: &[F],
+    ) -> Option<u64> {
+        let len = MerkleSumNodeTarget::num_public_inputs(token_num) + valuation.num_public_inputs();
+        let round_len = Self::pub_input_round_offset(token_num, valuation).end;
+        if public_inputs.len() != len && public_inputs.len() != round_len {
+            return None;
+        }
+        Some(public_inputs[Self::pub_input_user_count_offset(token_num)].to_canonical_u64())
+    }
+
+    /// The number of subproofs verified by the circuit.
+    pub fn subproof_num(&self) -> usize {
+        self.proof_with_pub_input_targets.len()
+    }
+
     /// Builds a N-ary merkle sum tree and sets its root as a public input. We use a N-ary merkle sum tree instead of the binary one since it requires less hash gates.
-    pub fn build_recursive_merkle_sum_tree_circuit(&mut self, builder: &mut CircuitBuilder<F, D>) {
+    /// The valuation public inputs of every subproof must be the ones of this round, and are forwarded to the parent.
+    pub fn build_recursive_merkle_sum_tree_circuit(
+        &mut self,
+        builder: &mut CircuitBuilder<F, D>,
+        valuation: &Valuation,
+    ) {
         let mut merkle_sum_tree_node_targets: Vec<MerkleSumNodeTarget> = Vec::new();
+        let mut valuation_targets: Vec<Vec<Target>> = Vec::new();
 
-        (0..N).for_each(|i| {
-            let targets = std::mem::take(&mut self.proof_with_pub_input_targets[i].public_inputs);
+        (0..self.subproof_num()).for_each(|i| {
+            let mut targets =
+                std::mem::take(&mut self.proof_with_pub_input_targets[i].public_inputs);
+            let child_valuation_targets =
+                targets.split_off(targets.len() - valuation.num_public_inputs());
+            valuation.connect_public_inputs(builder, &child_valuation_targets);
             merkle_sum_tree_node_targets.push(MerkleSumNodeTarget::from(targets));
+            valuation_targets.push(child_valuation_targets);
         });
 
-        let parent_merkle_sum_node_target = MerkleSumNodeTarget::get_parent_from_children::<N>(
-            builder,
-            &merkle_sum_tree_node_targets,
-        );
+        let parent_merkle_sum_node_target =
+            MerkleSumNodeTarget::get_parent_from_children(builder, &merkle_sum_tree_node_targets);
 
-        (0..N).for_each(|i| {
-            let public_input_target = Vec::<Target>::from(merkle_sum_tree_node_targets[i]);
+        (0..self.subproof_num()).for_each(|i| {
+            let mut public_input_target =
+                Vec::<Target>::from(merkle_sum_tree_node_targets[i].clone());
+            public_input_target.extend(valuation_targets[i].iter());
             self.proof_with_pub_input_targets[i].public_inputs = public_input_target;
         });
 
         parent_merkle_sum_node_target.register_as_public_input(builder);
+        valuation.register_public_inputs(builder);
     }
 
-    /// Sets recursive targets with values from subproof PIs and the verifier cd.
+    /// Sets recursive targets with values from subproof PIs and the verifier cd, and the round info if this is the root circuit.
     pub fn set_targets<C: GenericConfig<D, F = F>>(
         &self,
         pw: &mut PartialWitness<F>,
         sub_proofs: Vec<ProofWithPublicInputs<F, C, D>>,
         inner_circuit_vd: &VerifierOnlyCircuitData<C, D>,
+        round: &RoundInfo,
     ) where
         C::Hasher: AlgebraicHasher<F>,
     {
+        assert_eq!(self.subproof_num(), sub_proofs.len());
         pw.set_verifier_data_target(&self.verifier_circuit_target, inner_circuit_vd);
 
-        (0..N).for_each(|i| {
+        (0..self.subproof_num()).for_each(|i| {
             pw.set_proof_with_pis_target(&self.proof_with_pub_input_targets[i], &sub_proofs[i]);
         });
+
+        if let Some(round_targets) = &self.round_targets {
+            round_targets.set_targets(pw, round);
+        }
+    }
+
+    pub fn serialize(&self, dst: &mut Vec<u8>) -> IoResult<()> {
+        dst.write_usize(self.subproof_num())?;
+        for proof_with_pub_input_target in self.proof_with_pub_input_targets.iter() {
+            dst.write_target_proof_with_public_inputs(proof_with_pub_input_target)?;
+        }
+        dst.write_target_verifier_circuit(&self.verifier_circuit_target)?;
+        dst.write_bool(self.round_targets.is_some())?;
+        if let Some(round_targets) = &self.round_targets {
+            round_targets.serialize(dst)?;
+        }
+        Ok(())
+    }
+
+    pub fn deserialize(src: &mut Buffer) -> IoResult<Self> {
+        let subproof_num = src.read_usize()?;
+        let proof_with_pub_input_targets = (0..subproof_num)
+            .map(|_| src.read_target_proof_with_public_inputs())
+            .collect::<IoResult<Vec<ProofWithPublicInputsTarget<D>>>>()?;
+        let verifier_circuit_target = src.read_target_verifier_circuit()?;
+        let round_targets =
+            if src.read_bool()? { Some(RoundTargets::deserialize(src)?) } else { None };
+        Ok(Self { proof_with_pub_input_targets, verifier_circuit_target, round_targets })
     }
 }
 
-/// We verify N subproofs in the circuit using the verifier CD. We also ensure the verifier data = constant vd_digest in the circuit to ensure the
+/// We verify `subproof_num` subproofs in the circuit using the verifier CD. We also ensure the verifier data = constant vd_digest in the circuit to ensure the
 /// vd is embedded in circuit.
 pub fn verify_n_subproof_circuit<
     // C: GenericConfig<D, F = F>,
     InnerC: GenericConfig<D, F = F>,
-    const N: usize,
 >(
     builder: &mut CircuitBuilder<F, D>,
     subproof_common_cd: &CommonCircuitData<F, D>,
     subproof_verifier_cd: &VerifierOnlyCircuitData<InnerC, D>,
-) -> RecursiveTargets<N>
+    subproof_num: usize,
+) -> RecursiveTargets
 where
     InnerC::Hasher: AlgebraicHasher<F>,
 {
@@ -107,7 +255,7 @@ where
 
     // _inner_verifier_circuit_data.circuit_digest;
     let mut proof_with_pis_targets: Vec<ProofWithPublicInputsTarget<D>> = vec![];
-    (0..N).for_each(|_| {
+    (0..subproof_num).for_each(|_| {
         let proof_with_pub_input_target =
             builder.add_virtual_proof_with_pis::<InnerC>(subproof_common_cd);
         builder.verify_proof::<InnerC>(
@@ -121,20 +269,24 @@ where
     RecursiveTargets {
         verifier_circuit_target: verifier_circuit_targets,
         proof_with_pub_input_targets: proof_with_pis_targets,
+        round_targets: None,
     }
 }
 
-/// build recursive circuit that proves N subproofs and geneate parent merkle sum node targets
+/// build recursive circuit that proves `subproof_num` subproofs and geneate parent merkle sum node targets. The root circuit additionally exposes the
+/// round info as public inputs.
 // This circuit hardcode the constraint that the verifier_circuit_target.circuit_digest must be equal to that inner_verifier_circuit_data.circuit_digest;
 pub fn build_recursive_n_circuit<
     // C: GenericConfig<D, F = F>,
     InnerC: GenericConfig<D, F = F>,
-    const N: usize,
 >(
     inner_common_circuit_data: &CommonCircuitData<F, D>,
     inner_verifier_circuit_data: &VerifierOnlyCircuitData<InnerC, D>,
+    subproof_num: usize,
+    valuation: &Valuation,
+    is_root: bool,
     circuit_config: CircuitConfig,
-) -> (CircuitData<F, C, D>, RecursiveTargets<N>)
+) -> (CircuitData<F, C, D>, RecursiveTargets)
 where
     InnerC::Hasher: AlgebraicHasher<F>,
 {
@@ -143,8 +295,105 @@ where
         &mut builder,
         inner_common_circuit_data,
         inner_verifier_circuit_data,
+        subproof_num,
     );
-    recursive_targets.build_recursive_merkle_sum_tree_circuit(&mut builder);
+    recursive_targets.build_recursive_merkle_sum_tree_circuit(&mut builder, valuation);
+    if is_root {
+        recursive_targets.round_targets = Some(RoundTargets::new_public_inputs(&mut builder));
+    }
     let circuit_data = builder.build::<C>();
     (circuit_data, recursive_targets)
 }
+
+/// Targets of the merge circuit, which verifies the top proof of the recursive tree over the full batches and the proof of the smaller
+/// last batch, and merges their roots. It is the root circuit if there is a last batch, hence its public inputs are the same as the ones
+/// of a root recursive circuit.
+#[derive(Clone)]
+pub struct MergeTargets {
+    pub top_targets: RecursiveTargets,
+    pub last_batch_targets: RecursiveTargets,
+    pub round_targets: RoundTargets,
+}
+
+impl MergeTargets {
+    /// Sets the top proof and the last batch proof with their verifier data, and the round info.
+    pub fn set_targets(
+        &self,
+        pw: &mut PartialWitness<F>,
+        top_proof: &ProofWithPublicInputs<F, C, D>,
+        top_circuit_vd: &VerifierOnlyCircuitData<C, D>,
+        last_batch_proof: &ProofWithPublicInputs<F, C, D>,
+        last_batch_circuit_vd: &VerifierOnlyCircuitData<C, D>,
+        round: &RoundInfo,
+    ) {
+        self.top_targets.set_targets(
+            pw,
+            vec![top_proof.clone()],
+            top_circuit_vd,
+            &RoundInfo::default(),
+        );
+        self.last_batch_targets.set_targets(
+            pw,
+            vec![last_batch_proof.clone()],
+            last_batch_circuit_vd,
+            &RoundInfo::default(),
+        );
+        self.round_targets.set_targets(pw, round);
+    }
+
+    pub fn serialize(&self, dst: &mut Vec<u8>) -> IoResult<()> {
+        self.top_targets.serialize(dst)?;
+        self.last_batch_targets.serialize(dst)?;
+        self.round_targets.serialize(dst)
+    }
+
+    pub fn deserialize(src: &mut Buffer) -> IoResult<Self> {
+        let top_targets = RecursiveTargets::deserialize(src)?;
+        let last_batch_targets = RecursiveTargets::deserialize(src)?;
+        let round_targets = RoundTargets::deserialize(src)?;
+        Ok(Self { top_targets, last_batch_targets, round_targets })
+    }
+}
+
+/// build the merge circuit, whose parent node is the recursive parent of the root of the top circuit (left) and the root of the last
+/// batch circuit (right). Both verifier data are hardcoded in the circuit like in `build_recursive_n_circuit`.
+pub fn build_merge_circuit(
+    top_circuit: &CircuitData<F, C, D>,
+    last_batch_circuit: &CircuitData<F, C, D>,
+    valuation: &Valuation,
+    circuit_config: CircuitConfig,
+) -> (CircuitData<F, C, D>, MergeTargets) {
+    let mut builder = CircuitBuilder::<F, D>::new(circuit_config);
+    let top_targets = verify_n_subproof_circuit::<C>(
+        &mut builder,
+        &top_circuit.common,
+        &top_circuit.verifier_only,
+        1,
+    );
+    let last_batch_targets = verify_n_subproof_circuit::<C>(
+        &mut builder,
+        &last_batch_circuit.common,
+        &last_batch_circuit.verifier_only,
+        1,
+    );
+
+    let children: Vec<MerkleSumNodeTarget> = [&top_targets, &last_batch_targets]
+        .iter()
+        .map(|targets| {
+            let public_inputs = &targets.proof_with_pub_input_targets[0].public_inputs;
+            let (node_targets, valuation_targets) =
+                public_inputs.split_at(public_inputs.len() - valuation.num_public_inputs());
+            valuation.connect_public_inputs(&mut builder, valuation_targets);
+            MerkleSumNodeTarget::from(node_targets.to_vec())
+        })
+        .collect();
+    let parent_merkle_sum_node_target =
+        MerkleSumNodeTarget::get_parent_from_children(&mut builder, &children);
+
+    parent_merkle_sum_node_target.register_as_public_input(&mut builder);
+    valuation.register_public_inputs(&mut builder);
+    let round_targets = RoundTargets::new_public_inputs(&mut builder);
+
+    let circuit_data = builder.build::<C>();
+    (circuit_data, MergeTargets { top_targets, last_batch_targets, round_targets })
+}
@@ -0,0 +1,467 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use ripemd::Ripemd160;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+};
+
+use crate::{error::PoRError, types::MAX_POSITIVE_AMOUNT_LOG, TokenInfo};
+
+/// The address format of a reserve address, which determines how its ownership signature is verified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReserveChain {
+    /// A P2PKH (`1...`) or P2WPKH (`bc1q...`) address, signed with the bitcoin signed message format (base64).
+    Btc,
+    /// An ethereum address, signed with `personal_sign` (hex).
+    Eth,
+}
+
+/// An exchange address holding `balance` of `token`, together with a signature of the snapshot message by the address key.
+/// The balance must be in the same fixed-point unit as the user balances of the token.
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveAddress {
+    pub token: String,
+    pub chain: ReserveChain,
+    pub address: String,
+    #[serde_as(as = "PickFirst<(DisplayFromStr, _)>")]
+    pub balance: u64,
+    pub signature: String,
+}
+
+/// A snapshot of the exchange addresses, where every address signs the same message, e.g., one naming the exchange and the round.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReserveSnapshot {
+    pub message: String,
+    pub addresses: Vec<ReserveAddress>,
+}
+
+impl ReserveSnapshot {
+    pub fn load(path: &str) -> Result<Self, PoRError> {
+        let file = File::open(path).map_err(PoRError::Io)?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| PoRError::InvalidParameter(format!("invalid reserve snapshot: {:?}", e)))
+    }
+
+    /// Verify the ownership signature of every address. An address can only be counted once per token.
+    pub fn verify_ownership(&self) -> Result<(), PoRError> {
+        let mut seen = HashSet::new();
+        for reserve in self.addresses.iter() {
+            if !seen.insert((reserve.token.as_str(), reserve.chain, normalize_address(reserve))) {
+                return Err(PoRError::InvalidParameter(format!(
+                    "address {} is listed more than once for token {}",
+                    reserve.address, reserve.token
+                )));
+            }
+            verify_address_ownership(reserve, &self.message)?;
+        }
+        Ok(())
+    }
+
+    /// The total balance of the addresses of each token.
+    pub fn token_reserves(&self) -> Result<HashMap<String, u64>, PoRError> {
+        let mut reserves: HashMap<String, u64> = HashMap::new();
+        for reserve in self.addresses.iter() {
+            let sum = reserves.entry(reserve.token.clone()).or_default();
+            *sum = sum.checked_add(reserve.balance).ok_or_else(|| {
+                PoRError::InvalidParameter(format!("reserves of token {} overflow", reserve.token))
+            })?;
+        }
+        Ok(reserves)
+    }
+
+    /// The reserves of every token in `tokens`, after verifying the ownership of every address. The reserves are at most
+    /// MAX_POSITIVE_AMOUNT, as required by the solvency circuit.
+    pub fn verified_reserves(&self, tokens: &[String]) -> Result<Vec<u64>, PoRError> {
+        self.verify_ownership()?;
+        let mut token_reserves = self.token_reserves()?;
+        let reserves = tokens
+            .iter()
+            .map(|token| {
+                let reserve = token_reserves.remove(token).unwrap_or(0);
+                if reserve >= 1 << MAX_POSITIVE_AMOUNT_LOG {
+                    return Err(PoRError::InvalidParameter(format!(
+                        "reserves of token {} exceed 2^{}",
+                        token, MAX_POSITIVE_AMOUNT_LOG
+                    )));
+                }
+                Ok(reserve)
+            })
+            .collect::<Result<Vec<u64>, PoRError>>()?;
+        if let Some(token) = token_reserves.keys().next() {
+            return Err(PoRError::InvalidParameter(format!("reserves of unknown token {}", token)));
+        }
+        Ok(reserves)
+    }
+}
+
+fn normalize_address(reserve: &ReserveAddress) -> String {
+    match reserve.chain {
+        // bech32 addresses are case-insensitive, and ethereum addresses only use the case as a checksum.
+        ReserveChain::Btc if reserve.address.starts_with("1") => reserve.address.clone(),
+        _ => reserve.address.to_lowercase(),
+    }
+}
+
+/// Verify that the signature of the message is made by the key of the address.
+pub fn verify_address_ownership(reserve: &ReserveAddress, message: &str) -> Result<(), PoRError> {
+    let owned = match reserve.chain {
+        ReserveChain::Btc => verify_btc_ownership(&reserve.address, message, &reserve.signature),
+        ReserveChain::Eth => verify_eth_ownership(&reserve.address, message, &reserve.signature),
+    };
+    if !owned {
+        tracing::error!("fail to verify the ownership of address {}", reserve.address);
+        return Err(PoRError::InvalidReserveOwnership(reserve.address.clone()));
+    }
+    Ok(())
+}
+
+/// Recover the public key from a 65-byte recoverable signature, i.e., r || s followed by the recovery id.
+fn recover_public_key(prehash: &[u8], rs: &[u8], recovery_id: u8) -> Option<VerifyingKey> {
+    let mut signature = Signature::from_slice(rs).ok()?;
+    let mut recovery_id = RecoveryId::from_byte(recovery_id)?;
+    // k256 only accepts low-s signatures, flip a high-s one together with the parity of R.
+    if let Some(normalized) = signature.normalize_s() {
+        signature = normalized;
+        recovery_id = RecoveryId::new(!recovery_id.is_y_odd(), recovery_id.is_x_reduced());
+    }
+    VerifyingKey::recover_from_prehash(prehash, &signature, recovery_id).ok()
+}
+
+fn hash160(bytes: &[u8]) -> Vec<u8> {
+    Ripemd160::digest(Sha256::digest(bytes)).to_vec()
+}
+
+/// The message hash of the bitcoin signed message format, i.e., the double sha256 of the prefixed message.
+pub fn btc_message_hash(message: &str) -> [u8; 32] {
+    let mut prefixed = b"\x18Bitcoin Signed Message:\n".to_vec();
+    // the length of the message as a compact size.
+    let len = message.len() as u64;
+    match len {
+        0..=0xfc => prefixed.push(len as u8),
+        0xfd..=0xffff => {
+            prefixed.push(0xfd);
+            prefixed.extend((len as u16).to_le_bytes());
+        }
+        0x10000..=0xffffffff => {
+            prefixed.push(0xfe);
+            prefixed.extend((len as u32).to_le_bytes());
+        }
+        _ => {
+            prefixed.push(0xff);
+            prefixed.extend(len.to_le_bytes());
+        }
+    }
+    prefixed.extend(message.as_bytes());
+    Sha256::digest(Sha256::digest(&prefixed)).into()
+}
+
+/// The public key hash of a P2PKH or a P2WPKH address.
+fn btc_pubkey_hash(address: &str) -> Option<Vec<u8>> {
+    if address.to_lowercase().starts_with("bc1") {
+        let (_, version, program) = bech32::segwit::decode(address).ok()?;
+        if version != bech32::segwit::VERSION_0 || program.len() != 20 {
+            return None;
+        }
+        return Some(program);
+    }
+    let payload = bs58::decode(address).with_check(Some(0x00)).into_vec().ok()?;
+    if payload.len() != 21 {
+        return None;
+    }
+    Some(payload[1..].to_vec())
+}
+
+/// Verify a base64 signature in the bitcoin signed message format, whose header byte is 27 + recovery id, plus 4 if
+/// the key is compressed, plus 8 or 12 for segwit addresses (BIP-137).
+pub fn verify_btc_ownership(address: &str, message: &str, signature: &str) -> bool {
+    let (Some(pubkey_hash), Ok(signature)) = (btc_pubkey_hash(address), BASE64.decode(signature))
+    else {
+        return false;
+    };
+    if signature.len() != 65 || !(27..=42).contains(&signature[0]) {
+        return false;
+    }
+    let header = signature[0] - 27;
+    let compressed = header >= 4;
+    let Some(public_key) =
+        recover_public_key(&btc_message_hash(message), &signature[1..], header & 3)
+    else {
+        return false;
+    };
+    let encoded = public_key.to_encoded_point(compressed);
+    hash160(encoded.as_bytes()) == pubkey_hash
+}
+
+/// The message hash of ethereum `personal_sign`, i.e., keccak256 of the EIP-191 prefixed message.
+pub fn eth_message_hash(message: &str) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    hasher.update(format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes());
+    hasher.update(message.as_bytes());
+    hasher.finalize().into()
+}
+
+/// The ethereum address of a public key, i.e., the last 20 bytes of keccak256 of the uncompressed key.
+fn eth_address(public_key: &VerifyingKey) -> Vec<u8> {
+    let encoded = public_key.to_encoded_point(false);
+    Keccak256::digest(&encoded.as_bytes()[1..])[12..].to_vec()
+}
+
+/// Verify a hex signature of `personal_sign`, i.e., r || s || v where v is 27 or 28 (or 0 or 1).
+pub fn verify_eth_ownership(address: &str, message: &str, signature: &str) -> bool {
+    let (Ok(address), Ok(signature)) = (
+        hex::decode(address.trim_start_matches("0x")),
+        hex::decode(signature.trim_start_matches("0x")),
+    ) else {
+        return false;
+    };
+    if address.len() != 20 || signature.len() != 65 {
+        return false;
+    }
+    let recovery_id = match signature[64] {
+        v @ 0..=1 => v,
+        v @ 27..=28 => v - 27,
+        _ => return false,
+    };
+    match recover_public_key(&eth_message_hash(message), &signature[..64], recovery_id) {
+        Some(public_key) => eth_address(&public_key) == address,
+        None => false,
+    }
+}
+
+/// The reserves and liabilities of one token. The liabilities are the net user balances, i.e., equity minus debt, and
+/// zero if the users owe more of the token than they hold.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenSolvency {
+    pub token: String,
+    pub reserves: u64,
+    pub liabilities: u64,
+    /// reserves / liabilities, none if there are no liabilities.
+    pub ratio: Option<f64>,
+}
+
+impl TokenSolvency {
+    fn new(token: String, reserves: u64, liabilities: u64) -> Self {
+        let ratio = match liabilities {
+            0 => None,
+            _ => Some(reserves as f64 / liabilities as f64),
+        };
+        Self { token, reserves, liabilities, ratio }
+    }
+
+    pub fn is_solvent(&self) -> bool {
+        self.reserves >= self.liabilities
+    }
+}
+
+/// The per-token comparison of the verified reserves against the liabilities proven by the root proof.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SolvencyReport {
+    pub message: String,
+    pub tokens: Vec<TokenSolvency>,
+}
+
+impl SolvencyReport {
+    /// Verify the ownership of the reserve addresses and compare their balances with the liabilities of each token.
+    /// Tokens are reported in the order of the liabilities, followed by tokens which only have reserves.
+    pub fn new(reserves: &ReserveSnapshot, liabilities: &[TokenInfo]) -> Result<Self, PoRError> {
+        reserves.verify_ownership()?;
+        let mut token_reserves = reserves.token_reserves()?;
+
+        let mut tokens: Vec<TokenSolvency> = liabilities
+            .iter()
+            .map(|info| {
+                let reserves = token_reserves.remove(&info.token).unwrap_or(0);
+                TokenSolvency::new(
+                    info.token.clone(),
+                    reserves,
+                    u64::try_from(info.balance_sum.max(0)).unwrap_or(u64::MAX),
+                )
+            })
+            .collect();
+        let mut reserve_only_tokens: Vec<(String, u64)> = token_reserves.into_iter().collect();
+        reserve_only_tokens.sort();
+        tokens.extend(
+            reserve_only_tokens
+                .into_iter()
+                .map(|(token, reserves)| TokenSolvency::new(token, reserves, 0)),
+        );
+
+        Ok(Self { message: reserves.message.clone(), tokens })
+    }
+
+    pub fn is_solvent(&self) -> bool {
+        self.tokens.iter().all(|token| token.is_solvent())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use k256::ecdsa::SigningKey;
+
+    use super::*;
+
+    const MESSAGE: &str = "proof of reserves round 3";
+
+    /// The private key 1, whose addresses are well known.
+    fn signing_key() -> SigningKey {
+        let mut secret = [0u8; 32];
+        secret[31] = 1;
+        SigningKey::from_slice(&secret).unwrap()
+    }
+
+    fn sign(prehash: &[u8; 32]) -> ([u8; 64], u8) {
+        let (signature, recovery_id) = signing_key().sign_prehash_recoverable(prehash).unwrap();
+        (signature.to_bytes().into(), recovery_id.to_byte())
+    }
+
+    fn btc_signature(header_base: u8) -> String {
+        let (rs, recovery_id) = sign(&btc_message_hash(MESSAGE));
+        let mut signature = vec![header_base + recovery_id];
+        signature.extend(rs);
+        BASE64.encode(signature)
+    }
+
+    fn eth_signature() -> String {
+        let (rs, recovery_id) = sign(&eth_message_hash(MESSAGE));
+        let mut signature = rs.to_vec();
+        signature.push(27 + recovery_id);
+        format!("0x{}", hex::encode(signature))
+    }
+
+    fn reserve(token: &str, chain: ReserveChain, address: &str, balance: u64) -> ReserveAddress {
+        let signature = match (chain, address.starts_with("bc1")) {
+            (ReserveChain::Btc, true) => btc_signature(39),
+            (ReserveChain::Btc, false) => btc_signature(31),
+            (ReserveChain::Eth, _) => eth_signature(),
+        };
+        ReserveAddress {
+            token: token.to_owned(),
+            chain,
+            address: address.to_owned(),
+            balance,
+            signature,
+        }
+    }
+
+    #[test]
+    fn test_btc_ownership() {
+        // compressed P2PKH and P2WPKH addresses of the private key 1.
+        assert!(verify_btc_ownership(
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH",
+            MESSAGE,
+            &btc_signature(31)
+        ));
+        assert!(verify_btc_ownership(
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            MESSAGE,
+            &btc_signature(39)
+        ));
+        // the uncompressed P2PKH address of the same key.
+        assert!(verify_btc_ownership(
+            "1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm",
+            MESSAGE,
+            &btc_signature(27)
+        ));
+        assert!(!verify_btc_ownership(
+            "1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm",
+            MESSAGE,
+            &btc_signature(31)
+        ));
+
+        // signed by another key or for another message.
+        assert!(!verify_btc_ownership(
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+            MESSAGE,
+            &btc_signature(31)
+        ));
+        assert!(!verify_btc_ownership(
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH",
+            "proof of reserves round 4",
+            &btc_signature(31)
+        ));
+    }
+
+    #[test]
+    fn test_eth_ownership() {
+        let signature = eth_signature();
+        assert!(verify_eth_ownership(
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf",
+            MESSAGE,
+            &signature
+        ));
+        assert!(verify_eth_ownership(
+            "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf",
+            MESSAGE,
+            &signature
+        ));
+        assert!(!verify_eth_ownership(
+            "0x2B5AD5c4795c026514f8317c7a215E218DcCD6cF",
+            MESSAGE,
+            &signature
+        ));
+        assert!(!verify_eth_ownership(
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf",
+            "proof of reserves round 4",
+            &signature
+        ));
+    }
+
+    #[test]
+    fn test_solvency_report() {
+        let snapshot = ReserveSnapshot {
+            message: MESSAGE.to_owned(),
+            addresses: vec![
+                reserve("BTC", ReserveChain::Btc, "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH", 60),
+                reserve("BTC", ReserveChain::Btc, "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", 40),
+                reserve("ETH", ReserveChain::Eth, "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf", 50),
+                reserve("USDT", ReserveChain::Eth, "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf", 7),
+            ],
+        };
+        let liabilities = vec![
+            TokenInfo { token: "BTC".to_owned(), equity_sum: 90, debt_sum: 10, balance_sum: 80 },
+            TokenInfo { token: "ETH".to_owned(), equity_sum: 100, debt_sum: 0, balance_sum: 100 },
+            TokenInfo { token: "XRP".to_owned(), equity_sum: 0, debt_sum: 5, balance_sum: -5 },
+        ];
+
+        let report = SolvencyReport::new(&snapshot, &liabilities).unwrap();
+        assert_eq!(
+            report.tokens,
+            vec![
+                TokenSolvency::new("BTC".to_owned(), 100, 80),
+                TokenSolvency::new("ETH".to_owned(), 50, 100),
+                TokenSolvency::new("XRP".to_owned(), 0, 0),
+                TokenSolvency::new("USDT".to_owned(), 7, 0),
+            ]
+        );
+        assert_eq!(report.tokens[0].ratio, Some(1.25));
+        assert!(!report.is_solvent());
+        assert!(!report.tokens[1].is_solvent());
+
+        let tokens = ["BTC", "ETH", "USDT", "XRP"].map(|token| token.to_owned());
+        assert_eq!(snapshot.verified_reserves(&tokens).unwrap(), vec![100, 50, 7, 0]);
+        assert!(snapshot.verified_reserves(&tokens[..2]).is_err());
+
+        // an address cannot be counted twice.
+        let mut duplicated = snapshot.clone();
+        duplicated.addresses.push(reserve(
+            "ETH",
+            ReserveChain::Eth,
+            "0x7e5f4552091a69125d5dfcb7b8c2659029395bdf",
+            50,
+        ));
+        assert!(SolvencyReport::new(&duplicated, &liabilities).is_err());
+
+        // the signature must be made by the address key.
+        let mut forged = snapshot.clone();
+        forged.addresses[2].address = "0x2B5AD5c4795c026514f8317c7a215E218DcCD6cF".to_owned();
+        assert!(SolvencyReport::new(&forged, &liabilities).is_err());
+    }
+}
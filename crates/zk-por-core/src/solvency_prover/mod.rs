@@ -0,0 +1,2 @@
+pub mod prover;
+pub mod solvency_circuit;
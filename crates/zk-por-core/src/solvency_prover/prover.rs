@@ -0,0 +1,61 @@
+use plonky2::{
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_data::{CircuitData, VerifierOnlyCircuitData},
+        proof::ProofWithPublicInputs,
+        prover::prove,
+    },
+};
+use tracing::error;
+
+use crate::{
+    circuit_utils::prove_timing,
+    types::{C, D, F},
+};
+
+use super::solvency_circuit::SolvencyTargets;
+
+pub struct SolvencyProver {
+    pub root_proof: ProofWithPublicInputs<F, C, D>,
+    pub root_circuit_vd: VerifierOnlyCircuitData<C, D>,
+    pub reserves: Vec<u64>, // one per token, in the same unit as the user balances
+}
+
+impl SolvencyProver {
+    /// Get proof with a pre-compiled solvency circuit and solvency targets.
+    pub fn get_proof_with_circuit_data(
+        &self,
+        solvency_targets: &SolvencyTargets,
+        cd: &CircuitData<F, C, D>,
+    ) -> ProofWithPublicInputs<F, C, D> {
+        let mut pw = PartialWitness::<F>::new();
+        let CircuitData { prover_only, common, .. } = &cd;
+
+        solvency_targets.set_targets(
+            &mut pw,
+            &self.root_proof,
+            &self.root_circuit_vd,
+            &self.reserves,
+        );
+
+        let mut t = prove_timing();
+        let proof_res = prove(prover_only, common, pw, &mut t);
+
+        match proof_res {
+            Ok(proof) => {
+                let proof_verification_res = cd.verify(proof.clone());
+                match proof_verification_res {
+                    Ok(_) => proof,
+                    Err(e) => {
+                        error!("Proof verification failed: {:?}", e);
+                        panic!("Proof verification failed!");
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Proof generation failed: {:?}", e);
+                panic!("Proof generation failed!");
+            }
+        }
+    }
+}
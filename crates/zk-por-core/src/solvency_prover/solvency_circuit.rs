@@ -0,0 +1,147 @@
+use plonky2::{
+    hash::hash_types::NUM_HASH_OUT_ELTS,
+    iop::{
+        target::Target,
+        witness::{PartialWitness, WitnessWrite},
+    },
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, VerifierOnlyCircuitData},
+        proof::ProofWithPublicInputs,
+    },
+};
+use plonky2_field::types::{Field, PrimeField64};
+
+use crate::{
+    circuit_utils::assert_non_negative_unsigned,
+    recursive_prover::recursive_circuit::{verify_n_subproof_circuit, RecursiveTargets, RoundInfo},
+    types::{C, D, F},
+    valuation::Valuation,
+};
+
+/// Targets of the solvency circuit, which verifies the root proof of the liabilities and proves that the reserves of every token
+/// cover its liabilities, i.e., reserves_i + token_debt_i - token_equity_i >= 0.
+///
+/// The public inputs are the root hash, the per-token reserves, the user count and the round info of the root proof, followed by
+/// the per-token equity and debt sums unless the liabilities are hidden.
+#[derive(Clone)]
+pub struct SolvencyTargets {
+    pub root_targets: RecursiveTargets,
+    pub reserves: Vec<Target>,
+}
+
+impl SolvencyTargets {
+    pub fn pub_input_root_hash_offset() -> std::ops::Range<usize> {
+        0..NUM_HASH_OUT_ELTS
+    }
+
+    pub fn pub_input_reserves_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = Self::pub_input_root_hash_offset().end;
+        start..start + token_num
+    }
+
+    pub fn pub_input_user_count_offset(token_num: usize) -> usize {
+        Self::pub_input_reserves_offset(token_num).end
+    }
+
+    pub fn pub_input_round_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = Self::pub_input_user_count_offset(token_num) + 1;
+        start..start + 2
+    }
+
+    /// Only public if the liabilities are not hidden.
+    pub fn pub_input_token_equity_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = Self::pub_input_round_offset(token_num).end;
+        start..start + token_num
+    }
+
+    /// Only public if the liabilities are not hidden.
+    pub fn pub_input_token_debt_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = Self::pub_input_token_equity_offset(token_num).end;
+        start..start + token_num
+    }
+
+    pub fn reserves_from_public_inputs(token_num: usize, public_inputs: &[F]) -> Vec<u64> {
+        public_inputs[Self::pub_input_reserves_offset(token_num)]
+            .iter()
+            .map(|x| x.to_canonical_u64())
+            .collect()
+    }
+
+    pub fn round_from_public_inputs(token_num: usize, public_inputs: &[F]) -> RoundInfo {
+        let offset = Self::pub_input_round_offset(token_num);
+        RoundInfo {
+            round_num: public_inputs[offset.start].to_canonical_u64(),
+            snapshot_timestamp: public_inputs[offset.start + 1].to_canonical_u64(),
+        }
+    }
+
+    /// Sets the root proof, the root verifier data and the reserves of every token.
+    pub fn set_targets(
+        &self,
+        pw: &mut PartialWitness<F>,
+        root_proof: &ProofWithPublicInputs<F, C, D>,
+        root_vd: &VerifierOnlyCircuitData<C, D>,
+        reserves: &[u64],
+    ) {
+        assert_eq!(self.reserves.len(), reserves.len());
+        self.root_targets.set_targets(pw, vec![root_proof.clone()], root_vd, &RoundInfo::default());
+        reserves.iter().zip(self.reserves.iter()).for_each(|(reserve, target)| {
+            pw.set_target(*target, F::from_canonical_u64(*reserve));
+        });
+    }
+}
+
+/// Build the solvency circuit on top of the root circuit of the `CircuitRegistry`. The reserves are public inputs, such that one
+/// circuit serves every round. If `hide_liabilities`, the per-token sums are not exposed, in which case the circuit config should be
+/// zero-knowledge and the root proof itself must not be published.
+pub fn build_solvency_circuit(
+    root_circuit: &CircuitData<F, C, D>,
+    token_num: usize,
+    valuation: &Valuation,
+    hide_liabilities: bool,
+    circuit_config: CircuitConfig,
+) -> (CircuitData<F, C, D>, SolvencyTargets) {
+    let mut builder = CircuitBuilder::<F, D>::new(circuit_config);
+    let root_targets = verify_n_subproof_circuit::<C>(
+        &mut builder,
+        &root_circuit.common,
+        &root_circuit.verifier_only,
+        1,
+    );
+    let root_public_inputs = root_targets.proof_with_pub_input_targets[0].public_inputs.clone();
+    let round_offset = RecursiveTargets::pub_input_round_offset(token_num, valuation);
+    assert_eq!(
+        root_public_inputs.len(),
+        round_offset.end,
+        "the root circuit must expose the round"
+    );
+
+    builder.register_public_inputs(&root_public_inputs[RecursiveTargets::pub_input_hash_offset()]);
+
+    let token_equity =
+        &root_public_inputs[RecursiveTargets::pub_input_token_equity_offset(token_num)];
+    let token_debt = &root_public_inputs[RecursiveTargets::pub_input_token_debt_offset(token_num)];
+    let reserves: Vec<Target> =
+        (0..token_num).map(|_| builder.add_virtual_public_input()).collect();
+    for i in 0..token_num {
+        // the per-token sums are at most the total equity and debt, which lie in [0, MAX_POSITIVE_AMOUNT], hence constraining the
+        // reserves in the same range ensures that the difference cannot wrap around the field.
+        assert_non_negative_unsigned(&mut builder, reserves[i]);
+        let covered = builder.add(reserves[i], token_debt[i]);
+        let diff_between_reserves_liabilities = builder.sub(covered, token_equity[i]);
+        assert_non_negative_unsigned(&mut builder, diff_between_reserves_liabilities);
+    }
+
+    builder.register_public_input(
+        root_public_inputs[RecursiveTargets::pub_input_user_count_offset(token_num)],
+    );
+    builder.register_public_inputs(&root_public_inputs[round_offset]);
+    if !hide_liabilities {
+        builder.register_public_inputs(token_equity);
+        builder.register_public_inputs(token_debt);
+    }
+
+    let circuit_data = builder.build::<C>();
+    (circuit_data, SolvencyTargets { root_targets, reserves })
+}
@@ -1,6 +1,6 @@
 use plonky2::plonk::{
     circuit_data::{CommonCircuitData, VerifierOnlyCircuitData},
-    config::{GenericConfig, PoseidonGoldilocksConfig},
+    config::{GenericConfig, PoseidonBN128GoldilocksConfig, PoseidonGoldilocksConfig},
     proof::ProofWithPublicInputs,
 };
 
@@ -10,6 +10,12 @@ pub const D: usize = 2;
 // Constrict our values to 62 bits.
 pub const MAX_POSITIVE_AMOUNT_LOG: usize = 62;
 
+// Constrict the fixed-point token prices to 32 bits, such that a price-weighted amount cannot wrap around the field.
+pub const MAX_PRICE_LOG: usize = 32;
+
+// Haircut ratios are fixed-point fractions of 2^HAIRCUT_LOG.
+pub const HAIRCUT_LOG: usize = 16;
+
 // Number of accounts in one merkle sum tree batch.
 pub const MERKLE_SUM_TREE_BATCH_SIZE: usize = 1;
 
@@ -18,5 +24,9 @@ pub const ASSETS_KEY: &str = "assets";
 pub type C = PoseidonGoldilocksConfig;
 pub type F = <C as GenericConfig<D>>::F;
 
+// The config of the wrap circuit on top of the root circuit, whose proofs are hashed with Poseidon over BN254 instead of Goldilocks,
+// such that a Groth16 circuit over BN254 verifies them cheaply.
+pub type WrapC = PoseidonBN128GoldilocksConfig;
+
 pub type ProofTuple<F, C, const D: usize> =
     (ProofWithPublicInputs<F, C, D>, VerifierOnlyCircuitData<C, D>, CommonCircuitData<F, D>);
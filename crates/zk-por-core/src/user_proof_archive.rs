@@ -0,0 +1,316 @@
+use memmap2::Mmap;
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{database::UserId, error::PoRError, merkle_proof::MerkleProof};
+
+pub const USER_PROOF_ARCHIVE_MAGIC: &[u8; 8] = b"ZKPORUPA";
+pub const USER_PROOF_ARCHIVE_VERSION: u32 = 1;
+/// The magic, the version in u32 and the entry count in u64.
+const HEADER_BYTES: usize = 8 + 4 + 8;
+/// The user id in 32 bytes, the file offset of the record in u64 and its length in u32.
+const INDEX_ENTRY_BYTES: usize = 32 + 8 + 4;
+
+/// The file offset and the length of the proof record of a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct IndexEntry {
+    user_id: [u8; 32],
+    offset: u64,
+    len: u32,
+}
+
+impl IndexEntry {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.user_id.to_vec();
+        bytes.extend(self.offset.to_le_bytes());
+        bytes.extend(self.len.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        assert_eq!(bytes.len(), INDEX_ENTRY_BYTES);
+        Self {
+            user_id: bytes[0..32].try_into().unwrap(),
+            offset: u64::from_le_bytes(bytes[32..40].try_into().unwrap()),
+            len: u32::from_le_bytes(bytes[40..44].try_into().unwrap()),
+        }
+    }
+}
+
+/// Writes the merkle proofs of all users into a single archive file, instead of a json file per user:
+/// - a header of the magic, the version and the number of entries,
+/// - the index of the entries sorted by user id, each mapping the user id to the offset and length of its record,
+/// - the records, each a proof encoded by `MerkleProof::to_bytes`, in the order they are appended.
+///
+/// The index is written on `finish`, as the proofs are appended in the order of the users in the gmst rather than of their ids.
+pub struct UserProofArchiveWriter {
+    writer: BufWriter<File>,
+    entry_count: usize,
+    entries: Vec<IndexEntry>,
+    offset: u64,
+}
+
+impl UserProofArchiveWriter {
+    /// Create the archive at `path` for exactly `entry_count` proofs, truncating any existing file.
+    pub fn create(path: &Path, entry_count: usize) -> Result<Self, PoRError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(USER_PROOF_ARCHIVE_MAGIC)?;
+        writer.write_all(&USER_PROOF_ARCHIVE_VERSION.to_le_bytes())?;
+        writer.write_all(&(entry_count as u64).to_le_bytes())?;
+        // the space of the index is reserved before the records.
+        let records_offset = (HEADER_BYTES + entry_count * INDEX_ENTRY_BYTES) as u64;
+        writer.seek(SeekFrom::Start(records_offset))?;
+        Ok(Self {
+            writer,
+            entry_count,
+            entries: Vec::with_capacity(entry_count),
+            offset: records_offset,
+        })
+    }
+
+    pub fn append(&mut self, proof: &MerkleProof) -> Result<(), PoRError> {
+        if self.entries.len() == self.entry_count {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "more than {} proofs are appended",
+                self.entry_count
+            )));
+        }
+        let bytes = proof.to_bytes();
+        let user_id = bytes[0..32].try_into().unwrap();
+        self.writer.write_all(&bytes)?;
+        self.entries.push(IndexEntry { user_id, offset: self.offset, len: bytes.len() as u32 });
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Write the index, which requires all `entry_count` proofs to be appended with distinct user ids.
+    pub fn finish(mut self) -> Result<(), PoRError> {
+        if self.entries.len() != self.entry_count {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "{} proofs are appended instead of {}",
+                self.entries.len(),
+                self.entry_count
+            )));
+        }
+        self.entries.sort_unstable();
+        if let Some(pair) = self.entries.windows(2).find(|pair| pair[0].user_id == pair[1].user_id)
+        {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "duplicate user id {}",
+                UserId(pair[0].user_id).to_string()
+            )));
+        }
+
+        self.writer.seek(SeekFrom::Start(HEADER_BYTES as u64))?;
+        for entry in self.entries.iter() {
+            self.writer.write_all(&entry.to_bytes())?;
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the proofs of an archive written by `UserProofArchiveWriter`. The archive is memory-mapped, and a proof is looked up by a
+/// binary search of its user id in the index, such that only the pages of the index and the records read are loaded.
+pub struct UserProofArchive {
+    mmap: Mmap,
+    entry_count: usize,
+}
+
+impl UserProofArchive {
+    /// Open the archive at `path`, checking its header and that its index is sorted by distinct user ids.
+    pub fn open(path: &Path) -> Result<Self, PoRError> {
+        let file = File::open(path)?;
+        // safety: the archive is not supposed to be modified while it is read.
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < HEADER_BYTES || &mmap[0..8] != USER_PROOF_ARCHIVE_MAGIC {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "{:?} is not a user proof archive",
+                path
+            )));
+        }
+        let version = u32::from_le_bytes(mmap[8..12].try_into().unwrap());
+        if version != USER_PROOF_ARCHIVE_VERSION {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "unsupported version {}, expected {}",
+                version, USER_PROOF_ARCHIVE_VERSION
+            )));
+        }
+        let entry_count = u64::from_le_bytes(mmap[12..20].try_into().unwrap()) as usize;
+        if entry_count
+            .checked_mul(INDEX_ENTRY_BYTES)
+            .map_or(true, |index_len| mmap.len() < HEADER_BYTES + index_len)
+        {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "the index of {} entries is truncated",
+                entry_count
+            )));
+        }
+
+        let archive = Self { mmap, entry_count };
+        if (1..entry_count).any(|i| archive.entry(i - 1).user_id >= archive.entry(i).user_id) {
+            return Err(PoRError::InvalidUserProofArchive(
+                "the index is not sorted by distinct user ids".to_string(),
+            ));
+        }
+        Ok(archive)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entry_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_count == 0
+    }
+
+    /// The user id of the `i`-th entry in the order of the index.
+    pub fn user_id_at(&self, i: usize) -> String {
+        UserId(self.entry(i).user_id).to_string()
+    }
+
+    /// The proof of the `i`-th entry in the order of the index.
+    pub fn proof_at(&self, i: usize) -> Result<MerkleProof, PoRError> {
+        let entry = self.entry(i);
+        let start = entry.offset as usize;
+        let end = start.checked_add(entry.len as usize).filter(|end| *end <= self.mmap.len());
+        let Some(end) = end else {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "the record of user {} is out of the archive",
+                UserId(entry.user_id).to_string()
+            )));
+        };
+        let proof = MerkleProof::from_bytes(&self.mmap[start..end])?;
+        if proof.account.id != UserId(entry.user_id).to_string() {
+            return Err(PoRError::InvalidUserProofArchive(format!(
+                "the record of user {} holds the proof of user {}",
+                UserId(entry.user_id).to_string(),
+                proof.account.id
+            )));
+        }
+        Ok(proof)
+    }
+
+    /// The proof of the user with the hex `user_id`, or none if the user is not in the archive.
+    pub fn get(&self, user_id: &str) -> Result<Option<MerkleProof>, PoRError> {
+        let user_id = UserId::from_hex_string(user_id.to_string())?;
+        let mut low = 0;
+        let mut high = self.entry_count;
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.entry(mid).user_id.cmp(&user_id.0) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return self.proof_at(mid).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    fn entry(&self, i: usize) -> IndexEntry {
+        assert!(i < self.entry_count);
+        let start = HEADER_BYTES + i * INDEX_ENTRY_BYTES;
+        IndexEntry::from_bytes(&self.mmap[start..start + INDEX_ENTRY_BYTES])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UserProofArchive, UserProofArchiveWriter};
+    use crate::{
+        account::LeafHashMode,
+        error::PoRError,
+        global::GlobalConfig,
+        merkle_proof::{test::build_gmst_db, MerkleProof},
+        valuation::Valuation,
+    };
+    use std::fs;
+    use tempdir::TempDir;
+
+    fn build_proofs() -> (Vec<MerkleProof>, GlobalConfig, crate::merkle_sum_tree::MerkleSumNode) {
+        let (gmst, db, accounts) = build_gmst_db(
+            GlobalConfig {
+                num_of_tokens: 3,
+                num_of_batches: 5,
+                batch_size: 3,
+                recursion_branchout_num: 4,
+                last_batch_size: Some(2),
+            },
+            &Valuation::default(),
+        );
+        let proofs = accounts
+            .iter()
+            .map(|account| MerkleProof::new_from_account(account, db.clone(), &gmst.cfg).unwrap())
+            .collect();
+        (proofs, gmst.cfg, gmst.get_root().unwrap())
+    }
+
+    #[test]
+    fn test_user_proof_archive() {
+        let (proofs, cfg, root) = build_proofs();
+        let dir = TempDir::new("archive").unwrap();
+        let path = dir.path().join("user_proofs.bin");
+        let mut writer = UserProofArchiveWriter::create(&path, proofs.len()).unwrap();
+        proofs.iter().for_each(|proof| writer.append(proof).unwrap());
+        writer.finish().unwrap();
+
+        let archive = UserProofArchive::open(&path).unwrap();
+        assert_eq!(proofs.len(), archive.len());
+        for proof in proofs.iter() {
+            let archived_proof = archive.get(&proof.account.id).unwrap().unwrap();
+            assert_eq!(proof.account, archived_proof.account);
+            assert_eq!(proof.index, archived_proof.index);
+            archived_proof
+                .verify_merkle_proof(&root, &cfg, LeafHashMode::PerToken, &Valuation::default())
+                .unwrap();
+        }
+        // the entries are in the order of the user ids.
+        let user_ids: Vec<String> = (0..archive.len()).map(|i| archive.user_id_at(i)).collect();
+        let mut sorted_user_ids: Vec<String> =
+            proofs.iter().map(|proof| proof.account.id.clone()).collect();
+        sorted_user_ids.sort();
+        assert_eq!(sorted_user_ids, user_ids);
+        (0..archive.len()).for_each(|i| {
+            assert_eq!(user_ids[i], archive.proof_at(i).unwrap().account.id);
+        });
+
+        assert!(archive.get(&"0".repeat(64)).unwrap().is_none());
+        assert!(archive.get("not a user id").is_err());
+    }
+
+    #[test]
+    fn test_reject_invalid_archive() {
+        let (proofs, _, _) = build_proofs();
+        let dir = TempDir::new("archive").unwrap();
+        let path = dir.path().join("user_proofs.bin");
+
+        // fewer proofs than the entry count.
+        let mut writer = UserProofArchiveWriter::create(&path, proofs.len()).unwrap();
+        writer.append(&proofs[0]).unwrap();
+        assert!(matches!(writer.finish(), Err(PoRError::InvalidUserProofArchive(_))));
+
+        // duplicate user ids.
+        let mut writer = UserProofArchiveWriter::create(&path, 2).unwrap();
+        writer.append(&proofs[0]).unwrap();
+        writer.append(&proofs[0]).unwrap();
+        assert!(matches!(writer.finish(), Err(PoRError::InvalidUserProofArchive(_))));
+
+        let mut writer = UserProofArchiveWriter::create(&path, proofs.len()).unwrap();
+        proofs.iter().for_each(|proof| writer.append(proof).unwrap());
+        writer.finish().unwrap();
+        let bytes = fs::read(&path).unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] ^= 1;
+        fs::write(&path, &wrong_magic).unwrap();
+        assert!(UserProofArchive::open(&path).is_err());
+
+        // a truncated archive cuts off the record of the last user.
+        fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        let archive = UserProofArchive::open(&path).unwrap();
+        assert!((0..archive.len()).any(|i| archive.proof_at(i).is_err()));
+    }
+}
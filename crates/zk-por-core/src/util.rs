@@ -14,11 +14,46 @@ pub fn pad_to_multiple_of(n: usize, multiple: usize) -> usize {
     }
 }
 
+/// The number of nodes at each level of a binary merkle sum tree with `num_of_leaves` leaves, from the leaves to the root.
+/// A level with an odd number of nodes, other than the root, is padded with an empty node, such that every node has a sibling.
+/// For `num_of_leaves` of a power of 2, there is no padding at all.
+pub fn tree_level_sizes(num_of_leaves: usize) -> Vec<usize> {
+    assert!(num_of_leaves > 0, "a tree must have at least one leaf");
+    let mut level_sizes = Vec::new();
+    let mut level_size = num_of_leaves;
+    while level_size > 1 {
+        let padded_level_size = pad_to_multiple_of(level_size, 2);
+        level_sizes.push(padded_level_size);
+        level_size = padded_level_size / 2;
+    }
+    level_sizes.push(1);
+    level_sizes
+}
+
+/// The number of nodes, including the padded ones, of a binary merkle sum tree with `num_of_leaves` leaves.
+pub fn tree_node_num(num_of_leaves: usize) -> usize {
+    tree_level_sizes(num_of_leaves).iter().sum()
+}
+
+/// The level from the leaves (the leaves are at level 0) of the node at `node_idx`, and the index of the first node at that level.
+/// Nodes are indexed level by level, starting from the leaves.
+pub fn locate_node(level_sizes: &[usize], node_idx: usize) -> (usize, usize) {
+    let mut level_start = 0;
+    for (level, level_size) in level_sizes.iter().enumerate() {
+        if node_idx < level_start + level_size {
+            return (level, level_start);
+        }
+        level_start += level_size;
+    }
+    panic!("node index {} is out of a tree of {} nodes", node_idx, level_start);
+}
+
 /// node level is the level from tree root; the root node has level of 0;
 /// `node_idx` is the index of the nodes in a vector; the root node has the largest ndoe_idx
 pub fn get_node_level(batch_size: usize, node_idx: usize) -> usize {
-    let total_nums = 2 * batch_size - 1;
-    ((total_nums - node_idx) as f64).log(2.0).floor() as usize
+    let level_sizes = tree_level_sizes(batch_size);
+    let (level_from_bottom, _) = locate_node(&level_sizes, node_idx);
+    level_sizes.len() - 1 - level_from_bottom
 }
 
 /// Given a hash string, get a hashout
@@ -43,7 +78,9 @@ pub mod test_util {
 
     use crate::util::get_node_level;
 
-    use super::{get_hash_from_hash_string, pad_to_multiple_of};
+    use super::{
+        get_hash_from_hash_string, locate_node, pad_to_multiple_of, tree_level_sizes, tree_node_num,
+    };
 
     #[test]
     fn test_get_node_level() {
@@ -54,6 +91,25 @@ pub mod test_util {
         assert_eq!(get_node_level(8, 0), 3);
         assert_eq!(get_node_level(8, 4), 3);
         assert_eq!(get_node_level(8, 7), 3);
+
+        // 5 leaves, i.e., [0-4, 5e], [6-8, 9e], [10-11], [12]
+        assert_eq!(get_node_level(5, 12), 0);
+        assert_eq!(get_node_level(5, 11), 1);
+        assert_eq!(get_node_level(5, 9), 2);
+        assert_eq!(get_node_level(5, 5), 3);
+    }
+
+    #[test]
+    fn test_tree_level_sizes() {
+        assert_eq!(tree_level_sizes(1), vec![1]);
+        assert_eq!(tree_level_sizes(8), vec![8, 4, 2, 1]);
+        assert_eq!(tree_level_sizes(5), vec![6, 4, 2, 1]);
+        assert_eq!(tree_level_sizes(12), vec![12, 6, 4, 2, 1]);
+        assert_eq!(tree_node_num(8), 15);
+        assert_eq!(tree_node_num(12), 25);
+        assert_eq!(locate_node(&tree_level_sizes(12), 15), (1, 12));
+        assert_eq!(locate_node(&tree_level_sizes(12), 18), (2, 18));
+        assert_eq!(locate_node(&tree_level_sizes(12), 24), (4, 24));
     }
 
     #[test]
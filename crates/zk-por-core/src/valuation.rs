@@ -0,0 +1,403 @@
+use plonky2::{iop::target::Target, plonk::circuit_builder::CircuitBuilder};
+use plonky2_field::types::{Field, PrimeField64};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, fs::File, io::BufReader};
+
+use crate::{
+    circuit_utils::{
+        mul_non_negative_unsigned_by_haircut, mul_non_negative_unsigned_by_price,
+        sum_non_negative_unsigned,
+    },
+    config::ConfigProver,
+    error::PoRError,
+    merkle_sum_prover::circuits::merkle_sum_circuit::MerkleSumNodeTarget,
+    types::{D, F, HAIRCUT_LOG, MAX_PRICE_LOG},
+};
+
+/// How the token balances of an account are valued into the `sum_equity` and `sum_debt` of its merkle sum node.
+///
+/// By default, the raw token amounts are summed up. With a price snapshot, every token amount is multiplied by the
+/// fixed-point price of the token, such that the sums and the non-negative constraint of each user are in USD.
+/// The per-token sums of the merkle sum nodes are always raw token amounts.
+///
+/// With haircuts, the non-negative constraint of each user only counts the haircut equity as collateral, i.e.,
+/// Σ(equity_i * haircut_i * price_i) >= Σ(debt_i * price_i).
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Valuation {
+    /// One fixed-point price per token in `ConfigProver.tokens`, in [1, 2^MAX_PRICE_LOG).
+    #[serde(default)]
+    pub prices: Option<Vec<u64>>,
+    /// One haircut ratio per token in `ConfigProver.tokens`, as a fraction of 2^HAIRCUT_LOG in [0, 2^HAIRCUT_LOG].
+    #[serde(default)]
+    pub haircuts: Option<Vec<u64>>,
+}
+
+impl Valuation {
+    pub fn new_price_weighted(prices: Vec<u64>) -> Result<Self, PoRError> {
+        if let Some(price) = prices.iter().find(|&&price| price == 0 || price >= 1 << MAX_PRICE_LOG)
+        {
+            return Err(PoRError::InvalidParameter(format!(
+                "price {} is not in [1, 2^{})",
+                price, MAX_PRICE_LOG
+            )));
+        }
+        Ok(Self { prices: Some(prices), haircuts: None })
+    }
+
+    /// Apply the haircuts, given as fractions of 2^HAIRCUT_LOG, to the equity of every user.
+    pub fn with_haircuts(mut self, haircuts: Vec<u64>) -> Result<Self, PoRError> {
+        if let Some(haircut) = haircuts.iter().find(|&&haircut| haircut > 1 << HAIRCUT_LOG) {
+            return Err(PoRError::InvalidParameter(format!(
+                "haircut {} is not in [0, 2^{}]",
+                haircut, HAIRCUT_LOG
+            )));
+        }
+        self.haircuts = Some(haircuts);
+        Ok(self)
+    }
+
+    /// Convert the haircut ratios in [0, 1] of the tokens into fractions of 2^HAIRCUT_LOG, rounded down. Tokens without a
+    /// haircut ratio are fully counted as collateral.
+    pub fn haircuts_from_ratios(
+        ratios: &HashMap<String, f64>,
+        tokens: &[String],
+    ) -> Result<Vec<u64>, PoRError> {
+        if let Some(token) = ratios.keys().find(|token| !tokens.contains(token)) {
+            return Err(PoRError::InvalidParameter(format!("haircut for unknown token {}", token)));
+        }
+        tokens
+            .iter()
+            .map(|token| {
+                let ratio = ratios.get(token).copied().unwrap_or(1.0);
+                if !(0.0..=1.0).contains(&ratio) {
+                    return Err(PoRError::InvalidParameter(format!(
+                        "haircut {} of token {} is not in [0, 1]",
+                        ratio, token
+                    )));
+                }
+                Ok((ratio * (1u64 << HAIRCUT_LOG) as f64).floor() as u64)
+            })
+            .collect()
+    }
+
+    /// Load the price snapshot of the tokens from a json file of `{"BTC": "6000000", "ETH": 250000, ...}`.
+    pub fn load_price_snapshot(path: &str, tokens: &[String]) -> Result<Self, PoRError> {
+        let file = File::open(path).map_err(PoRError::Io)?;
+        let snapshot: HashMap<String, Value> = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| PoRError::InvalidParameter(format!("invalid price snapshot: {:?}", e)))?;
+
+        let prices = tokens
+            .iter()
+            .map(|token| {
+                let price = match snapshot.get(token) {
+                    Some(Value::String(price)) => price.parse::<u64>().ok(),
+                    Some(Value::Number(price)) => price.as_u64(),
+                    _ => None,
+                };
+                price.ok_or_else(|| {
+                    PoRError::InvalidParameter(format!("no valid price for token {}", token))
+                })
+            })
+            .collect::<Result<Vec<u64>, PoRError>>()?;
+        Self::new_price_weighted(prices)
+    }
+
+    /// The valuation configured for the prover, i.e., the price snapshot and the haircuts.
+    pub fn from_config(cfg: &ConfigProver) -> Result<Self, PoRError> {
+        let valuation = match &cfg.price_snapshot_path {
+            Some(path) => Self::load_price_snapshot(path, &cfg.tokens)?,
+            None => Self::default(),
+        };
+        match &cfg.haircuts {
+            Some(ratios) => {
+                valuation.with_haircuts(Self::haircuts_from_ratios(ratios, &cfg.tokens)?)
+            }
+            None => Ok(valuation),
+        }
+    }
+
+    pub fn is_price_weighted(&self) -> bool {
+        self.prices.is_some()
+    }
+
+    /// The prices followed by the haircuts, in the order of their public inputs.
+    fn public_input_values(&self) -> Vec<F> {
+        self.prices
+            .iter()
+            .chain(self.haircuts.iter())
+            .flatten()
+            .map(|x| F::from_canonical_u64(*x))
+            .collect()
+    }
+
+    /// The number of public inputs appended to every batch and recursive proof.
+    pub fn num_public_inputs(&self) -> usize {
+        self.prices.as_ref().map(|prices| prices.len()).unwrap_or(0)
+            + self.haircuts.as_ref().map(|haircuts| haircuts.len()).unwrap_or(0)
+    }
+
+    /// The valuation public inputs follow the merkle sum node public inputs, starting with the prices if price-weighted.
+    pub fn pub_input_price_offset(token_num: usize) -> std::ops::Range<usize> {
+        let start = MerkleSumNodeTarget::num_public_inputs(token_num);
+        start..start + token_num
+    }
+
+    /// The haircuts follow the prices, or the merkle sum node public inputs if not price-weighted.
+    pub fn pub_input_haircut_offset(&self, token_num: usize) -> std::ops::Range<usize> {
+        let start = if self.is_price_weighted() {
+            Self::pub_input_price_offset(token_num).end
+        } else {
+            MerkleSumNodeTarget::num_public_inputs(token_num)
+        };
+        start..start + token_num
+    }
+
+    /// Read a valuation of the same kind as this one, i.e., with or without prices and haircuts, from the public inputs of a proof.
+    pub fn read_public_inputs(
+        &self,
+        token_num: usize,
+        public_inputs: &[F],
+    ) -> Result<Self, PoRError> {
+        // legacy proofs without valuation public inputs sum up the raw token amounts.
+        if self.num_public_inputs() == 0 {
+            return Ok(Self::default());
+        }
+        let expected_len =
+            MerkleSumNodeTarget::num_public_inputs(token_num) + self.num_public_inputs();
+        if public_inputs.len() < expected_len {
+            return Err(PoRError::InvalidProof);
+        }
+        let read = |offset: std::ops::Range<usize>| {
+            public_inputs[offset].iter().map(|x| x.to_canonical_u64()).collect::<Vec<u64>>()
+        };
+
+        let mut valuation = Self::default();
+        if self.is_price_weighted() {
+            valuation = Self::new_price_weighted(read(Self::pub_input_price_offset(token_num)))?;
+        }
+        if self.haircuts.is_some() {
+            valuation = valuation.with_haircuts(read(self.pub_input_haircut_offset(token_num)))?;
+        }
+        Ok(valuation)
+    }
+
+    /// Value the token amounts, i.e., Σ(amount_i * price_i), or Σ(amount_i) without prices.
+    /// It must be consistent with `Valuation::value_targets`.
+    pub fn value(&self, amounts: &[F]) -> F {
+        self.value_tokens(amounts).into_iter().fold(F::ZERO, |acc, x| acc + x)
+    }
+
+    /// Value every token amount, i.e., amount_i * price_i, or amount_i without prices.
+    fn value_tokens(&self, amounts: &[F]) -> Vec<F> {
+        match &self.prices {
+            Some(prices) => {
+                assert_eq!(prices.len(), amounts.len());
+                amounts
+                    .iter()
+                    .zip(prices.iter())
+                    .map(|(amount, price)| *amount * F::from_canonical_u64(*price))
+                    .collect()
+            }
+            None => amounts.to_vec(),
+        }
+    }
+
+    /// The collateral of the equity, i.e., Σ(floor(equity_i * price_i * haircut_i / 2^HAIRCUT_LOG)), or the valued equity without
+    /// haircuts. It must be consistent with `Valuation::collateral_targets`.
+    pub fn collateral(&self, equity: &[F]) -> F {
+        let valued = self.value_tokens(equity);
+        match &self.haircuts {
+            Some(haircuts) => {
+                assert_eq!(haircuts.len(), valued.len());
+                valued.iter().zip(haircuts.iter()).fold(F::ZERO, |acc, (value, haircut)| {
+                    let product = value.to_canonical_u64() as u128 * *haircut as u128;
+                    acc + F::from_canonical_u64((product >> HAIRCUT_LOG) as u64)
+                })
+            }
+            None => valued.into_iter().fold(F::ZERO, |acc, x| acc + x),
+        }
+    }
+
+    /// Value every token amount target in the circuit. The valued amounts are not range-checked yet.
+    pub fn value_token_targets(
+        &self,
+        builder: &mut CircuitBuilder<F, D>,
+        amounts: &[Target],
+    ) -> Vec<Target> {
+        match &self.prices {
+            Some(prices) => {
+                assert_eq!(prices.len(), amounts.len());
+                amounts
+                    .iter()
+                    .zip(prices.iter())
+                    .map(|(amount, price)| {
+                        mul_non_negative_unsigned_by_price(
+                            builder,
+                            *amount,
+                            F::from_canonical_u64(*price),
+                        )
+                    })
+                    .collect()
+            }
+            None => amounts.to_vec(),
+        }
+    }
+
+    /// Value the token amount targets in the circuit. Every token amount, every valued amount and every running sum is
+    /// constrained to MAX_POSITIVE_AMOUNT_LOG bits, such that the sum cannot wrap around the field.
+    pub fn value_targets(&self, builder: &mut CircuitBuilder<F, D>, amounts: &[Target]) -> Target {
+        let valued = self.value_token_targets(builder, amounts);
+        sum_non_negative_unsigned(builder, &valued)
+    }
+
+    /// Sum up the collateral of the valued equity targets, as returned by `Valuation::value_token_targets`. Returns `None` without
+    /// haircuts, in which case the collateral is the valued equity itself.
+    pub fn collateral_targets(
+        &self,
+        builder: &mut CircuitBuilder<F, D>,
+        valued_equity: &[Target],
+    ) -> Option<Target> {
+        let haircuts = self.haircuts.as_ref()?;
+        assert_eq!(haircuts.len(), valued_equity.len());
+        let collateral = valued_equity
+            .iter()
+            .zip(haircuts.iter())
+            .map(|(value, haircut)| {
+                mul_non_negative_unsigned_by_haircut(
+                    builder,
+                    *value,
+                    F::from_canonical_u64(*haircut),
+                )
+            })
+            .collect::<Vec<Target>>();
+        Some(sum_non_negative_unsigned(builder, &collateral))
+    }
+
+    /// Register the prices and haircuts as public inputs, such that they are published in the root proof.
+    pub fn register_public_inputs(&self, builder: &mut CircuitBuilder<F, D>) {
+        let targets =
+            self.public_input_values().into_iter().map(|x| builder.constant(x)).collect::<Vec<_>>();
+        builder.register_public_inputs(&targets);
+    }
+
+    /// Constrain the valuation public inputs of a subproof to be the ones of this round.
+    pub fn connect_public_inputs(&self, builder: &mut CircuitBuilder<F, D>, targets: &[Target]) {
+        assert_eq!(targets.len(), self.num_public_inputs());
+        self.public_input_values().into_iter().zip(targets.iter()).for_each(|(value, target)| {
+            let value_target = builder.constant(value);
+            builder.connect(*target, value_target);
+        });
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use plonky2_field::types::Field;
+    use std::collections::HashMap;
+
+    use super::Valuation;
+    use crate::{circuit_utils::run_circuit_test, types::F};
+
+    #[test]
+    fn test_valuation() {
+        let amounts = vec![F::from_canonical_u64(3), F::from_canonical_u64(5)];
+        assert_eq!(Valuation::default().value(&amounts), F::from_canonical_u64(8));
+
+        let valuation = Valuation::new_price_weighted(vec![100, 7]).unwrap();
+        assert_eq!(valuation.value(&amounts), F::from_canonical_u64(335));
+
+        assert!(Valuation::new_price_weighted(vec![0, 7]).is_err());
+        assert!(Valuation::new_price_weighted(vec![1 << 32, 7]).is_err());
+    }
+
+    #[test]
+    fn test_value_targets() {
+        run_circuit_test(|builder, _pw| {
+            let valuation = Valuation::new_price_weighted(vec![100, (1 << 32) - 1]).unwrap();
+            let amounts = vec![
+                builder.constant(F::from_canonical_u64(3)),
+                builder.constant(F::from_canonical_u64((1 << 30) - 1)),
+            ];
+            let value = valuation.value_targets(builder, &amounts);
+            let expected =
+                builder.constant(F::from_canonical_u64(300 + ((1 << 30) - 1) * ((1 << 32) - 1)));
+            builder.connect(value, expected);
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_value_targets_overflow_panic() {
+        // the valued amount exceeds 62 bits although both the amount and the price are valid.
+        run_circuit_test(|builder, _pw| {
+            let valuation = Valuation::new_price_weighted(vec![(1 << 32) - 1]).unwrap();
+            let amounts = vec![builder.constant(F::from_canonical_u64(1 << 40))];
+            valuation.value_targets(builder, &amounts);
+        });
+    }
+
+    #[test]
+    fn test_haircuts() {
+        let tokens = vec!["BTC".to_owned(), "ETH".to_owned(), "USDT".to_owned()];
+        let ratios = HashMap::from([("BTC".to_owned(), 0.95), ("ETH".to_owned(), 0.5)]);
+        let haircuts = Valuation::haircuts_from_ratios(&ratios, &tokens).unwrap();
+        assert_eq!(haircuts, vec![62259, 1 << 15, 1 << 16]);
+
+        let unknown = HashMap::from([("DOGE".to_owned(), 0.5)]);
+        assert!(Valuation::haircuts_from_ratios(&unknown, &tokens).is_err());
+        let invalid = HashMap::from([("BTC".to_owned(), 1.5)]);
+        assert!(Valuation::haircuts_from_ratios(&invalid, &tokens).is_err());
+        assert!(Valuation::default().with_haircuts(vec![(1 << 16) + 1]).is_err());
+
+        let valuation = Valuation::new_price_weighted(vec![100, 7, 1])
+            .unwrap()
+            .with_haircuts(haircuts)
+            .unwrap();
+        let equity =
+            vec![F::from_canonical_u64(3), F::from_canonical_u64(5), F::from_canonical_u64(9)];
+        // floor(300 * 0.95) + floor(35 * 0.5) + 9
+        assert_eq!(valuation.collateral(&equity), F::from_canonical_u64(285 + 17 + 9));
+        assert_eq!(valuation.value(&equity), F::from_canonical_u64(344));
+    }
+
+    #[test]
+    fn test_collateral_targets() {
+        let valuation = Valuation::new_price_weighted(vec![100, 7])
+            .unwrap()
+            .with_haircuts(vec![62259, 1 << 15])
+            .unwrap();
+        let equity = vec![F::from_canonical_u64(3), F::from_canonical_u64(5)];
+        let expected_collateral = valuation.collateral(&equity);
+        run_circuit_test(|builder, _pw| {
+            let equity = equity.into_iter().map(|x| builder.constant(x)).collect::<Vec<_>>();
+            let valued_equity = valuation.value_token_targets(builder, &equity);
+            let collateral = valuation.collateral_targets(builder, &valued_equity).unwrap();
+            let expected = builder.constant(expected_collateral);
+            builder.connect(collateral, expected);
+        });
+        assert!(Valuation::default().haircuts.is_none());
+    }
+
+    #[test]
+    fn test_read_public_inputs() {
+        let token_num = 2;
+        let valuation = Valuation::default().with_haircuts(vec![1 << 15, 1 << 16]).unwrap();
+        let mut public_inputs = vec![F::ZERO; 6 + 2 * token_num + 1];
+        public_inputs.extend(valuation.public_input_values());
+        assert_eq!(valuation.read_public_inputs(token_num, &public_inputs).unwrap(), valuation);
+
+        // a valuation with prices expects more public inputs.
+        let price_weighted = Valuation::new_price_weighted(vec![1, 1])
+            .unwrap()
+            .with_haircuts(vec![1 << 15, 1 << 16])
+            .unwrap();
+        assert!(price_weighted.read_public_inputs(token_num, &public_inputs).is_err());
+
+        // legacy root proofs only expose the root hash and the total equity and debt.
+        assert_eq!(
+            Valuation::default().read_public_inputs(token_num, &[F::ZERO; 6]).unwrap(),
+            Valuation::default()
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use plonky2::{
+    plonk::{
+        circuit_data::{CircuitData, VerifierCircuitData, VerifierOnlyCircuitData},
+        proof::ProofWithPublicInputs,
+    },
+    util::serialization::DefaultGateSerializer,
+};
+use std::{fs, path::Path};
+
+use crate::{
+    error::PoRError,
+    types::{WrapC, D, F},
+};
+
+pub const WRAP_DIRNAME: &str = "wrap";
+/// The wrap proof, the common data and the verifier-only data in the serde json encoding of plonky2, which external Groth16 wrappers
+/// over BN254 read.
+pub const WRAP_PROOF_FILENAME: &str = "proof_with_public_inputs.json";
+pub const WRAP_COMMON_DATA_FILENAME: &str = "common_circuit_data.json";
+pub const WRAP_VERIFIER_ONLY_DATA_FILENAME: &str = "verifier_only_circuit_data.json";
+/// The verifier data in the byte encoding of plonky2, as the common data in json cannot be read back.
+pub const WRAP_VERIFIER_DATA_FILENAME: &str = "verifier_circuit_data.bin";
+
+/// The wrap proof together with the verifier data of the wrap circuit, which are dumped to and loaded from a directory.
+pub struct WrapArtifacts {
+    pub proof: ProofWithPublicInputs<F, WrapC, D>,
+    pub verifier_data: VerifierCircuitData<F, WrapC, D>,
+}
+
+impl WrapArtifacts {
+    pub fn new(
+        proof: ProofWithPublicInputs<F, WrapC, D>,
+        circuit: &CircuitData<F, WrapC, D>,
+    ) -> Self {
+        Self { proof, verifier_data: circuit.verifier_data() }
+    }
+
+    /// Dump the artifacts under `dir`, which is created if absent.
+    pub fn dump(&self, dir: &Path) -> Result<(), PoRError> {
+        fs::create_dir_all(dir)?;
+        let to_json = |what: &str, json: serde_json::Result<Vec<u8>>| {
+            json.map_err(|e| {
+                PoRError::InvalidWrapArtifacts(format!("fail to serialize {}: {}", what, e))
+            })
+        };
+        fs::write(
+            dir.join(WRAP_PROOF_FILENAME),
+            to_json("proof", serde_json::to_vec(&self.proof))?,
+        )?;
+        fs::write(
+            dir.join(WRAP_COMMON_DATA_FILENAME),
+            to_json("common data", serde_json::to_vec(&self.verifier_data.common))?,
+        )?;
+        fs::write(
+            dir.join(WRAP_VERIFIER_ONLY_DATA_FILENAME),
+            to_json("verifier-only data", serde_json::to_vec(&self.verifier_data.verifier_only))?,
+        )?;
+        let verifier_data_bytes =
+            self.verifier_data.to_bytes(&DefaultGateSerializer).map_err(|_| {
+                PoRError::InvalidWrapArtifacts("fail to serialize verifier data".to_string())
+            })?;
+        fs::write(dir.join(WRAP_VERIFIER_DATA_FILENAME), verifier_data_bytes)?;
+        Ok(())
+    }
+
+    /// Load the artifacts dumped by `dump` under `dir`. The verifier-only data in json must be the one of the verifier data.
+    pub fn load(dir: &Path) -> Result<Self, PoRError> {
+        let proof: ProofWithPublicInputs<F, WrapC, D> =
+            serde_json::from_slice(&fs::read(dir.join(WRAP_PROOF_FILENAME))?).map_err(|e| {
+                PoRError::InvalidWrapArtifacts(format!("fail to parse proof: {}", e))
+            })?;
+        let verifier_data = VerifierCircuitData::<F, WrapC, D>::from_bytes(
+            fs::read(dir.join(WRAP_VERIFIER_DATA_FILENAME))?,
+            &DefaultGateSerializer,
+        )
+        .map_err(|_| PoRError::InvalidWrapArtifacts("fail to parse verifier data".to_string()))?;
+        let verifier_only: VerifierOnlyCircuitData<WrapC, D> =
+            serde_json::from_slice(&fs::read(dir.join(WRAP_VERIFIER_ONLY_DATA_FILENAME))?)
+                .map_err(|e| {
+                    PoRError::InvalidWrapArtifacts(format!(
+                        "fail to parse verifier-only data: {}",
+                        e
+                    ))
+                })?;
+        if verifier_only != verifier_data.verifier_only {
+            return Err(PoRError::InvalidWrapArtifacts(
+                "the verifier-only data does not match the verifier data".to_string(),
+            ));
+        }
+        Ok(Self { proof, verifier_data })
+    }
+
+    /// Verify the wrap proof natively against the verifier data.
+    pub fn verify(&self) -> Result<(), PoRError> {
+        self.verifier_data.verify(self.proof.clone()).map_err(|_| PoRError::InvalidProof)
+    }
+}
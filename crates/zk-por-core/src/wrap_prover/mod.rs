@@ -0,0 +1,3 @@
+pub mod artifacts;
+pub mod prover;
+pub mod wrap_circuit;
@@ -0,0 +1,55 @@
+use plonky2::{
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_data::{CircuitData, VerifierOnlyCircuitData},
+        proof::ProofWithPublicInputs,
+        prover::prove,
+    },
+};
+use tracing::error;
+
+use crate::{
+    circuit_utils::prove_timing,
+    types::{WrapC, C, D, F},
+};
+
+use super::wrap_circuit::WrapTargets;
+
+pub struct WrapProver {
+    pub root_proof: ProofWithPublicInputs<F, C, D>,
+    pub root_circuit_vd: VerifierOnlyCircuitData<C, D>,
+}
+
+impl WrapProver {
+    /// Get proof with a pre-compiled wrap circuit and wrap targets.
+    pub fn get_proof_with_circuit_data(
+        &self,
+        wrap_targets: &WrapTargets,
+        cd: &CircuitData<F, WrapC, D>,
+    ) -> ProofWithPublicInputs<F, WrapC, D> {
+        let mut pw = PartialWitness::<F>::new();
+        let CircuitData { prover_only, common, .. } = &cd;
+
+        wrap_targets.set_targets(&mut pw, &self.root_proof, &self.root_circuit_vd);
+
+        let mut t = prove_timing();
+        let proof_res = prove(prover_only, common, pw, &mut t);
+
+        match proof_res {
+            Ok(proof) => {
+                let proof_verification_res = cd.verify(proof.clone());
+                match proof_verification_res {
+                    Ok(_) => proof,
+                    Err(e) => {
+                        error!("Proof verification failed: {:?}", e);
+                        panic!("Proof verification failed!");
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Proof generation failed: {:?}", e);
+                panic!("Proof generation failed!");
+            }
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use plonky2::{
+    iop::witness::PartialWitness,
+    plonk::{
+        circuit_builder::CircuitBuilder,
+        circuit_data::{CircuitConfig, CircuitData, CommonCircuitData, VerifierOnlyCircuitData},
+        proof::ProofWithPublicInputs,
+    },
+};
+
+use crate::{
+    recursive_prover::recursive_circuit::{verify_n_subproof_circuit, RecursiveTargets, RoundInfo},
+    types::{WrapC, C, D, F},
+};
+
+/// Targets of the wrap circuit, which verifies the root proof and exposes the public inputs of the root proof unchanged, i.e., at the
+/// offsets of `RecursiveTargets`. Unlike every other circuit, the wrap circuit is proven with `WrapC`, such that its proof is handed
+/// to an external Groth16 wrapper over BN254 for on-chain verification.
+#[derive(Clone)]
+pub struct WrapTargets {
+    pub root_targets: RecursiveTargets,
+}
+
+impl WrapTargets {
+    /// Sets the root proof and the root verifier data.
+    pub fn set_targets(
+        &self,
+        pw: &mut PartialWitness<F>,
+        root_proof: &ProofWithPublicInputs<F, C, D>,
+        root_vd: &VerifierOnlyCircuitData<C, D>,
+    ) {
+        self.root_targets.set_targets(pw, vec![root_proof.clone()], root_vd, &RoundInfo::default());
+    }
+}
+
+/// Build the wrap circuit on top of the root circuit, given by its common data and verifier data, such that a verifier rebuilds it
+/// from the root verifier data in the proof file.
+pub fn build_wrap_circuit(
+    root_common: &CommonCircuitData<F, D>,
+    root_verifier_only: &VerifierOnlyCircuitData<C, D>,
+    circuit_config: CircuitConfig,
+) -> (CircuitData<F, WrapC, D>, WrapTargets) {
+    let mut builder = CircuitBuilder::<F, D>::new(circuit_config);
+    let root_targets =
+        verify_n_subproof_circuit::<C>(&mut builder, root_common, root_verifier_only, 1);
+    let root_public_inputs = root_targets.proof_with_pub_input_targets[0].public_inputs.clone();
+    builder.register_public_inputs(&root_public_inputs);
+
+    let circuit_data = builder.build::<WrapC>();
+    (circuit_data, WrapTargets { root_targets })
+}
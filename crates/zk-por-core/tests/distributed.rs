@@ -0,0 +1,198 @@
+use std::net::TcpListener;
+use zk_por_core::{
+    account::{gen_accounts_with_random_data, Account, LeafHashMode},
+    circuit_config::STANDARD_CONFIG,
+    circuit_registry::registry::CircuitRegistry,
+    distributed::{
+        coordinator::Coordinator,
+        protocol::{
+            circuit_digests, max_message_bytes, read_message, write_message, Request, Response,
+            RoundShape,
+        },
+        worker::{accept_coordinator, run_worker},
+    },
+    e2e::batch_prove_accounts,
+    error::PoRError,
+    merkle_sum_tree::{MerkleSumNode, MerkleSumTree},
+    recursive_prover::recursive_circuit::RoundInfo,
+    valuation::Valuation,
+};
+
+const RECURSION_BRANCHOUT_NUM: usize = 2;
+const BATCH_SIZE: usize = 2;
+const TOKEN_NUM: usize = 2;
+const WORKER_NUM: usize = 3;
+const ROUND: RoundInfo = RoundInfo { round_num: 5, snapshot_timestamp: 1726444800 };
+const WORKER_SECRET: &str = "worker secret";
+// 8 batches, such that every worker proves more than one batch.
+const SHAPE: RoundShape = RoundShape {
+    batch_size: BATCH_SIZE,
+    batch_num: 8,
+    last_batch_size: None,
+    recursion_branchout_num: RECURSION_BRANCHOUT_NUM,
+};
+
+fn init_registry(last_batch_size: Option<usize>) -> CircuitRegistry {
+    CircuitRegistry::init(
+        BATCH_SIZE,
+        last_batch_size,
+        TOKEN_NUM,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+        vec![STANDARD_CONFIG; 3],
+    )
+}
+
+fn batch_roots(accounts: &[Account]) -> Vec<MerkleSumNode> {
+    accounts
+        .chunks(BATCH_SIZE)
+        .map(|accounts| {
+            MerkleSumTree::new_tree_from_accounts(
+                accounts,
+                LeafHashMode::PerToken,
+                &Valuation::default(),
+            )
+            .get_root()
+        })
+        .collect()
+}
+
+#[test]
+fn test_distributed_prove() {
+    let registry = init_registry(None);
+    let listeners: Vec<TcpListener> =
+        (0..WORKER_NUM).map(|_| TcpListener::bind("127.0.0.1:0").unwrap()).collect();
+    let worker_addrs: Vec<String> =
+        listeners.iter().map(|listener| listener.local_addr().unwrap().to_string()).collect();
+
+    std::thread::scope(|s| {
+        for listener in listeners {
+            let registry = &registry;
+            s.spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let shape = accept_coordinator(&mut stream, Some(WORKER_SECRET)).unwrap();
+                assert_eq!(SHAPE, shape);
+                run_worker(stream, registry).unwrap();
+            });
+        }
+
+        let accounts = gen_accounts_with_random_data(BATCH_SIZE * SHAPE.batch_num, TOKEN_NUM);
+        let mut coordinator =
+            Coordinator::connect(&worker_addrs, &registry, SHAPE, Some(WORKER_SECRET)).unwrap();
+        let batch_proofs =
+            coordinator.batch_prove_accounts(&accounts, &batch_roots(&accounts)).unwrap();
+        assert_eq!(SHAPE.batch_num, batch_proofs.len());
+        batch_proofs.iter().zip(batch_roots(&accounts)).for_each(|(proof, root)| {
+            assert_eq!(root, MerkleSumNode::new_from_public_inputs(&proof.public_inputs));
+        });
+
+        let mut last_level_proofs = batch_proofs;
+        for level in 1..=registry.get_recursive_levels() {
+            last_level_proofs =
+                coordinator.prove_subproofs(&last_level_proofs, level, ROUND).unwrap();
+        }
+        assert_eq!(1, last_level_proofs.len());
+        registry.get_root_circuit().verify(last_level_proofs.pop().unwrap()).unwrap();
+        coordinator.shutdown().unwrap();
+    });
+}
+
+#[test]
+fn test_authenticate() {
+    let registry = init_registry(None);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let worker_addr = listener.local_addr().unwrap().to_string();
+
+    std::thread::scope(|s| {
+        let worker = s.spawn(move || {
+            (0..2)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    accept_coordinator(&mut stream, Some(WORKER_SECRET))
+                })
+                .collect::<Vec<_>>()
+        });
+
+        // the coordinator rejects a worker that does not know its secret.
+        let result =
+            Coordinator::connect(&[worker_addr.clone()], &registry, SHAPE, Some("other secret"));
+        assert!(matches!(result, Err(PoRError::Distributed(_))));
+        // the worker rejects a coordinator without the secret before building any circuit.
+        let result = Coordinator::connect(&[worker_addr], &registry, SHAPE, None);
+        assert!(matches!(result, Err(PoRError::Distributed(_))));
+
+        let worker_results = worker.join().unwrap();
+        assert!(worker_results.iter().all(|result| result.is_err()));
+    });
+}
+
+#[test]
+fn test_max_message_bytes() {
+    let registry = init_registry(Some(1));
+    let max_len = max_message_bytes(&registry);
+
+    // the largest requests of the circuits fit into a message.
+    let accounts = gen_accounts_with_random_data(BATCH_SIZE, TOKEN_NUM);
+    assert!(Request::Batch { accounts }.to_bytes().len() <= max_len);
+    let top_level = registry.get_recursive_levels();
+    let top_digest = &registry.get_level_circuit(top_level - 1).verifier_only.circuit_digest;
+    let subproof = registry.get_empty_proof(top_digest).unwrap().to_bytes();
+    let request = Request::Recursive {
+        level: top_level,
+        round: ROUND,
+        subproofs: vec![subproof; RECURSION_BRANCHOUT_NUM],
+    };
+    assert!(request.to_bytes().len() <= max_len);
+
+    // so do the proofs of the last batch and merge circuits.
+    for circuit in [registry.get_last_batch_circuit().unwrap().0, registry.get_root_circuit()] {
+        let proof = registry.get_empty_proof(&circuit.verifier_only.circuit_digest).unwrap();
+        assert!(Response::Proof { proof: proof.to_bytes() }.to_bytes().len() <= max_len);
+    }
+
+    // a longer message is rejected before it is read.
+    let mut bytes = Vec::new();
+    write_message(&mut bytes, &vec![0u8; max_len + 1]).unwrap();
+    assert!(read_message(&mut &bytes[..], max_len).is_err());
+    assert_eq!(max_len + 1, read_message(&mut &bytes[..], max_len + 1).unwrap().len());
+}
+
+#[test]
+fn test_reject_wrong_proof() {
+    let registry = init_registry(None);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let worker_addr = listener.local_addr().unwrap().to_string();
+
+    let accounts = gen_accounts_with_random_data(BATCH_SIZE, TOKEN_NUM);
+    let other_accounts = gen_accounts_with_random_data(BATCH_SIZE, TOKEN_NUM);
+    // a valid proof of another batch.
+    let wrong_proof = batch_prove_accounts(&registry, other_accounts, 1, BATCH_SIZE).pop().unwrap();
+
+    std::thread::scope(|s| {
+        let registry = &registry;
+        s.spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            accept_coordinator(&mut stream, None).unwrap();
+            let setup = Response::Setup { circuit_digests: circuit_digests(registry) };
+            write_message(&mut stream, &setup.to_bytes()).unwrap();
+            let max_len = max_message_bytes(registry);
+            loop {
+                let response =
+                    match Request::from_bytes(&read_message(&mut stream, max_len).unwrap()) {
+                        Ok(Request::Batch { .. }) => {
+                            Response::Proof { proof: wrong_proof.to_bytes() }
+                        }
+                        _ => return,
+                    };
+                write_message(&mut stream, &response.to_bytes()).unwrap();
+            }
+        });
+
+        let mut coordinator = Coordinator::connect(&[worker_addr], registry, SHAPE, None).unwrap();
+        let result = coordinator.batch_prove_accounts(&accounts, &batch_roots(&accounts));
+        assert!(matches!(result, Err(PoRError::Distributed(_))));
+        coordinator.shutdown().unwrap();
+    });
+}
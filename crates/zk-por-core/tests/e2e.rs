@@ -1,16 +1,18 @@
 use plonky2_field::types::Field;
 use zk_por_core::{
-    account::gen_accounts_with_random_data,
+    account::{gen_accounts_with_random_data, Account, LeafHashMode},
     circuit_config::STANDARD_CONFIG,
     circuit_registry::registry::CircuitRegistry,
-    e2e::{batch_prove_accounts, recursive_prove_subproofs},
+    e2e::{batch_prove_accounts, prove_last_batch, recursive_prove_subproofs},
+    global::GlobalConfig,
+    recursive_prover::recursive_circuit::{RecursiveTargets, RoundInfo},
     types::F,
+    valuation::Valuation,
 };
 
 use zk_por_tracing::{init_tracing, TraceConfig};
 
-#[test]
-fn test_prove() {
+fn run_prove(valuation: Valuation) {
     let cfg = TraceConfig {
         prefix: "zkpor".to_string(),
         dir: "logs".to_string(),
@@ -26,10 +28,15 @@ fn test_prove() {
     const RECURSION_BRANCHOUT_NUM: usize = 4;
     let batch_size = 8;
     let token_num = 4;
+    let prices = valuation.prices.clone().unwrap_or(vec![1; token_num]);
 
-    let circuit_registry = CircuitRegistry::<RECURSION_BRANCHOUT_NUM>::init(
+    let circuit_registry = CircuitRegistry::init(
         batch_size,
+        None,
         token_num,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &valuation,
         STANDARD_CONFIG,
         vec![STANDARD_CONFIG; 2],
     );
@@ -37,19 +44,36 @@ fn test_prove() {
     let proving_thread_num = 2;
 
     // a total of 9 batches (3x3) to test for padding in each level.
+    let mut user_count = 0;
     let mut equity_sum = 0;
     let mut debt_sum = 0;
+    let mut token_equity_sums = vec![0; token_num];
+    let mut token_debt_sums = vec![0; token_num];
     let mut batch_proofs = vec![];
     for _ in 0..3 {
-        let accounts = gen_accounts_with_random_data(batch_size * 3, 4);
+        // the last account of every 3 batches is a padding account, which is not counted as a user.
+        let mut accounts = gen_accounts_with_random_data(batch_size * 3 - 1, 4);
+        user_count += accounts.len() as u64;
+        accounts.push(Account::get_empty_account(token_num));
+
+        accounts.iter().for_each(|account| {
+            (0..token_num).for_each(|i| {
+                token_equity_sums[i] += account.equity[i].0;
+                token_debt_sums[i] += account.debt[i].0;
+            });
+        });
 
         equity_sum += accounts
             .iter()
-            .map(|account| account.equity.iter().map(|e| e.0).sum::<u64>())
+            .map(|account| {
+                account.equity.iter().zip(prices.iter()).map(|(e, p)| e.0 * p).sum::<u64>()
+            })
             .sum::<u64>();
         debt_sum += accounts
             .iter()
-            .map(|account| account.debt.iter().map(|e| e.0).sum::<u64>())
+            .map(|account| {
+                account.debt.iter().zip(prices.iter()).map(|(d, p)| d.0 * p).sum::<u64>()
+            })
             .sum::<u64>();
 
         let proofs =
@@ -57,9 +81,140 @@ fn test_prove() {
         batch_proofs.extend(proofs.into_iter());
     }
 
-    let root_proof = recursive_prove_subproofs(batch_proofs, &circuit_registry, proving_thread_num);
+    let round = RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 };
+    let root_proof =
+        recursive_prove_subproofs(batch_proofs, &circuit_registry, proving_thread_num, round, None);
 
     tracing::debug!("equity_sum: {}, debt_sum: {}", equity_sum, debt_sum);
     assert_eq!(F::from_canonical_u64(equity_sum), root_proof.public_inputs[0],);
     assert_eq!(F::from_canonical_u64(debt_sum), root_proof.public_inputs[1],);
+
+    let token_equity_offset = RecursiveTargets::pub_input_token_equity_offset(token_num);
+    let token_debt_offset = RecursiveTargets::pub_input_token_debt_offset(token_num);
+    assert_eq!(
+        token_equity_sums.into_iter().map(F::from_canonical_u64).collect::<Vec<F>>(),
+        root_proof.public_inputs[token_equity_offset].to_vec()
+    );
+    assert_eq!(
+        token_debt_sums.into_iter().map(F::from_canonical_u64).collect::<Vec<F>>(),
+        root_proof.public_inputs[token_debt_offset].to_vec()
+    );
+
+    if valuation.is_price_weighted() {
+        let price_offset = RecursiveTargets::pub_input_price_offset(token_num);
+        assert_eq!(
+            prices.into_iter().map(F::from_canonical_u64).collect::<Vec<F>>(),
+            root_proof.public_inputs[price_offset].to_vec()
+        );
+    }
+    if let Some(haircuts) = &valuation.haircuts {
+        let haircut_offset = valuation.pub_input_haircut_offset(token_num);
+        assert_eq!(
+            haircuts.iter().map(|h| F::from_canonical_u64(*h)).collect::<Vec<F>>(),
+            root_proof.public_inputs[haircut_offset].to_vec()
+        );
+    }
+    assert_eq!(
+        Some(user_count),
+        RecursiveTargets::user_count_from_public_inputs(
+            token_num,
+            &valuation,
+            &root_proof.public_inputs
+        )
+    );
+    assert_eq!(
+        Some(round),
+        RecursiveTargets::round_from_public_inputs(
+            token_num,
+            &valuation,
+            &root_proof.public_inputs
+        )
+    );
+    assert_eq!(
+        valuation.read_public_inputs(token_num, &root_proof.public_inputs).unwrap(),
+        valuation
+    );
+}
+
+#[test]
+fn test_prove() {
+    run_prove(Valuation::default());
+}
+
+#[test]
+fn test_prove_price_weighted() {
+    run_prove(Valuation::new_price_weighted(vec![6_000_000, 250_000, 100, 1]).unwrap());
+}
+
+#[test]
+fn test_prove_haircut() {
+    // the random accounts have one less debt than equity per token, which the haircut equity still covers.
+    let valuation = Valuation::new_price_weighted(vec![6_000_000, 250_000, 100, 1])
+        .unwrap()
+        .with_haircuts(vec![65500, 65500, 65500, 1 << 16])
+        .unwrap();
+    run_prove(valuation);
+}
+
+#[test]
+fn test_prove_last_batch() {
+    const RECURSION_BRANCHOUT_NUM: usize = 4;
+    let batch_size = 3;
+    let token_num = 2;
+    let user_num = 8;
+    let valuation = Valuation::default();
+
+    // 2 full batches of 3 accounts, and a last batch of 2 accounts instead of a padded full batch.
+    let (num_of_batches, last_batch_size) = GlobalConfig::split_batches(user_num, batch_size);
+    assert_eq!((2, Some(2)), (num_of_batches, last_batch_size));
+
+    let circuit_registry = CircuitRegistry::init(
+        batch_size,
+        last_batch_size,
+        token_num,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &valuation,
+        STANDARD_CONFIG,
+        vec![STANDARD_CONFIG],
+    );
+
+    let mut accounts = gen_accounts_with_random_data(user_num, token_num);
+    let equity_sum: u64 =
+        accounts.iter().map(|account| account.equity.iter().map(|e| e.0).sum::<u64>()).sum();
+    let debt_sum: u64 =
+        accounts.iter().map(|account| account.debt.iter().map(|d| d.0).sum::<u64>()).sum();
+    let last_batch_accounts = accounts.split_off(num_of_batches * batch_size);
+
+    let batch_proofs = batch_prove_accounts(&circuit_registry, accounts, 2, batch_size);
+    assert_eq!(num_of_batches, batch_proofs.len());
+    let last_batch_proof = prove_last_batch(&circuit_registry, last_batch_accounts);
+
+    let round = RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 };
+    let root_proof = recursive_prove_subproofs(
+        batch_proofs,
+        &circuit_registry,
+        2,
+        round,
+        Some(last_batch_proof),
+    );
+
+    assert_eq!(F::from_canonical_u64(equity_sum), root_proof.public_inputs[0]);
+    assert_eq!(F::from_canonical_u64(debt_sum), root_proof.public_inputs[1]);
+    assert_eq!(
+        Some(user_num as u64),
+        RecursiveTargets::user_count_from_public_inputs(
+            token_num,
+            &valuation,
+            &root_proof.public_inputs
+        )
+    );
+    assert_eq!(
+        Some(round),
+        RecursiveTargets::round_from_public_inputs(
+            token_num,
+            &valuation,
+            &root_proof.public_inputs
+        )
+    );
 }
@@ -0,0 +1,596 @@
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use plonky2_field::types::Field;
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+use zk_por_core::{
+    account::{gen_accounts_with_random_data, Account, LeafHashMode, SaltSeed},
+    checkpoint::{Checkpoint, CheckpointProver},
+    circuit_config::{get_recursive_circuit_configs, STANDARD_CONFIG},
+    circuit_registry::registry::CircuitRegistry,
+    database::init_db,
+    error::PoRError,
+    global::GlobalMst,
+    merkle_proof::MerkleProof,
+    merkle_sum_tree::MerkleSumNode,
+    parser::{AccountParser, JsonFileManager},
+    pipeline::{
+        LocalProver, PipelineConfig, PipelineProver, ProvingPipeline, UserProofFormat,
+        GLOBAL_INFO_FILENAME, GLOBAL_PROOF_FILENAME, USER_PROOF_ARCHIVE_FILENAME,
+        USER_PROOF_DIRNAME,
+    },
+    proof_file::ProofFormat,
+    proof_store::ProofStore,
+    recursive_prover::recursive_circuit::RoundInfo,
+    types::{C, D, F},
+    user_proof_archive::UserProofArchive,
+    valuation::Valuation,
+    Info, Proof,
+};
+
+const BATCH_SIZE: usize = 4;
+const RECURSION_BRANCHOUT_NUM: usize = 2;
+const TOKEN_NUM: usize = 2;
+// 5 full batches and a last batch of 2 accounts.
+const USER_NUM: usize = BATCH_SIZE * 5 + 2;
+
+/// Reads the accounts from memory, such that a second parser reads the same accounts.
+struct VecAccountParser {
+    accounts: Vec<Account>,
+}
+
+impl AccountParser for VecAccountParser {
+    fn read_n_accounts(
+        &mut self,
+        offset: usize,
+        n: usize,
+        _: &impl JsonFileManager,
+    ) -> Vec<Account> {
+        self.accounts[offset..std::cmp::min(offset + n, self.accounts.len())].to_vec()
+    }
+
+    fn total_num_of_users(&self) -> usize {
+        self.accounts.len()
+    }
+}
+
+fn pipeline_config(batch_size: usize, round: RoundInfo) -> PipelineConfig {
+    PipelineConfig {
+        round_num: round.round_num as usize,
+        salt_epoch: round.round_num as usize,
+        snapshot_timestamp: round.snapshot_timestamp,
+        batch_size,
+        recursion_branchout_num: RECURSION_BRANCHOUT_NUM,
+        tokens: vec!["BTC".to_string(), "ETH".to_string()],
+        leaf_hash_mode: LeafHashMode::PerToken,
+        valuation: Valuation::default(),
+        // 2 batches in every parse round, such that the last parse round holds a full batch and the last batch.
+        per_parse_account_num: batch_size * 2,
+        user_proof_format: UserProofFormat::Json,
+        compress_root_proof: false,
+    }
+}
+
+fn new_pipeline<'a>(
+    cfg: PipelineConfig,
+    accounts: &[Account],
+    registry: &'a CircuitRegistry,
+) -> Result<ProvingPipeline<'a, VecAccountParser>, PoRError> {
+    let global_mst = Arc::new(RwLock::new(GlobalMst::new(cfg.global_config(accounts.len()))));
+    ProvingPipeline::new(
+        cfg,
+        VecAccountParser { accounts: accounts.to_vec() },
+        init_db(None, global_mst.clone()),
+        global_mst,
+        registry,
+        SaltSeed::from_secret("pipeline"),
+    )
+}
+
+/// Prove the accounts through all stages of a pipeline, dumping the artifacts to `output_dir`, and return the root node.
+fn prove_round(
+    registry: &CircuitRegistry,
+    accounts: &[Account],
+    round: RoundInfo,
+    user_proof_format: UserProofFormat,
+    compress_root_proof: bool,
+    output_dir: &Path,
+) -> MerkleSumNode {
+    let cfg = PipelineConfig {
+        user_proof_format,
+        compress_root_proof,
+        ..pipeline_config(BATCH_SIZE, round)
+    };
+    let mut pipeline = new_pipeline(cfg, accounts, registry).unwrap();
+    let mut prover = LocalProver {
+        circuit_registry: registry,
+        batch_prove_threads_num: 2,
+        recursive_prove_threads_num: 2,
+    };
+    pipeline.prove_batches(&mut prover).unwrap();
+    let proof = pipeline.prove_recursion(&mut prover).unwrap();
+    registry.get_root_circuit().verify(proof.proof.clone()).unwrap();
+    assert_eq!(Some(2), proof.general.last_batch_size);
+    let public_inputs = &proof.proof.public_inputs;
+    assert_eq!(
+        [F::from_canonical_u64(round.round_num), F::from_canonical_u64(round.snapshot_timestamp)],
+        public_inputs[public_inputs.len() - 2..]
+    );
+
+    pipeline.persist_gmst();
+    pipeline
+        .dump_artifacts(&proof, output_dir, VecAccountParser { accounts: accounts.to_vec() })
+        .unwrap();
+
+    // the dumped json proof decompresses to the root proof, and converts to the binary format and back.
+    let (json_proof, compressed) =
+        Proof::decode(&fs::read(output_dir.join(GLOBAL_PROOF_FILENAME)).unwrap()).unwrap();
+    assert_eq!(compress_root_proof, compressed);
+    assert_eq!(proof.proof, json_proof.proof);
+    let binary_bytes = json_proof.to_bytes(ProofFormat::Binary).unwrap();
+    assert_eq!(ProofFormat::Binary, ProofFormat::detect(&binary_bytes));
+    let binary_proof = Proof::from_bytes(&binary_bytes).unwrap();
+    assert_eq!(
+        json_proof.to_bytes(ProofFormat::Json).unwrap(),
+        binary_proof.to_bytes(ProofFormat::Json).unwrap()
+    );
+    registry.get_root_circuit().verify(binary_proof.proof).unwrap();
+    assert!(matches!(
+        Proof::from_bytes(&binary_bytes[..binary_bytes.len() - 1]),
+        Err(PoRError::InvalidProofFile(_))
+    ));
+
+    // the compressed root proof is smaller in both formats, and decompresses to the root proof.
+    let compressed_proof = registry.get_root_circuit().compress(proof.proof.clone()).unwrap();
+    for format in [ProofFormat::Json, ProofFormat::Binary] {
+        let bytes = proof.to_compressed_bytes(format, &compressed_proof).unwrap();
+        assert!(bytes.len() < proof.to_bytes(format).unwrap().len());
+        let (decompressed_proof, compressed) = Proof::decode(&bytes).unwrap();
+        assert!(compressed);
+        assert_eq!(proof.proof, decompressed_proof.proof);
+    }
+
+    let info: Info =
+        serde_json::from_slice(&fs::read(output_dir.join(GLOBAL_INFO_FILENAME)).unwrap()).unwrap();
+    assert_eq!(accounts.len() as u64, info.user_count);
+    MerkleSumNode::new_from_public_inputs(public_inputs)
+}
+
+fn verify_user_proofs(
+    accounts: &[Account],
+    root: &MerkleSumNode,
+    user_proof_format: UserProofFormat,
+    output_dir: &Path,
+) {
+    let global_cfg =
+        pipeline_config(BATCH_SIZE, RoundInfo::default()).global_config(accounts.len());
+    let archive = match user_proof_format {
+        UserProofFormat::Json => None,
+        UserProofFormat::Archive => {
+            let archive =
+                UserProofArchive::open(&output_dir.join(USER_PROOF_ARCHIVE_FILENAME)).unwrap();
+            assert_eq!(accounts.len(), archive.len());
+            Some(archive)
+        }
+    };
+    for account in accounts.iter() {
+        let user_proof = match &archive {
+            Some(archive) => archive.get(&account.id).unwrap().unwrap(),
+            None => {
+                let user_proof_path =
+                    output_dir.join(USER_PROOF_DIRNAME).join(format!("{}.json", account.id));
+                serde_json::from_slice::<MerkleProof>(&fs::read(user_proof_path).unwrap()).unwrap()
+            }
+        };
+        user_proof
+            .verify_merkle_proof(root, &global_cfg, LeafHashMode::PerToken, &Valuation::default())
+            .unwrap();
+    }
+}
+
+fn init_registry() -> CircuitRegistry {
+    CircuitRegistry::init(
+        BATCH_SIZE,
+        Some(2),
+        TOKEN_NUM,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+        get_recursive_circuit_configs(5, RECURSION_BRANCHOUT_NUM),
+    )
+}
+
+#[test]
+fn test_proving_pipeline() {
+    let registry = init_registry();
+    let accounts = gen_accounts_with_random_data(USER_NUM, TOKEN_NUM);
+    let round = RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 };
+
+    // the circuits of the registry are not built for another batch size.
+    let result = new_pipeline(pipeline_config(BATCH_SIZE * 2, round), &accounts, &registry);
+    assert!(matches!(result, Err(PoRError::CircuitMismatch)));
+
+    // the gmst is not built for the number of users.
+    let cfg = pipeline_config(BATCH_SIZE, round);
+    let global_mst = Arc::new(RwLock::new(GlobalMst::new(cfg.global_config(accounts.len() - 1))));
+    let result = ProvingPipeline::new(
+        cfg,
+        VecAccountParser { accounts: accounts.clone() },
+        init_db(None, global_mst.clone()),
+        global_mst,
+        &registry,
+        SaltSeed::from_secret("pipeline"),
+    );
+    assert!(matches!(result, Err(PoRError::InvalidParameter(_))));
+
+    // the accounts of a parse round are not a multiple of the batch size.
+    let cfg = PipelineConfig {
+        per_parse_account_num: BATCH_SIZE * 2 + 1,
+        ..pipeline_config(BATCH_SIZE, round)
+    };
+    let result = new_pipeline(cfg, &accounts, &registry);
+    assert!(matches!(result, Err(PoRError::InvalidParameter(_))));
+
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    let root = prove_round(&registry, &accounts, round, UserProofFormat::Json, false, dir.path());
+    verify_user_proofs(&accounts, &root, UserProofFormat::Json, dir.path());
+}
+
+/// Prove the accounts of a round in the salt epoch with the proofs of the previous round in `proof_store_dir`, to which the proofs of
+/// this round are saved, and return the number of reused proofs at every level.
+fn prove_round_with_proof_store(
+    registry: &CircuitRegistry,
+    accounts: &[Account],
+    round: RoundInfo,
+    salt_epoch: usize,
+    proof_store_dir: &Path,
+    output_dir: &Path,
+) -> Vec<usize> {
+    let cfg = PipelineConfig { salt_epoch, ..pipeline_config(BATCH_SIZE, round) };
+    let mut pipeline = new_pipeline(cfg, accounts, registry).unwrap();
+    let proof_store =
+        ProofStore::load(proof_store_dir, registry, round.round_num as usize, salt_epoch).unwrap();
+    pipeline.set_proof_store(proof_store);
+    let mut prover = LocalProver {
+        circuit_registry: registry,
+        batch_prove_threads_num: 2,
+        recursive_prove_threads_num: 2,
+    };
+    pipeline.prove_batches(&mut prover).unwrap();
+    let proof = pipeline.prove_recursion(&mut prover).unwrap();
+    registry.get_root_circuit().verify(proof.proof.clone()).unwrap();
+    pipeline.persist_gmst();
+    pipeline.proof_store().unwrap().save(proof_store_dir).unwrap();
+    let reused_proof_nums = (0..=registry.get_recursive_levels())
+        .map(|level| pipeline.proof_store().unwrap().reused_proof_num(level))
+        .collect();
+
+    pipeline
+        .dump_artifacts(&proof, output_dir, VecAccountParser { accounts: accounts.to_vec() })
+        .unwrap();
+    let root = MerkleSumNode::new_from_public_inputs(&proof.proof.public_inputs);
+    verify_user_proofs(accounts, &root, UserProofFormat::Json, output_dir);
+    reused_proof_nums
+}
+
+#[test]
+fn test_reuse_proofs_across_rounds() {
+    let registry = init_registry();
+    let proof_store_dir = tempdir::TempDir::new("proof_store").unwrap();
+    let rounds = [
+        RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 },
+        RoundInfo { round_num: 4, snapshot_timestamp: 1726531200 },
+        RoundInfo { round_num: 5, snapshot_timestamp: 1726617600 },
+    ];
+    let salt_epoch = 3;
+    let mut accounts = gen_accounts_with_random_data(USER_NUM, TOKEN_NUM);
+
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    let reused_proof_nums = prove_round_with_proof_store(
+        &registry,
+        &accounts,
+        rounds[0],
+        salt_epoch,
+        proof_store_dir.path(),
+        dir.path(),
+    );
+    assert_eq!(vec![0; 4], reused_proof_nums);
+
+    // change a user of the first batch in the next round of the epoch. The other 4 batches, the 2 of 3 nodes at level 1 and the 1 of
+    // 2 nodes at level 2 above them are reused, while the top recursive node changes.
+    accounts[0] = gen_accounts_with_random_data(1, TOKEN_NUM).pop().unwrap();
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    let reused_proof_nums = prove_round_with_proof_store(
+        &registry,
+        &accounts,
+        rounds[1],
+        salt_epoch,
+        proof_store_dir.path(),
+        dir.path(),
+    );
+    assert_eq!(vec![4, 2, 1, 0], reused_proof_nums);
+
+    // the salts of a new epoch change all leaf hashes, hence no proof is reused.
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    let reused_proof_nums = prove_round_with_proof_store(
+        &registry,
+        &accounts,
+        rounds[2],
+        salt_epoch + 1,
+        proof_store_dir.path(),
+        dir.path(),
+    );
+    assert_eq!(vec![0; 4], reused_proof_nums);
+}
+
+#[test]
+fn test_concurrent_pipelines() {
+    let registry = init_registry();
+    let rounds = [
+        RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 },
+        RoundInfo { round_num: 4, snapshot_timestamp: 1726531200 },
+    ];
+    let user_proof_formats = [UserProofFormat::Json, UserProofFormat::Archive];
+    let compress_root_proofs = [false, true];
+    let accounts: Vec<Vec<Account>> =
+        rounds.iter().map(|_| gen_accounts_with_random_data(USER_NUM, TOKEN_NUM)).collect();
+    let dirs: Vec<tempdir::TempDir> =
+        rounds.iter().map(|_| tempdir::TempDir::new("pipeline").unwrap()).collect();
+
+    // two trees are built side by side in this process, each with its own gmst.
+    let roots: Vec<MerkleSumNode> = std::thread::scope(|s| {
+        let handles: Vec<_> = rounds
+            .iter()
+            .zip(accounts.iter())
+            .zip(user_proof_formats.iter().zip(compress_root_proofs.iter()))
+            .zip(dirs.iter())
+            .map(|(((round, accounts), (user_proof_format, compress_root_proof)), dir)| {
+                let registry = &registry;
+                s.spawn(move || {
+                    prove_round(
+                        registry,
+                        accounts,
+                        *round,
+                        *user_proof_format,
+                        *compress_root_proof,
+                        dir.path(),
+                    )
+                })
+            })
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+    assert_ne!(roots[0], roots[1]);
+
+    for i in 0..rounds.len() {
+        verify_user_proofs(&accounts[i], &roots[i], user_proof_formats[i], dirs[i].path());
+    }
+    // the user proofs of one tree do not verify against the root of the other.
+    let user_proof: MerkleProof = serde_json::from_slice(
+        &fs::read(
+            dirs[0].path().join(USER_PROOF_DIRNAME).join(format!("{}.json", accounts[0][0].id)),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(user_proof
+        .verify_merkle_proof(
+            &roots[1],
+            &pipeline_config(BATCH_SIZE, rounds[1]).global_config(USER_NUM),
+            LeafHashMode::PerToken,
+            &Valuation::default()
+        )
+        .is_err());
+}
+
+#[test]
+fn test_fewer_users_than_batch_size() {
+    // the users are a last batch after no full batch, instead of a padded full batch.
+    let user_num = BATCH_SIZE - 1;
+    let registry = CircuitRegistry::init(
+        BATCH_SIZE,
+        Some(user_num),
+        TOKEN_NUM,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+        get_recursive_circuit_configs(0, RECURSION_BRANCHOUT_NUM),
+    );
+    let accounts = gen_accounts_with_random_data(user_num, TOKEN_NUM);
+    let round = RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 };
+
+    let mut pipeline =
+        new_pipeline(pipeline_config(BATCH_SIZE, round), &accounts, &registry).unwrap();
+    let mut prover = LocalProver {
+        circuit_registry: &registry,
+        batch_prove_threads_num: 2,
+        recursive_prove_threads_num: 2,
+    };
+    pipeline.prove_batches(&mut prover).unwrap();
+    let proof = pipeline.prove_recursion(&mut prover).unwrap();
+    registry.get_root_circuit().verify(proof.proof.clone()).unwrap();
+    assert_eq!(Some(user_num), proof.general.last_batch_size);
+
+    pipeline.persist_gmst();
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    pipeline
+        .dump_artifacts(&proof, dir.path(), VecAccountParser { accounts: accounts.clone() })
+        .unwrap();
+    let info: Info =
+        serde_json::from_slice(&fs::read(dir.path().join(GLOBAL_INFO_FILENAME)).unwrap()).unwrap();
+    assert_eq!(user_num as u64, info.user_count);
+
+    let root = MerkleSumNode::new_from_public_inputs(&proof.proof.public_inputs);
+    verify_user_proofs(&accounts, &root, UserProofFormat::Json, dir.path());
+}
+
+/// A prover crashing at the recursive level `crash_level`, which records whether it proves any batch and the levels it proves.
+struct CrashingProver<'a> {
+    prover: LocalProver<'a>,
+    crash_level: Option<usize>,
+    proven_batches: bool,
+    proven_levels: Vec<usize>,
+}
+
+impl<'a> CrashingProver<'a> {
+    fn new(registry: &'a CircuitRegistry, crash_level: Option<usize>) -> Self {
+        let prover = LocalProver {
+            circuit_registry: registry,
+            batch_prove_threads_num: 2,
+            recursive_prove_threads_num: 2,
+        };
+        Self { prover, crash_level, proven_batches: false, proven_levels: vec![] }
+    }
+}
+
+impl PipelineProver for CrashingProver<'_> {
+    fn prove_batches(
+        &mut self,
+        accounts: Vec<Account>,
+        batch_roots: &[MerkleSumNode],
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        self.proven_batches = true;
+        self.prover.prove_batches(accounts, batch_roots)
+    }
+
+    fn prove_last_batch(
+        &mut self,
+        accounts: Vec<Account>,
+    ) -> Result<ProofWithPublicInputs<F, C, D>, PoRError> {
+        self.proven_batches = true;
+        self.prover.prove_last_batch(accounts)
+    }
+
+    fn prove_subproofs(
+        &mut self,
+        subproofs: Vec<ProofWithPublicInputs<F, C, D>>,
+        level: usize,
+        round: RoundInfo,
+    ) -> Result<Vec<ProofWithPublicInputs<F, C, D>>, PoRError> {
+        if self.crash_level == Some(level) {
+            return Err(PoRError::Unknown);
+        }
+        self.proven_levels.push(level);
+        self.prover.prove_subproofs(subproofs, level, round)
+    }
+}
+
+/// Prove the accounts of a round in the salt epoch with the proofs of the previous round in `proof_store_dir` through the checkpoint,
+/// dumping the artifacts to `output_dir`, and return the number of reused proofs at every level, or the error of the crashed prover.
+#[allow(clippy::too_many_arguments)]
+fn prove_round_with_checkpoint(
+    registry: &CircuitRegistry,
+    accounts: &[Account],
+    round: RoundInfo,
+    salt_epoch: usize,
+    proof_store_dir: &Path,
+    checkpoint: &mut Checkpoint,
+    prover: &mut CrashingProver,
+    output_dir: &Path,
+) -> Result<Vec<usize>, PoRError> {
+    let cfg = PipelineConfig { salt_epoch, ..pipeline_config(BATCH_SIZE, round) };
+    let mut pipeline = new_pipeline(cfg, accounts, registry)?;
+    let proof_store =
+        ProofStore::load(proof_store_dir, registry, round.round_num as usize, salt_epoch)?;
+    pipeline.set_proof_store(proof_store);
+    let mut checkpoint_prover = CheckpointProver::new(prover, checkpoint, registry);
+    pipeline.prove_batches(&mut checkpoint_prover)?;
+    let proof = pipeline.prove_recursion(&mut checkpoint_prover)?;
+    registry.get_root_circuit().verify(proof.proof.clone()).unwrap();
+    assert_eq!(Some(2), proof.general.last_batch_size);
+    pipeline.persist_gmst();
+    let reused_proof_nums = (0..=registry.get_recursive_levels())
+        .map(|level| pipeline.proof_store().unwrap().reused_proof_num(level))
+        .collect();
+
+    // the gmst holds the nodes of the checkpointed batches, the last batch and the checkpointed level.
+    pipeline.dump_artifacts(
+        &proof,
+        output_dir,
+        VecAccountParser { accounts: accounts.to_vec() },
+    )?;
+    let root = MerkleSumNode::new_from_public_inputs(&proof.proof.public_inputs);
+    verify_user_proofs(accounts, &root, UserProofFormat::Json, output_dir);
+    Ok(reused_proof_nums)
+}
+
+#[test]
+fn test_resume_with_proof_store_and_last_batch() {
+    let registry = init_registry();
+    let proof_store_dir = tempdir::TempDir::new("proof_store").unwrap();
+    let checkpoint_dir = tempdir::TempDir::new("checkpoint").unwrap();
+    let rounds = [
+        RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 },
+        RoundInfo { round_num: 4, snapshot_timestamp: 1726531200 },
+    ];
+    let salt_epoch = 3;
+    let mut accounts = gen_accounts_with_random_data(USER_NUM, TOKEN_NUM);
+
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    let reused_proof_nums = prove_round_with_proof_store(
+        &registry,
+        &accounts,
+        rounds[0],
+        salt_epoch,
+        proof_store_dir.path(),
+        dir.path(),
+    );
+    assert_eq!(vec![0; 4], reused_proof_nums);
+
+    // the next round of the epoch changes a user of the first batch, and crashes at level 2 after the 3 parse rounds, the last batch
+    // and level 1 are checkpointed. Only the proofs of the changed batch and nodes are proven and checkpointed, while the others are
+    // reused from the store.
+    accounts[0] = gen_accounts_with_random_data(1, TOKEN_NUM).pop().unwrap();
+    let checkpoint_path = checkpoint_dir.path().join("checkpoint");
+    let mut checkpoint = Checkpoint::create(
+        checkpoint_path.clone(),
+        "config".to_string(),
+        "input".to_string(),
+        rounds[1].snapshot_timestamp,
+        pipeline_config(BATCH_SIZE, rounds[1]).per_parse_account_num,
+    )
+    .unwrap();
+    let mut prover = CrashingProver::new(&registry, Some(2));
+    let dir = tempdir::TempDir::new("pipeline").unwrap();
+    let result = prove_round_with_checkpoint(
+        &registry,
+        &accounts,
+        rounds[1],
+        salt_epoch,
+        proof_store_dir.path(),
+        &mut checkpoint,
+        &mut prover,
+        dir.path(),
+    );
+    assert!(matches!(result, Err(PoRError::Unknown)));
+    assert!(prover.proven_batches);
+    assert_eq!(vec![1], prover.proven_levels);
+    assert_eq!(3, checkpoint.manifest.parse_rounds);
+    assert!(checkpoint.manifest.last_batch);
+    assert_eq!(1, checkpoint.manifest.recursive_levels);
+
+    // the resumed run loads the batches, the last batch and level 1 from the checkpoint, reuses the same proofs from the store, and
+    // only proves the levels above.
+    let mut checkpoint = Checkpoint::resume(checkpoint_path, "config", "input").unwrap();
+    let mut prover = CrashingProver::new(&registry, None);
+    let reused_proof_nums = prove_round_with_checkpoint(
+        &registry,
+        &accounts,
+        rounds[1],
+        salt_epoch,
+        proof_store_dir.path(),
+        &mut checkpoint,
+        &mut prover,
+        dir.path(),
+    )
+    .unwrap();
+    assert!(!prover.proven_batches);
+    assert_eq!(vec![2, 3], prover.proven_levels);
+    assert_eq!(vec![4, 2, 1, 0], reused_proof_nums);
+    assert_eq!(3, checkpoint.manifest.recursive_levels);
+    checkpoint.remove().unwrap();
+}
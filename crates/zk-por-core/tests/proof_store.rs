@@ -0,0 +1,42 @@
+use plonky2_field::types::Field;
+use zk_por_core::{
+    account::{gen_accounts_with_random_data, Account, LeafHashMode},
+    merkle_sum_tree::{MerkleSumNode, MerkleSumTree},
+    proof_store::ProofStore,
+    types::F,
+    valuation::Valuation,
+};
+
+const BATCH_SIZE: usize = 2;
+const TOKEN_NUM: usize = 2;
+
+fn batch_roots(accounts: &[Account]) -> Vec<MerkleSumNode> {
+    accounts
+        .chunks(BATCH_SIZE)
+        .map(|accounts| {
+            MerkleSumTree::new_tree_from_accounts(
+                accounts,
+                LeafHashMode::Sum,
+                &Valuation::default(),
+            )
+            .get_root()
+        })
+        .collect()
+}
+
+#[test]
+fn test_batch_key_commits_to_token_amounts() {
+    // the leaf hashes only commit to the sums, which are the same for both accounts.
+    let mut account = gen_accounts_with_random_data(1, TOKEN_NUM).pop().unwrap();
+    account.equity = vec![F::from_canonical_u64(1), F::from_canonical_u64(2)];
+    let mut swapped_account = account.clone();
+    swapped_account.equity = vec![F::from_canonical_u64(2), F::from_canonical_u64(1)];
+
+    let root = batch_roots(&[account.clone(), account.clone()])[0];
+    let swapped_root = batch_roots(&[swapped_account.clone(), account.clone()])[0];
+    assert_eq!(root, swapped_root);
+    assert_ne!(
+        ProofStore::batch_key(&root, &[account.clone(), account.clone()]),
+        ProofStore::batch_key(&swapped_root, &[swapped_account, account]),
+    );
+}
@@ -1,15 +1,19 @@
-use plonky2::{hash::hash_types::HashOut, plonk::proof::ProofWithPublicInputs};
 use zk_por_core::{
-    account::gen_accounts_with_random_data,
+    account::{gen_accounts_with_random_data, LeafHashMode},
     circuit_config::STANDARD_CONFIG,
     merkle_sum_prover::circuits::merkle_sum_circuit::build_merkle_sum_tree_circuit,
-    recursive_prover::{prover::RecursiveProver, recursive_circuit::RecursiveTargets},
-    types::{C, D, F},
+    merkle_sum_tree::MerkleSumNode,
+    recursive_prover::prover::RecursiveProver,
+    types::{C, F},
+    valuation::Valuation,
 };
 
 use zk_por_core::{
     merkle_sum_prover::prover::MerkleSumTreeProver,
-    recursive_prover::{prover::hash_n_subhashes, recursive_circuit::build_recursive_n_circuit},
+    recursive_prover::{
+        prover::hash_n_subnodes,
+        recursive_circuit::{build_recursive_n_circuit, RecursiveTargets, RoundInfo},
+    },
 };
 
 use plonky2_field::types::Field;
@@ -31,8 +35,13 @@ fn test() {
         .fold(F::ZERO, |acc, x| acc + x.debt.iter().fold(F::ZERO, |acc_2, y| acc_2 + *y));
 
     let start = std::time::Instant::now();
-    let (merkle_sum_circuit, account_targets) =
-        build_merkle_sum_tree_circuit(batch_size, token_num, STANDARD_CONFIG);
+    let (merkle_sum_circuit, account_targets) = build_merkle_sum_tree_circuit(
+        batch_size,
+        token_num,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+    );
     println!("build merkle sum tree circuit in : {:?}", start.elapsed());
 
     let prover = MerkleSumTreeProver { accounts };
@@ -42,34 +51,37 @@ fn test() {
         prover.get_proof_with_circuit_data(account_targets.as_slice(), &merkle_sum_circuit);
     println!("prove merkle sum tree in : {:?}", start.elapsed());
 
-    let sub_proofs: [ProofWithPublicInputs<F, C, D>; RECURSION_BRANCHOUT_NUM] =
-        std::array::from_fn(|_| merkle_sum_proof.clone());
+    let sub_proofs = vec![merkle_sum_proof.clone(); RECURSION_BRANCHOUT_NUM];
 
     let start = std::time::Instant::now();
-    let (recursive_circuit, recursive_targets) =
-        build_recursive_n_circuit::<C, RECURSION_BRANCHOUT_NUM>(
-            &merkle_sum_circuit.common,
-            &merkle_sum_circuit.verifier_only,
-            STANDARD_CONFIG,
-        );
+    let (recursive_circuit, recursive_targets) = build_recursive_n_circuit::<C>(
+        &merkle_sum_circuit.common,
+        &merkle_sum_circuit.verifier_only,
+        RECURSION_BRANCHOUT_NUM,
+        &Valuation::default(),
+        true,
+        STANDARD_CONFIG,
+    );
+    assert_eq!(RECURSION_BRANCHOUT_NUM, recursive_targets.subproof_num());
     println!("build recursive N circuit in : {:?}", start.elapsed());
 
     let start = std::time::Instant::now();
-    let hash_offset = RecursiveTargets::<RECURSION_BRANCHOUT_NUM>::pub_input_hash_offset();
-    let subhashes = sub_proofs
-        .clone()
-        .map(|proof| HashOut::<F>::from_partial(&proof.public_inputs[hash_offset.clone()]));
+    let subnodes: Vec<MerkleSumNode> = sub_proofs
+        .iter()
+        .map(|proof| MerkleSumNode::new_from_public_inputs(&proof.public_inputs))
+        .collect();
     let recursive_prover = RecursiveProver {
         sub_proofs: sub_proofs,
         sub_circuit_vd: merkle_sum_circuit.verifier_only.clone(),
+        round: RoundInfo { round_num: 508787475, snapshot_timestamp: 1726444800 },
     };
     let recursive_proof_result =
         recursive_prover.get_proof_with_circuit_data(recursive_targets, &recursive_circuit);
     println!("prove recursive subproofs in : {:?}", start.elapsed());
 
-    let expected_hash = hash_n_subhashes::<F, D>(&subhashes.to_vec());
+    let expected_hash = hash_n_subnodes(&subnodes);
     let actual_hash =
-        HashOut::<F>::from_partial(&recursive_proof_result.public_inputs[hash_offset]);
+        MerkleSumNode::new_from_public_inputs(&recursive_proof_result.public_inputs).hash;
 
     assert_eq!(expected_hash, actual_hash);
 
@@ -82,4 +94,23 @@ fn test() {
         debt_sum * F::from_canonical_u32(RECURSION_BRANCHOUT_NUM as u32),
         recursive_proof_result.public_inputs[1]
     );
+
+    assert_eq!(
+        Some((batch_size * RECURSION_BRANCHOUT_NUM) as u64),
+        RecursiveTargets::user_count_from_public_inputs(
+            token_num,
+            &Valuation::default(),
+            &recursive_proof_result.public_inputs
+        )
+    );
+
+    // the root circuit exposes the round info as the last public inputs.
+    assert_eq!(
+        Some(RoundInfo { round_num: 508787475, snapshot_timestamp: 1726444800 }),
+        RecursiveTargets::round_from_public_inputs(
+            token_num,
+            &Valuation::default(),
+            &recursive_proof_result.public_inputs
+        )
+    );
 }
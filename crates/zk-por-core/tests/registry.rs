@@ -3,8 +3,11 @@ use plonky2::{
 };
 use plonky2_field::types::Field;
 use zk_por_core::{
+    account::LeafHashMode,
     circuit_registry::registry::CircuitRegistry,
+    error::PoRError,
     types::{C, D, F},
+    valuation::Valuation,
 };
 
 #[test]
@@ -13,14 +16,23 @@ fn test() {
     let recursive_levels = 2;
     let recursive_level_configs = vec![zk_por_core::circuit_config::STANDARD_CONFIG; 2];
 
-    let registry =
-        CircuitRegistry::<2>::init(1024, 2, batch_circuit_config, recursive_level_configs);
+    let registry = CircuitRegistry::init(
+        1024,
+        None,
+        2,
+        2,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        batch_circuit_config,
+        recursive_level_configs,
+    );
 
     let batch_circuit = registry.get_batch_circuit().0;
     let batch_proof =
         registry.get_empty_proof(&batch_circuit.verifier_only.circuit_digest).unwrap().clone();
 
     assert_eq!(recursive_levels, registry.get_recursive_levels());
+    assert_eq!(2, registry.get_recursion_branchout_num());
     assert_eq!(F::ZERO, batch_proof.public_inputs[0]);
     assert_eq!(F::ZERO, batch_proof.public_inputs[1]);
     assert!(batch_circuit.verify(batch_proof).is_ok());
@@ -41,6 +53,226 @@ fn test() {
     assert_eq!(inner_vd_digest, root_circuit.verifier_only.circuit_digest);
 }
 
+#[test]
+fn test_last_batch() {
+    let registry = CircuitRegistry::init(
+        4,
+        Some(3),
+        2,
+        2,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        zk_por_core::circuit_config::STANDARD_CONFIG,
+        vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+    );
+
+    let (last_batch_circuit, account_targets) = registry.get_last_batch_circuit().unwrap();
+    assert_eq!(3, account_targets.len());
+    assert_ne!(
+        registry.get_batch_circuit().0.verifier_only.circuit_digest,
+        last_batch_circuit.verifier_only.circuit_digest
+    );
+
+    // the merge circuit is the root circuit instead of the top recursive circuit.
+    let merge_circuit = registry.get_merge_circuit().unwrap().0;
+    assert_eq!(
+        merge_circuit.verifier_only.circuit_digest,
+        registry.get_root_circuit().verifier_only.circuit_digest
+    );
+    assert_ne!(
+        registry.get_top_recursive_circuit().verifier_only.circuit_digest,
+        registry.get_root_circuit().verifier_only.circuit_digest
+    );
+
+    // the last batch and merge circuits have empty proofs like the other circuits.
+    for circuit in [last_batch_circuit, merge_circuit] {
+        let empty_proof = registry.get_empty_proof(&circuit.verifier_only.circuit_digest).unwrap();
+        assert!(circuit.verify(empty_proof.clone()).is_ok());
+    }
+}
+
+#[test]
+fn test_root_digest_changes_with_last_batch_size() {
+    let init = |last_batch_size: Option<usize>| {
+        CircuitRegistry::init(
+            4,
+            last_batch_size,
+            2,
+            2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+            zk_por_core::circuit_config::STANDARD_CONFIG,
+            vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+        )
+    };
+    let registries = [init(None), init(Some(1)), init(Some(3))];
+
+    // the batch and recursive circuits do not depend on the number of users modulo the batch size, but the root circuit does.
+    for registry in registries.iter().skip(1) {
+        assert_eq!(
+            registries[0].get_batch_circuit().0.verifier_only.circuit_digest,
+            registry.get_batch_circuit().0.verifier_only.circuit_digest
+        );
+        assert_eq!(
+            registries[0].get_top_recursive_circuit().verifier_only.circuit_digest,
+            registry.get_top_recursive_circuit().verifier_only.circuit_digest
+        );
+    }
+    let root_vd_digests = registries
+        .iter()
+        .map(|registry| registry.get_root_circuit().verifier_only.circuit_digest)
+        .collect::<Vec<_>>();
+    assert_ne!(root_vd_digests[0], root_vd_digests[1]);
+    assert_ne!(root_vd_digests[0], root_vd_digests[2]);
+    assert_ne!(root_vd_digests[1], root_vd_digests[2]);
+}
+
+#[test]
+fn test_save_load() {
+    let dir = tempdir::TempDir::new("circuit_registry").unwrap();
+    let init = |token_num: usize| {
+        CircuitRegistry::init(
+            4,
+            Some(3),
+            token_num,
+            2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+            zk_por_core::circuit_config::STANDARD_CONFIG,
+            vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+        )
+    };
+    let load = |token_num: usize| {
+        CircuitRegistry::load(
+            dir.path(),
+            4,
+            Some(3),
+            token_num,
+            2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+            zk_por_core::circuit_config::STANDARD_CONFIG,
+            vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+        )
+    };
+
+    let registry = init(2);
+    assert!(load(2).is_err());
+    let cache_dir = registry.save(dir.path()).unwrap();
+    assert_eq!(dir.path().join(registry.cache_key()), cache_dir);
+
+    let loaded = load(2).unwrap();
+    assert_eq!(registry.cache_key(), loaded.cache_key());
+    let batch_vd_digest = registry.get_batch_circuit().0.verifier_only.circuit_digest;
+    assert_eq!(batch_vd_digest, loaded.get_batch_circuit().0.verifier_only.circuit_digest);
+    assert_eq!(
+        registry.get_root_circuit().verifier_only.circuit_digest,
+        loaded.get_root_circuit().verifier_only.circuit_digest
+    );
+    assert_eq!(
+        registry.get_empty_proof(&batch_vd_digest),
+        loaded.get_empty_proof(&batch_vd_digest)
+    );
+    let loaded_empty_proof = loaded.get_empty_proof(&batch_vd_digest).unwrap().clone();
+    assert!(loaded.get_batch_circuit().0.verify(loaded_empty_proof).is_ok());
+    let merge_vd_digest = registry.get_merge_circuit().unwrap().0.verifier_only.circuit_digest;
+    assert_eq!(
+        registry.get_empty_proof(&merge_vd_digest),
+        loaded.get_empty_proof(&merge_vd_digest)
+    );
+
+    // the cache of other parameters is not found.
+    assert!(load(3).is_err());
+
+    // a stale circuit is rejected by its digest.
+    let last_batch_dir = cache_dir.join("last_batch_3");
+    std::fs::copy(last_batch_dir.join("merge_circuit.bin"), cache_dir.join("batch_circuit.bin"))
+        .unwrap();
+    assert!(load(2).is_err());
+
+    // a stale batch circuit is rejected by the rebuilt one, even if the manifest agrees with it.
+    std::fs::copy(
+        last_batch_dir.join("last_batch_circuit.bin"),
+        cache_dir.join("batch_circuit.bin"),
+    )
+    .unwrap();
+    let last_batch_manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(last_batch_dir.join("registry.json")).unwrap())
+            .unwrap();
+    let manifest_path = cache_dir.join("registry.json");
+    let mut manifest: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&manifest_path).unwrap()).unwrap();
+    manifest["circuit_digests"][0] = last_batch_manifest["last_batch_circuit_digest"].clone();
+    std::fs::write(&manifest_path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+    assert!(matches!(load(2), Err(PoRError::InvalidCircuitCache(_))));
+}
+
+#[test]
+fn test_load_other_last_batch_size() {
+    let dir = tempdir::TempDir::new("circuit_registry").unwrap();
+    let load = |last_batch_size: Option<usize>| {
+        CircuitRegistry::load(
+            dir.path(),
+            4,
+            last_batch_size,
+            2,
+            2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+            zk_por_core::circuit_config::STANDARD_CONFIG,
+            vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+        )
+    };
+    let load_or_init = |last_batch_size: Option<usize>| {
+        CircuitRegistry::load_or_init(
+            dir.path(),
+            4,
+            last_batch_size,
+            2,
+            2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+            zk_por_core::circuit_config::STANDARD_CONFIG,
+            vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+        )
+    };
+
+    // the cache of a round is found by the next round of another last batch size, which only builds the last batch circuits.
+    let registry = load_or_init(Some(3));
+    assert!(load(Some(1)).is_err());
+    let next_registry = load_or_init(Some(1));
+    assert_eq!(registry.cache_key(), next_registry.cache_key());
+    assert_eq!(
+        registry.get_top_recursive_circuit().verifier_only.circuit_digest,
+        next_registry.get_top_recursive_circuit().verifier_only.circuit_digest
+    );
+    assert_eq!(1, next_registry.get_last_batch_circuit().unwrap().1.len());
+    assert_eq!(
+        CircuitRegistry::init(
+            4,
+            Some(1),
+            2,
+            2,
+            LeafHashMode::PerToken,
+            &Valuation::default(),
+            zk_por_core::circuit_config::STANDARD_CONFIG,
+            vec![zk_por_core::circuit_config::STANDARD_CONFIG],
+        )
+        .get_root_circuit()
+        .verifier_only
+        .circuit_digest,
+        next_registry.get_root_circuit().verifier_only.circuit_digest
+    );
+    for last_batch_size in [Some(1), Some(3)] {
+        let loaded = load(last_batch_size).unwrap();
+        assert_eq!(last_batch_size, loaded.get_last_batch_circuit().map(|(_, t)| t.len()));
+    }
+
+    // the top recursive circuit of a round without a last batch is a root circuit, hence is cached apart.
+    assert!(load(None).is_err());
+    assert_ne!(registry.cache_key(), load_or_init(None).cache_key());
+}
+
 #[ignore] // avoid this test as this test takes a long time to run, and not necessary for the CI.
 #[test]
 fn test_serialize_root_circuit() {
@@ -51,9 +283,13 @@ fn test_serialize_root_circuit() {
     let batch_size = 1024;
     let token_num = 220;
     const RECURSION_BRANCHOUT_NUM: usize = 64;
-    let registry = CircuitRegistry::<RECURSION_BRANCHOUT_NUM>::init(
+    let registry = CircuitRegistry::init(
         batch_size,
+        None,
         token_num,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
         batch_circuit_config,
         recursive_level_configs,
     );
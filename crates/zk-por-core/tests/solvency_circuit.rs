@@ -0,0 +1,87 @@
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use plonky2_field::types::Field;
+use zk_por_core::{
+    account::{gen_accounts_with_random_data, LeafHashMode},
+    circuit_config::{STANDARD_CONFIG, STANDARD_ZK_CONFIG},
+    circuit_registry::registry::CircuitRegistry,
+    e2e::{batch_prove_accounts, recursive_prove_subproofs},
+    recursive_prover::recursive_circuit::RoundInfo,
+    solvency_prover::{
+        prover::SolvencyProver,
+        solvency_circuit::{build_solvency_circuit, SolvencyTargets},
+    },
+    types::{C, D, F},
+    valuation::Valuation,
+};
+
+const RECURSION_BRANCHOUT_NUM: usize = 2;
+const BATCH_SIZE: usize = 4;
+const TOKEN_NUM: usize = 2;
+const ROUND: RoundInfo = RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 };
+
+/// The registry and the root proof of 8 random users, who hold one more of every token than they owe, i.e., the liabilities of
+/// every token are 8.
+fn prove_root() -> (CircuitRegistry, ProofWithPublicInputs<F, C, D>) {
+    let registry = CircuitRegistry::init(
+        BATCH_SIZE,
+        None,
+        TOKEN_NUM,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+        vec![STANDARD_CONFIG],
+    );
+    let accounts = gen_accounts_with_random_data(BATCH_SIZE * 2, TOKEN_NUM);
+    let batch_proofs = batch_prove_accounts(&registry, accounts, 2, BATCH_SIZE);
+    let root_proof = recursive_prove_subproofs(batch_proofs, &registry, 2, ROUND, None);
+    (registry, root_proof)
+}
+
+fn prove_solvency(reserves: Vec<u64>, hide_liabilities: bool) -> ProofWithPublicInputs<F, C, D> {
+    let (registry, root_proof) = prove_root();
+    let root_circuit = registry.get_root_circuit();
+    let (solvency_circuit, solvency_targets) = build_solvency_circuit(
+        root_circuit,
+        TOKEN_NUM,
+        &Valuation::default(),
+        hide_liabilities,
+        STANDARD_ZK_CONFIG,
+    );
+    let prover = SolvencyProver {
+        root_proof,
+        root_circuit_vd: root_circuit.verifier_only.clone(),
+        reserves,
+    };
+    prover.get_proof_with_circuit_data(&solvency_targets, &solvency_circuit)
+}
+
+#[test]
+fn test_solvency() {
+    let proof = prove_solvency(vec![8, 100], false);
+
+    assert_eq!(
+        vec![8, 100],
+        SolvencyTargets::reserves_from_public_inputs(TOKEN_NUM, &proof.public_inputs)
+    );
+    assert_eq!(ROUND, SolvencyTargets::round_from_public_inputs(TOKEN_NUM, &proof.public_inputs));
+    assert_eq!(
+        F::from_canonical_u64(8),
+        proof.public_inputs[SolvencyTargets::pub_input_user_count_offset(TOKEN_NUM)]
+    );
+    let equity = &proof.public_inputs[SolvencyTargets::pub_input_token_equity_offset(TOKEN_NUM)];
+    let debt = &proof.public_inputs[SolvencyTargets::pub_input_token_debt_offset(TOKEN_NUM)];
+    (0..TOKEN_NUM).for_each(|i| assert_eq!(F::from_canonical_u64(8), equity[i] - debt[i]));
+}
+
+#[test]
+fn test_solvency_hide_liabilities() {
+    let proof = prove_solvency(vec![8, 8], true);
+    assert_eq!(SolvencyTargets::pub_input_round_offset(TOKEN_NUM).end, proof.public_inputs.len());
+}
+
+#[test]
+#[should_panic]
+fn test_insolvency_panic() {
+    prove_solvency(vec![7, 100], true);
+}
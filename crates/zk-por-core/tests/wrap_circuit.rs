@@ -0,0 +1,85 @@
+use plonky2::plonk::proof::ProofWithPublicInputs;
+use plonky2_field::types::Field;
+use zk_por_core::{
+    account::{gen_accounts_with_random_data, LeafHashMode},
+    circuit_config::STANDARD_CONFIG,
+    circuit_registry::registry::CircuitRegistry,
+    e2e::{batch_prove_accounts, recursive_prove_subproofs},
+    error::PoRError,
+    recursive_prover::recursive_circuit::RoundInfo,
+    types::{WrapC, C, D, F},
+    valuation::Valuation,
+    wrap_prover::{artifacts::WrapArtifacts, prover::WrapProver, wrap_circuit::build_wrap_circuit},
+};
+
+const RECURSION_BRANCHOUT_NUM: usize = 2;
+const BATCH_SIZE: usize = 4;
+const TOKEN_NUM: usize = 2;
+const ROUND: RoundInfo = RoundInfo { round_num: 3, snapshot_timestamp: 1726444800 };
+
+/// The registry and the root proof of 8 random users.
+fn prove_root() -> (CircuitRegistry, ProofWithPublicInputs<F, C, D>) {
+    let registry = CircuitRegistry::init(
+        BATCH_SIZE,
+        None,
+        TOKEN_NUM,
+        RECURSION_BRANCHOUT_NUM,
+        LeafHashMode::PerToken,
+        &Valuation::default(),
+        STANDARD_CONFIG,
+        vec![STANDARD_CONFIG],
+    );
+    let accounts = gen_accounts_with_random_data(BATCH_SIZE * 2, TOKEN_NUM);
+    let batch_proofs = batch_prove_accounts(&registry, accounts, 2, BATCH_SIZE);
+    let root_proof = recursive_prove_subproofs(batch_proofs, &registry, 2, ROUND, None);
+    (registry, root_proof)
+}
+
+fn prove_wrap(
+    registry: &CircuitRegistry,
+    root_proof: ProofWithPublicInputs<F, C, D>,
+) -> WrapArtifacts {
+    let root_circuit = registry.get_root_circuit();
+    let (wrap_circuit, wrap_targets) =
+        build_wrap_circuit(&root_circuit.common, &root_circuit.verifier_only, STANDARD_CONFIG);
+    let prover = WrapProver { root_proof, root_circuit_vd: root_circuit.verifier_only.clone() };
+    let proof = prover.get_proof_with_circuit_data(&wrap_targets, &wrap_circuit);
+    WrapArtifacts::new(proof, &wrap_circuit)
+}
+
+#[test]
+fn test_wrap() {
+    let (registry, root_proof) = prove_root();
+    let artifacts = prove_wrap(&registry, root_proof.clone());
+    assert_eq!(root_proof.public_inputs, artifacts.proof.public_inputs);
+    artifacts.verify().unwrap();
+
+    // the wrap circuit is rebuilt the same from the root verifier data.
+    let root_vd = registry.get_root_circuit().verifier_data();
+    let (wrap_circuit, _) =
+        build_wrap_circuit(&root_vd.common, &root_vd.verifier_only, STANDARD_CONFIG);
+    assert_eq!(
+        wrap_circuit.verifier_only.circuit_digest,
+        artifacts.verifier_data.verifier_only.circuit_digest
+    );
+
+    let dir = tempdir::TempDir::new("wrap").unwrap();
+    artifacts.dump(dir.path()).unwrap();
+    let loaded = WrapArtifacts::load(dir.path()).unwrap();
+    assert_eq!(artifacts.proof, loaded.proof);
+    loaded.verify().unwrap();
+
+    // a wrap proof with other public inputs does not verify.
+    let mut proof: ProofWithPublicInputs<F, WrapC, D> = loaded.proof.clone();
+    proof.public_inputs[0] += F::ONE;
+    let tampered = WrapArtifacts { proof, verifier_data: loaded.verifier_data };
+    assert!(matches!(tampered.verify(), Err(PoRError::InvalidProof)));
+}
+
+#[test]
+#[should_panic]
+fn test_wrap_invalid_root_proof_panic() {
+    let (registry, mut root_proof) = prove_root();
+    root_proof.public_inputs[0] += F::ONE;
+    prove_wrap(&registry, root_proof);
+}